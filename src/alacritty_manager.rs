@@ -1,68 +1,428 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::process::Command;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::{Component, Path, PathBuf};
+use std::os::unix::process::ExitStatusExt;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use anyhow::{Result, anyhow};
+use regex::Regex;
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::JoinHandle;
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::types::{AlacrittyInstance, SpawnParams, SendKeysParams, ScreenshotParams, NeovimContextParams};
-use crate::neovim_context::{NeovimContextExtractor, NeovimContext};
+use crate::types::{
+    AlacrittyInstance, InstanceStatus, WatchEvent, InstanceFilter, SpawnParams, GetInstanceParams, ListByTagParams, SendKeysParams, SendKeysSequenceParams, KeySequenceResult, SendKeysToTagParams, BatchSendKeysParams, BatchResult,
+    SendTextParams, SetClipboardParams, GetClipboardParams, ClipboardContent, ReloadConfigParams, ReloadResult, ScreenshotParams, ScreenshotDiffParams, DiffResult, ScreenshotRegionParams, MinimapParams, CloseParams,
+    ResizeParams, DimensionsParams, Dimensions, SetOptionParams, WriteFileParams, WriteFileResult, ReadFileParams, SearchParams, SearchResult, MemoryUsage, FileContent, FileError, RenameParams, DuplicateParams, DuplicateResult, FocusParams, KillSummary, ProcessTreeParams, ProcessNode, EnvironmentParams, EnvVar, ScrollbackParams, ScrollbackPage, WaitForOutputParams, MonitorParams, TimedScreenshot, OutputMatch, RunCommandParams, RunCommandResult,
+    NeovimContextParams, NeovimHoverParams, CompletionParams, ReferencesParams, DefinitionParams, CodeActionsParams, ApplyCodeActionParams, MarksParams, RegistersParams, JumplistParams, SessionVariablesParams, SpellErrorsParams, NeovimChangesParams, SearchHistoryParams, QuickfixParams, GitStatusParams, ExecuteCommandParams, RunTestParams, WorkspaceDiagnosticsParams, CloneBufferParams, CloneBufferResult,
+    NeovimInstanceList, SetNeovimOptionParams, HealthStatus,
+};
+use crate::error::AlacrittyMcpError;
+use crate::neovim_context::{set_option_keys, validate_ex_command, ExecResult, TestRunResult, NeovimContextExtractor, NeovimContext, TerminalContext, HoverResult, CompletionItem, Reference, DefinitionResult, CodeAction, Mark, RegisterContent, JumpEntry, SearchHistoryEntry, SessionVariables, SpellError, BufferChangeEvent, BufferWatch, QuickfixEntry, GitStatus, EditorType,
+    DiagnosticSeverity, WorkspaceDiagnostics};
+use crate::config::Config;
+use crate::platform::Platform;
+use crate::state_file::{StateFile, is_pid_alive, process_start_time};
+use crate::search::search_directory;
+use crate::terminal_parser::{command_output, diff_screens, minimap, DEFAULT_PROMPT_PATTERN};
+
+pub const MIN_TERMINAL_DIMENSION: u16 = 10;
+pub const MAX_TERMINAL_DIMENSION: u16 = 500;
+pub const MAX_TITLE_BYTES: usize = 255;
+pub const MIN_FONT_SIZE: f32 = 4.0;
+pub const MAX_FONT_SIZE: f32 = 72.0;
+
+/// First Alacritty release with `alacritty msg config`.
+pub const MIN_IPC_CONFIG_VERSION: (u32, u32) = (0, 13);
+
+/// `read_file` returns at most this much content unless `max_bytes` says otherwise.
+pub const DEFAULT_READ_MAX_BYTES: usize = 1024 * 1024;
+pub const DEFAULT_SEARCH_RESULTS: u32 = 100;
+pub const MAX_SEARCH_RESULTS: u32 = 1000;
+/// A null byte this early in a file marks it as binary.
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+/// `read_file` reads files this much at a time.
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+
+/// How many text screenshots are kept for `screenshot_diff`; older ones are
+/// dropped first.
+pub const MAX_STORED_SCREENSHOTS: usize = 64;
+
+/// Largest content `set_clipboard` accepts, in bytes.
+pub const MAX_CLIPBOARD_BYTES: usize = 10 * 1024 * 1024;
+
+/// How long `send_ipc_message` waits for Alacritty to take a message.
+pub const IPC_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Most screenshots one `monitor_instance` call collects.
+pub const MAX_MONITOR_SCREENSHOTS: u32 = 120;
+
+/// Longest pause `send_keys_sequence` accepts after a single event.
+pub const MAX_KEY_DELAY_MS: u32 = 10_000;
+
+/// How often `spawn_instance` looks for the new window on X11. The wait
+/// before each attempt grows by `WINDOW_ID_RETRY_DELAY`, so all ten take 11s.
+pub const WINDOW_ID_ATTEMPTS: u32 = 10;
+pub const WINDOW_ID_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(200);
+
+pub const DEFAULT_TEST_TIMEOUT_SECS: u64 = 60;
+pub const MAX_TEST_TIMEOUT_SECS: u64 = 600;
+
+/// Colon-separated directories `spawn_instance` may start terminals in,
+/// subdirectories included. Unset or empty allows any directory.
+pub const ALLOWED_DIRS_ENV: &str = "ALACRITTY_MCP_ALLOWED_DIRS";
+
+/// Variables whose names contain one of these have their values hidden by
+/// `list_instance_environment` unless `show_secrets` is set.
+pub const SECRET_ENV_MARKERS: &[&str] = &["SECRET", "TOKEN", "PASSWORD", "KEY"];
+pub const REDACTED_VALUE: &str = "<redacted>";
+
+pub const DEFAULT_RUN_COMMAND_TIMEOUT_MS: u64 = 30_000;
+const RUN_COMMAND_POLL_INTERVAL_MS: u64 = 250;
+
+// A typical Alacritty cell size, assumed where the real one is unknown: sway
+// resizes in pixels and does not report size hints
+const DEFAULT_CELL_WIDTH_PX: u32 = 9;
+const DEFAULT_CELL_HEIGHT_PX: u32 = 18;
 
 pub struct AlacrittyManager {
-    instances: HashMap<String, AlacrittyInstance>,
+    /// Shared with watcher tasks. Never held across an `.await` that does I/O,
+    /// so concurrent tool calls only wait for each other's bookkeeping.
+    instances: Arc<RwLock<HashMap<String, AlacrittyInstance>>>,
     neovim_extractor: NeovimContextExtractor,
+    platform: Platform,
+    state_file: Option<StateFile>,
+    config: Config,
+    /// Recent text screenshots, oldest first
+    screenshots: Mutex<VecDeque<StoredScreenshot>>,
+    started_at: Instant,
+}
+
+struct StoredScreenshot {
+    id: String,
+    instance_id: String,
+    text: String,
 }
 
 impl AlacrittyManager {
+    /// Uses the config file at `Config::default_path`, if there is one.
     pub fn new() -> Self {
+        Self::with_config(Config::load_default())
+    }
+
+    /// Persists the registry to the config's `state_file_path`, or to
+    /// `StateFile::default_path` when it does not set one.
+    pub fn with_config(config: Config) -> Self {
+        let state_file = config.state_file_path.clone()
+            .or_else(StateFile::default_path)
+            .map(StateFile::new);
+        Self::build(config, state_file)
+    }
+
+    /// Creates a manager that persists its registry to `state_file`, restoring
+    /// any still-running instances recorded there. `None` disables persistence.
+    pub fn with_state_file(state_file: Option<StateFile>) -> Self {
+        Self::build(Config::load_default(), state_file)
+    }
+
+    /// Uses `config` and `state_file` as given, without looking for either in
+    /// their default locations.
+    pub fn with_config_and_state_file(config: Config, state_file: Option<StateFile>) -> Self {
+        Self::build(config, state_file)
+    }
+
+    fn build(config: Config, state_file: Option<StateFile>) -> Self {
+        let instances = match &state_file {
+            Some(file) => file.load(&config.alacritty_binary).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable state file {}: {}", file.path().display(), e);
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+
         Self {
-            instances: HashMap::new(),
-            neovim_extractor: NeovimContextExtractor::new(),
+            instances: Arc::new(RwLock::new(instances)),
+            neovim_extractor: NeovimContextExtractor::new().with_command_timeout(config.socket_timeout()),
+            platform: Platform::detect(),
+            state_file,
+            config,
+            screenshots: Mutex::new(VecDeque::new()),
+            started_at: Instant::now(),
         }
     }
 
-    pub async fn list_instances(&mut self) -> Result<Vec<AlacrittyInstance>> {
+    /// A cheap liveness report for monitoring. Goes by the registry as it
+    /// stands rather than probing the processes.
+    pub async fn health(&self) -> HealthStatus {
+        let instances = self.instances.read().await
+            .values()
+            .filter(|instance| instance.status == InstanceStatus::Running)
+            .count();
+        HealthStatus {
+            status: "ok".to_string(),
+            instances,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Polls the managed instances every second on a background task, calling
+    /// `callback` once when an instance's process exits and once when an
+    /// instance without a window ID gets one. Abort the returned handle to
+    /// stop watching.
+    pub fn watch_instances(&self, callback: impl Fn(String, WatchEvent) + Send + 'static) -> JoinHandle<()> {
+        let instances = Arc::clone(&self.instances);
+        let platform = self.platform;
+
+        tokio::spawn(async move {
+            // Last known window per instance; instances are dropped once exited
+            let mut seen: HashMap<String, Option<u32>> = HashMap::new();
+            let mut exited: Vec<String> = Vec::new();
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+
+            loop {
+                interval.tick().await;
+                let snapshot: HashMap<String, (u32, Option<u32>, Option<String>)> = instances.read().await
+                    .iter()
+                    .map(|(id, instance)| (id.clone(), (instance.pid, instance.window_id, instance.window_class.clone())))
+                    .collect();
+                exited.retain(|instance_id| snapshot.contains_key(instance_id));
+                seen.retain(|instance_id, _| snapshot.contains_key(instance_id));
+
+                for (instance_id, (pid, window_id, window_class)) in snapshot {
+                    if exited.contains(&instance_id) {
+                        continue;
+                    }
+                    if !is_pid_alive(pid) {
+                        seen.remove(&instance_id);
+                        exited.push(instance_id.clone());
+                        callback(instance_id, WatchEvent::Exited);
+                        continue;
+                    }
+
+                    let window_id = match window_id {
+                        Some(window_id) => Some(window_id),
+                        None if platform == Platform::X11 => find_window_id(pid, window_class.as_deref()).ok(),
+                        None => None,
+                    };
+
+                    // The first sighting only records the state, so windows that
+                    // were already known are not reported
+                    if let (Some(None), Some(window_id)) = (seen.insert(instance_id.clone(), window_id), window_id) {
+                        callback(instance_id, WatchEvent::NewWindow { window_id });
+                    }
+                }
+            }
+        })
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub async fn list_instances(&self, filter: Option<InstanceFilter>) -> Result<Vec<AlacrittyInstance>> {
         self.refresh_instances().await?;
-        Ok(self.instances.values().cloned().collect())
+        let mut instances = self.instances.write().await;
+        for instance in instances.values_mut() {
+            // Once exited the PID may be reused, so never flip back to running
+            if instance.status == InstanceStatus::Running {
+                instance.status = process_status(instance.pid);
+            }
+        }
+        Ok(instances.values()
+            .filter(|instance| match &filter {
+                Some(filter) => filter.matches(instance),
+                None => true,
+            })
+            .cloned()
+            .collect())
     }
 
-    pub async fn spawn_instance(&mut self, params: SpawnParams) -> Result<AlacrittyInstance> {
-        let instance_id = Uuid::new_v4().to_string();
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    /// Running instances started with `nvim` or with `nvim` anywhere in
+    /// their process tree, e.g. opened from the shell.
+    pub async fn list_neovim_instances(&self, filter: Option<InstanceFilter>) -> Result<NeovimInstanceList> {
+        let mut instances = Vec::new();
+        let mut socket_paths = BTreeMap::new();
 
-        let mut cmd = Command::new("alacritty");
-        
-        // Set title if provided
-        if let Some(title) = &params.title {
-            cmd.args(&["--title", title]);
-        } else {
-            cmd.args(&["--title", &format!("alacritty-mcp-{}", &instance_id[..8])]);
+        for instance in self.list_instances(filter).await? {
+            if instance.status != InstanceStatus::Running {
+                continue;
+            }
+            let started_with_nvim = instance.command.split_whitespace().next().map(is_nvim).unwrap_or(false);
+            if !started_with_nvim && !read_process_tree(instance.pid).map(|tree| runs_nvim(&tree)).unwrap_or(false) {
+                continue;
+            }
+
+            if let Ok(socket) = self.neovim_extractor.find_neovim_socket(instance.pid).await {
+                socket_paths.insert(instance.id.clone(), socket);
+            }
+            instances.push(instance);
+        }
+
+        Ok(NeovimInstanceList {
+            neovim_detected_count: instances.len() as u32,
+            instances,
+            socket_paths,
+        })
+    }
+
+    pub async fn list_instances_by_tag(&self, params: ListByTagParams) -> Result<Vec<AlacrittyInstance>> {
+        Ok(self.list_instances(None).await?
+            .into_iter()
+            .filter(|instance| instance.tags.contains(&params.tag))
+            .collect())
+    }
+
+    /// Refreshes a single instance without scanning every Alacritty process.
+    /// Instances whose process has exited are dropped from the registry.
+    pub async fn get_instance(&self, params: GetInstanceParams) -> Result<AlacrittyInstance> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        if instance.status == InstanceStatus::Exited || !is_pid_alive(instance.pid) {
+            self.instances.write().await.remove(&params.instance_id);
+            self.registry_changed().await;
+            return Err(match instance.exit_code {
+                Some(code) => anyhow!("Instance {} has exited (PID {}, exit code {})", params.instance_id, instance.pid, code),
+                None => anyhow!("Instance {} has exited (PID {})", params.instance_id, instance.pid),
+            });
+        }
+
+        if instance.window_id.is_none() && self.platform == Platform::X11 {
+            let _ = self.get_window_id_for_instance(&params.instance_id).await;
+        }
+
+        let mut instances = self.instances.write().await;
+        let instance = instances.get_mut(&params.instance_id)
+            .ok_or_else(|| AlacrittyMcpError::InstanceNotFound(params.instance_id.clone()))?;
+        instance.status = InstanceStatus::Running;
+        Ok(instance.clone())
+    }
+
+    /// Memory of the instance's Alacritty process, not counting the shell
+    /// or anything else running inside the terminal.
+    pub async fn get_memory_usage(&self, id: &str) -> Result<MemoryUsage> {
+        let instance = self.instance(id).await?;
+        if instance.status == InstanceStatus::Exited || !is_pid_alive(instance.pid) {
+            return Err(anyhow!("Instance {} has exited (PID {})", id, instance.pid));
+        }
+
+        read_memory_usage(instance.pid)
+    }
+
+    pub async fn spawn_instance(&self, params: SpawnParams) -> Result<AlacrittyInstance> {
+        if let Some(config_file) = &params.config_file {
+            let path = std::path::Path::new(config_file);
+            if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                return Err(AlacrittyMcpError::InvalidParams(format!("config_file must be a .toml file, got {}", config_file)).into());
+            }
+            if !path.is_file() {
+                return Err(AlacrittyMcpError::InvalidParams(format!("config_file does not exist: {}", config_file)).into());
+            }
+        }
+        if let Some(geometry) = &params.geometry {
+            let bounds = MIN_TERMINAL_DIMENSION..=MAX_TERMINAL_DIMENSION;
+            if !bounds.contains(&geometry.cols) || !bounds.contains(&geometry.rows) {
+                return Err(AlacrittyMcpError::InvalidParams(format!(
+                    "geometry cols and rows must be between {} and {}, got {}x{}",
+                    MIN_TERMINAL_DIMENSION, MAX_TERMINAL_DIMENSION, geometry.cols, geometry.rows
+                )).into());
+            }
+            if geometry.x.is_some() != geometry.y.is_some() {
+                return Err(AlacrittyMcpError::InvalidParams("geometry x and y must be given together".to_string()).into());
+            }
         }
+        if let Some(font_size) = params.font_size {
+            if !(MIN_FONT_SIZE..=MAX_FONT_SIZE).contains(&font_size) {
+                return Err(AlacrittyMcpError::InvalidParams(format!(
+                    "font_size must be between {} and {}, got {}", MIN_FONT_SIZE, MAX_FONT_SIZE, font_size
+                )).into());
+            }
+        }
+        if params.font_family.as_deref().is_some_and(|family| family.trim().is_empty()) {
+            return Err(AlacrittyMcpError::InvalidParams("font_family must not be empty".to_string()).into());
+        }
+
+        let instance_id = Uuid::new_v4().to_string();
+        let timestamp = unix_timestamp();
+
+        // Spawned through tokio so the child can be awaited, and reaped, below
+        let mut cmd = tokio::process::Command::new(&self.config.alacritty_binary);
+
+        let title = params.title.clone()
+            .unwrap_or_else(|| format!("{}-{}", self.config.title_prefix(), &instance_id[..8]));
+        cmd.args(["--title", &title]);
+
+        // Add class for identification
+        let window_class = instance_class(&instance_id);
+        cmd.args(["--class", &window_class]);
 
         // Set working directory if provided
-        if let Some(wd) = &params.working_directory {
-            cmd.args(&["--working-directory", wd]);
+        let mut working_directory = params.working_directory.clone()
+            .or_else(|| self.config.default_working_directory.clone());
+        if let Some(allowed) = allowed_dirs() {
+            // Without a directory Alacritty starts in ours, so that is checked
+            // and then passed on explicitly
+            let wd = match working_directory.take() {
+                Some(wd) => wd,
+                None => std::env::current_dir()?.to_string_lossy().to_string(),
+            };
+            check_allowed_dir(&wd, &allowed)?;
+            working_directory = Some(wd);
+        }
+        if let Some(wd) = &working_directory {
+            cmd.args(["--working-directory", wd]);
+        }
+
+        if let Some(config_file) = &params.config_file {
+            cmd.args(["--config-file", config_file]);
+        }
+
+        // Must come before `--command`, which takes the rest of the arguments
+        if let Some(geometry) = &params.geometry {
+            cmd.args(["--option", &format!("window.dimensions.columns={}", geometry.cols)]);
+            cmd.args(["--option", &format!("window.dimensions.lines={}", geometry.rows)]);
+            if let (Some(x), Some(y)) = (geometry.x, geometry.y) {
+                cmd.args(["--option", &format!("window.position.x={}", x)]);
+                cmd.args(["--option", &format!("window.position.y={}", y)]);
+            }
+        }
+        if let Some(font_size) = params.font_size {
+            cmd.args(["--option", &format!("font.size={}", font_size)]);
+        }
+        // Option values are TOML, so names with spaces need quoting
+        if let Some(font_family) = &params.font_family {
+            cmd.args(["--option", &format!("font.normal.family={}", toml::Value::String(font_family.clone()))]);
         }
 
         // Set command if provided
         if let Some(command) = &params.command {
-            cmd.args(&["--command"]);
+            cmd.args(["--command"]);
             cmd.arg(command);
             if let Some(args) = &params.args {
                 cmd.args(args);
             }
         }
 
-        // Add class for identification
-        cmd.args(&["--class", &format!("alacritty-mcp-{}", instance_id)]);
+        // The server's environment is inherited, caller-supplied values win
+        if let Some(env) = &params.env {
+            cmd.envs(env);
+        }
 
-        let child = cmd.spawn()?;
-        let pid = child.id();
+        let mut child = cmd.spawn()?;
+        let pid = child.id().ok_or_else(|| anyhow!("alacritty exited before its PID was read"))?;
 
-        let title = params.title.unwrap_or_else(|| format!("alacritty-mcp-{}", &instance_id[..8]));
+        let working_directory = working_directory.or_else(|| read_cwd(pid));
         let command_str = params.command.unwrap_or_else(|| "shell".to_string());
+        let args = params.args.unwrap_or_default();
 
         let instance = AlacrittyInstance {
             id: instance_id.clone(),
@@ -70,328 +430,2409 @@ impl AlacrittyManager {
             window_id: None,
             title,
             command: command_str,
+            args,
             created_at: timestamp,
+            status: InstanceStatus::Running,
+            last_focused_at: None,
+            tags: params.tags.unwrap_or_default(),
+            working_directory,
+            exit_code: None,
+            window_class: Some(window_class),
+            ipc_socket_path: None,
+            last_activity_at: timestamp,
+            process_started_at: process_start_time(pid),
         };
 
-        self.instances.insert(instance_id.clone(), instance.clone());
-
-        // Give the window time to appear
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        
-        // Try to get the window ID
-        if let Ok(window_id) = self.get_window_id_for_instance(&instance_id).await {
-            if let Some(inst) = self.instances.get_mut(&instance_id) {
-                inst.window_id = Some(window_id);
+        self.instances.write().await.insert(instance_id.clone(), instance.clone());
+
+        // Reap the process as soon as it exits so it never lingers as a
+        // zombie, and keep its exit code around for `list_instances`
+        let instances = Arc::clone(&self.instances);
+        let reaped_id = instance_id.clone();
+        tokio::spawn(async move {
+            let exit_code = match child.wait().await {
+                Ok(status) => status.code().or_else(|| status.signal().map(|signal| 128 + signal)),
+                Err(e) => {
+                    warn!("Failed to wait for alacritty PID {}: {}", pid, e);
+                    None
+                }
+            };
+            if let Some(instance) = instances.write().await.get_mut(&reaped_id) {
+                instance.status = InstanceStatus::Exited;
+                instance.exit_code = exit_code;
             }
+        });
+
+        // Give the window time to appear. Wayland has no global window IDs,
+        // so there is nothing to look up there.
+        if self.platform == Platform::X11 {
+            self.wait_for_window_id(&instance_id).await;
+        } else {
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
         }
+        // Alacritty older than 0.13, or with `ipc_socket` off, has none
+        let _ = self.ipc_socket(&instance_id).await;
 
-        Ok(instance)
+        self.registry_changed().await;
+        Ok(self.instances.read().await.get(&instance_id).cloned().unwrap_or(instance))
     }
 
-    pub async fn send_keys(&self, params: SendKeysParams) -> Result<()> {
-        let instance = self.instances.get(&params.instance_id)
-            .ok_or_else(|| anyhow!("Instance not found: {}", params.instance_id))?;
+    /// Spawns a second terminal running the same command and arguments with the same tags,
+    /// titled `<title> (2)` and started in the directory the original's
+    /// shell is currently in.
+    pub async fn duplicate_instance(&self, params: DuplicateParams) -> Result<DuplicateResult> {
+        let original = self.get_instance(GetInstanceParams { instance_id: params.instance_id }).await?;
 
-        if let Some(window_id) = instance.window_id {
-            // Use xdotool to send keys to the specific window
-            let output = Command::new("xdotool")
-                .args(&["key", "--window", &window_id.to_string()])
-                .arg(&params.keys)
-                .output()?;
+        let working_directory = shell_cwd(original.pid);
 
-            if !output.status.success() {
-                return Err(anyhow!("Failed to send keys: {}", 
-                    String::from_utf8_lossy(&output.stderr)));
-            }
-        } else {
-            // Fallback: try to find window and send keys
-            let window_id = self.get_window_id_for_instance(&params.instance_id).await?;
-            let output = Command::new("xdotool")
-                .args(&["key", "--window", &window_id.to_string()])
-                .arg(&params.keys)
-                .output()?;
+        let duplicate = self.spawn_instance(SpawnParams {
+            command: Some(original.command.clone()).filter(|command| command != "shell"),
+            args: Some(original.args.clone()).filter(|args| !args.is_empty()),
+            working_directory,
+            title: Some(format!("{} (2)", original.title)),
+            tags: Some(original.tags.clone()),
+            ..Default::default()
+        }).await?;
+
+        Ok(DuplicateResult { original, duplicate })
+    }
 
-            if !output.status.success() {
-                return Err(anyhow!("Failed to send keys: {}", 
-                    String::from_utf8_lossy(&output.stderr)));
+    pub async fn send_keys(&self, params: SendKeysParams) -> Result<()> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        let output = match self.platform {
+            Platform::X11 => {
+                let window_id = match instance.window_id {
+                    Some(wid) => wid,
+                    None => self.get_window_id_for_instance(&params.instance_id).await?,
+                };
+
+                // Use xdotool to send keys to the specific window
+                Command::new("xdotool")
+                    .args(["key", "--window", &window_id.to_string()])
+                    .arg(&params.keys)
+                    .output()?
+            }
+            Platform::Wayland => {
+                // ydotool cannot target a window, keys go to the focused one
+                Command::new("ydotool")
+                    .arg("key")
+                    .args(ydotool_key_args(&params.keys)?)
+                    .output()?
             }
+        };
+
+        if !output.status.success() {
+            return Err(self.window_command_failed(format!("Failed to send keys: {}",
+                String::from_utf8_lossy(&output.stderr))));
         }
 
+        self.touch(&params.instance_id).await;
         Ok(())
     }
 
-    pub async fn screenshot_instance(&self, params: ScreenshotParams) -> Result<String> {
-        let instance = self.instances.get(&params.instance_id)
-            .ok_or_else(|| anyhow!("Instance not found: {}", params.instance_id))?;
-
-        let window_id = if let Some(wid) = instance.window_id {
-            wid
-        } else {
-            self.get_window_id_for_instance(&params.instance_id).await?
-        };
+    /// Sends each event's keys in order, pausing for its `delay_after_ms`
+    /// before going on, for applications that need input paced.
+    pub async fn send_keys_sequence(&self, params: SendKeysSequenceParams) -> Result<KeySequenceResult> {
+        if params.sequence.is_empty() {
+            return Err(AlacrittyMcpError::InvalidParams("sequence must contain at least one event".to_string()).into());
+        }
+        if let Some(delay) = params.sequence.iter().filter_map(|event| event.delay_after_ms).find(|&delay| delay > MAX_KEY_DELAY_MS) {
+            return Err(AlacrittyMcpError::InvalidParams(format!(
+                "delay_after_ms must be at most {}, got {}", MAX_KEY_DELAY_MS, delay
+            )).into());
+        }
 
-        let format = params.format.as_deref().unwrap_or("text");
+        let started = Instant::now();
+        let total = params.sequence.len();
+        for (sent, event) in params.sequence.into_iter().enumerate() {
+            self.send_keys(SendKeysParams {
+                instance_id: params.instance_id.clone(),
+                keys: event.keys,
+            }).await.map_err(|e| anyhow!("Sent {} of {} events, then: {}", sent, total, e))?;
 
-        match format {
-            "text" => self.screenshot_text(window_id).await,
-            "image" => self.screenshot_image(window_id).await,
-            _ => Err(anyhow!("Unsupported format: {}", format)),
+            if let Some(delay) = event.delay_after_ms.filter(|&delay| delay > 0) {
+                tokio::time::sleep(std::time::Duration::from_millis(delay as u64)).await;
+            }
         }
+
+        Ok(KeySequenceResult {
+            events_sent: total as u32,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        })
     }
 
-    async fn screenshot_text(&self, window_id: u32) -> Result<String> {
-        // Use xdotool to get text content from the terminal
-        let output = Command::new("xdotool")
-            .args(&["getwindowgeometry", &window_id.to_string()])
-            .output()?;
+    /// Sends the same keys to every instance carrying `tag`, returning the IDs
+    /// that received them. On Wayland each window is focused first, since
+    /// ydotool only reaches the focused one.
+    pub async fn send_keys_to_tag(&self, params: SendKeysToTagParams) -> Result<Vec<String>> {
+        let mut instance_ids: Vec<String> = self.instances.read().await.values()
+            .filter(|instance| instance.tags.contains(&params.tag))
+            .map(|instance| instance.id.clone())
+            .collect();
+        instance_ids.sort();
 
-        if !output.status.success() {
-            return Err(anyhow!("Failed to get window geometry"));
+        if instance_ids.is_empty() {
+            return Err(anyhow!("No instances tagged '{}'", params.tag));
         }
 
-        // Get the window content using xwininfo and xwd
-        let output = Command::new("xwininfo")
-            .args(&["-id", &window_id.to_string(), "-tree"])
-            .output()?;
-
-        if !output.status.success() {
-            return Err(anyhow!("Failed to get window info"));
+        for instance_id in &instance_ids {
+            if self.platform == Platform::Wayland {
+                self.focus_instance(FocusParams { instance_id: instance_id.clone() }).await?;
+            }
+            self.send_keys(SendKeysParams {
+                instance_id: instance_id.clone(),
+                keys: params.keys.clone(),
+            }).await?;
         }
 
-        // For text extraction, we'll use a different approach
-        // Copy all text from the terminal using xsel or xclip
-        let _select_output = Command::new("xdotool")
-            .args(&["windowactivate", &window_id.to_string()])
-            .output()?;
-
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        Ok(instance_ids)
+    }
 
-        // Select all text
-        let _select_output = Command::new("xdotool")
-            .args(&["key", "--window", &window_id.to_string(), "ctrl+shift+a"])
-            .output()?;
+    /// Sends each target its own keys. On X11 all deliveries run at once, as
+    /// each goes to its own window; on Wayland they take turns, because every
+    /// one has to focus its window first. A failed delivery is recorded and
+    /// the rest go ahead.
+    pub async fn batch_send_keys(&self, params: BatchSendKeysParams) -> Result<BatchResult> {
+        if params.targets.is_empty() {
+            return Err(AlacrittyMcpError::InvalidParams("targets must not be empty".to_string()).into());
+        }
+        let mut seen = std::collections::HashSet::new();
+        if let Some(target) = params.targets.iter().find(|target| !seen.insert(&target.instance_id)) {
+            return Err(AlacrittyMcpError::InvalidParams(format!(
+                "Instance {} is targeted more than once", target.instance_id
+            )).into());
+        }
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        let deliver = |instance_id: String, keys: String| async move {
+            if self.platform == Platform::Wayland {
+                self.focus_instance(FocusParams { instance_id: instance_id.clone() }).await?;
+            }
+            self.send_keys(SendKeysParams { instance_id, keys }).await
+        };
 
-        // Copy to clipboard
-        let _copy_output = Command::new("xdotool")
-            .args(&["key", "--window", &window_id.to_string(), "ctrl+shift+c"])
-            .output()?;
+        let outcomes = match self.platform {
+            Platform::X11 => futures::future::join_all(params.targets.iter().map(|target| {
+                deliver(target.instance_id.clone(), target.keys.clone())
+            })).await,
+            Platform::Wayland => {
+                let mut outcomes = Vec::new();
+                for target in &params.targets {
+                    outcomes.push(deliver(target.instance_id.clone(), target.keys.clone()).await);
+                }
+                outcomes
+            }
+        };
 
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        let mut result = BatchResult::default();
+        for (target, outcome) in params.targets.into_iter().zip(outcomes) {
+            match outcome {
+                Ok(()) => result.succeeded.push(target.instance_id),
+                Err(e) => {
+                    result.failed.insert(target.instance_id, e.to_string());
+                }
+            }
+        }
+        Ok(result)
+    }
 
-        // Get clipboard content
-        let clipboard_output = Command::new("xclip")
-            .args(&["-o", "-selection", "clipboard"])
-            .output()?;
+    /// Puts `content` on the clipboard with `xclip` or `wl-copy` and returns
+    /// its size in bytes. With an instance the tool runs on that instance's
+    /// display, read from its environment.
+    pub async fn set_clipboard(&self, params: SetClipboardParams) -> Result<usize> {
+        if params.content.len() > MAX_CLIPBOARD_BYTES {
+            return Err(AlacrittyMcpError::InvalidParams(format!(
+                "Clipboard content is {} bytes, at most {} are accepted", params.content.len(), MAX_CLIPBOARD_BYTES
+            )).into());
+        }
 
-        if clipboard_output.status.success() {
-            Ok(String::from_utf8_lossy(&clipboard_output.stdout).to_string())
-        } else {
-            Err(anyhow!("Failed to get clipboard content"))
+        let program = match self.platform {
+            Platform::X11 => "xclip",
+            Platform::Wayland => "wl-copy",
+        };
+        let mut cmd = self.clipboard_command(program, params.instance_id.as_deref()).await?;
+        if self.platform == Platform::X11 {
+            cmd.args(["-selection", "clipboard"]);
         }
+        // Both fork to keep serving the selection, and the fork would hold
+        // on to captured output until it is replaced
+        cmd.stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+
+        let mut child = cmd.spawn().map_err(|e| anyhow!("Cannot run {}: {}", program, e))?;
+        // Dropping stdin closes it, which tells the tool the content is complete
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(params.content.as_bytes()).await?;
+        }
+        let status = child.wait().await?;
+        if !status.success() {
+            return Err(anyhow!("{} failed with {}", program, status));
+        }
+
+        Ok(params.content.len())
     }
 
-    async fn screenshot_image(&self, window_id: u32) -> Result<String> {
-        // Take a screenshot of the window using imagemagick
-        let temp_file = format!("/tmp/alacritty_screenshot_{}.png", window_id);
-        
-        let output = Command::new("import")
-            .args(&["-window", &window_id.to_string(), &temp_file])
-            .output()?;
+    /// Reads the clipboard with `xclip -o` or `wl-paste`, on the display of
+    /// `instance_id` if one is given. Binary content, e.g. a copied image,
+    /// is an error.
+    pub async fn get_clipboard(&self, params: GetClipboardParams) -> Result<ClipboardContent> {
+        let program = match self.platform {
+            Platform::X11 => "xclip",
+            Platform::Wayland => "wl-paste",
+        };
+        let mut cmd = self.clipboard_command(program, params.instance_id.as_deref()).await?;
+        match self.platform {
+            Platform::X11 => cmd.args(["-selection", "clipboard", "-o"]),
+            Platform::Wayland => cmd.arg("--no-newline"),
+        };
 
+        let output = cmd.output().await.map_err(|e| anyhow!("Cannot run {}: {}", program, e))?;
         if !output.status.success() {
-            return Err(anyhow!("Failed to take screenshot: {}", 
-                String::from_utf8_lossy(&output.stderr)));
+            return Err(anyhow!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        if output.stdout.contains(&0) {
+            return Err(anyhow!("The clipboard holds {} bytes of binary data, not text", output.stdout.len()));
         }
 
-        // Read the file and encode as base64
-        let image_data = std::fs::read(&temp_file)?;
-        let base64_data = base64::encode(&image_data);
-        
-        // Clean up temp file
-        let _ = std::fs::remove_file(&temp_file);
-
-        Ok(format!("data:image/png;base64,{}", base64_data))
+        Ok(ClipboardContent {
+            size_bytes: output.stdout.len() as u32,
+            text: String::from_utf8_lossy(&output.stdout).to_string(),
+        })
     }
 
-    pub async fn get_neovim_context(&self, params: NeovimContextParams) -> Result<NeovimContext> {
-        let instance = self.instances.get(&params.instance_id)
-            .ok_or_else(|| anyhow!("Instance not found: {}", params.instance_id))?;
-
-        // First, check if this terminal is likely running Neovim
-        if let Ok(screenshot) = self.screenshot_text(instance.window_id.unwrap_or(0)).await {
-            if !self.neovim_extractor.detect_neovim_in_terminal(&screenshot) {
-                // Try to detect by process inspection
-                if !self.is_neovim_process(instance.pid).await? {
-                    return Err(anyhow!("Terminal does not appear to be running Neovim"));
+    /// A clipboard tool set up to run on the display of `instance_id`, whose
+    /// display variables are copied from its environment, or on the
+    /// server's own display.
+    async fn clipboard_command(&self, program: &str, instance_id: Option<&str>) -> Result<tokio::process::Command> {
+        let display_vars: &[&str] = match self.platform {
+            Platform::X11 => &["DISPLAY", "XAUTHORITY"],
+            Platform::Wayland => &["WAYLAND_DISPLAY", "XDG_RUNTIME_DIR"],
+        };
+        // Run without blocking the runtime, and killed if the call is cancelled
+        let mut cmd = tokio::process::Command::new(program);
+        cmd.kill_on_drop(true);
+        if let Some(instance_id) = instance_id {
+            let instance = self.instance(instance_id).await?;
+            for var in display_vars {
+                if let Some(value) = read_environ_var(instance.pid, var) {
+                    cmd.env(var, value);
                 }
             }
         }
-
-        // Extract Neovim context
-        self.neovim_extractor
-            .extract_context_from_instance(&params.instance_id, instance.pid)
-            .await
+        Ok(cmd)
     }
 
-    async fn is_neovim_process(&self, pid: u32) -> Result<bool> {
-        // Check if the process or any child process is nvim
-        let output = Command::new("ps")
-            .args(&["--ppid", &pid.to_string(), "-o", "comm="])
-            .output()?;
-
-        if output.status.success() {
-            let processes = String::from_utf8_lossy(&output.stdout);
-            if processes.lines().any(|line| line.trim().contains("nvim")) {
-                return Ok(true);
+    /// Types `text` verbatim, without interpreting it as key names.
+    pub async fn send_text(&self, params: SendTextParams) -> Result<()> {
+        let instance = self.instance(&params.instance_id).await?;
+        let delay = params.delay_ms.unwrap_or(0).to_string();
+
+        let output = match self.platform {
+            Platform::X11 => {
+                let window_id = match instance.window_id {
+                    Some(wid) => wid,
+                    None => self.get_window_id_for_instance(&params.instance_id).await?,
+                };
+
+                Command::new("xdotool")
+                    .args(["type", "--clearmodifiers", "--delay", &delay])
+                    .args(["--window", &window_id.to_string(), "--"])
+                    .arg(&params.text)
+                    .output()?
             }
+            Platform::Wayland => {
+                Command::new("ydotool")
+                    .args(["type", "--key-delay", &delay, "--"])
+                    .arg(&params.text)
+                    .output()?
+            }
+        };
+
+        if !output.status.success() {
+            return Err(self.window_command_failed(format!("Failed to send text: {}",
+                String::from_utf8_lossy(&output.stderr))));
         }
 
-        // Also check the main process
-        let output = Command::new("ps")
-            .args(&["-p", &pid.to_string(), "-o", "comm="])
-            .output()?;
+        self.touch(&params.instance_id).await;
+        Ok(())
+    }
 
-        if output.status.success() {
-            let process_name = String::from_utf8_lossy(&output.stdout);
-            return Ok(process_name.trim().contains("nvim"));
+    /// Makes Alacritty re-read its config file, e.g. after `write_file`
+    /// changed it. Alacritty has no `SIGHUP` handler, so that signal would
+    /// close the terminal; instead the file's modification time is bumped,
+    /// which sets off Alacritty's `live_config_reload` (on by default).
+    pub async fn reload_config(&self, params: ReloadConfigParams) -> Result<ReloadResult> {
+        let instance = self.instance(&params.instance_id).await?;
+        if instance.status == InstanceStatus::Exited || !is_pid_alive(instance.pid) {
+            return Err(anyhow!("Instance {} has exited (PID {})", params.instance_id, instance.pid));
         }
 
-        Ok(false)
+        let config_file = alacritty_config_file(instance.pid).ok_or_else(|| anyhow!(
+            "Instance {} has no config file to reload; Alacritty only watches a config file it loaded at startup",
+            params.instance_id
+        ))?;
+        std::fs::File::options()
+            .append(true)
+            .open(&config_file)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+            .map_err(|e| anyhow!("Cannot touch {}: {}", config_file.display(), e))?;
+
+        Ok(ReloadResult { config_file: config_file.to_string_lossy().to_string() })
     }
 
-    async fn refresh_instances(&mut self) -> Result<()> {
-        // Get all alacritty processes
-        let output = Command::new("pgrep")
-            .args(&["-f", "alacritty"])
-            .output()?;
-
-        if !output.status.success() {
-            // No alacritty processes running
-            self.instances.clear();
-            return Ok(());
-        }
+    pub async fn close_instance(&self, params: CloseParams) -> Result<()> {
+        let instance = self.instance(&params.instance_id).await?;
+        let pid = Pid::from_raw(instance.pid as i32);
+        let timeout = tokio::time::Duration::from_millis(params.timeout_ms.unwrap_or(5000));
 
-        let pids_str = String::from_utf8_lossy(&output.stdout);
-        let running_pids: Vec<u32> = pids_str
-            .lines()
-            .filter_map(|line| line.trim().parse().ok())
-            .collect();
+        if process_status(instance.pid) == InstanceStatus::Running {
+            match signal::kill(pid, Signal::SIGTERM) {
+                Ok(()) | Err(Errno::ESRCH) => {}
+                Err(e) => return Err(anyhow!("Failed to send SIGTERM to PID {}: {}", instance.pid, e)),
+            }
 
-        // Remove instances that are no longer running
-        self.instances.retain(|_, instance| running_pids.contains(&instance.pid));
+            // Wait for a graceful exit before escalating
+            let deadline = tokio::time::Instant::now() + timeout;
+            while process_status(instance.pid) == InstanceStatus::Running
+                && tokio::time::Instant::now() < deadline
+            {
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            }
 
-        // Add new instances that we haven't seen before
-        for pid in running_pids {
-            if !self.instances.values().any(|inst| inst.pid == pid) {
-                if let Ok(instance) = self.create_instance_from_pid(pid).await {
-                    self.instances.insert(instance.id.clone(), instance);
+            if process_status(instance.pid) == InstanceStatus::Running {
+                match signal::kill(pid, Signal::SIGKILL) {
+                    Ok(()) | Err(Errno::ESRCH) => {}
+                    Err(e) => return Err(anyhow!("Failed to send SIGKILL to PID {}: {}", instance.pid, e)),
                 }
             }
+            wait_until_reaped(instance.pid).await;
         }
 
+        self.instances.write().await.remove(&params.instance_id);
+        self.registry_changed().await;
         Ok(())
     }
 
-    async fn create_instance_from_pid(&self, pid: u32) -> Result<AlacrittyInstance> {
-        // Get process info
-        let cmdline_path = format!("/proc/{}/cmdline", pid);
-        let cmdline = std::fs::read_to_string(cmdline_path)?;
-        let args: Vec<&str> = cmdline.split('\0').filter(|s| !s.is_empty()).collect();
+    /// Returns the terminal's process and everything running beneath it, e.g.
+    /// the shell and the `cargo build` it started.
+    pub async fn get_process_tree(&self, params: ProcessTreeParams) -> Result<ProcessNode> {
+        let instance = self.instance(&params.instance_id).await?;
 
-        let mut title = format!("alacritty-{}", pid);
-        let mut command = "shell".to_string();
+        read_process_tree(instance.pid)
+    }
 
-        // Parse command line arguments
-        let mut i = 0;
-        while i < args.len() {
-            match args[i] {
-                "--title" | "-t" => {
-                    if i + 1 < args.len() {
-                        title = args[i + 1].to_string();
-                        i += 2;
-                    } else {
-                        i += 1;
-                    }
-                }
-                "--command" | "-e" => {
-                    if i + 1 < args.len() {
-                        command = args[i + 1].to_string();
-                        i += 2;
-                    } else {
-                        i += 1;
-                    }
-                }
-                _ => i += 1,
+    /// The environment the terminal was started with, sorted by name. Values
+    /// of likely secrets are redacted unless `show_secrets` is set.
+    pub async fn list_instance_environment(&self, params: EnvironmentParams) -> Result<Vec<EnvVar>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        let mut environment = read_environment(instance.pid)?;
+        if let Some(prefix) = &params.filter_prefix {
+            environment.retain(|var| var.key.starts_with(prefix.as_str()));
+        }
+        if !params.show_secrets.unwrap_or(false) {
+            for var in environment.iter_mut().filter(|var| is_secret_env_key(&var.key)) {
+                var.value = REDACTED_VALUE.to_string();
             }
         }
+        Ok(environment)
+    }
 
-        let instance_id = Uuid::new_v4().to_string();
-        let window_id = self.get_window_id_for_pid(pid).await.ok();
+    /// Terminates every managed instance, or only those tagged `tag_filter`.
+    /// All of them get SIGTERM at once, and whatever is still running after
+    /// two seconds gets SIGKILL.
+    pub async fn kill_all_instances(&self, tag_filter: Option<String>) -> Result<KillSummary> {
+        let targets: Vec<(String, u32)> = self.instances.read().await.values()
+            .filter(|instance| match &tag_filter {
+                Some(tag) => instance.tags.contains(tag),
+                None => true,
+            })
+            .map(|instance| (instance.id.clone(), instance.pid))
+            .collect();
 
-        Ok(AlacrittyInstance {
-            id: instance_id,
-            pid,
-            window_id,
-            title,
-            command,
-            created_at: 0, // We don't know the actual creation time
-        })
+        Ok(self.kill_instances(targets).await)
     }
 
-    async fn get_window_id_for_pid(&self, pid: u32) -> Result<u32> {
-        let output = Command::new("xdotool")
-            .args(&["search", "--pid", &pid.to_string(), "--class", "Alacritty"])
-            .output()?;
-
-        if output.status.success() {
-            let window_ids = String::from_utf8_lossy(&output.stdout);
-            if let Some(first_line) = window_ids.lines().next() {
-                if let Ok(window_id) = first_line.trim().parse::<u32>() {
-                    return Ok(window_id);
-                }
-            }
+    /// Terminates, as `kill_all_instances` does, every instance with no
+    /// activity for more than `older_than_secs` seconds. Instances whose
+    /// idle time is unknown are left alone.
+    pub async fn kill_idle_instances(&self, older_than_secs: u64) -> Result<KillSummary> {
+        if older_than_secs == 0 {
+            return Err(AlacrittyMcpError::InvalidParams("older_than_secs must be at least 1".to_string()).into());
         }
 
-        Err(anyhow!("Could not find window ID for PID {}", pid))
+        let targets: Vec<(String, u32)> = self.instances.read().await.values()
+            .filter(|instance| instance.idle_secs().is_some_and(|idle| idle > older_than_secs))
+            .map(|instance| (instance.id.clone(), instance.pid))
+            .collect();
+
+        Ok(self.kill_instances(targets).await)
     }
 
-    async fn get_window_id_for_instance(&self, instance_id: &str) -> Result<u32> {
-        let instance = self.instances.get(instance_id)
-            .ok_or_else(|| anyhow!("Instance not found: {}", instance_id))?;
+    /// Sends SIGTERM to every running target, SIGKILL to those still running
+    /// after two seconds, and removes them all from the registry.
+    async fn kill_instances(&self, targets: Vec<(String, u32)>) -> KillSummary {
+        let mut summary = KillSummary { killed: 0, already_exited: 0 };
+        let mut running = Vec::new();
+        for &(_, pid) in &targets {
+            if process_status(pid) != InstanceStatus::Running {
+                summary.already_exited += 1;
+                continue;
+            }
 
-        if let Some(window_id) = instance.window_id {
+            match signal::kill(Pid::from_raw(pid as i32), Signal::SIGTERM) {
+                Ok(()) | Err(Errno::ESRCH) => running.push(pid),
+                Err(e) => warn!("Failed to send SIGTERM to PID {}: {}", pid, e),
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(2);
+        while running.iter().any(|pid| process_status(*pid) == InstanceStatus::Running)
+            && tokio::time::Instant::now() < deadline
+        {
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+
+        for pid in running {
+            if process_status(pid) == InstanceStatus::Running {
+                let pid = Pid::from_raw(pid as i32);
+                match signal::kill(pid, Signal::SIGKILL) {
+                    Ok(()) | Err(Errno::ESRCH) => {}
+                    Err(e) => warn!("Failed to send SIGKILL to PID {}: {}", pid, e),
+                }
+            }
+            wait_until_reaped(pid).await;
+            summary.killed += 1;
+        }
+
+        let mut instances = self.instances.write().await;
+        for (instance_id, _) in &targets {
+            instances.remove(instance_id);
+        }
+        drop(instances);
+        self.registry_changed().await;
+        summary
+    }
+
+    /// Sets the window title and records it. Only X11 exposes window titles
+    /// to other clients, so this fails on Wayland.
+    pub async fn rename_instance(&self, params: RenameParams) -> Result<()> {
+        if params.new_title.is_empty() || params.new_title.len() > MAX_TITLE_BYTES {
+            return Err(AlacrittyMcpError::InvalidParams(format!(
+                "new_title must be 1-{} bytes, got {}",
+                MAX_TITLE_BYTES, params.new_title.len()
+            )).into());
+        }
+
+        let instance = self.instance(&params.instance_id).await?;
+
+        if self.platform == Platform::Wayland {
+            return Err(anyhow!("Renaming windows is not supported on Wayland"));
+        }
+
+        let window_id = match instance.window_id {
+            Some(wid) => wid,
+            None => self.get_window_id_for_instance(&params.instance_id).await?,
+        };
+
+        let output = Command::new("xdotool")
+            .args(["set_window", "--name", &params.new_title, &window_id.to_string()])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(AlacrittyMcpError::XdotoolFailed(format!("Failed to rename window: {}",
+                String::from_utf8_lossy(&output.stderr))).into());
+        }
+
+        if let Some(inst) = self.instances.write().await.get_mut(&params.instance_id) {
+            inst.window_id = Some(window_id);
+            inst.title = params.new_title;
+        }
+        self.registry_changed().await;
+
+        Ok(())
+    }
+
+    pub async fn focus_instance(&self, params: FocusParams) -> Result<()> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        let output = match self.platform {
+            Platform::X11 => {
+                let window_id = match instance.window_id {
+                    Some(wid) => wid,
+                    None => self.get_window_id_for_instance(&params.instance_id).await?,
+                };
+
+                if let Some(inst) = self.instances.write().await.get_mut(&params.instance_id) {
+                    inst.window_id = Some(window_id);
+                }
+
+                Command::new("xdotool")
+                    .args(["windowactivate", "--sync", &window_id.to_string()])
+                    .output()?
+            }
+            Platform::Wayland => {
+                Command::new("swaymsg")
+                    .arg(format!("[pid={}] focus", instance.pid))
+                    .output()?
+            }
+        };
+
+        if !output.status.success() {
+            return Err(self.window_command_failed(format!("Failed to focus window: {}",
+                String::from_utf8_lossy(&output.stderr))));
+        }
+
+        if let Some(inst) = self.instances.write().await.get_mut(&params.instance_id) {
+            inst.last_focused_at = Some(unix_timestamp());
+        }
+        self.registry_changed().await;
+
+        Ok(())
+    }
+
+    pub async fn resize_instance(&self, params: ResizeParams) -> Result<()> {
+        let bounds = MIN_TERMINAL_DIMENSION..=MAX_TERMINAL_DIMENSION;
+        if !bounds.contains(&params.cols) || !bounds.contains(&params.rows) {
+            return Err(AlacrittyMcpError::InvalidParams(format!(
+                "cols and rows must be between {} and {}, got {}x{}",
+                MIN_TERMINAL_DIMENSION, MAX_TERMINAL_DIMENSION, params.cols, params.rows
+            )).into());
+        }
+
+        let instance = self.instance(&params.instance_id).await?;
+
+        let output = match self.platform {
+            Platform::X11 => {
+                let window_id = match instance.window_id {
+                    Some(wid) => wid,
+                    None => self.get_window_id_for_instance(&params.instance_id).await?,
+                };
+
+                // --usehints makes xdotool size the window in terminal cells
+                Command::new("xdotool")
+                    .args(["windowsize", "--usehints", &window_id.to_string()])
+                    .args([params.cols.to_string(), params.rows.to_string()])
+                    .output()?
+            }
+            Platform::Wayland => {
+                let width = params.cols as u32 * DEFAULT_CELL_WIDTH_PX;
+                let height = params.rows as u32 * DEFAULT_CELL_HEIGHT_PX;
+                Command::new("swaymsg")
+                    .arg(format!("[pid={}] resize set {} px {} px", instance.pid, width, height))
+                    .output()?
+            }
+        };
+
+        if !output.status.success() {
+            return Err(self.window_command_failed(format!("Failed to resize window: {}",
+                String::from_utf8_lossy(&output.stderr))));
+        }
+
+        Ok(())
+    }
+
+    /// The window's size in pixels and in character cells. On X11 the cell
+    /// size and padding come from the window's size hints, the ones
+    /// `resize_instance` sizes by; without hints, and on Wayland, a typical
+    /// cell size is assumed.
+    pub async fn get_terminal_dimensions(&self, params: DimensionsParams) -> Result<Dimensions> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        let (pixel_width, pixel_height, hints) = match self.platform {
+            Platform::X11 => {
+                let window_id = match instance.window_id {
+                    Some(wid) => wid,
+                    None => self.get_window_id_for_instance(&params.instance_id).await?,
+                };
+                let (width, height) = x11_window_size(window_id)?;
+                (width, height, x11_size_hints(window_id))
+            }
+            Platform::Wayland => {
+                let (_, _, width, height) = self.sway_window_rect(instance.pid)
+                    .ok_or_else(|| anyhow!("Could not determine window geometry for PID {}", instance.pid))?;
+                (width.max(0) as u32, height.max(0) as u32, None)
+            }
+        };
+
+        let SizeHints { cell_width, cell_height, base_width, base_height } = hints.unwrap_or(SizeHints {
+            cell_width: DEFAULT_CELL_WIDTH_PX,
+            cell_height: DEFAULT_CELL_HEIGHT_PX,
+            base_width: 0,
+            base_height: 0,
+        });
+        let cells = |pixels: u32, base: u32, cell: u32| (pixels.saturating_sub(base) / cell.max(1)).min(u16::MAX as u32) as u16;
+
+        Ok(Dimensions {
+            cols: cells(pixel_width, base_width, cell_width),
+            rows: cells(pixel_height, base_height, cell_height),
+            pixel_width,
+            pixel_height,
+        })
+    }
+
+    /// Changes one config option of a running terminal through Alacritty's
+    /// IPC socket, as `alacritty msg config` does. The change is not written
+    /// to the config file.
+    pub async fn set_alacritty_option(&self, params: SetOptionParams) -> Result<()> {
+        static CONFIG_KEY: OnceLock<Regex> = OnceLock::new();
+        let key_re = CONFIG_KEY.get_or_init(|| Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*(\.[A-Za-z_][A-Za-z0-9_]*)*$").unwrap());
+        if !key_re.is_match(&params.key) {
+            return Err(AlacrittyMcpError::InvalidParams(format!("Invalid config key '{}', expected e.g. font.size", params.key)).into());
+        }
+        if params.value.trim().is_empty() {
+            return Err(AlacrittyMcpError::InvalidParams("value must not be empty".to_string()).into());
+        }
+
+        let instance = self.instance(&params.instance_id).await?;
+
+        let version = alacritty_version(&self.config.alacritty_binary)?;
+        if (version.0, version.1) < MIN_IPC_CONFIG_VERSION {
+            return Err(anyhow!(
+                "Alacritty {}.{}.{} cannot change options at runtime; upgrade to {}.{} or newer for `alacritty msg config`",
+                version.0, version.1, version.2, MIN_IPC_CONFIG_VERSION.0, MIN_IPC_CONFIG_VERSION.1
+            ));
+        }
+
+        let socket = self.ipc_socket(&params.instance_id).await?;
+
+        // Each instance is its own process, so "every window" means just this one
+        let window = match (self.platform, instance.window_id) {
+            (Platform::X11, Some(window_id)) => window_id.to_string(),
+            _ => "-1".to_string(),
+        };
+
+        let output = Command::new(&self.config.alacritty_binary)
+            .arg("msg")
+            .arg("--socket")
+            .arg(&socket)
+            .args(["config", "-w", &window])
+            .arg(format!("{}={}", params.key, params.value))
+            .output()?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Failed to set {}: {}", params.key,
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        Ok(())
+    }
+
+    pub async fn screenshot_instance(&self, params: ScreenshotParams) -> Result<String> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        let format = params.format.as_deref().unwrap_or("text");
+
+        let screenshot = match (self.platform, format) {
+            (Platform::X11, "text" | "image") => {
+                let window_id = if let Some(wid) = instance.window_id {
+                    wid
+                } else {
+                    self.get_window_id_for_instance(&params.instance_id).await?
+                };
+
+                if format == "text" {
+                    self.screenshot_text(window_id).await
+                } else {
+                    self.screenshot_image(window_id).await
+                }
+            }
+            (Platform::Wayland, "text") => self.screenshot_text_wayland().await,
+            (Platform::Wayland, "image") => self.screenshot_image_wayland(instance.pid).await,
+            _ => Err(anyhow!("Unsupported format: {}", format)),
+        }?;
+
+        self.touch(&params.instance_id).await;
+        Ok(screenshot)
+    }
+
+    /// Keeps a text screenshot for `screenshot_diff` and returns its ID.
+    pub fn store_screenshot(&self, instance_id: &str, text: &str) -> String {
+        let id = Uuid::new_v4().to_string();
+        let mut screenshots = self.screenshots.lock().unwrap();
+        if screenshots.len() >= MAX_STORED_SCREENSHOTS {
+            screenshots.pop_front();
+        }
+        screenshots.push_back(StoredScreenshot {
+            id: id.clone(),
+            instance_id: instance_id.to_string(),
+            text: text.to_string(),
+        });
+        id
+    }
+
+    /// Takes a fresh text screenshot and diffs it against the stored one.
+    pub async fn screenshot_diff(&self, params: ScreenshotDiffParams) -> Result<DiffResult> {
+        let previous = {
+            let screenshots = self.screenshots.lock().unwrap();
+            let stored = screenshots
+                .iter()
+                .find(|screenshot| screenshot.id == params.since_screenshot_id)
+                .ok_or_else(|| AlacrittyMcpError::InvalidParams(format!(
+                    "Unknown screenshot_id '{}'; only the last {} text screenshots are kept",
+                    params.since_screenshot_id, MAX_STORED_SCREENSHOTS
+                )))?;
+            if stored.instance_id != params.instance_id {
+                return Err(AlacrittyMcpError::InvalidParams(format!(
+                    "Screenshot {} was taken of instance {}, not {}",
+                    stored.id, stored.instance_id, params.instance_id
+                )).into());
+            }
+            stored.text.clone()
+        };
+
+        let current = self.screenshot_instance(ScreenshotParams {
+            instance_id: params.instance_id,
+            format: Some("text".to_string()),
+        }).await?;
+        Ok(diff_screens(&previous, &current))
+    }
+
+    /// The text screenshot shrunk to a coarse `#`/`.` map of where the screen
+    /// has content.
+    pub async fn minimap_screenshot(&self, params: MinimapParams) -> Result<String> {
+        let text = self.screenshot_instance(ScreenshotParams {
+            instance_id: params.instance_id,
+            format: Some("text".to_string()),
+        }).await?;
+        Ok(minimap(&text))
+    }
+
+    /// Captures a rectangle of the window as a base64 PNG. The rectangle is
+    /// relative to the window's top-left corner and must lie inside it.
+    pub async fn screenshot_region(&self, params: ScreenshotRegionParams) -> Result<String> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        let temp_file = format!("/tmp/alacritty_screenshot_region_{}.png", instance.pid);
+        let mut cmd = match self.platform {
+            Platform::X11 => {
+                let window_id = match instance.window_id {
+                    Some(wid) => wid,
+                    None => self.get_window_id_for_instance(&params.instance_id).await?,
+                };
+                let (width, height) = x11_window_size(window_id)?;
+                check_region(&params, width, height)?;
+
+                let mut cmd = Command::new("import");
+                cmd.args(["-window", &window_id.to_string()])
+                    .args(["-crop", &format!("{}x{}+{}+{}", params.width, params.height, params.x, params.y)])
+                    .arg("+repage");
+                cmd
+            }
+            Platform::Wayland => {
+                // grim takes output coordinates, so offset by the window position
+                let (x, y, width, height) = self.sway_window_rect(instance.pid)
+                    .ok_or_else(|| anyhow!("Could not determine window geometry for PID {}", instance.pid))?;
+                check_region(&params, width.max(0) as u32, height.max(0) as u32)?;
+
+                let mut cmd = Command::new("grim");
+                cmd.args(["-g", &format!("{},{} {}x{}",
+                    x + params.x as i64, y + params.y as i64, params.width, params.height)]);
+                cmd
+            }
+        };
+
+        let output = cmd.arg(&temp_file).output()?;
+        if !output.status.success() {
+            return Err(anyhow!("Failed to take screenshot: {}", 
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        let image_data = std::fs::read(&temp_file)?;
+        let base64_data = base64::encode(&image_data);
+        let _ = std::fs::remove_file(&temp_file);
+
+        Ok(format!("data:image/png;base64,{}", base64_data))
+    }
+
+    /// Reads the whole scrollback history (select-all + copy, like the text
+    /// screenshot) and returns the requested page of it.
+    pub async fn get_scrollback(&self, params: ScrollbackParams) -> Result<ScrollbackPage> {
+        if let (Some(start), Some(end)) = (params.start_line, params.end_line) {
+            if start > end {
+                return Err(AlacrittyMcpError::InvalidParams(format!(
+                    "start_line ({}) must not be greater than end_line ({})", start, end
+                )).into());
+            }
+        }
+
+        let text = self.screenshot_instance(ScreenshotParams {
+            instance_id: params.instance_id.clone(),
+            format: Some("text".to_string()),
+        }).await?;
+
+        Ok(ScrollbackPage::slice(&text, params.start_line, params.end_line))
+    }
+
+    /// Polls the terminal text until a line matches `pattern`, returning that
+    /// line and how many snapshots it took.
+    pub async fn wait_for_output(&self, params: WaitForOutputParams) -> Result<OutputMatch> {
+        self.wait_for_output_with_progress(params, |_, _| {}).await
+    }
+
+    /// Like `wait_for_output`, calling `on_poll` after every poll with the
+    /// number of polls so far and the most the timeout allows.
+    pub async fn wait_for_output_with_progress(
+        &self,
+        params: WaitForOutputParams,
+        mut on_poll: impl FnMut(u32, u32),
+    ) -> Result<OutputMatch> {
+        let pattern = Regex::new(&params.pattern)
+            .map_err(|e| AlacrittyMcpError::InvalidParams(format!("Invalid pattern '{}': {}", params.pattern, e)))?;
+        let timeout_ms = params.timeout_ms.unwrap_or(10_000);
+        let poll_interval_ms = params.poll_interval_ms.unwrap_or(500).max(1);
+        let poll_interval = tokio::time::Duration::from_millis(poll_interval_ms);
+        let max_polls = (timeout_ms / poll_interval_ms).clamp(1, u32::MAX as u64) as u32;
+
+        let mut polls = 0;
+        let poll = async {
+            let mut interval = tokio::time::interval(poll_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                polls += 1;
+
+                let text = self.screenshot_instance(ScreenshotParams {
+                    instance_id: params.instance_id.clone(),
+                    format: Some("text".to_string()),
+                }).await?;
+                on_poll(polls, max_polls);
+
+                if let Some(line) = text.lines().find(|line| pattern.is_match(line)) {
+                    return Ok::<_, anyhow::Error>(line.to_string());
+                }
+            }
+        };
+
+        match tokio::time::timeout(tokio::time::Duration::from_millis(timeout_ms), poll).await {
+            Ok(line) => Ok(OutputMatch { line: line?, polls }),
+            Err(_) => Err(anyhow!(
+                "Timed out after {}ms waiting for pattern '{}' ({} polls)",
+                timeout_ms, params.pattern, polls
+            )),
+        }
+    }
+
+    pub async fn monitor_instance(&self, params: MonitorParams) -> Result<Vec<TimedScreenshot>> {
+        self.monitor_instance_with_progress(params, |_, _| {}).await
+    }
+
+    /// Takes a screenshot every `interval_ms` for `duration_secs`, at most
+    /// `MAX_MONITOR_SCREENSHOTS` of them, calling `on_capture` after each
+    /// with the number taken so far and the number planned.
+    pub async fn monitor_instance_with_progress(
+        &self,
+        params: MonitorParams,
+        mut on_capture: impl FnMut(u32, u32),
+    ) -> Result<Vec<TimedScreenshot>> {
+        if params.interval_ms == 0 {
+            return Err(AlacrittyMcpError::InvalidParams("interval_ms must be at least 1".to_string()).into());
+        }
+        let count = monitor_screenshot_count(params.duration_secs, params.interval_ms);
+
+        let started = Instant::now();
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(params.interval_ms as u64));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut screenshots = Vec::with_capacity(count as usize);
+        for taken in 1..=count {
+            interval.tick().await;
+            let timestamp_ms = started.elapsed().as_millis() as u64;
+            let content = self.screenshot_instance(ScreenshotParams {
+                instance_id: params.instance_id.clone(),
+                format: params.format.clone(),
+            }).await?;
+            screenshots.push(TimedScreenshot { timestamp_ms, content });
+            on_capture(taken, count);
+        }
+
+        Ok(screenshots)
+    }
+
+    /// Types `command` at the shell prompt, presses Return and waits for the
+    /// next prompt, returning what was printed in between.
+    pub async fn run_command(&self, params: RunCommandParams) -> Result<RunCommandResult> {
+        let pattern = params.prompt_pattern.as_deref().unwrap_or(DEFAULT_PROMPT_PATTERN);
+        let prompt = Regex::new(pattern)
+            .map_err(|e| AlacrittyMcpError::InvalidParams(format!("Invalid prompt_pattern '{}': {}", pattern, e)))?;
+        let timeout_ms = params.timeout_ms.unwrap_or(DEFAULT_RUN_COMMAND_TIMEOUT_MS);
+        let screenshot = || self.screenshot_instance(ScreenshotParams {
+            instance_id: params.instance_id.clone(),
+            format: Some("text".to_string()),
+        });
+
+        self.focus_instance(FocusParams { instance_id: params.instance_id.clone() }).await?;
+
+        // The command is echoed on the line the cursor is on, which is the
+        // last one captured now
+        let from_line = screenshot().await?.lines().count().saturating_sub(1);
+
+        let started = Instant::now();
+        self.send_text(SendTextParams {
+            instance_id: params.instance_id.clone(),
+            text: params.command.clone(),
+            delay_ms: None,
+        }).await?;
+        self.send_keys(SendKeysParams {
+            instance_id: params.instance_id.clone(),
+            keys: "Return".to_string(),
+        }).await?;
+
+        let poll = async {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(RUN_COMMAND_POLL_INTERVAL_MS));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let text = screenshot().await?;
+                if let Some(found) = command_output(&text, from_line, &params.command, &prompt) {
+                    return Ok::<_, anyhow::Error>(found);
+                }
+            }
+        };
+
+        match tokio::time::timeout(tokio::time::Duration::from_millis(timeout_ms), poll).await {
+            Ok(found) => {
+                let (stdout, exit_code) = found?;
+                Ok(RunCommandResult { stdout, exit_code, elapsed_ms: started.elapsed().as_millis() as u64 })
+            }
+            Err(_) => Err(anyhow!(
+                "Timed out after {}ms waiting for the prompt after '{}'", timeout_ms, params.command
+            )),
+        }
+    }
+
+    async fn capture_text(&self, instance: &AlacrittyInstance) -> Result<String> {
+        match self.platform {
+            Platform::X11 => self.screenshot_text(instance.window_id.unwrap_or(0)).await,
+            Platform::Wayland => self.screenshot_text_wayland().await,
+        }
+    }
+
+    async fn screenshot_text(&self, window_id: u32) -> Result<String> {
+        // Use xdotool to get text content from the terminal
+        let output = Command::new("xdotool")
+            .args(["getwindowgeometry", &window_id.to_string()])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(AlacrittyMcpError::XdotoolFailed(format!("Failed to get window geometry: {}",
+                String::from_utf8_lossy(&output.stderr))).into());
+        }
+
+        // Get the window content using xwininfo and xwd
+        let output = Command::new("xwininfo")
+            .args(["-id", &window_id.to_string(), "-tree"])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Failed to get window info"));
+        }
+
+        // For text extraction, we'll use a different approach
+        // Copy all text from the terminal using xsel or xclip
+        let _select_output = Command::new("xdotool")
+            .args(["windowactivate", &window_id.to_string()])
+            .output()?;
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        // Select all text
+        let _select_output = Command::new("xdotool")
+            .args(["key", "--window", &window_id.to_string(), "ctrl+shift+a"])
+            .output()?;
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        // Copy to clipboard
+        let _copy_output = Command::new("xdotool")
+            .args(["key", "--window", &window_id.to_string(), "ctrl+shift+c"])
+            .output()?;
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+        // Get clipboard content
+        let clipboard_output = Command::new("xclip")
+            .args(["-o", "-selection", "clipboard"])
+            .output()?;
+
+        if clipboard_output.status.success() {
+            Ok(String::from_utf8_lossy(&clipboard_output.stdout).to_string())
+        } else {
+            Err(anyhow!("Failed to get clipboard content"))
+        }
+    }
+
+    async fn screenshot_image(&self, window_id: u32) -> Result<String> {
+        // Take a screenshot of the window using imagemagick
+        let temp_file = format!("/tmp/alacritty_screenshot_{}.png", window_id);
+        
+        let output = Command::new("import")
+            .args(["-window", &window_id.to_string(), &temp_file])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Failed to take screenshot: {}", 
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        // Read the file and encode as base64
+        let image_data = std::fs::read(&temp_file)?;
+        let base64_data = base64::encode(&image_data);
+        
+        // Clean up temp file
+        let _ = std::fs::remove_file(&temp_file);
+
+        Ok(format!("data:image/png;base64,{}", base64_data))
+    }
+
+    async fn screenshot_text_wayland(&self) -> Result<String> {
+        // Select all and copy in the focused terminal, then read it back with wl-paste
+        for keys in ["ctrl+shift+a", "ctrl+shift+c"] {
+            let _output = Command::new("ydotool")
+                .arg("key")
+                .args(ydotool_key_args(keys)?)
+                .output()?;
+
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+
+        let clipboard_output = Command::new("wl-paste")
+            .arg("--no-newline")
+            .output()?;
+
+        if clipboard_output.status.success() {
+            Ok(String::from_utf8_lossy(&clipboard_output.stdout).to_string())
+        } else {
+            Err(anyhow!("Failed to get clipboard content"))
+        }
+    }
+
+    async fn screenshot_image_wayland(&self, pid: u32) -> Result<String> {
+        let temp_file = format!("/tmp/alacritty_screenshot_{}.png", pid);
+
+        // Crop to the window when the compositor can tell us where it is
+        let mut cmd = Command::new("grim");
+        if let Some((x, y, width, height)) = self.sway_window_rect(pid) {
+            cmd.args(["-g", &format!("{},{} {}x{}", x, y, width, height)]);
+        }
+        let output = cmd.arg(&temp_file).output()?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Failed to take screenshot: {}", 
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        let image_data = std::fs::read(&temp_file)?;
+        let base64_data = base64::encode(&image_data);
+        let _ = std::fs::remove_file(&temp_file);
+
+        Ok(format!("data:image/png;base64,{}", base64_data))
+    }
+
+    fn sway_window_rect(&self, pid: u32) -> Option<(i64, i64, i64, i64)> {
+        let output = Command::new("swaymsg")
+            .args(["-t", "get_tree", "--raw"])
+            .output()
+            .ok()?;
+
+        if !output.status.success() {
+            return None;
+        }
+
+        let tree: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+        find_sway_node_rect(&tree, pid)
+    }
+
+    /// Writes `content` to a path relative to the instance's working
+    /// directory, creating missing parent directories. Paths that would end
+    /// up outside that directory are rejected.
+    pub async fn write_file(&self, params: WriteFileParams) -> Result<WriteFileResult> {
+        let mode = params.mode.as_deref().map(parse_mode).transpose()?;
+        let root = self.working_directory(&params.instance_id).await?;
+        let path = resolve_relative_path(Path::new(&root), &params.relative_path)?;
+
+        // Symlinked directories or files could still point elsewhere, so check
+        // the deepest part of the path that already exists
+        let existing = path.ancestors()
+            .find(|ancestor| ancestor.symlink_metadata().is_ok())
+            .unwrap_or(Path::new(&root));
+        check_within(&root, &existing.canonicalize()?, &params.relative_path)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true);
+        if params.overwrite.unwrap_or(false) {
+            options.create(true).truncate(true);
+        } else {
+            options.create_new(true);
+        }
+        let mut file = match options.open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Err(anyhow!("{} already exists, set overwrite to replace it", path.display()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        std::io::Write::write_all(&mut file, params.content.as_bytes())?;
+        if let Some(mode) = mode {
+            std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(mode))?;
+        }
+
+        Ok(WriteFileResult {
+            path: path.to_string_lossy().to_string(),
+            bytes_written: params.content.len(),
+        })
+    }
+
+    /// Reads a text file relative to the instance's working directory,
+    /// optionally only the lines `start_line..=end_line` (1-based).
+    pub async fn read_file(&self, params: ReadFileParams) -> Result<FileContent> {
+        let start_line = params.start_line.unwrap_or(1);
+        if start_line == 0 {
+            return Err(AlacrittyMcpError::InvalidParams("start_line is 1-based".to_string()).into());
+        }
+        if let Some(end_line) = params.end_line {
+            if end_line < start_line {
+                return Err(AlacrittyMcpError::InvalidParams(format!(
+                    "end_line {} is before start_line {}", end_line, start_line
+                )).into());
+            }
+        }
+
+        let root = self.working_directory(&params.instance_id).await?;
+        let path = resolve_relative_path(Path::new(&root), &params.relative_path)?
+            .canonicalize()?;
+        check_within(&root, &path, &params.relative_path)?;
+
+        // Anything but a regular file, e.g. a FIFO, could block the read forever
+        if !tokio::fs::metadata(&path).await?.is_file() {
+            return Err(AlacrittyMcpError::InvalidParams(format!(
+                "Path '{}' is not a regular file", params.relative_path
+            )).into());
+        }
+
+        let max_bytes = params.max_bytes.unwrap_or(DEFAULT_READ_MAX_BYTES);
+        let end_line = params.end_line;
+        tokio::task::spawn_blocking(move || read_text_file(&path, start_line, end_line, max_bytes)).await?
+    }
+
+    /// Searches the instance's working directory, or `path` within it, for
+    /// lines matching `pattern`, using `rg` or else `grep -r`.
+    pub async fn search_in_instance(&self, params: SearchParams) -> Result<Vec<SearchResult>> {
+        let regex = Regex::new(&params.pattern).map_err(|e| AlacrittyMcpError::InvalidParams(format!(
+            "Invalid pattern '{}': {}", params.pattern, e
+        )))?;
+        let max_results = params.max_results.unwrap_or(DEFAULT_SEARCH_RESULTS).min(MAX_SEARCH_RESULTS);
+        if max_results == 0 {
+            return Err(AlacrittyMcpError::InvalidParams("max_results must be at least 1".to_string()).into());
+        }
+
+        let root = self.working_directory(&params.instance_id).await?;
+        let path = params.path.as_deref().filter(|path| !path.is_empty() && *path != ".");
+        if let Some(relative) = path {
+            let resolved = resolve_relative_path(Path::new(&root), relative)?.canonicalize()?;
+            check_within(&root, &resolved, relative)?;
+        }
+
+        search_directory(Path::new(&root), &params.pattern, &regex, path, params.file_glob.as_deref(), max_results as usize).await
+    }
+
+    pub async fn get_neovim_context(&self, params: NeovimContextParams) -> Result<NeovimContext> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        // First, check if this terminal is likely running Neovim
+        let screenshot = self.capture_text(&instance).await.ok();
+        if let Some(screenshot) = &screenshot {
+            match self.neovim_extractor.detect_editor_in_terminal(screenshot) {
+                EditorType::Neovim => {}
+                EditorType::Helix => return Err(anyhow!("Terminal is running Helix, not Neovim")),
+                EditorType::Unknown => {
+                    // Try to detect by process inspection
+                    if !self.is_neovim_process(instance.pid).await? {
+                        return Err(anyhow!("Terminal does not appear to be running Neovim"));
+                    }
+                }
+            }
+        }
+
+        // Extract Neovim context
+        match self.neovim_extractor
+            .extract_context_from_instance(instance.pid, &params, screenshot.as_deref())
+            .await?
+        {
+            TerminalContext::Neovim(context) => Ok(*context),
+            TerminalContext::Helix(_) | TerminalContext::Tmux(_) | TerminalContext::PlainTerminal => {
+                self.neovim_extractor.extract_basic_context(instance.pid).await
+            }
+        }
+    }
+
+    /// `Ok(None)` means Neovim answered but no language server had hover
+    /// information for that position.
+    pub async fn get_neovim_hover(&self, params: NeovimHoverParams) -> Result<Option<HoverResult>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_hover(instance.pid, params.line, params.col).await
+    }
+
+    pub async fn get_neovim_completions(&self, params: CompletionParams) -> Result<Vec<CompletionItem>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_completions(instance.pid, params.max_items).await
+    }
+
+    pub async fn get_neovim_references(&self, params: ReferencesParams) -> Result<BTreeMap<String, Vec<Reference>>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_references(instance.pid, params.include_declaration.unwrap_or(true)).await
+    }
+
+    pub async fn get_neovim_symbol_definition(&self, params: DefinitionParams) -> Result<Vec<DefinitionResult>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_definition(instance.pid, params.line, params.col).await
+    }
+
+    pub async fn get_neovim_code_actions(&self, params: CodeActionsParams) -> Result<Vec<CodeAction>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_lsp_code_actions(instance.pid).await
+    }
+
+    pub async fn apply_code_action(&self, params: ApplyCodeActionParams) -> Result<CodeAction> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.apply_lsp_code_action(instance.pid, params.index).await
+    }
+
+    pub async fn get_neovim_marks(&self, params: MarksParams) -> Result<Vec<Mark>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_marks(instance.pid).await
+    }
+
+    pub async fn get_neovim_registers(&self, params: RegistersParams) -> Result<BTreeMap<String, RegisterContent>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_registers(instance.pid).await
+    }
+
+    pub async fn get_neovim_jumplist(&self, params: JumplistParams) -> Result<Vec<JumpEntry>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_jumplist(instance.pid, params.max_entries).await
+    }
+
+    pub async fn get_neovim_session_variables(&self, params: SessionVariablesParams) -> Result<SessionVariables> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_session_variables(instance.pid, params.scope.as_deref()).await
+    }
+
+    pub async fn get_neovim_spell_errors(&self, params: SpellErrorsParams) -> Result<Vec<SpellError>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_spell_errors(instance.pid, params.max_errors).await
+    }
+
+    /// Streams the buffer changes of the Neovim running in the instance to
+    /// `tx`, see `NeovimContextExtractor::watch_buffer_changes`.
+    pub async fn watch_neovim_changes(&self, params: NeovimChangesParams, tx: mpsc::Sender<BufferChangeEvent>) -> Result<BufferWatch> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        let socket_path = self.neovim_extractor.find_neovim_socket(instance.pid).await?;
+        self.neovim_extractor.watch_buffer_changes(&socket_path, tx).await
+    }
+
+    pub async fn get_neovim_search_history(&self, params: SearchHistoryParams) -> Result<Vec<SearchHistoryEntry>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_search_history(instance.pid, params.count).await
+    }
+
+    pub async fn get_neovim_quickfix(&self, params: QuickfixParams) -> Result<Vec<QuickfixEntry>> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_quickfix_list(instance.pid, params.location_list).await
+    }
+
+    pub async fn get_neovim_git_status(&self, params: GitStatusParams) -> Result<GitStatus> {
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_git_status(instance.pid).await
+    }
+
+    pub async fn neovim_execute_command(&self, params: ExecuteCommandParams) -> Result<ExecResult> {
+        // Rejected commands should not wait for a socket lookup
+        validate_ex_command(&params.command)?;
+        let instance = self.instance(&params.instance_id).await?;
+        let socket_path = self.neovim_extractor.find_neovim_socket(instance.pid).await?;
+
+        self.neovim_extractor.execute_command(&socket_path, &params.command).await
+    }
+
+    pub async fn neovim_run_test(&self, params: RunTestParams) -> Result<TestRunResult> {
+        let timeout_secs = params.timeout_secs.unwrap_or(DEFAULT_TEST_TIMEOUT_SECS);
+        if !(1..=MAX_TEST_TIMEOUT_SECS).contains(&timeout_secs) {
+            return Err(AlacrittyMcpError::InvalidParams(format!(
+                "timeout_secs must be between 1 and {}, got {}", MAX_TEST_TIMEOUT_SECS, timeout_secs
+            )).into());
+        }
+        for (name, value) in [("test_file", &params.test_file), ("test_name", &params.test_name)] {
+            if value.as_deref().is_some_and(|value| value.trim().is_empty()) {
+                return Err(AlacrittyMcpError::InvalidParams(format!("{} must not be empty", name)).into());
+            }
+        }
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.run_tests(
+            instance.pid,
+            params.test_file.as_deref(),
+            params.test_name.as_deref(),
+            std::time::Duration::from_secs(timeout_secs),
+        ).await
+    }
+
+    pub async fn get_neovim_workspace_diagnostics(&self, params: WorkspaceDiagnosticsParams) -> Result<WorkspaceDiagnostics> {
+        let min_severity = match params.min_severity.as_deref() {
+            Some(name) => Some(DiagnosticSeverity::parse(name).ok_or_else(|| AlacrittyMcpError::InvalidParams(format!(
+                "Invalid min_severity '{}', expected error, warning, info or hint", name
+            )))?),
+            None => None,
+        };
+        let instance = self.instance(&params.instance_id).await?;
+
+        self.neovim_extractor.get_workspace_diagnostics(instance.pid, min_severity).await
+    }
+
+    pub async fn set_neovim_option(&self, params: SetNeovimOptionParams) -> Result<()> {
+        // Rejected options should not wait for a socket lookup
+        set_option_keys(&params.option, &params.value)?;
+        let instance = self.instance(&params.instance_id).await?;
+        let socket_path = self.neovim_extractor.find_neovim_socket(instance.pid).await?;
+
+        self.neovim_extractor.set_neovim_option(&socket_path, &params.option, &params.value).await
+    }
+
+    /// Opens the file and line one Neovim instance is on in another terminal:
+    /// a new one, or `target_instance_id` if given (it must be at a shell
+    /// prompt, `nvim` is typed into it).
+    pub async fn clone_neovim_buffer(&self, params: CloneBufferParams) -> Result<CloneBufferResult> {
+        let source = self.instance(&params.source_instance_id).await?;
+        let context = self.get_neovim_context(NeovimContextParams {
+            instance_id: params.source_instance_id.clone(),
+            include_diagnostics: Some(false),
+            include_buffers: Some(false),
+            context_lines: Some(0),
+        }).await?;
+
+        let file_path = context.current_buffer
+            .map(|buffer| buffer.file_path)
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| anyhow!("Instance {} has no file open in Neovim", params.source_instance_id))?;
+        let line = context.cursor_position.map(|cursor| cursor.line).filter(|&line| line > 0).unwrap_or(1);
+
+        let target_instance_id = match params.target_instance_id {
+            Some(target_instance_id) => {
+                self.send_text(SendTextParams {
+                    instance_id: target_instance_id.clone(),
+                    text: format!("nvim +{} {}", line, shell_quote(&file_path)),
+                    delay_ms: None,
+                }).await?;
+                self.send_keys(SendKeysParams {
+                    instance_id: target_instance_id.clone(),
+                    keys: "Return".to_string(),
+                }).await?;
+                target_instance_id
+            }
+            None => {
+                let file_name = Path::new(&file_path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| file_path.clone());
+                self.spawn_instance(SpawnParams {
+                    command: Some("nvim".to_string()),
+                    args: Some(vec![format!("+{}", line), file_path.clone()]),
+                    working_directory: shell_cwd(source.pid).or(source.working_directory),
+                    title: Some(format!("nvim {}", file_name)),
+                    tags: Some(source.tags),
+                    ..Default::default()
+                }).await?.id
+            }
+        };
+
+        Ok(CloneBufferResult {
+            source_instance_id: params.source_instance_id,
+            target_instance_id,
+            file_path,
+            line,
+        })
+    }
+
+    async fn is_neovim_process(&self, pid: u32) -> Result<bool> {
+        // Check if the process or any child process is nvim
+        let output = Command::new("ps")
+            .args(["--ppid", &pid.to_string(), "-o", "comm="])
+            .output()?;
+
+        if output.status.success() {
+            let processes = String::from_utf8_lossy(&output.stdout);
+            if processes.lines().any(|line| line.trim().contains("nvim")) {
+                return Ok(true);
+            }
+        }
+
+        // Also check the main process
+        let output = Command::new("ps")
+            .args(["-p", &pid.to_string(), "-o", "comm="])
+            .output()?;
+
+        if output.status.success() {
+            let process_name = String::from_utf8_lossy(&output.stdout);
+            return Ok(process_name.trim().contains("nvim"));
+        }
+
+        Ok(false)
+    }
+
+    async fn refresh_instances(&self) -> Result<()> {
+        let running_pids = running_alacritty_pids()?;
+
+        // Instances that are no longer running stay listed as exited until
+        // they are closed
+        let now = unix_timestamp();
+        for instance in self.instances.write().await.values_mut() {
+            let status = if running_pids.contains(&instance.pid) { instance.status } else { InstanceStatus::Exited };
+            if status != instance.status {
+                instance.status = status;
+                instance.last_activity_at = now;
+            }
+        }
+
+        self.adopt_pids(running_pids).await;
+        self.registry_changed().await;
+        Ok(())
+    }
+
+    /// Adds every running Alacritty window that is not in the registry yet,
+    /// such as terminals opened by hand before the server started. Returns
+    /// the newly adopted instances.
+    pub async fn import_existing_instances(&self) -> Result<Vec<AlacrittyInstance>> {
+        let imported = self.adopt_pids(running_alacritty_pids()?).await;
+        if !imported.is_empty() {
+            self.registry_changed().await;
+        }
+        Ok(imported)
+    }
+
+    /// Registers the PIDs we haven't seen before. The lock is released while
+    /// looking them up, so check again before inserting.
+    async fn adopt_pids(&self, pids: Vec<u32>) -> Vec<AlacrittyInstance> {
+        let new_pids: Vec<u32> = {
+            let instances = self.instances.read().await;
+            pids.into_iter()
+                .filter(|pid| !instances.values().any(|inst| inst.pid == *pid))
+                .collect()
+        };
+
+        let mut adopted = Vec::new();
+        for pid in new_pids {
+            if let Ok(instance) = self.create_instance_from_pid(pid).await {
+                let mut instances = self.instances.write().await;
+                if !instances.values().any(|inst| inst.pid == pid) {
+                    instances.insert(instance.id.clone(), instance.clone());
+                    adopted.push(instance);
+                }
+            }
+        }
+        adopted
+    }
+
+    /// Records activity on an instance now, for `kill_idle_instances`.
+    async fn touch(&self, instance_id: &str) {
+        if let Some(instance) = self.instances.write().await.get_mut(instance_id) {
+            instance.last_activity_at = unix_timestamp();
+        }
+        self.registry_changed().await;
+    }
+
+    /// Called after every change to `instances`: writes the state file.
+    async fn registry_changed(&self) {
+        if let Some(file) = &self.state_file {
+            if let Err(e) = file.save(&*self.instances.read().await) {
+                warn!("Failed to write state file {}: {}", file.path().display(), e);
+            }
+        }
+    }
+
+    async fn create_instance_from_pid(&self, pid: u32) -> Result<AlacrittyInstance> {
+        // Get process info
+        let cmdline_path = format!("/proc/{}/cmdline", pid);
+        let cmdline = std::fs::read_to_string(cmdline_path)?;
+        let args: Vec<&str> = cmdline.split('\0').filter(|s| !s.is_empty()).collect();
+
+        // `pgrep -f` also matches processes that merely mention alacritty.
+        // The second argument covers wrappers run through an interpreter.
+        let is_alacritty = args.iter()
+            .take(2)
+            .any(|arg| arg.rsplit('/').next() == Some("alacritty"));
+        if !is_alacritty {
+            return Err(anyhow!("PID {} is not an Alacritty process", pid));
+        }
+
+        let mut title = format!("alacritty-{}", pid);
+        let mut command = "shell".to_string();
+        let mut command_args = Vec::new();
+        let mut working_directory = None;
+        let mut instance_id = None;
+
+        // Parse command line arguments
+        let mut i = 0;
+        while i < args.len() {
+            let value = args.get(i + 1).map(|value| value.to_string());
+            match args[i] {
+                "--title" | "-T" | "-t" => {
+                    if let Some(value) = value {
+                        title = value;
+                    }
+                    i += 2;
+                }
+                "--command" | "-e" => {
+                    // Everything after the program is its arguments
+                    if let Some(value) = value {
+                        command = value;
+                        command_args = args[i + 2..].iter().map(|arg| arg.to_string()).collect();
+                    }
+                    break;
+                }
+                "--working-directory" => {
+                    working_directory = value;
+                    i += 2;
+                }
+                "--class" => {
+                    // Windows spawned by an earlier server keep their ID
+                    instance_id = value.as_deref()
+                        .and_then(|class| class.strip_prefix(INSTANCE_CLASS_PREFIX))
+                        .filter(|id| Uuid::parse_str(id).is_ok())
+                        .map(str::to_string);
+                    i += 2;
+                }
+                _ => i += 1,
+            }
+        }
+
+        let window_class = instance_id.as_deref().map(instance_class);
+        let instance_id = instance_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let window_id = match self.platform {
+            Platform::X11 => find_window_id(pid, window_class.as_deref()).ok(),
+            Platform::Wayland => None,
+        };
+
+        let process_started_at = process_start_time(pid);
+        let created_at = process_started_at.unwrap_or(0);
+        Ok(AlacrittyInstance {
+            id: instance_id,
+            pid,
+            window_id,
+            title,
+            command,
+            args: command_args,
+            created_at,
+            status: InstanceStatus::Running,
+            last_focused_at: None,
+            tags: Vec::new(),
+            working_directory: working_directory.or_else(|| read_cwd(pid)),
+            exit_code: None,
+            window_class,
+            ipc_socket_path: find_ipc_socket(pid).map(|socket| socket.to_string_lossy().to_string()),
+            last_activity_at: created_at,
+            process_started_at,
+        })
+    }
+
+    /// Sends one JSON message, as `alacritty msg` would, to the instance's IPC
+    /// socket and returns whatever Alacritty answers, usually nothing.
+    pub async fn send_ipc_message(&self, id: &str, payload: &str) -> Result<String> {
+        let message: serde_json::Value = serde_json::from_str(payload)
+            .map_err(|e| AlacrittyMcpError::InvalidParams(format!("IPC payload is not JSON: {}", e)))?;
+        let socket = self.ipc_socket(id).await?;
+
+        let mut stream = tokio::net::UnixStream::connect(&socket).await
+            .map_err(|e| anyhow!("Cannot connect to {}: {}", socket.display(), e))?;
+        // Alacritty reads one message per line
+        let line = format!("{}\n", serde_json::to_string(&message)?);
+        let exchange = async {
+            stream.write_all(line.as_bytes()).await?;
+            stream.shutdown().await?;
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await?;
+            Ok::<_, std::io::Error>(reply)
+        };
+        tokio::time::timeout(IPC_TIMEOUT, exchange).await
+            .map_err(|_| anyhow!("No answer on {} within {}s", socket.display(), IPC_TIMEOUT.as_secs()))?
+            .map_err(|e| anyhow!("IPC message to {} failed: {}", socket.display(), e))
+    }
+
+    /// The instance's IPC socket: the one found earlier if it is still there,
+    /// or a fresh lookup, which is then kept in the registry.
+    async fn ipc_socket(&self, instance_id: &str) -> Result<PathBuf> {
+        let instance = self.instance(instance_id).await?;
+        if let Some(socket) = instance.ipc_socket_path.map(PathBuf::from).filter(|socket| socket.exists()) {
+            return Ok(socket);
+        }
+
+        let socket = find_ipc_socket(instance.pid).ok_or_else(|| anyhow!(
+            "No IPC socket found for Alacritty PID {}; check that `ipc_socket` is enabled in its config",
+            instance.pid
+        ))?;
+        if let Some(instance) = self.instances.write().await.get_mut(instance_id) {
+            instance.ipc_socket_path = Some(socket.to_string_lossy().to_string());
+        }
+        self.registry_changed().await;
+        Ok(socket)
+    }
+
+    /// Directory the instance's shell is in, which file paths are relative to.
+    async fn working_directory(&self, instance_id: &str) -> Result<String> {
+        let instance = self.instance(instance_id).await?;
+        shell_cwd(instance.pid)
+            .ok_or_else(|| anyhow!("Could not read the working directory of instance {}", instance_id))
+    }
+
+    /// A copy of one instance, so the registry lock is not held while the
+    /// caller talks to the display server.
+    async fn instance(&self, instance_id: &str) -> Result<AlacrittyInstance> {
+        validate_instance_id(instance_id)?;
+        self.instances.read().await.get(instance_id)
+            .cloned()
+            .ok_or_else(|| AlacrittyMcpError::InstanceNotFound(instance_id.to_string()).into())
+    }
+
+    /// A window command that exited unsuccessfully. On X11 xdotool ran it,
+    /// so the error says so.
+    fn window_command_failed(&self, message: String) -> anyhow::Error {
+        match self.platform {
+            Platform::X11 => AlacrittyMcpError::XdotoolFailed(message).into(),
+            Platform::Wayland => anyhow!(message),
+        }
+    }
+
+    /// Looks up a new instance's window until it shows up, waiting longer
+    /// after each miss. Gives up after `WINDOW_ID_ATTEMPTS` tries, or once
+    /// the terminal has exited, leaving `window_id` for later calls to find.
+    async fn wait_for_window_id(&self, instance_id: &str) -> Option<u32> {
+        let mut last_error = None;
+        for attempt in 1..=WINDOW_ID_ATTEMPTS {
+            tokio::time::sleep(WINDOW_ID_RETRY_DELAY * attempt).await;
+
+            match self.get_window_id_for_instance(instance_id).await {
+                Ok(window_id) => return Some(window_id),
+                Err(e) => last_error = Some(e),
+            }
+            let exited = match self.instance(instance_id).await {
+                Ok(instance) => instance.status == InstanceStatus::Exited,
+                Err(_) => true,
+            };
+            if exited {
+                break;
+            }
+        }
+
+        if let Some(e) = last_error {
+            warn!("No window found for instance {}; send_keys will retry the lookup: {}", instance_id, e);
+        }
+        None
+    }
+
+    /// The instance's window, looked up once and then kept in the registry.
+    async fn get_window_id_for_instance(&self, instance_id: &str) -> Result<u32> {
+        let instance = self.instance(instance_id).await?;
+
+        if let Some(window_id) = instance.window_id {
+            return Ok(window_id);
+        }
+
+        let window_id = find_window_id(instance.pid, instance.window_class.as_deref())?;
+        if let Some(inst) = self.instances.write().await.get_mut(instance_id) {
+            inst.window_id = Some(window_id);
+        }
+        self.registry_changed().await;
+        Ok(window_id)
+    }
+}
+
+/// Start of the `--class` the server gives the windows it spawns, followed
+/// by the instance ID.
+const INSTANCE_CLASS_PREFIX: &str = "alacritty-mcp-";
+
+fn instance_class(instance_id: &str) -> String {
+    format!("{}{}", INSTANCE_CLASS_PREFIX, instance_id)
+}
+
+/// Searches by `class` when the server set one, which matches exactly one
+/// window, and by PID otherwise. The PID search is also the fallback, in
+/// case the window manager did not keep the class.
+fn find_window_id(pid: u32, class: Option<&str>) -> Result<u32> {
+    if let Some(class) = class {
+        // xdotool matches the class as a regex, so anchor it
+        if let Some(window_id) = xdotool_search(&["--class", &format!("^{}$", class)])? {
             return Ok(window_id);
         }
+    }
+
+    xdotool_search(&["--pid", &pid.to_string(), "--class", "Alacritty"])?
+        .ok_or_else(|| AlacrittyMcpError::WindowNotFound(pid).into())
+}
+
+/// The first window `xdotool search` finds, if any.
+fn xdotool_search(args: &[&str]) -> Result<Option<u32>> {
+    let output = Command::new("xdotool")
+        .arg("search")
+        .args(args)
+        .output()?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .and_then(|line| line.trim().parse().ok()))
+}
+
+/// PIDs of every process whose command line mentions alacritty.
+fn running_alacritty_pids() -> Result<Vec<u32>> {
+    let output = Command::new("pgrep")
+        .args(["-f", "alacritty"])
+        .output()?;
+
+    // pgrep exits with 1 when nothing matched
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+
+    let own_pid = std::process::id();
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .filter(|pid| *pid != own_pid)
+        .collect())
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Window size in pixels from `xdotool getwindowgeometry --shell`.
+fn x11_window_size(window_id: u32) -> Result<(u32, u32)> {
+    let output = Command::new("xdotool")
+        .args(["getwindowgeometry", "--shell", &window_id.to_string()])
+        .output()?;
+
+    if !output.status.success() {
+        return Err(AlacrittyMcpError::XdotoolFailed(format!("Failed to get window geometry: {}",
+            String::from_utf8_lossy(&output.stderr))).into());
+    }
+
+    let geometry = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| -> Option<u32> {
+        geometry
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))
+            .and_then(|value| value.trim().parse().ok())
+    };
+
+    match (field("WIDTH"), field("HEIGHT")) {
+        (Some(width), Some(height)) => Ok((width, height)),
+        _ => Err(anyhow!("Unexpected window geometry output: {}", geometry.trim())),
+    }
+}
+
+/// Cell size and padding from a window's `WM_NORMAL_HINTS`.
+struct SizeHints {
+    cell_width: u32,
+    cell_height: u32,
+    /// Window size with no cells, i.e. the padding around the grid
+    base_width: u32,
+    base_height: u32,
+}
+
+/// Reads the resize increment (one cell) and base size Alacritty sets on its
+/// window, as printed by `xprop`. `None` when the window has no increment.
+fn x11_size_hints(window_id: u32) -> Option<SizeHints> {
+    let output = Command::new("xprop")
+        .args(["-id", &window_id.to_string(), "WM_NORMAL_HINTS"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let hints = String::from_utf8_lossy(&output.stdout);
+    // e.g. "program specified resize increment: 9 by 18"
+    let size = |name: &str| -> Option<(u32, u32)> {
+        let value = hints.lines().find_map(|line| line.trim().strip_prefix(name))?;
+        let (width, height) = value.trim().split_once(" by ")?;
+        Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+    };
+
+    let (cell_width, cell_height) = size("program specified resize increment:").filter(|&(w, h)| w > 0 && h > 0)?;
+    let (base_width, base_height) = size("program specified base size:").unwrap_or((0, 0));
+    Some(SizeHints { cell_width, cell_height, base_width, base_height })
+}
+
+fn check_region(params: &ScreenshotRegionParams, window_width: u32, window_height: u32) -> Result<()> {
+    let fits = params.width > 0
+        && params.height > 0
+        && params.x.checked_add(params.width).is_some_and(|right| right <= window_width)
+        && params.y.checked_add(params.height).is_some_and(|bottom| bottom <= window_height);
+
+    if !fits {
+        return Err(AlacrittyMcpError::InvalidParams(format!(
+            "Region {}x{}+{}+{} does not fit inside the {}x{} window",
+            params.width, params.height, params.x, params.y, window_width, window_height
+        )).into());
+    }
+    Ok(())
+}
+
+fn find_sway_node_rect(node: &serde_json::Value, pid: u32) -> Option<(i64, i64, i64, i64)> {
+    if node["pid"].as_u64() == Some(pid as u64) {
+        let rect = &node["rect"];
+        return Some((
+            rect["x"].as_i64()?,
+            rect["y"].as_i64()?,
+            rect["width"].as_i64()?,
+            rect["height"].as_i64()?,
+        ));
+    }
+
+    ["nodes", "floating_nodes"].iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten()
+        .find_map(|child| find_sway_node_rect(child, pid))
+}
+
+/// Reports whether a process is still alive. Zombies count as exited: the
+/// ones we spawned are reaped by the task waiting on their `Child`.
+fn process_status(pid: u32) -> InstanceStatus {
+    if is_pid_alive(pid) {
+        InstanceStatus::Running
+    } else {
+        InstanceStatus::Exited
+    }
+}
+
+/// Gives whoever reaps `pid` (usually our own reaper task) up to a second to
+/// do so, so callers see the process fully gone once a close returns.
+async fn wait_until_reaped(pid: u32) {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(1);
+    while proc_dir.exists() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+    }
+}
+
+/// Builds the tree from the parent PID in every `/proc/<pid>/stat`.
+/// `/proc/<pid>/task/*/children` would be cheaper but needs a kernel built
+/// with `CONFIG_PROC_CHILDREN`.
+pub fn read_process_tree(root: u32) -> Result<ProcessNode> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+            continue;
+        };
+        if let Some(ppid) = read_parent_pid(pid) {
+            children.entry(ppid).or_default().push(pid);
+        }
+    }
+
+    if !std::path::Path::new(&format!("/proc/{}", root)).exists() {
+        return Err(anyhow!("Process {} is not running", root));
+    }
+    Ok(build_process_node(root, &children))
+}
+
+fn build_process_node(pid: u32, children: &HashMap<u32, Vec<u32>>) -> ProcessNode {
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|comm| comm.trim_end().to_string())
+        .unwrap_or_default();
+    let args = std::fs::read(format!("/proc/{}/cmdline", pid))
+        .map(|cmdline| {
+            cmdline
+                .split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut child_pids = children.get(&pid).cloned().unwrap_or_default();
+    child_pids.sort();
+
+    ProcessNode {
+        pid,
+        name,
+        args,
+        children: child_pids
+            .into_iter()
+            .map(|child| build_process_node(child, children))
+            .collect(),
+    }
+}
+
+fn is_nvim(program: &str) -> bool {
+    program.rsplit('/').next() == Some("nvim")
+}
+
+/// Whether `nvim` is the name of, or any argument of, a process in the tree.
+/// The root's arguments include Alacritty's own `--command nvim`.
+fn runs_nvim(node: &ProcessNode) -> bool {
+    node.name == "nvim"
+        || node.args.iter().any(|arg| is_nvim(arg))
+        || node.children.iter().any(runs_nvim)
+}
+
+/// `(major, minor, patch)` from `alacritty --version`, e.g. `alacritty 0.13.2 (bb8ea18)`.
+fn alacritty_version(binary: &str) -> Result<(u32, u32, u32)> {
+    let output = Command::new(binary).arg("--version").output()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+
+    let version = text.split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("Unexpected `alacritty --version` output: {}", text.trim()))?;
+    let mut parts = version.split('.').map(|part| part.parse::<u32>());
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor)), patch) => Ok((major, minor, patch.and_then(|p| p.ok()).unwrap_or(0))),
+        _ => Err(anyhow!("Unexpected `alacritty --version` output: {}", text.trim())),
+    }
+}
+
+/// The IPC socket of the Alacritty process `pid`. Alacritty tells the
+/// programs it runs through `$ALACRITTY_SOCKET`; otherwise the usual
+/// locations are searched.
+fn find_ipc_socket(pid: u32) -> Option<PathBuf> {
+    let from_env = read_process_tree(pid)
+        .ok()
+        .into_iter()
+        .flat_map(|tree| tree.children)
+        .find_map(|child| read_environ_var(child.pid, "ALACRITTY_SOCKET"))
+        .map(PathBuf::from);
+    if let Some(socket) = from_env.filter(|socket| socket.exists()) {
+        return Some(socket);
+    }
+
+    let legacy = PathBuf::from(format!("/tmp/alacritty-{}.sock", pid));
+    if legacy.exists() {
+        return Some(legacy);
+    }
+
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty())?;
+    let nested = Path::new(&runtime_dir).join("alacritty").join(format!("alacritty-{}.sock", pid));
+    if nested.exists() {
+        return Some(nested);
+    }
+
+    // Alacritty names it `Alacritty-<display>-<pid>.sock` in the runtime dir
+    let suffix = format!("-{}.sock", pid);
+    std::fs::read_dir(runtime_dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("Alacritty-") && name.ends_with(&suffix))
+        })
+}
+
+pub fn read_memory_usage(pid: u32) -> Result<MemoryUsage> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid))
+        .map_err(|e| anyhow!("Cannot read memory of PID {}: {}", pid, e))?;
+    Ok(MemoryUsage::from_proc_status(&status))
+}
+
+/// Looks `name` up in `/proc/<pid>/environ`, the environment the process
+/// was started with.
+pub fn read_environ_var(pid: u32, name: &str) -> Option<String> {
+    let environ = std::fs::read(format!("/proc/{}/environ", pid)).ok()?;
+    let prefix = format!("{}=", name);
+    environ
+        .split(|byte| *byte == 0)
+        .filter_map(|entry| std::str::from_utf8(entry).ok())
+        .find_map(|entry| entry.strip_prefix(&prefix).map(|value| value.to_string()))
+}
+
+/// Every variable in `/proc/<pid>/environ`, sorted by name. Entries without
+/// `=` are skipped; bytes that are not UTF-8 are replaced.
+pub fn read_environment(pid: u32) -> Result<Vec<EnvVar>> {
+    let environ = std::fs::read(format!("/proc/{}/environ", pid))
+        .map_err(|e| anyhow!("Cannot read the environment of PID {}: {}", pid, e))?;
+
+    let mut environment: Vec<EnvVar> = environ
+        .split(|byte| *byte == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = entry.split_once('=')?;
+            Some(EnvVar { key: key.to_string(), value: value.to_string() })
+        })
+        .collect();
+    environment.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(environment)
+}
+
+/// Whether the variable's name contains one of `SECRET_ENV_MARKERS`, in
+/// any case.
+pub fn is_secret_env_key(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    SECRET_ENV_MARKERS.iter().any(|marker| key.contains(marker))
+}
+
+/// The config file the Alacritty with this PID loaded: its `--config-file`,
+/// or the first file that exists in Alacritty's search order, resolved with
+/// the process's own `XDG_CONFIG_HOME` and `HOME`. `.yml` files are what
+/// versions before 0.13 read.
+fn alacritty_config_file(pid: u32) -> Option<PathBuf> {
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<String> = cmdline
+        .split(|byte| *byte == 0)
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect();
+    if let Some(path) = args.iter().position(|arg| arg == "--config-file").and_then(|i| args.get(i + 1)) {
+        let path = PathBuf::from(path);
+        return Some(match read_cwd(pid) {
+            Some(cwd) if path.is_relative() => Path::new(&cwd).join(path),
+            _ => path,
+        });
+    }
+
+    let env_dir = |name: &str| read_environ_var(pid, name).filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    let home = env_dir("HOME");
+    let xdg_config_home = env_dir("XDG_CONFIG_HOME").or_else(|| home.as_ref().map(|home| home.join(".config")));
+
+    let mut candidates = Vec::new();
+    if let Some(dir) = &xdg_config_home {
+        candidates.push(dir.join("alacritty").join("alacritty"));
+        candidates.push(dir.join("alacritty"));
+    }
+    if let Some(home) = &home {
+        candidates.push(home.join(".config").join("alacritty").join("alacritty"));
+        candidates.push(home.join(".alacritty"));
+    }
+    candidates.push(PathBuf::from("/etc/alacritty/alacritty"));
+
+    candidates
+        .iter()
+        .flat_map(|stem| ["toml", "yml"].map(|extension| stem.with_extension(extension)))
+        .find(|path| path.is_file())
+}
+
+/// The directories `ALLOWED_DIRS_ENV` lists, or `None` when it is unset or
+/// empty.
+pub fn allowed_dirs() -> Option<Vec<PathBuf>> {
+    let value = std::env::var_os(ALLOWED_DIRS_ENV)?;
+    let dirs: Vec<PathBuf> = std::env::split_paths(&value).filter(|dir| !dir.as_os_str().is_empty()).collect();
+    (!dirs.is_empty()).then_some(dirs)
+}
+
+/// Fails unless `dir` is one of `allowed` or lies beneath one. Both sides are
+/// canonicalized first, so `..` and symlinks cannot lead outside; allowed
+/// directories that do not exist match nothing.
+pub fn check_allowed_dir(dir: &str, allowed: &[PathBuf]) -> Result<()> {
+    let canonical = std::fs::canonicalize(dir).map_err(|e| {
+        AlacrittyMcpError::InvalidParams(format!("working_directory {} cannot be resolved: {}", dir, e))
+    })?;
+
+    if allowed.iter().filter_map(|prefix| std::fs::canonicalize(prefix).ok()).any(|prefix| canonical.starts_with(prefix)) {
+        return Ok(());
+    }
+    Err(AlacrittyMcpError::InvalidParams(format!(
+        "working_directory {} is outside the directories allowed by {}",
+        dir, ALLOWED_DIRS_ENV
+    )).into())
+}
+
+fn read_cwd(pid: u32) -> Option<String> {
+    std::fs::read_link(format!("/proc/{}/cwd", pid))
+        .ok()
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+/// Working directory of the shell inside the terminal. Alacritty itself never
+/// changes directory, the process inside it does.
+fn shell_cwd(pid: u32) -> Option<String> {
+    read_process_tree(pid)
+        .ok()
+        .and_then(|tree| tree.children.first().map(|child| child.pid))
+        .and_then(read_cwd)
+        .or_else(|| read_cwd(pid))
+}
+
+/// How many screenshots `monitor_instance` takes: one per interval that fits
+/// in the duration, at least one and at most `MAX_MONITOR_SCREENSHOTS`.
+pub fn monitor_screenshot_count(duration_secs: u32, interval_ms: u32) -> u32 {
+    (duration_secs as u64 * 1000 / interval_ms.max(1) as u64).clamp(1, MAX_MONITOR_SCREENSHOTS as u64) as u32
+}
+
+/// Instance IDs are `Uuid::new_v4()` in its usual hyphenated lowercase
+/// form. Anything else cannot name an instance, so it is rejected as a bad
+/// parameter rather than looked up.
+pub fn validate_instance_id(instance_id: &str) -> Result<()> {
+    match Uuid::parse_str(instance_id) {
+        Ok(uuid) if uuid.get_version() == Some(uuid::Version::Random) && uuid.to_string() == instance_id => Ok(()),
+        _ => Err(AlacrittyMcpError::InvalidParams(format!("Invalid instance_id format: '{}'", instance_id)).into()),
+    }
+}
+
+/// Linux input event codes (`linux/input-event-codes.h`) for the xdotool
+/// key names `ydotool_key_args` understands beyond letters and digits,
+/// in lowercase.
+const EVDEV_KEYCODES: &[(&str, u16)] = &[
+    ("escape", 1), ("esc", 1), ("minus", 12), ("equal", 13), ("backspace", 14), ("tab", 15),
+    ("bracketleft", 26), ("bracketright", 27), ("return", 28), ("enter", 28),
+    ("ctrl", 29), ("control", 29), ("control_l", 29), ("semicolon", 39), ("apostrophe", 40),
+    ("grave", 41), ("shift", 42), ("shift_l", 42), ("backslash", 43), ("comma", 51),
+    ("period", 52), ("slash", 53), ("shift_r", 54), ("alt", 56), ("alt_l", 56), ("space", 57),
+    ("caps_lock", 58), ("f1", 59), ("f2", 60), ("f3", 61), ("f4", 62), ("f5", 63), ("f6", 64),
+    ("f7", 65), ("f8", 66), ("f9", 67), ("f10", 68), ("f11", 87), ("f12", 88),
+    ("control_r", 97), ("print", 99), ("alt_r", 100), ("home", 102), ("up", 103),
+    ("prior", 104), ("page_up", 104), ("left", 105), ("right", 106), ("end", 107),
+    ("down", 108), ("next", 109), ("page_down", 109), ("insert", 110), ("delete", 111),
+    ("pause", 119), ("super", 125), ("super_l", 125), ("super_r", 126), ("menu", 127),
+];
+
+/// Letters in keyboard order, from `q` (16) along each row.
+const EVDEV_LETTER_ROWS: [(&str, u16); 3] = [("qwertyuiop", 16), ("asdfghjkl", 30), ("zxcvbnm", 44)];
+
+/// The evdev keycode of one xdotool key name, e.g. `Return` or `v`, with
+/// whether it needs shift, as an uppercase letter does.
+fn evdev_keycode(name: &str) -> Option<(u16, bool)> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if let Some(digit) = c.to_digit(10) {
+            // 1 to 9 are 2 to 10, with 0 after them
+            return Some(((digit + 9) as u16 % 10 + 2, false));
+        }
+        let lower = c.to_ascii_lowercase();
+        let code = EVDEV_LETTER_ROWS.iter().find_map(|(row, first)| {
+            row.find(lower).map(|offset| first + offset as u16)
+        });
+        if let Some(code) = code {
+            return Some((code, c.is_ascii_uppercase()));
+        }
+    }
+
+    let name = name.to_ascii_lowercase();
+    EVDEV_KEYCODES.iter().find(|(key, _)| *key == name).map(|(_, code)| (*code, false))
+}
+
+/// Turns xdotool-style keys, such as `ctrl+shift+v Return`, into the
+/// `KEYCODE:STATE` pairs ydotool 1.x takes: each combination presses its
+/// keys in order and releases them in reverse.
+pub fn ydotool_key_args(keys: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    for combo in keys.split_whitespace() {
+        let mut codes = Vec::new();
+        for name in combo.split('+') {
+            let (code, shifted) = evdev_keycode(name).ok_or_else(|| AlacrittyMcpError::InvalidParams(format!(
+                "Unknown key '{}' in '{}'; ydotool needs names like ctrl, shift, Return, F5 or a", name, combo
+            )))?;
+            if shifted && !codes.contains(&42) {
+                codes.push(42);
+            }
+            codes.push(code);
+        }
+        args.extend(codes.iter().map(|code| format!("{}:1", code)));
+        args.extend(codes.iter().rev().map(|code| format!("{}:0", code)));
+    }
+
+    if args.is_empty() {
+        return Err(AlacrittyMcpError::InvalidParams("keys must name at least one key".to_string()).into());
+    }
+    Ok(args)
+}
+
+/// Single-quotes `arg` for a POSIX shell.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Joins `relative` onto `root` without touching the filesystem, rejecting
+/// absolute paths and `..` components that climb above `root`.
+fn resolve_relative_path(root: &Path, relative: &str) -> Result<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    let mut depth = 0usize;
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                depth += 1;
+            }
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => {
+                resolved.pop();
+                depth -= 1;
+            }
+            _ => return Err(AlacrittyMcpError::InvalidParams(format!(
+                "Path '{}' is outside the working directory", relative
+            )).into()),
+        }
+    }
+
+    if depth == 0 {
+        return Err(AlacrittyMcpError::InvalidParams(format!("Path '{}' does not name a file", relative)).into());
+    }
+    Ok(resolved)
+}
+
+fn check_within(root: &str, path: &Path, relative: &str) -> Result<()> {
+    let root = Path::new(root).canonicalize()?;
+    if !path.starts_with(&root) {
+        return Err(AlacrittyMcpError::InvalidParams(format!("Path '{}' is outside the working directory", relative)).into());
+    }
+    Ok(())
+}
+
+/// Reads the lines `start_line..=end_line` (1-based) of the file at `path`
+/// in one streaming pass, holding on to at most `max_bytes` of them. The
+/// whole file is still seen, to count its lines and to tell whether it is
+/// UTF-8; anything that isn't is shown byte for byte as Latin-1.
+fn read_text_file(path: &Path, start_line: u32, end_line: Option<u32>, max_bytes: usize) -> Result<FileContent> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)?;
+    let mut chunk = vec![0; READ_CHUNK_BYTES];
+    let mut line = 1u32;
+    let mut last_byte = None;
+    // One byte past `max_bytes` shows whether there was more
+    let mut kept = Vec::new();
+    let mut sniffed = 0;
+    let mut is_utf8 = true;
+    // The start of a character split across two chunks
+    let mut partial = Vec::new();
+
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        let bytes = &chunk[..read];
+
+        if sniffed < BINARY_SNIFF_BYTES {
+            if bytes[..read.min(BINARY_SNIFF_BYTES - sniffed)].contains(&0) {
+                return Err(FileError::Binary { path: path.to_string_lossy().to_string() }.into());
+            }
+            sniffed += read;
+        }
+
+        if is_utf8 {
+            partial.extend_from_slice(bytes);
+            match std::str::from_utf8(&partial) {
+                Ok(_) => partial.clear(),
+                Err(e) if e.error_len().is_none() => drop(partial.drain(..e.valid_up_to())),
+                Err(_) => is_utf8 = false,
+            }
+        }
+
+        for &byte in bytes {
+            if line >= start_line && end_line.is_none_or(|end| line <= end) && kept.len() <= max_bytes {
+                kept.push(byte);
+            }
+            if byte == b'\n' {
+                line = line.saturating_add(1);
+            }
+        }
+        last_byte = bytes.last().copied();
+    }
+
+    // A character cut off by the end of the file
+    is_utf8 &= partial.is_empty();
+    // `max_bytes` counts bytes of the file, whatever they become as text
+    let truncated = kept.len() > max_bytes;
+    kept.truncate(max_bytes);
+    let (content, encoding) = if is_utf8 {
+        // The cut may fall partway through a character
+        let valid = std::str::from_utf8(&kept).map_or_else(|e| e.valid_up_to(), str::len);
+        kept.truncate(valid);
+        (String::from_utf8(kept)?, "utf-8")
+    } else {
+        (kept.iter().map(|byte| *byte as char).collect(), "iso-8859-1")
+    };
+
+    // A last line without a newline counts too
+    let total_lines = line - 1 + u32::from(last_byte.is_some_and(|byte| byte != b'\n'));
+    Ok(FileContent {
+        path: path.to_string_lossy().to_string(),
+        content,
+        total_lines,
+        encoding: encoding.to_string(),
+        truncated,
+    })
+}
+
+/// Parses an octal permission string such as `644`, `0755` or `0o600`.
+fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if !digits.is_empty() && mode <= 0o7777 => Ok(mode),
+        _ => Err(AlacrittyMcpError::InvalidParams(format!("Invalid mode '{}', expected octal like 644", mode)).into()),
+    }
+}
+
+/// The fourth field of `/proc/<pid>/stat`. The command name before it is
+/// parenthesised and may itself contain spaces or parentheses.
+fn read_parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+    after_comm.split_whitespace().nth(1)?.parse().ok()
+}
 
-        self.get_window_id_for_pid(instance.pid).await
+impl Default for AlacrittyManager {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-// Add base64 encoding since we're using it
+/// Standard (padded) base64, as used in `data:` URIs, plus the URL-safe
+/// alphabet (`-_` for `+/`).
 pub mod base64 {
-    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    use anyhow::{Result, anyhow};
+    use ::base64::Engine;
+    use ::base64::alphabet;
+    use ::base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+    use ::base64::engine::general_purpose::{STANDARD, URL_SAFE};
+
+    /// URL-safe input is often stripped of its padding, so it may be left
+    /// out; padding that is there must still be right.
+    const URL_SAFE_DECODER: GeneralPurpose = GeneralPurpose::new(
+        &alphabet::URL_SAFE,
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+    );
 
     pub fn encode(input: &[u8]) -> String {
-        let mut result = String::new();
-        let mut i = 0;
-        
-        while i < input.len() {
-            let b1 = input[i];
-            let b2 = if i + 1 < input.len() { input[i + 1] } else { 0 };
-            let b3 = if i + 2 < input.len() { input[i + 2] } else { 0 };
-            
-            let bitmap = ((b1 as u32) << 16) | ((b2 as u32) << 8) | (b3 as u32);
-            
-            result.push(CHARS[((bitmap >> 18) & 63) as usize] as char);
-            result.push(CHARS[((bitmap >> 12) & 63) as usize] as char);
-            result.push(if i + 1 < input.len() { CHARS[((bitmap >> 6) & 63) as usize] as char } else { '=' });
-            result.push(if i + 2 < input.len() { CHARS[(bitmap & 63) as usize] as char } else { '=' });
-            
-            i += 3;
-        }
-        
-        result
+        STANDARD.encode(input)
+    }
+
+    /// Whitespace anywhere in `input`, e.g. from line-wrapped data, is
+    /// ignored. Padding is required.
+    pub fn decode(input: &str) -> Result<Vec<u8>> {
+        STANDARD.decode(strip_whitespace(input)).map_err(|e| anyhow!("Invalid base64 data: {}", e))
+    }
+
+    pub fn encode_url_safe(input: &[u8]) -> String {
+        URL_SAFE.encode(input)
+    }
+
+    pub fn decode_url_safe(input: &str) -> Result<Vec<u8>> {
+        URL_SAFE_DECODER.decode(strip_whitespace(input)).map_err(|e| anyhow!("Invalid URL-safe base64 data: {}", e))
+    }
+
+    fn strip_whitespace(input: &str) -> String {
+        input.chars().filter(|c| !c.is_ascii_whitespace()).collect()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use serde_json::Value;
+use tokio::task::AbortHandle;
+use tracing::debug;
+
+/// Tool calls that are still running, so that `$/cancelRequest` (or MCP's
+/// `notifications/cancelled`) can abort them. Clones share the same set.
+#[derive(Debug, Clone, Default)]
+pub struct InFlightRequests {
+    /// Keyed by the request ID's JSON text, as `Value` is not `Hash`. That
+    /// keeps `1` and `"1"` apart, as JSON-RPC requires.
+    handles: Arc<Mutex<HashMap<String, AbortHandle>>>,
+}
+
+impl InFlightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, id: &Value, handle: AbortHandle) {
+        self.handles.lock().unwrap().insert(id.to_string(), handle);
+    }
+
+    pub fn remove(&self, id: &Value) {
+        self.handles.lock().unwrap().remove(&id.to_string());
+    }
+
+    /// Aborts the call with this request ID. Returns whether one was running;
+    /// a call that already finished, or never existed, is not an error.
+    pub fn cancel(&self, id: &Value) -> bool {
+        match self.handles.lock().unwrap().remove(&id.to_string()) {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => {
+                debug!(id = %id, "Nothing to cancel");
+                false
+            }
+        }
+    }
+}
+
+/// The request ID `line` cancels, if it is a cancellation notification.
+/// Requests that carry an `id` of their own are left to the server.
+pub fn cancellation_target(line: &str) -> Option<Value> {
+    let message = serde_json::from_str::<Value>(line).ok()?;
+    if message.get("id").is_some() {
+        return None;
+    }
+    let method = message.get("method")?.as_str()?;
+    cancelled_request_id(method, message.get("params")).cloned()
+}
+
+/// The request a cancellation refers to: `params.id` for `$/cancelRequest`,
+/// `params.requestId` for `notifications/cancelled`. `None` for any other
+/// method.
+pub fn cancelled_request_id<'a>(method: &str, params: Option<&'a Value>) -> Option<&'a Value> {
+    let key = match method {
+        "$/cancelRequest" => "id",
+        "notifications/cancelled" => "requestId",
+        _ => return None,
+    };
+    params?.get(key).filter(|id| !id.is_null())
+}
@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::neovim_context::COMMAND_TIMEOUT;
+
+/// Server settings read from `config.toml`. Every field is optional in the
+/// file; anything left out keeps its default.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Program run to spawn terminals and for `alacritty msg`
+    pub alacritty_binary: String,
+    /// Used by `spawn_instance` when no `working_directory` is given
+    pub default_working_directory: Option<String>,
+    /// Start of the title given to instances spawned without one, followed
+    /// by the first eight characters of the instance ID
+    pub default_title_prefix: Option<String>,
+    /// How long an `nvim`, `tmux` or `lsof` call may take
+    pub socket_timeout_secs: u64,
+    /// Overrides the default location of the state file
+    pub state_file_path: Option<PathBuf>,
+    /// The file this was read from, if any
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            alacritty_binary: "alacritty".to_string(),
+            default_working_directory: None,
+            default_title_prefix: None,
+            socket_timeout_secs: COMMAND_TIMEOUT.as_secs(),
+            state_file_path: None,
+            path: None,
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/alacritty-mcp/config.toml`, falling back to
+    /// `~/.config` when `XDG_CONFIG_HOME` is unset.
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").filter(|dir| !dir.is_empty()).map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("alacritty-mcp").join("config.toml"))
+    }
+
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Cannot read config file {}: {}", path.display(), e))?;
+        let mut config: Config = toml::from_str(&contents)
+            .map_err(|e| anyhow!("Invalid config file {}: {}", path.display(), e))?;
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    /// The file at `default_path`, or the defaults when there is none. A file
+    /// that exists but cannot be parsed is logged and ignored.
+    pub fn load_default() -> Self {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(&path).unwrap_or_else(|e| {
+                warn!("{}", e);
+                Self::default()
+            }),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn socket_timeout(&self) -> Duration {
+        Duration::from_secs(self.socket_timeout_secs)
+    }
+
+    pub fn title_prefix(&self) -> &str {
+        self.default_title_prefix.as_deref().unwrap_or("alacritty-mcp")
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+/// Failures a client can act on, each reported with its own JSON-RPC error
+/// code. Anything else a tool returns is an internal error (`-32603`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlacrittyMcpError {
+    /// Arguments that are missing, of the wrong type, out of range or
+    /// malformed, or a call to a tool that does not exist
+    InvalidParams(String),
+    /// A well-formed instance ID the registry does not know
+    InstanceNotFound(String),
+    /// No Alacritty window belongs to this PID
+    WindowNotFound(u32),
+    /// xdotool ran but failed; the message includes its stderr
+    XdotoolFailed(String),
+    /// No socket could be found for the Neovim with this PID
+    NeovimSocketNotFound(u32),
+    /// An `nvim`, `tmux` or `lsof` call did not finish in time
+    NeovimRpcTimeout { command: String, timeout: Duration },
+}
+
+impl AlacrittyMcpError {
+    /// The JSON-RPC error code the server answers with. Beyond invalid
+    /// params these are from the range reserved for server errors.
+    pub fn code(&self) -> i32 {
+        match self {
+            AlacrittyMcpError::InvalidParams(_) => -32602,
+            AlacrittyMcpError::InstanceNotFound(_) => -32001,
+            AlacrittyMcpError::WindowNotFound(_) => -32003,
+            AlacrittyMcpError::XdotoolFailed(_) => -32004,
+            AlacrittyMcpError::NeovimSocketNotFound(_) => -32005,
+            AlacrittyMcpError::NeovimRpcTimeout { .. } => -32006,
+        }
+    }
+}
+
+impl std::fmt::Display for AlacrittyMcpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlacrittyMcpError::InvalidParams(message) | AlacrittyMcpError::XdotoolFailed(message) => f.write_str(message),
+            AlacrittyMcpError::InstanceNotFound(instance_id) => write!(f, "Instance not found: {}", instance_id),
+            AlacrittyMcpError::WindowNotFound(pid) => write!(f, "Could not find window ID for PID {}", pid),
+            AlacrittyMcpError::NeovimSocketNotFound(pid) => write!(f, "Could not find Neovim socket for PID {}", pid),
+            AlacrittyMcpError::NeovimRpcTimeout { command, timeout } => {
+                write!(f, "{} did not respond within {}s", command, timeout.as_secs())
+            }
+        }
+    }
+}
+
+impl std::error::Error for AlacrittyMcpError {}
+
+/// The JSON-RPC code for any error a handler returns: the variant's own code
+/// for an `AlacrittyMcpError`, `-32603` for everything else.
+pub fn error_code(error: &anyhow::Error) -> i32 {
+    error.downcast_ref::<AlacrittyMcpError>().map_or(-32603, AlacrittyMcpError::code)
+}
@@ -0,0 +1,101 @@
+use std::sync::OnceLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Set to `1` to let `NeovimContextExtractor` report Helix editors.
+pub const HELIX_ENV: &str = "ALACRITTY_MCP_HELIX";
+
+/// Helix's default status line: mode, file name and modified flag on the
+/// left; diagnostics, selections and `line:col` on the right.
+fn status_line_regex() -> &'static Regex {
+    static STATUS_LINE: OnceLock<Regex> = OnceLock::new();
+    STATUS_LINE.get_or_init(|| Regex::new(
+        r"^\s*(?P<mode>NOR|INS|SEL|NORMAL|INSERT|SELECT)\s+(?P<rest>.*?)\s*(?P<line>\d+):(?P<col>\d+)(\s+\S+)?\s*$"
+    ).unwrap())
+}
+
+/// Editing state of a Helix instance, as shown on its status line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HelixContext {
+    /// `None` for a scratch buffer
+    pub file_path: Option<String>,
+    /// `normal`, `insert` or `select`
+    pub mode: String,
+    pub selections: u32,
+    /// Total shown next to the `●` markers, all severities together
+    pub diagnostics: u32,
+    /// Cursor line and column, 1-based
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Reads Helix context off the screen. Helix has no remote-control socket
+/// like Neovim's, so the status line is all there is to go on.
+#[derive(Debug, Clone, Default)]
+pub struct HelixContextExtractor;
+
+impl HelixContextExtractor {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Whether `ALACRITTY_MCP_HELIX=1` is set.
+    pub fn enabled() -> bool {
+        std::env::var(HELIX_ENV).map(|value| value == "1").unwrap_or(false)
+    }
+
+    /// Finds the status line among the last two non-empty lines (the command
+    /// line sits below it) and parses it.
+    pub fn extract_from_screen(&self, terminal_content: &str) -> Option<HelixContext> {
+        terminal_content
+            .lines()
+            .rev()
+            .filter(|line| !line.trim().is_empty())
+            .take(2)
+            .find_map(|line| self.parse_status_line(line))
+    }
+
+    pub fn parse_status_line(&self, line: &str) -> Option<HelixContext> {
+        static SELECTIONS: OnceLock<Regex> = OnceLock::new();
+        static DIAGNOSTICS: OnceLock<Regex> = OnceLock::new();
+
+        let captures = status_line_regex().captures(line)?;
+        let rest = &captures["rest"];
+
+        let mode = match &captures["mode"] {
+            "NOR" | "NORMAL" => "normal",
+            "INS" | "INSERT" => "insert",
+            _ => "select",
+        };
+
+        // Sections are separated by runs of spaces; the first is the file
+        let file_path = rest
+            .split("  ")
+            .map(str::trim)
+            .find(|section| !section.is_empty())
+            .map(|section| section.trim_end_matches("[+]").trim())
+            .filter(|name| !name.is_empty() && !name.starts_with('[') && !name.starts_with('●'))
+            .map(|name| name.to_string());
+
+        let selections = SELECTIONS
+            .get_or_init(|| Regex::new(r"(\d+) sels?\b").unwrap())
+            .captures(rest)
+            .and_then(|captures| captures[1].parse().ok())
+            .unwrap_or(1);
+
+        let diagnostics = DIAGNOSTICS
+            .get_or_init(|| Regex::new(r"●\s*(\d+)").unwrap())
+            .captures_iter(rest)
+            .filter_map(|captures| captures[1].parse::<u32>().ok())
+            .sum();
+
+        Some(HelixContext {
+            file_path,
+            mode: mode.to_string(),
+            selections,
+            diagnostics,
+            line: captures["line"].parse().ok()?,
+            column: captures["col"].parse().ok()?,
+        })
+    }
+}
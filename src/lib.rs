@@ -1,9 +1,37 @@
 pub mod alacritty_manager;
 pub mod mcp_server;
+pub mod config;
+pub mod error;
 pub mod types;
 pub mod neovim_context;
+pub mod helix_context;
+pub mod platform;
+pub mod state_file;
+pub mod rate_limit;
+pub mod prompts;
+pub mod progress;
+pub mod cancellation;
+pub mod syntax;
+pub mod terminal_parser;
+pub mod search;
+pub mod transport;
+pub mod logging;
+pub mod middleware;
 
 pub use alacritty_manager::AlacrittyManager;
 pub use mcp_server::McpServer;
+pub use config::Config;
+pub use error::AlacrittyMcpError;
 pub use types::*;
-pub use neovim_context::*;
\ No newline at end of file
+pub use neovim_context::*;
+pub use helix_context::{HelixContext, HelixContextExtractor};
+pub use platform::Platform;
+pub use state_file::StateFile;
+pub use rate_limit::RateLimiter;
+pub use prompts::PromptLibrary;
+pub use progress::ProgressNotifier;
+pub use cancellation::InFlightRequests;
+pub use middleware::{LoggingMiddleware, MetricsMiddleware, Middleware};
+pub use syntax::find_function_context;
+pub use terminal_parser::{TerminalParser, TerminalGrid, TerminalRow};
+pub use transport::{serve_connection, HealthServer, TcpServer, UnixSocketServer};
@@ -0,0 +1,55 @@
+use std::sync::{Mutex, OnceLock};
+use anyhow::Result;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::error::AlacrittyMcpError;
+
+/// Levels accepted by MCP's `logging/setLevel`.
+pub const LOG_LEVELS: &[&str] = &["debug", "info", "warning", "error"];
+
+/// Swaps the filter of the subscriber installed by `main`. Unset when no
+/// reloadable subscriber is installed, e.g. in tests.
+static RELOAD_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// The level last set through `set_level`, as its MCP name.
+static CURRENT_LEVEL: Mutex<&'static str> = Mutex::new("info");
+
+/// Registers the handle `set_level` reloads. Only the first call has any
+/// effect, as there is only one global subscriber.
+pub fn install(handle: reload::Handle<EnvFilter, Registry>) {
+    let _ = RELOAD_HANDLE.set(handle);
+}
+
+/// The MCP level name, e.g. `warning`, as a `tracing::Level`.
+pub fn parse_level(level: &str) -> Option<tracing::Level> {
+    match level {
+        "debug" => Some(tracing::Level::DEBUG),
+        "info" => Some(tracing::Level::INFO),
+        "warning" => Some(tracing::Level::WARN),
+        "error" => Some(tracing::Level::ERROR),
+        _ => None,
+    }
+}
+
+pub fn current_level() -> &'static str {
+    *CURRENT_LEVEL.lock().unwrap()
+}
+
+/// Replaces the filter, `RUST_LOG` directives included, with one that lets
+/// through `level` and above from every target.
+pub fn set_level(level: &str) -> Result<()> {
+    let Some(tracing_level) = parse_level(level) else {
+        return Err(AlacrittyMcpError::InvalidParams(format!(
+            "Unsupported log level '{}'; expected one of: {}",
+            level, LOG_LEVELS.join(", ")
+        )).into());
+    };
+    let name = LOG_LEVELS.iter().find(|&&name| name == level).copied().unwrap_or("info");
+
+    if let Some(handle) = RELOAD_HANDLE.get() {
+        handle.reload(EnvFilter::default().add_directive(LevelFilter::from_level(tracing_level).into()))?;
+    }
+    *CURRENT_LEVEL.lock().unwrap() = name;
+    Ok(())
+}
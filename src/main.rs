@@ -1,53 +1,206 @@
-use std::io::{self, BufRead, Write};
-use anyhow::Result;
-use tracing::{info, error};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use tracing::{info, warn};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter};
 
-mod alacritty_manager;
-mod mcp_server;
-mod types;
-mod neovim_context;
+use alacritty_mcp::{logging, serve_connection, AlacrittyManager, Config, HealthServer, McpServer, TcpServer, UnixSocketServer};
 
-use alacritty_manager::AlacrittyManager;
-use mcp_server::McpServer;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+struct Args {
+    config: Option<PathBuf>,
+    state_file: Option<PathBuf>,
+    log_format: LogFormat,
+    socket: Option<PathBuf>,
+    /// Plain TCP is only served on a non-loopback address, where anyone can
+    /// connect without authentication, with TLS or `--allow-insecure-tcp`
+    tcp: Option<String>,
+    allow_insecure_tcp: bool,
+    /// Port for `GET /health`, on the same host as `--tcp`
+    health_port: Option<u16>,
+    /// Certificate chain and private key for `--tcp`, only read with the
+    /// `tls` feature
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    tls: Option<(PathBuf, PathBuf)>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut config = None;
+        let mut state_file = None;
+        let mut log_format = LogFormat::Text;
+        let mut socket = None;
+        let mut tcp = None;
+        let mut allow_insecure_tcp = false;
+        let mut health_port = None;
+        let mut cert = None;
+        let mut key = None;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = args.next().ok_or_else(|| anyhow!("--config requires a path"))?;
+                    config = Some(PathBuf::from(path));
+                }
+                "--state-file" => {
+                    let path = args.next().ok_or_else(|| anyhow!("--state-file requires a path"))?;
+                    state_file = Some(PathBuf::from(path));
+                }
+                "--log-format" => {
+                    log_format = match args.next().as_deref() {
+                        Some("text") => LogFormat::Text,
+                        Some("json") => LogFormat::Json,
+                        other => return Err(anyhow!("--log-format must be 'text' or 'json', got {:?}", other)),
+                    };
+                }
+                "--socket" => {
+                    let path = args.next().ok_or_else(|| anyhow!("--socket requires a path"))?;
+                    socket = Some(PathBuf::from(path));
+                }
+                "--tcp" => {
+                    tcp = Some(args.next().ok_or_else(|| anyhow!("--tcp requires a host:port address"))?);
+                }
+                "--allow-insecure-tcp" => allow_insecure_tcp = true,
+                "--health-port" => {
+                    let port = args.next().ok_or_else(|| anyhow!("--health-port requires a port"))?;
+                    health_port = Some(port.parse::<u16>().map_err(|_| anyhow!("--health-port must be a port number, got '{}'", port))?);
+                }
+                "--cert" => {
+                    let path = args.next().ok_or_else(|| anyhow!("--cert requires a path"))?;
+                    cert = Some(PathBuf::from(path));
+                }
+                "--key" => {
+                    let path = args.next().ok_or_else(|| anyhow!("--key requires a path"))?;
+                    key = Some(PathBuf::from(path));
+                }
+                _ => return Err(anyhow!("Unknown argument: {}", arg)),
+            }
+        }
+
+        if socket.is_some() && tcp.is_some() {
+            return Err(anyhow!("--socket and --tcp cannot be used together"));
+        }
+        if allow_insecure_tcp && tcp.is_none() {
+            return Err(anyhow!("--allow-insecure-tcp only applies to --tcp"));
+        }
+        if health_port.is_some() && tcp.is_none() {
+            return Err(anyhow!("--health-port only applies to --tcp"));
+        }
+        if (cert.is_some() || key.is_some()) && tcp.is_none() {
+            return Err(anyhow!("--cert and --key only apply to --tcp"));
+        }
+        let tls = match (cert, key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => return Err(anyhow!("--cert and --key must be given together")),
+        };
+        if cfg!(not(feature = "tls")) && tls.is_some() {
+            return Err(anyhow!("TLS support requires building with the 'tls' feature"));
+        }
+
+        Ok(Self { config, state_file, log_format, socket, tcp, allow_insecure_tcp, health_port, tls })
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-    
-    let manager = AlacrittyManager::new();
-    let mut server = McpServer::new(manager);
+    let args = Args::parse()?;
+
+    // Logs go to stderr, stdout carries the JSON-RPC stream. The filter is
+    // reloadable so `logging/setLevel` can change it.
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, reload_handle) = reload::Layer::new(filter);
+    let registry = tracing_subscriber::registry().with(filter);
+    match args.log_format {
+        LogFormat::Text => registry.with(fmt::layer().with_writer(io::stderr)).init(),
+        LogFormat::Json => registry.with(fmt::layer().json().with_writer(io::stderr)).init(),
+    }
+    logging::install(reload_handle);
     
+    // An explicit --config must exist, the default location is optional
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default(),
+    };
+    if let Some(path) = config.path() {
+        info!("Using config file {}", path.display());
+    }
+    if let Some(state_file) = args.state_file {
+        config.state_file_path = Some(state_file);
+    }
+    let manager = AlacrittyManager::with_config(config);
+
+    // Terminals opened before the server started are addressable straight away
+    match manager.import_existing_instances().await {
+        Ok(imported) => info!("Imported {} existing Alacritty instances", imported.len()),
+        Err(e) => warn!("Failed to import existing Alacritty instances: {}", e),
+    }
+
     info!("Starting Alacritty MCP Server");
-    
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-    
-    for line in stdin.lock().lines() {
-        let line = line?;
-        if line.trim().is_empty() {
-            continue;
-        }
-        
-        match server.handle_request(&line).await {
-            Ok(response) => {
-                writeln!(stdout, "{}", response)?;
-                stdout.flush()?;
-            }
-            Err(e) => {
-                error!("Error handling request: {}", e);
-                let error_response = serde_json::json!({
-                    "jsonrpc": "2.0",
-                    "error": {
-                        "code": -32603,
-                        "message": e.to_string()
-                    },
-                    "id": null
-                });
-                writeln!(stdout, "{}", error_response)?;
-                stdout.flush()?;
-            }
+
+    if let Some(path) = args.socket {
+        let listener = UnixSocketServer::bind(path)?;
+        // Returning drops the listener, which removes the socket file
+        return until_shutdown(listener.serve(Arc::new(manager))).await;
+    }
+
+    if let Some(addr) = args.tcp {
+        let listener = TcpServer::bind(addr.as_str()).await?;
+        #[cfg(feature = "tls")]
+        let listener = match &args.tls {
+            Some((cert, key)) => listener.with_tls(cert, key)?,
+            None => listener,
+        };
+        if listener.is_exposed() && !args.allow_insecure_tcp {
+            return Err(anyhow!(
+                "Refusing to serve {} without TLS: it is not a loopback address and there is no authentication. \
+                 Pass --cert and --key, bind to 127.0.0.1, or add --allow-insecure-tcp",
+                listener.local_addr()?
+            ));
         }
+        let manager = Arc::new(manager);
+        let Some(port) = args.health_port else {
+            return until_shutdown(listener.serve(manager)).await;
+        };
+        let health = HealthServer::bind((listener.local_addr()?.ip(), port)).await?;
+        return until_shutdown(async {
+            tokio::try_join!(listener.serve(Arc::clone(&manager)), health.serve(Arc::clone(&manager))).map(|_| ())
+        }).await;
     }
-    
-    Ok(())
-}
\ No newline at end of file
+
+    serve_connection(McpServer::new(manager), tokio::io::stdin(), tokio::io::stdout()).await
+}
+
+async fn until_shutdown(serve: impl std::future::Future<Output = Result<()>>) -> Result<()> {
+    tokio::select! {
+        result = serve => result,
+        _ = shutdown_signal() => {
+            info!("Shutting down");
+            Ok(())
+        }
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!("Cannot listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
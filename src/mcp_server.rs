@@ -1,71 +1,369 @@
+use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
 use anyhow::{Result, anyhow};
-use tracing::{error, debug};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{error, debug, info, info_span, warn, Instrument};
 
-use crate::alacritty_manager::AlacrittyManager;
+use crate::alacritty_manager::{read_memory_usage, AlacrittyManager, MIN_TERMINAL_DIMENSION, MAX_TERMINAL_DIMENSION, MAX_TITLE_BYTES, MIN_FONT_SIZE, MAX_FONT_SIZE, MAX_KEY_DELAY_MS, MAX_MONITOR_SCREENSHOTS, MAX_TEST_TIMEOUT_SECS};
+use crate::cancellation::{cancelled_request_id, InFlightRequests};
+use crate::error::{error_code, AlacrittyMcpError};
+use crate::logging;
+use crate::middleware::{default_middleware, Middleware};
+use crate::neovim_context::{BufferWatch, EXECUTABLE_COMMANDS};
+use crate::progress::ProgressNotifier;
+use crate::prompts::PromptLibrary;
+use crate::rate_limit::RateLimiter;
+use crate::terminal_parser::TerminalParser;
 use crate::types::{
-    JsonRpcRequest, JsonRpcResponse, JsonRpcError, Tool, ServerCapabilities,
-    InitializeParams, SpawnParams, SendKeysParams, ScreenshotParams, NeovimContextParams
+    JsonRpcRequest, JsonRpcResponse, JsonRpcError, Tool, ServerCapabilities, ResourcesCapability, Resource, ResourceReadParams,
+    PromptsCapability, PromptGetParams, LoggingCapability, SetLevelParams,
+    InitializeParams, InstanceStatus, MemoryUsage, ListInstancesParams, SpawnParams, GetInstanceParams, ListByTagParams, SendKeysParams, SendKeysSequenceParams, SendKeysToTagParams, BatchSendKeysParams, SetClipboardParams, GetClipboardParams, SendTextParams, ScreenshotParams, ScreenshotDiffParams, ToolContent, ScreenshotRegionParams, MinimapParams, CloseParams, ReloadConfigParams, ResizeParams, DimensionsParams, SetOptionParams, WriteFileParams, ReadFileParams, SearchParams, FileError,
+    RenameParams, DuplicateParams, FocusParams, KillAllParams, KillIdleParams, ProcessTreeParams, EnvironmentParams, ScrollbackParams, ParseTerminalParams, WaitForOutputParams, MonitorParams, RunCommandParams, NeovimContextParams, NeovimHoverParams, CompletionParams, ReferencesParams, DefinitionParams, CodeActionsParams, ApplyCodeActionParams, MarksParams, RegistersParams, JumplistParams, SessionVariablesParams, SpellErrorsParams, NeovimChangesParams, SearchHistoryParams, QuickfixParams, GitStatusParams, ExecuteCommandParams, RunTestParams, WorkspaceDiagnosticsParams, SetNeovimOptionParams, CloneBufferParams
 };
 
+/// MCP protocol versions the server can speak, newest first.
+pub const SUPPORTED_VERSIONS: &[&str] = &["2025-03-26", "2024-11-05"];
+
+/// Every instance is exposed as a resource at `alacritty://instances/<id>`.
+const INSTANCE_URI_PREFIX: &str = "alacritty://instances/";
+
+/// Read-only tools that may be called before `initialize`, for hosts that
+/// do not wait for its response.
+pub const DEFAULT_PRE_INIT_TOOLS: &[&str] = &["list_instances", "list_instances_by_tag", "get_instance"];
+
+/// Sent in `_meta` with every result given before `initialize`.
+pub const PRE_INIT_WARNING: &str = "server not fully initialized";
+
 pub struct McpServer {
-    manager: AlacrittyManager,
+    /// Shared when several connections are served from one process
+    manager: Arc<AlacrittyManager>,
     initialized: bool,
+    /// Set once the client sends `notifications/initialized`
+    client_initialized: bool,
+    rate_limiter: Option<RateLimiter>,
+    notification_tx: mpsc::UnboundedSender<String>,
+    notification_rx: Option<mpsc::UnboundedReceiver<String>>,
+    watcher: Option<JoinHandle<()>>,
+    /// Set when the client's `initialize` capabilities include `notifications`
+    notifications_enabled: bool,
+    /// `subscribe_neovim_changes` watches by instance ID
+    neovim_watches: Arc<Mutex<HashMap<String, BufferWatch>>>,
+    prompts: PromptLibrary,
+    in_flight: InFlightRequests,
+    middleware: Vec<Box<dyn Middleware>>,
+    /// Tools `tools/call` runs before `initialize`
+    pre_init_tools: Vec<String>,
 }
 
 impl McpServer {
-    pub fn new(manager: AlacrittyManager) -> Self {
+    /// Creates a server rate limited according to `ALACRITTY_MCP_RATE_LIMIT`.
+    pub fn new(manager: impl Into<Arc<AlacrittyManager>>) -> Self {
+        Self::with_rate_limiter(manager, RateLimiter::from_env())
+    }
+
+    /// Creates a server that answers requests beyond the limiter's rate with
+    /// error `-32029`. `None` disables rate limiting.
+    pub fn with_rate_limiter(manager: impl Into<Arc<AlacrittyManager>>, rate_limiter: Option<RateLimiter>) -> Self {
+        let (notification_tx, notification_rx) = mpsc::unbounded_channel();
         Self {
-            manager,
+            manager: manager.into(),
             initialized: false,
+            client_initialized: false,
+            rate_limiter,
+            notification_tx,
+            notification_rx: Some(notification_rx),
+            watcher: None,
+            notifications_enabled: false,
+            neovim_watches: Arc::new(Mutex::new(HashMap::new())),
+            prompts: PromptLibrary::load(),
+            in_flight: InFlightRequests::new(),
+            middleware: default_middleware(),
+            pre_init_tools: DEFAULT_PRE_INIT_TOOLS.iter().map(|tool| tool.to_string()).collect(),
         }
     }
 
-    pub async fn handle_request(&mut self, request_str: &str) -> Result<String> {
+    /// Replaces the middleware run around tool calls, by default a
+    /// `LoggingMiddleware` and a `MetricsMiddleware`.
+    pub fn with_middleware(mut self, middleware: Vec<Box<dyn Middleware>>) -> Self {
+        self.middleware = middleware;
+        self
+    }
+
+    /// Replaces the tools that may be called before `initialize`, by default
+    /// `DEFAULT_PRE_INIT_TOOLS`. Only read-only tools belong here; anything
+    /// that starts or changes terminals, `spawn_instance` above all, should
+    /// wait for the handshake.
+    pub fn with_pre_init_tools(mut self, tools: Vec<String>) -> Self {
+        self.pre_init_tools = tools;
+        self
+    }
+
+    /// Server-initiated messages (serialized JSON-RPC notifications) to be
+    /// written alongside the responses. Can only be taken once.
+    pub fn take_notifications(&mut self) -> Option<mpsc::UnboundedReceiver<String>> {
+        self.notification_rx.take()
+    }
+
+    /// The tool calls this server is running. The transport uses it to act on
+    /// cancellations that arrive while a request is still being served.
+    pub fn in_flight_requests(&self) -> InFlightRequests {
+        self.in_flight.clone()
+    }
+
+    /// Whether the client has confirmed the handshake with
+    /// `notifications/initialized`.
+    pub fn client_initialized(&self) -> bool {
+        self.client_initialized
+    }
+
+    /// Handles one line of input, which may be a single request or a batch.
+    /// Returns `None` when there is nothing to send back, i.e. for a
+    /// notification or a batch made up entirely of notifications.
+    pub async fn handle_request(&mut self, request_str: &str) -> Result<Option<String>> {
         debug!("Received request: {}", request_str);
         
-        let request: JsonRpcRequest = serde_json::from_str(request_str)
+        let message: Value = serde_json::from_str(request_str)
             .map_err(|e| anyhow!("Invalid JSON-RPC request: {}", e))?;
 
-        let response = match request.method.as_str() {
-            "initialize" => self.handle_initialize(request.params, request.id).await,
-            "tools/list" => self.handle_tools_list(request.id).await,
-            "tools/call" => self.handle_tools_call(request.params, request.id).await,
-            _ => {
-                let error = JsonRpcError {
-                    code: -32601,
-                    message: format!("Method not found: {}", request.method),
-                    data: None,
+        let response = match message {
+            Value::Array(batch) => self.handle_batch(batch).await?,
+            message => {
+                let is_notification = Self::is_notification(&message);
+                let request: JsonRpcRequest = match serde_json::from_value(message) {
+                    Ok(request) => request,
+                    // Even a malformed notification must not be answered
+                    Err(e) if is_notification => {
+                        warn!("Dropping invalid notification: {}", e);
+                        return Ok(None);
+                    }
+                    Err(e) => return Err(anyhow!("Invalid JSON-RPC request: {}", e)),
                 };
-                JsonRpcResponse {
-                    jsonrpc: "2.0".to_string(),
-                    result: None,
-                    error: Some(error),
-                    id: request.id,
+                let response = self.dispatch(request).await;
+                if is_notification {
+                    None
+                } else {
+                    Some(serde_json::to_value(response)?)
                 }
             }
         };
 
-        let response_str = serde_json::to_string(&response)?;
+        let response_str = match response {
+            Some(response) => serde_json::to_string(&response)?,
+            None => return Ok(None),
+        };
         debug!("Sending response: {}", response_str);
-        Ok(response_str)
+        Ok(Some(response_str))
+    }
+
+    async fn handle_batch(&mut self, batch: Vec<Value>) -> Result<Option<Value>> {
+        if batch.is_empty() {
+            let response = Self::error_response(None, -32600, "Invalid Request: empty batch".to_string());
+            return Ok(Some(serde_json::to_value(response)?));
+        }
+
+        // Everything but `tools/call` may update the server's own state, e.g.
+        // `initialize` or `logging/setLevel`, so it runs in order first. Tool
+        // calls only read it and then run concurrently, so a slow one does
+        // not hold up the rest.
+        let mut answered = Vec::new();
+        let mut tool_calls = Vec::new();
+        for (index, message) in batch.into_iter().enumerate() {
+            let is_notification = Self::is_notification(&message);
+            let response = match serde_json::from_value::<JsonRpcRequest>(message) {
+                Ok(request) if request.method == "tools/call" => match self.admit(&request) {
+                    Some(response) => response,
+                    None => {
+                        tool_calls.push((index, is_notification, request));
+                        continue;
+                    }
+                },
+                Ok(request) => self.dispatch(request).await,
+                Err(e) => Self::error_response(None, -32600, format!("Invalid Request: {}", e)),
+            };
+            answered.push((index, is_notification, response));
+        }
+
+        let this = &*self;
+        answered.extend(futures::future::join_all(tool_calls.into_iter().map(|(index, is_notification, request)| async move {
+            let span = info_span!("rpc", method = %request.method, id = ?request.id);
+            let response = this.handle_tools_call(request.params, request.id).instrument(span).await;
+            (index, is_notification, response)
+        })).await);
+
+        // Responses keep the order of the batch
+        answered.sort_by_key(|(index, _, _)| *index);
+        let mut responses = Vec::new();
+        for (_, is_notification, response) in answered {
+            if !is_notification {
+                responses.push(serde_json::to_value(response)?);
+            }
+        }
+
+        if responses.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Value::Array(responses)))
+        }
+    }
+
+    /// A message without an `id` member is a notification and gets no reply,
+    /// not even an error.
+    fn is_notification(message: &Value) -> bool {
+        message.as_object().is_some_and(|obj| !obj.contains_key("id"))
+    }
+
+    async fn dispatch(&mut self, request: JsonRpcRequest) -> JsonRpcResponse {
+        let span = info_span!("rpc", method = %request.method, id = ?request.id);
+
+        async move {
+            if let Some(response) = self.admit(&request) {
+                return response;
+            }
+
+            match request.method.as_str() {
+                "initialize" => self.handle_initialize(request.params, request.id).await,
+                "notifications/initialized" => {
+                    self.client_initialized = true;
+                    Self::empty_response(request.id)
+                }
+                "ping" | "$/ping" => Self::empty_response(request.id),
+                // Open before `initialize` so monitors can skip the handshake
+                "health" => self.handle_health(request.id).await,
+                "get_metrics" => self.handle_get_metrics(request.id),
+                "$/cancelRequest" | "notifications/cancelled" => {
+                    if let Some(target) = cancelled_request_id(&request.method, request.params.as_ref()) {
+                        self.in_flight.cancel(target);
+                    }
+                    Self::empty_response(request.id)
+                }
+                "tools/list" => self.handle_tools_list(request.id).await,
+                "tools/call" => self.handle_tools_call(request.params, request.id).await,
+                "resources/list" => self.handle_resources_list(request.id).await,
+                "resources/read" => self.handle_resources_read(request.params, request.id).await,
+                "prompts/list" => self.handle_prompts_list(request.id),
+                "prompts/get" => self.handle_prompts_get(request.params, request.id),
+                "logging/setLevel" => self.handle_set_level(request.params, request.id),
+                _ => Self::error_response(
+                    request.id,
+                    -32601,
+                    format!("Method not found: {}", request.method),
+                ),
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Checks a request before it is dispatched: its ID, then the rate limit.
+    /// Returns the error to send back when it is turned away.
+    fn admit(&mut self, request: &JsonRpcRequest) -> Option<JsonRpcResponse> {
+        // The ID cannot be echoed back, so the error goes out with a null one
+        if let Err(reason) = request.validate_id() {
+            return Some(Self::error_response(None, -32600, format!("Invalid Request: {}", reason)));
+        }
+
+        self.check_rate_limit(request)
+    }
+
+    /// Tool calls are limited per tool, everything else per method. Returns
+    /// the error to send back when the caller is over the limit.
+    fn check_rate_limit(&mut self, request: &JsonRpcRequest) -> Option<JsonRpcResponse> {
+        let limiter = self.rate_limiter.as_mut()?;
+        let key = match (request.method.as_str(), &request.params) {
+            ("tools/call", Some(params)) => match params.get("name").and_then(|name| name.as_str()) {
+                Some(tool_name) => format!("tools/call:{}", tool_name),
+                None => request.method.clone(),
+            },
+            _ => request.method.clone(),
+        };
+
+        let retry_after = limiter.check(&key).err()?;
+        let retry_after_ms = retry_after.as_millis().max(1) as u64;
+        warn!(key = %key, retry_after_ms, "Rate limit exceeded");
+
+        let mut response = Self::error_response(
+            request.id.clone(),
+            -32029,
+            format!("Rate limit exceeded for {}, retry after {}ms", key, retry_after_ms),
+        );
+        if let Some(error) = response.error.as_mut() {
+            error.data = Some(json!({ "retry_after_ms": retry_after_ms }));
+        }
+        Some(response)
+    }
+
+    fn empty_response(id: Option<Value>) -> JsonRpcResponse {
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: Some(json!({})),
+            error: None,
+            id,
+        }
+    }
+
+    fn error_response(id: Option<Value>, code: i32, message: String) -> JsonRpcResponse {
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: None,
+            error: Some(JsonRpcError {
+                code,
+                message,
+                data: None,
+            }),
+            id,
+        }
     }
 
     async fn handle_initialize(&mut self, params: Option<Value>, id: Option<Value>) -> JsonRpcResponse {
         match params.and_then(|p| serde_json::from_value::<InitializeParams>(p).ok()) {
-            Some(_init_params) => {
+            Some(init_params) => {
+                let protocol_version = match negotiate_version(&init_params.protocol_version) {
+                    Some(version) => version,
+                    None => {
+                        let error = JsonRpcError {
+                            code: -32002,
+                            message: format!(
+                                "Unsupported protocol version {}; supported versions: {}",
+                                init_params.protocol_version, SUPPORTED_VERSIONS.join(", ")
+                            ),
+                            data: Some(json!({
+                                "requested": init_params.protocol_version,
+                                "supported": SUPPORTED_VERSIONS
+                            })),
+                        };
+                        return JsonRpcResponse {
+                            jsonrpc: "2.0".to_string(),
+                            result: None,
+                            error: Some(error),
+                            id,
+                        };
+                    }
+                };
+
                 self.initialized = true;
+                self.notifications_enabled = init_params.capabilities.contains_key("notifications");
+                if self.notifications_enabled {
+                    self.start_watcher();
+                }
                 let capabilities = ServerCapabilities {
                     tools: self.get_tools(),
+                    resources: ResourcesCapability::default(),
+                    prompts: PromptsCapability::default(),
+                    logging: LoggingCapability { level: logging::current_level().to_string() },
                 };
                 JsonRpcResponse {
                     jsonrpc: "2.0".to_string(),
                     result: Some(json!({
-                        "protocolVersion": "2024-11-05",
+                        "protocolVersion": protocol_version,
                         "capabilities": capabilities,
                         "serverInfo": {
                             "name": "alacritty-mcp",
-                            "version": "0.1.0"
+                            "version": "0.1.0",
+                            "configPath": self.manager.config().path()
                         }
                     })),
                     error: None,
@@ -88,46 +386,225 @@ impl McpServer {
         }
     }
 
-    async fn handle_tools_list(&self, id: Option<Value>) -> JsonRpcResponse {
-        if !self.initialized {
-            let error = JsonRpcError {
-                code: -32002,
-                message: "Server not initialized".to_string(),
-                data: None,
-            };
-            return JsonRpcResponse {
+    /// Forwards instance exits and new windows to the client as
+    /// `notifications/message` log events.
+    fn start_watcher(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.abort();
+        }
+
+        let tx = self.notification_tx.clone();
+        self.watcher = Some(self.manager.watch_instances(move |instance_id, event| {
+            let mut data = serde_json::to_value(event).unwrap_or_else(|_| json!({}));
+            data["instance_id"] = json!(instance_id);
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/message",
+                "params": {
+                    "level": "info",
+                    "logger": "alacritty-mcp",
+                    "data": data
+                }
+            });
+            let _ = tx.send(notification.to_string());
+        }));
+    }
+
+    async fn handle_health(&self, id: Option<Value>) -> JsonRpcResponse {
+        let health = self.manager.health().await;
+        match serde_json::to_value(health) {
+            Ok(result) => JsonRpcResponse {
                 jsonrpc: "2.0".to_string(),
-                result: None,
-                error: Some(error),
+                result: Some(result),
+                error: None,
                 id,
-            };
+            },
+            Err(e) => Self::error_response(id, -32603, format!("Internal error: {}", e)),
+        }
+    }
+
+    /// Merges what each middleware reports, e.g. `tools` from the metrics
+    /// middleware. Counts are per server, so per connection.
+    fn handle_get_metrics(&self, id: Option<Value>) -> JsonRpcResponse {
+        let mut metrics = serde_json::Map::new();
+        for middleware in &self.middleware {
+            if let Some(Value::Object(reported)) = middleware.metrics() {
+                metrics.extend(reported);
+            }
         }
 
         JsonRpcResponse {
             jsonrpc: "2.0".to_string(),
-            result: Some(json!({
+            result: Some(Value::Object(metrics)),
+            error: None,
+            id,
+        }
+    }
+
+    fn handle_set_level(&self, params: Option<Value>, id: Option<Value>) -> JsonRpcResponse {
+        if !self.initialized {
+            return Self::error_response(id, -32002, "Server not initialized".to_string());
+        }
+
+        let params: SetLevelParams = match params.map(serde_json::from_value) {
+            Some(Ok(params)) => params,
+            Some(Err(e)) => return Self::error_response(id, -32602, format!("Invalid setLevel parameters: {}", e)),
+            None => return Self::error_response(id, -32602, "Missing setLevel parameters".to_string()),
+        };
+
+        match logging::set_level(&params.level) {
+            Ok(()) => {
+                info!(level = %params.level, "Log level changed");
+                Self::empty_response(id)
+            }
+            Err(e) => {
+                let code = error_code(&e);
+                Self::error_response(id, code, e.to_string())
+            }
+        }
+    }
+
+    /// Answers before `initialize` too, as some hosts ask for the tools
+    /// straight after connecting; the result then carries a warning.
+    async fn handle_tools_list(&self, id: Option<Value>) -> JsonRpcResponse {
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: Some(self.with_pre_init_warning(json!({
                 "tools": self.get_tools()
+            }))),
+            error: None,
+            id,
+        }
+    }
+
+    /// Adds `PRE_INIT_WARNING` to a result's `_meta` while `initialize` has
+    /// not succeeded yet.
+    fn with_pre_init_warning(&self, mut result: Value) -> Value {
+        if !self.initialized {
+            result["_meta"] = json!({ "_warning": PRE_INIT_WARNING });
+        }
+        result
+    }
+
+    async fn handle_resources_list(&self, id: Option<Value>) -> JsonRpcResponse {
+        if !self.initialized {
+            return Self::error_response(id, -32002, "Server not initialized".to_string());
+        }
+
+        let instances = match self.manager.list_instances(None).await {
+            Ok(instances) => instances,
+            Err(e) => return Self::error_response(id, -32603, e.to_string()),
+        };
+
+        let resources: Vec<Resource> = instances
+            .into_iter()
+            .map(|instance| Resource {
+                uri: format!("{}{}", INSTANCE_URI_PREFIX, instance.id),
+                name: instance.title,
+                description: Some(format!("Visible text of '{}' (PID {})", instance.command, instance.pid)),
+                mime_type: "text/plain".to_string(),
+            })
+            .collect();
+
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: Some(json!({
+                "resources": resources
             })),
             error: None,
             id,
         }
     }
 
-    async fn handle_tools_call(&mut self, params: Option<Value>, id: Option<Value>) -> JsonRpcResponse {
+    async fn handle_resources_read(&self, params: Option<Value>, id: Option<Value>) -> JsonRpcResponse {
         if !self.initialized {
-            let error = JsonRpcError {
-                code: -32002,
-                message: "Server not initialized".to_string(),
-                data: None,
-            };
-            return JsonRpcResponse {
+            return Self::error_response(id, -32002, "Server not initialized".to_string());
+        }
+
+        let params: ResourceReadParams = match params.map(serde_json::from_value) {
+            Some(Ok(params)) => params,
+            Some(Err(e)) => return Self::error_response(id, -32602, format!("Invalid resource read parameters: {}", e)),
+            None => return Self::error_response(id, -32602, "Missing resource read parameters".to_string()),
+        };
+
+        let instance_id = match params.uri.strip_prefix(INSTANCE_URI_PREFIX) {
+            Some(instance_id) if !instance_id.is_empty() => instance_id.to_string(),
+            _ => return Self::error_response(id, -32602, format!("Unsupported resource URI: {}", params.uri)),
+        };
+
+        // MCP reserves -32002 for unknown resources
+        if let Err(e) = self.manager.get_instance(GetInstanceParams { instance_id: instance_id.clone() }).await {
+            return Self::error_response(id, -32002, format!("Resource not found: {} ({})", params.uri, e));
+        }
+
+        let text = match self.manager.screenshot_instance(ScreenshotParams {
+            instance_id,
+            format: Some("text".to_string()),
+        }).await {
+            Ok(text) => text,
+            Err(e) => return Self::error_response(id, -32603, e.to_string()),
+        };
+
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: Some(json!({
+                "contents": [
+                    {
+                        "uri": params.uri,
+                        "mimeType": "text/plain",
+                        "text": text
+                    }
+                ]
+            })),
+            error: None,
+            id,
+        }
+    }
+
+    fn handle_prompts_list(&self, id: Option<Value>) -> JsonRpcResponse {
+        if !self.initialized {
+            return Self::error_response(id, -32002, "Server not initialized".to_string());
+        }
+
+        JsonRpcResponse {
+            jsonrpc: "2.0".to_string(),
+            result: Some(json!({
+                "prompts": self.prompts.list()
+            })),
+            error: None,
+            id,
+        }
+    }
+
+    fn handle_prompts_get(&self, params: Option<Value>, id: Option<Value>) -> JsonRpcResponse {
+        if !self.initialized {
+            return Self::error_response(id, -32002, "Server not initialized".to_string());
+        }
+
+        let params: PromptGetParams = match params.map(serde_json::from_value) {
+            Some(Ok(params)) => params,
+            Some(Err(e)) => return Self::error_response(id, -32602, format!("Invalid prompt parameters: {}", e)),
+            None => return Self::error_response(id, -32602, "Missing prompt parameters".to_string()),
+        };
+
+        match self.prompts.get(&params.name, &params.arguments) {
+            Ok((prompt, messages)) => JsonRpcResponse {
                 jsonrpc: "2.0".to_string(),
-                result: None,
-                error: Some(error),
+                result: Some(json!({
+                    "description": prompt.description,
+                    "messages": messages
+                })),
+                error: None,
                 id,
-            };
+            },
+            Err(e) => {
+                let code = error_code(&e);
+                Self::error_response(id, code, e.to_string())
+            }
         }
+    }
 
+    async fn handle_tools_call(&self, params: Option<Value>, id: Option<Value>) -> JsonRpcResponse {
         let call_params = match params {
             Some(p) => p,
             None => {
@@ -162,37 +639,93 @@ impl McpServer {
             }
         };
 
+        // Only the pre-init tools run before the handshake
+        if !self.initialized && !self.pre_init_tools.iter().any(|tool| tool == tool_name) {
+            let error = JsonRpcError {
+                code: -32002,
+                message: "Server not initialized".to_string(),
+                data: None,
+            };
+            return JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                result: None,
+                error: Some(error),
+                id,
+            };
+        }
+
         let arguments = call_params.get("arguments").cloned().unwrap_or(json!({}));
 
-        let result = match tool_name {
-            "list_instances" => self.handle_list_instances().await,
-            "spawn_instance" => self.handle_spawn_instance(arguments).await,
-            "send_keys" => self.handle_send_keys(arguments).await,
-            "screenshot_instance" => self.handle_screenshot_instance(arguments).await,
-            "get_neovim_context" => self.handle_get_neovim_context(arguments).await,
-            _ => Err(anyhow!("Unknown tool: {}", tool_name)),
+        // Progress is only sent once the client has finished the handshake
+        let progress = call_params
+            .get("_meta")
+            .and_then(|meta| meta.get("progressToken"))
+            .filter(|_| self.client_initialized)
+            .map(|token| ProgressNotifier::new(token.clone(), self.notification_tx.clone()));
+
+        let span = info_span!("tool", name = %tool_name);
+        for middleware in &self.middleware {
+            if let Err(e) = middleware.before_call(tool_name, &arguments).instrument(span.clone()).await {
+                span.in_scope(|| warn!("Tool call rejected: {}", e));
+                return Self::error_response(id, error_code(&e), e.to_string());
+            }
+        }
+
+        // The call runs on its own task so that a cancellation can abort it
+        let runner = ToolRunner {
+            manager: Arc::clone(&self.manager),
+            notification_tx: self.notifications_enabled.then(|| self.notification_tx.clone()),
+            neovim_watches: Arc::clone(&self.neovim_watches),
+        };
+        let tool = tool_name.to_string();
+        let started = Instant::now();
+        let task = tokio::spawn(
+            async move { runner.call_tool(&tool, arguments, progress.as_ref()).await }.instrument(span.clone()),
+        );
+        if let Some(request_id) = &id {
+            self.in_flight.insert(request_id, task.abort_handle());
+        }
+        let result = task.await;
+        if let Some(request_id) = &id {
+            self.in_flight.remove(request_id);
+        }
+
+        let cancelled = result.as_ref().is_err_and(|e| e.is_cancelled());
+        let result = match result {
+            Ok(result) => result,
+            Err(e) if e.is_cancelled() => Err(anyhow!("Request cancelled")),
+            Err(e) => Err(anyhow!("Tool call failed: {}", e)),
         };
+        let elapsed = started.elapsed();
+        for middleware in &self.middleware {
+            middleware.after_call(tool_name, &result, elapsed).instrument(span.clone()).await;
+        }
+
+        // No `notifications/cancelled` goes back: in MCP that cancels a
+        // request the server sent, so the client would take it for one of
+        // its own
+        if cancelled {
+            return Self::error_response(id, -32800, "Request cancelled".to_string());
+        }
 
         match result {
             Ok(content) => JsonRpcResponse {
                 jsonrpc: "2.0".to_string(),
-                result: Some(json!({
-                    "content": [
-                        {
-                            "type": "text",
-                            "text": content
-                        }
-                    ]
-                })),
+                result: Some(self.with_pre_init_warning(json!({
+                    "content": [content]
+                }))),
                 error: None,
                 id,
             },
             Err(e) => {
                 error!("Tool call error: {}", e);
+                let code = error_code(&e);
+                let data = e.downcast_ref::<FileError>()
+                    .and_then(|file_error| serde_json::to_value(file_error).ok());
                 let error = JsonRpcError {
-                    code: -32603,
+                    code,
                     message: e.to_string(),
-                    data: None,
+                    data,
                 };
                 JsonRpcResponse {
                     jsonrpc: "2.0".to_string(),
@@ -204,74 +737,112 @@ impl McpServer {
         }
     }
 
-    async fn handle_list_instances(&mut self) -> Result<String> {
-        let instances = self.manager.list_instances().await?;
-        let json_result = serde_json::to_string_pretty(&instances)?;
-        Ok(format!("Found {} Alacritty instances:\n{}", instances.len(), json_result))
-    }
-
-    async fn handle_spawn_instance(&mut self, arguments: Value) -> Result<String> {
-        let params: SpawnParams = serde_json::from_value(arguments)
-            .map_err(|e| anyhow!("Invalid spawn parameters: {}", e))?;
-        
-        let instance = self.manager.spawn_instance(params).await?;
-        let json_result = serde_json::to_string_pretty(&instance)?;
-        Ok(format!("Spawned new Alacritty instance:\n{}", json_result))
-    }
-
-    async fn handle_send_keys(&mut self, arguments: Value) -> Result<String> {
-        let params: SendKeysParams = serde_json::from_value(arguments)
-            .map_err(|e| anyhow!("Invalid send keys parameters: {}", e))?;
-        
-        self.manager.send_keys(params.clone()).await?;
-        Ok(format!("Sent keys '{}' to instance {}", params.keys, params.instance_id))
-    }
-
-    async fn handle_screenshot_instance(&mut self, arguments: Value) -> Result<String> {
-        let params: ScreenshotParams = serde_json::from_value(arguments)
-            .map_err(|e| anyhow!("Invalid screenshot parameters: {}", e))?;
-        
-        let screenshot = self.manager.screenshot_instance(params.clone()).await?;
-        let format = params.format.as_deref().unwrap_or("text");
-        
-        match format {
-            "text" => Ok(format!("Screenshot text from instance {}:\n{}", params.instance_id, screenshot)),
-            "image" => Ok(format!("Screenshot image from instance {} (base64): {}", params.instance_id, screenshot)),
-            _ => Err(anyhow!("Unsupported format: {}", format)),
-        }
-    }
-
-    async fn handle_get_neovim_context(&mut self, arguments: Value) -> Result<String> {
-        let params: NeovimContextParams = serde_json::from_value(arguments)
-            .map_err(|e| anyhow!("Invalid neovim context parameters: {}", e))?;
-        
-        let context = self.manager.get_neovim_context(params.clone()).await?;
-        let json_result = serde_json::to_string_pretty(&context)?;
-        
-        Ok(format!("Neovim context for instance {}:\n{}", params.instance_id, json_result))
-    }
-
     fn get_tools(&self) -> Vec<Tool> {
         vec![
             Tool {
                 name: "list_instances".to_string(),
-                description: "List all running Alacritty terminal instances".to_string(),
+                description: "List running Alacritty terminal instances, optionally filtered".to_string(),
                 input_schema: json!({
                     "type": "object",
-                    "properties": {},
+                    "properties": {
+                        "filter": {
+                            "type": "object",
+                            "description": "Only list instances matching every field that is set",
+                            "properties": {
+                                "title_contains": {
+                                    "type": "string",
+                                    "description": "Substring of the window title"
+                                },
+                                "command_eq": {
+                                    "type": "string",
+                                    "description": "Exact command the terminal was started with ('shell' for none)"
+                                },
+                                "has_window": {
+                                    "type": "boolean",
+                                    "description": "Whether the instance has a known window ID"
+                                }
+                            },
+                            "additionalProperties": false
+                        }
+                    },
                     "additionalProperties": false
                 }),
             },
             Tool {
-                name: "spawn_instance".to_string(),
-                description: "Spawn a new Alacritty terminal instance".to_string(),
+                name: "list_instances_by_tag".to_string(),
+                description: "List the Alacritty instances carrying a tag".to_string(),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
-                        "command": {
+                        "tag": {
                             "type": "string",
-                            "description": "Command to run in the terminal"
-                        },
+                            "description": "Tag given to the instances at spawn time"
+                        }
+                    },
+                    "required": ["tag"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "list_neovim_instances".to_string(),
+                description: "List running Alacritty instances that have Neovim in them, with Neovim's socket where it can be found".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "filter": {
+                            "type": "object",
+                            "description": "Same filter as list_instances, applied before looking for Neovim",
+                            "properties": {
+                                "title_contains": {"type": "string"},
+                                "command_eq": {"type": "string"},
+                                "has_window": {"type": "boolean"}
+                            },
+                            "additionalProperties": false
+                        }
+                    },
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_instance".to_string(),
+                description: "Get up-to-date details for a single Alacritty instance".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_instance_memory".to_string(),
+                description: "Get the memory an Alacritty instance's process uses: resident, virtual and swapped, in kB".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "spawn_instance".to_string(),
+                description: "Spawn a new Alacritty terminal instance".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "command": {
+                            "type": "string",
+                            "description": "Command to run in the terminal"
+                        },
                         "args": {
                             "type": "array",
                             "items": {
@@ -286,8 +857,63 @@ impl McpServer {
                         "title": {
                             "type": "string",
                             "description": "Title for the terminal window"
+                        },
+                        "env": {
+                            "type": "object",
+                            "additionalProperties": {
+                                "type": "string"
+                            },
+                            "description": "Environment variables to set, merged over the server's environment"
+                        },
+                        "tags": {
+                            "type": "array",
+                            "items": {
+                                "type": "string"
+                            },
+                            "description": "Tags for grouping this instance with others"
+                        },
+                        "config_file": {
+                            "type": "string",
+                            "description": "Path to an Alacritty .toml config used instead of the user's default config, e.g. for a high-contrast theme"
+                        },
+                        "geometry": {
+                            "type": "object",
+                            "description": "Initial window size in cells and, optionally, position in pixels. Tiling window managers may ignore the position",
+                            "properties": {
+                                "cols": {"type": "integer", "minimum": MIN_TERMINAL_DIMENSION, "maximum": MAX_TERMINAL_DIMENSION},
+                                "rows": {"type": "integer", "minimum": MIN_TERMINAL_DIMENSION, "maximum": MAX_TERMINAL_DIMENSION},
+                                "x": {"type": "integer", "description": "Left edge; requires y"},
+                                "y": {"type": "integer", "description": "Top edge; requires x"}
+                            },
+                            "required": ["cols", "rows"],
+                            "additionalProperties": false
+                        },
+                        "font_size": {
+                            "type": "number",
+                            "description": "Font size in points, e.g. larger for screenshots that will be read back",
+                            "minimum": MIN_FONT_SIZE,
+                            "maximum": MAX_FONT_SIZE
+                        },
+                        "font_family": {
+                            "type": "string",
+                            "description": "Font family for regular text, e.g. \"JetBrains Mono\""
+                        }
+                    },
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "duplicate_instance".to_string(),
+                description: "Spawn a new Alacritty instance with the same command and tags as an existing one, in the directory its shell is in".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance to duplicate"
                         }
                     },
+                    "required": ["instance_id"],
                     "additionalProperties": false
                 }),
             },
@@ -310,9 +936,153 @@ impl McpServer {
                     "additionalProperties": false
                 }),
             },
+            Tool {
+                name: "send_keys_sequence".to_string(),
+                description: "Send several key events to an Alacritty instance in one call, pausing after each as requested, e.g. to replay input into a TUI that needs pacing".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "sequence": {
+                            "type": "array",
+                            "minItems": 1,
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "keys": {
+                                        "type": "string",
+                                        "description": "Keys to send, in the same format as send_keys"
+                                    },
+                                    "delay_after_ms": {
+                                        "type": "integer",
+                                        "minimum": 0,
+                                        "maximum": MAX_KEY_DELAY_MS,
+                                        "description": "Milliseconds to wait after this event"
+                                    }
+                                },
+                                "required": ["keys"],
+                                "additionalProperties": false
+                            }
+                        }
+                    },
+                    "required": ["instance_id", "sequence"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "send_keys_to_tag".to_string(),
+                description: "Send the same key commands to every Alacritty instance carrying a tag".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "tag": {
+                            "type": "string",
+                            "description": "Tag given to the instances at spawn time"
+                        },
+                        "keys": {
+                            "type": "string",
+                            "description": "Keys to send (xdotool format, e.g., 'ctrl+c', 'Return', 'Hello')"
+                        }
+                    },
+                    "required": ["tag", "keys"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "batch_send_keys".to_string(),
+                description: "Send key commands to several Alacritty instances at once, each with its own keys. A failure for one instance does not stop the others".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "targets": {
+                            "type": "array",
+                            "description": "Instances and the keys each should receive; every instance at most once",
+                            "minItems": 1,
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "instance_id": {
+                                        "type": "string",
+                                        "description": "ID of the Alacritty instance"
+                                    },
+                                    "keys": {
+                                        "type": "string",
+                                        "description": "Keys to send (xdotool format, e.g., 'ctrl+c', 'Return', 'Hello')"
+                                    }
+                                },
+                                "required": ["instance_id", "keys"],
+                                "additionalProperties": false
+                            }
+                        }
+                    },
+                    "required": ["targets"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "set_clipboard".to_string(),
+                description: "Put text on the clipboard, to paste into a terminal with send_keys ctrl+shift+v instead of typing it".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "content": {
+                            "type": "string",
+                            "description": "Text to put on the clipboard, at most 10 MB"
+                        },
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of an Alacritty instance whose display's clipboard to use (default: the server's display)"
+                        }
+                    },
+                    "required": ["content"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_clipboard".to_string(),
+                description: "Read the text on the clipboard, e.g. what was copied in a terminal".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of an Alacritty instance whose display's clipboard to read (default: the server's display)"
+                        }
+                    },
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "send_text".to_string(),
+                description: "Type text verbatim into an Alacritty instance, without key notation".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "text": {
+                            "type": "string",
+                            "description": "Text to type exactly as given, including spaces, punctuation and Unicode"
+                        },
+                        "delay_ms": {
+                            "type": "number",
+                            "description": "Delay in milliseconds between characters",
+                            "default": 0,
+                            "minimum": 0
+                        }
+                    },
+                    "required": ["instance_id", "text"],
+                    "additionalProperties": false
+                }),
+            },
             Tool {
                 name: "screenshot_instance".to_string(),
-                description: "Take a screenshot of an Alacritty instance".to_string(),
+                description: "Take a screenshot of an Alacritty instance. Text screenshots come with a screenshot_id for screenshot_diff".to_string(),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
@@ -332,37 +1102,1654 @@ impl McpServer {
                 }),
             },
             Tool {
-                name: "get_neovim_context".to_string(),
-                description: "Extract comprehensive Neovim context including cursor position, diagnostics, open buffers, and LSP status".to_string(),
+                name: "screenshot_diff".to_string(),
+                description: "Take a fresh text screenshot and return only the lines added or removed since an earlier one".to_string(),
                 input_schema: json!({
                     "type": "object",
                     "properties": {
                         "instance_id": {
                             "type": "string",
-                            "description": "ID of the Alacritty instance running Neovim"
+                            "description": "ID of the Alacritty instance"
                         },
-                        "include_diagnostics": {
-                            "type": "boolean",
-                            "description": "Include LSP diagnostics in the context",
-                            "default": true
+                        "since_screenshot_id": {
+                            "type": "string",
+                            "description": "screenshot_id from an earlier text screenshot_instance of the same instance"
+                        }
+                    },
+                    "required": ["instance_id", "since_screenshot_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "screenshot_region".to_string(),
+                description: "Take an image screenshot of a rectangular region of an Alacritty instance".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
                         },
-                        "include_buffers": {
-                            "type": "boolean", 
-                            "description": "Include list of open buffers",
-                            "default": true
+                        "x": {
+                            "type": "number",
+                            "description": "Left edge of the region, in pixels from the window's left edge",
+                            "minimum": 0
                         },
-                        "context_lines": {
+                        "y": {
                             "type": "number",
-                            "description": "Number of lines around cursor to include",
-                            "default": 5,
-                            "minimum": 0,
-                            "maximum": 50
+                            "description": "Top edge of the region, in pixels from the window's top edge",
+                            "minimum": 0
+                        },
+                        "width": {
+                            "type": "number",
+                            "description": "Width of the region in pixels",
+                            "minimum": 1
+                        },
+                        "height": {
+                            "type": "number",
+                            "description": "Height of the region in pixels",
+                            "minimum": 1
+                        }
+                    },
+                    "required": ["instance_id", "x", "y", "width", "height"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "minimap_screenshot".to_string(),
+                description: "Get a tiny (at most 40x20) map of which parts of an Alacritty instance's screen have content, '#' for text and '.' for blank".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
                         }
                     },
                     "required": ["instance_id"],
                     "additionalProperties": false
                 }),
             },
-        ]
-    }
-}
\ No newline at end of file
+            Tool {
+                name: "get_scrollback".to_string(),
+                description: "Read the full scrollback history of an Alacritty instance, optionally a range of lines".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "start_line": {
+                            "type": "number",
+                            "description": "First line to return (0-based, inclusive)",
+                            "minimum": 0
+                        },
+                        "end_line": {
+                            "type": "number",
+                            "description": "Line to stop at (exclusive)",
+                            "minimum": 0
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "parse_terminal_content".to_string(),
+                description: "Split raw terminal text into rows with ANSI escape sequences stripped and shell prompts flagged".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "text": {
+                            "type": "string",
+                            "description": "Raw screen text, e.g. the output of screenshot_instance with format 'text'"
+                        }
+                    },
+                    "required": ["text"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "monitor_instance".to_string(),
+                description: format!(
+                    "Take screenshots of an Alacritty instance at a fixed interval for a while, e.g. to follow a progress bar, and return the whole series (at most {})",
+                    MAX_MONITOR_SCREENSHOTS
+                ),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "duration_secs": {
+                            "type": "integer",
+                            "description": "How long to keep taking screenshots",
+                            "minimum": 0
+                        },
+                        "interval_ms": {
+                            "type": "integer",
+                            "description": "Milliseconds between screenshots",
+                            "minimum": 1
+                        },
+                        "format": {
+                            "type": "string",
+                            "enum": ["text", "image"],
+                            "description": "Format of each screenshot",
+                            "default": "text"
+                        }
+                    },
+                    "required": ["instance_id", "duration_secs", "interval_ms"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "wait_for_output".to_string(),
+                description: "Wait until a line of an Alacritty instance's output matches a regex pattern".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "pattern": {
+                            "type": "string",
+                            "description": "Regular expression to match against each line of terminal output"
+                        },
+                        "timeout_ms": {
+                            "type": "number",
+                            "description": "Give up after this many milliseconds",
+                            "default": 10000,
+                            "minimum": 0
+                        },
+                        "poll_interval_ms": {
+                            "type": "number",
+                            "description": "Milliseconds between snapshots of the terminal",
+                            "default": 500,
+                            "minimum": 1
+                        }
+                    },
+                    "required": ["instance_id", "pattern"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "run_command".to_string(),
+                description: "Run a shell command in an Alacritty instance and return its output once the next prompt appears".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance, sitting at a shell prompt"
+                        },
+                        "command": {
+                            "type": "string",
+                            "description": "Command line to type; Return is pressed after it"
+                        },
+                        "timeout_ms": {
+                            "type": "number",
+                            "description": "Give up after this many milliseconds",
+                            "default": 30000,
+                            "minimum": 0
+                        },
+                        "prompt_pattern": {
+                            "type": "string",
+                            "description": "Regular expression matching the shell prompt line. A named group 'exit_code' is reported as the exit code",
+                            "default": "[$#>%]\\s*$"
+                        }
+                    },
+                    "required": ["instance_id", "command"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "reload_config".to_string(),
+                description: "Make a running Alacritty instance re-read its config file, e.g. after editing it".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "close_instance".to_string(),
+                description: "Close an Alacritty instance, sending SIGTERM and falling back to SIGKILL".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "timeout_ms": {
+                            "type": "number",
+                            "description": "Milliseconds to wait for the process to exit after SIGTERM before sending SIGKILL",
+                            "default": 5000,
+                            "minimum": 0
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "kill_all_instances".to_string(),
+                description: "Kill every managed Alacritty instance, optionally only those carrying a tag".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "tag": {
+                            "type": "string",
+                            "description": "Only kill instances carrying this tag"
+                        }
+                    },
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "kill_idle_instances".to_string(),
+                description: "Kill every managed Alacritty instance that has had no activity (keys sent, screenshots taken, status changes) for longer than a threshold".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "older_than_secs": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Kill instances idle for longer than this many seconds"
+                        }
+                    },
+                    "required": ["older_than_secs"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "list_instance_environment".to_string(),
+                description: "List the environment variables an Alacritty instance was started with, sorted by name. Values of variables named like secrets (SECRET, TOKEN, PASSWORD, KEY) are redacted unless show_secrets is set".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the target instance"
+                        },
+                        "filter_prefix": {
+                            "type": "string",
+                            "description": "Only return variables whose name starts with this, e.g. PATH"
+                        },
+                        "show_secrets": {
+                            "type": "boolean",
+                            "description": "Return the values of secret-looking variables instead of redacting them",
+                            "default": false
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_process_tree".to_string(),
+                description: "Get the processes running inside an Alacritty instance, as a tree rooted at the terminal".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "write_file".to_string(),
+                description: "Write a text file relative to the working directory of the shell in an Alacritty instance".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance whose working directory the path is relative to"
+                        },
+                        "relative_path": {
+                            "type": "string",
+                            "description": "Path of the file, relative to the working directory. Must not leave it."
+                        },
+                        "content": {
+                            "type": "string",
+                            "description": "Text to write"
+                        },
+                        "mode": {
+                            "type": "string",
+                            "description": "Octal file permissions, e.g. '644' or '755'"
+                        },
+                        "overwrite": {
+                            "type": "boolean",
+                            "description": "Replace the file if it already exists (default: false)"
+                        }
+                    },
+                    "required": ["instance_id", "relative_path", "content"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "read_file".to_string(),
+                description: "Read a text file relative to the working directory of the shell in an Alacritty instance, whole or a range of lines".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance whose working directory the path is relative to"
+                        },
+                        "relative_path": {
+                            "type": "string",
+                            "description": "Path of the file, relative to the working directory. Must not leave it."
+                        },
+                        "start_line": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "First line to return, 1-based (default: 1)"
+                        },
+                        "end_line": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Last line to return, inclusive (default: end of file)"
+                        },
+                        "max_bytes": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "description": "Maximum bytes of content to return (default: 1048576)"
+                        }
+                    },
+                    "required": ["instance_id", "relative_path"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "search_in_instance".to_string(),
+                description: "Search files under the working directory of the shell in an Alacritty instance for a regex, with ripgrep or grep, returning each match with its surrounding lines".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance whose working directory is searched"
+                        },
+                        "pattern": {
+                            "type": "string",
+                            "description": "Regular expression to search for"
+                        },
+                        "path": {
+                            "type": "string",
+                            "description": "File or directory to search, relative to the working directory. Must not leave it. (default: the whole working directory)"
+                        },
+                        "file_glob": {
+                            "type": "string",
+                            "description": "Only search files matching this glob, e.g. \"*.rs\""
+                        },
+                        "max_results": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": 1000,
+                            "description": "Maximum matching lines to return (default: 100)"
+                        }
+                    },
+                    "required": ["instance_id", "pattern"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "resize_instance".to_string(),
+                description: "Resize an Alacritty instance to the given number of columns and rows".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "cols": {
+                            "type": "number",
+                            "description": "Number of terminal columns",
+                            "minimum": MIN_TERMINAL_DIMENSION,
+                            "maximum": MAX_TERMINAL_DIMENSION
+                        },
+                        "rows": {
+                            "type": "number",
+                            "description": "Number of terminal rows",
+                            "minimum": MIN_TERMINAL_DIMENSION,
+                            "maximum": MAX_TERMINAL_DIMENSION
+                        }
+                    },
+                    "required": ["instance_id", "cols", "rows"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_terminal_dimensions".to_string(),
+                description: "Get the size of an Alacritty instance in columns and rows and in pixels".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "set_alacritty_option".to_string(),
+                description: "Change a config option of a running Alacritty instance via IPC (Alacritty 0.13+), without touching the config file".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "key": {
+                            "type": "string",
+                            "description": "Dotted config key, e.g. 'font.size' or 'colors.primary.background'"
+                        },
+                        "value": {
+                            "type": "string",
+                            "description": "TOML value, e.g. '14' or '\"#1d1f21\"' (strings need quotes)"
+                        }
+                    },
+                    "required": ["instance_id", "key", "value"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "rename_instance".to_string(),
+                description: "Change the window title of an Alacritty instance (X11 only)".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        },
+                        "new_title": {
+                            "type": "string",
+                            "description": "New window title",
+                            "minLength": 1,
+                            "maxLength": MAX_TITLE_BYTES
+                        }
+                    },
+                    "required": ["instance_id", "new_title"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "focus_instance".to_string(),
+                description: "Bring an Alacritty instance's window to the foreground".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_context".to_string(),
+                description: "Extract comprehensive Neovim context including cursor position, diagnostics, open buffers, and LSP status".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "include_diagnostics": {
+                            "type": "boolean",
+                            "description": "Include LSP diagnostics in the context",
+                            "default": true
+                        },
+                        "include_buffers": {
+                            "type": "boolean", 
+                            "description": "Include list of open buffers",
+                            "default": true
+                        },
+                        "context_lines": {
+                            "type": "number",
+                            "description": "Number of lines around cursor to include",
+                            "default": 5,
+                            "minimum": 0,
+                            "maximum": 50
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_hover".to_string(),
+                description: "Get LSP hover documentation from Neovim, at the cursor or a given position".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "line": {
+                            "type": "number",
+                            "description": "1-based line, defaults to the cursor line",
+                            "minimum": 1
+                        },
+                        "col": {
+                            "type": "number",
+                            "description": "1-based column, defaults to the cursor column",
+                            "minimum": 1
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_completions".to_string(),
+                description: "Get the LSP completion items Neovim would offer at the cursor".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "max_items": {
+                            "type": "number",
+                            "description": "Most completion items to return (default: 50, max: 500)",
+                            "minimum": 1,
+                            "maximum": 500
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_references".to_string(),
+                description: "Find every use of the symbol under Neovim's cursor via LSP, grouped by file".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "include_declaration": {
+                            "type": "boolean",
+                            "description": "Include the symbol's declaration (default: true)"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_symbol_definition".to_string(),
+                description: "Find where the symbol under Neovim's cursor, or at a given position, is defined via LSP".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "line": {
+                            "type": "number",
+                            "description": "1-based line, defaults to the cursor line",
+                            "minimum": 1
+                        },
+                        "col": {
+                            "type": "number",
+                            "description": "1-based column, defaults to the cursor column",
+                            "minimum": 1
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_code_actions".to_string(),
+                description: "List the LSP code actions (quick fixes, refactorings) Neovim's language servers offer on the cursor line".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "apply_code_action".to_string(),
+                description: "Apply one of the LSP code actions listed by get_neovim_code_actions, editing the buffer in Neovim".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "index": {
+                            "type": "integer",
+                            "description": "0-based position of the action in the list get_neovim_code_actions returned",
+                            "minimum": 0
+                        }
+                    },
+                    "required": ["instance_id", "index"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_marks".to_string(),
+                description: "List the marks (a-z and A-Z) set in Neovim's current buffer, with the text of each marked line".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_registers".to_string(),
+                description: "Read Neovim's non-empty registers: a-z, the unnamed register \" and the clipboards + and *, e.g. recently yanked text or recorded macros".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_jumplist".to_string(),
+                description: "List the positions in Neovim's jumplist, across files, oldest first, with the one <C-o> and <C-i> count from marked as current".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "max_entries": {
+                            "type": "integer",
+                            "description": "How many of the newest jumps to return",
+                            "default": 50,
+                            "minimum": 1,
+                            "maximum": 100
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "subscribe_neovim_changes".to_string(),
+                description: "Stream edits to the buffers of the Neovim running in an Alacritty instance as notifications/message events, until unsubscribed. Needs the notifications capability".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "unsubscribe_neovim_changes".to_string(),
+                description: "Stop the notifications started by subscribe_neovim_changes".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_search_history".to_string(),
+                description: "List the patterns recently searched for in Neovim with / or ?, most recent first, each marked as a regex or plain text".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "count": {
+                            "type": "integer",
+                            "description": "How many patterns to return",
+                            "default": 10,
+                            "minimum": 1,
+                            "maximum": 50
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_spell_errors".to_string(),
+                description: "List the words Neovim's spell checker flags in the current buffer, with their position, kind and up to five suggestions. Needs Neovim 0.10 and :set spell".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "max_errors": {
+                            "type": "integer",
+                            "description": "How many errors to return, from the top of the buffer",
+                            "default": 50,
+                            "minimum": 1,
+                            "maximum": 500
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_session_variables".to_string(),
+                description: "Get the g:, b:, w: and t: variables of Neovim, where plugins keep their state, for the current buffer, window and tab page. Names starting with _ are left out".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "scope": {
+                            "type": "string",
+                            "enum": ["global", "buffer", "window", "tab"],
+                            "description": "Only fetch this scope (default: all four)"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_quickfix".to_string(),
+                description: "List the entries of Neovim's quickfix list (or the current window's location list), e.g. compiler errors from :make, leaving out those that repeat an LSP diagnostic".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "location_list": {
+                            "type": "boolean",
+                            "description": "Read the current window's location list instead of the quickfix list",
+                            "default": false
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_git_status".to_string(),
+                description: "Get the git status (branch, modified, staged and untracked files, commits ahead/behind upstream) of the directory Neovim is working in".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "neovim_execute_command".to_string(),
+                description: format!(
+                    "Run a read-only Ex command in Neovim and return what it prints. Only these commands are accepted: {}. \
+                     Rejected: shell commands (!), chaining with |, backticks, ! forms, echo with function calls, \
+                     and set other than queries such as 'set tabstop?' (use set_neovim_option to change options)",
+                    EXECUTABLE_COMMANDS.join(", ")
+                ),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "command": {
+                            "type": "string",
+                            "description": "Ex command without the leading ':', e.g. 'ls' or 'set filetype?'"
+                        }
+                    },
+                    "required": ["instance_id", "command"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "neovim_run_test".to_string(),
+                description: "Run tests in Neovim with Neotest (or :Dispatch make test without it) and wait for the results: counts of passed, failed and pending tests and the failures as diagnostics".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "test_file": {
+                            "type": "string",
+                            "description": "Test file to run, relative to Neovim's working directory. Without it and test_name, the test nearest the cursor runs"
+                        },
+                        "test_name": {
+                            "type": "string",
+                            "description": "Test to run within test_file, or within the current file"
+                        },
+                        "timeout_secs": {
+                            "type": "integer",
+                            "description": "How long to wait for the run to finish",
+                            "default": 60,
+                            "minimum": 1,
+                            "maximum": MAX_TEST_TIMEOUT_SECS
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "get_neovim_workspace_diagnostics".to_string(),
+                description: "Get the LSP diagnostics of every buffer open in Neovim, grouped by file and sorted by severity".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "min_severity": {
+                            "type": "string",
+                            "enum": ["error", "warning", "info", "hint"],
+                            "description": "Leave out diagnostics less severe than this (default: hint, i.e. everything)"
+                        }
+                    },
+                    "required": ["instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "set_neovim_option".to_string(),
+                description: "Set a Vim option (e.g. number, tabstop, filetype) in Neovim; only a fixed list of safe options is accepted".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "option": {
+                            "type": "string",
+                            "description": "Option name, e.g. 'relativenumber', 'shiftwidth' or 'colorcolumn'"
+                        },
+                        "value": {
+                            "type": "string",
+                            "description": "'true' or 'false' for on/off options, a number, or a plain word like 'rust' or '80,120'"
+                        }
+                    },
+                    "required": ["instance_id", "option", "value"],
+                    "additionalProperties": false
+                }),
+            },
+            Tool {
+                name: "clone_neovim_buffer".to_string(),
+                description: "Open the file and line one Neovim instance is on in another Alacritty instance, e.g. to view it side by side".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "source_instance_id": {
+                            "type": "string",
+                            "description": "ID of the Alacritty instance running Neovim"
+                        },
+                        "target_instance_id": {
+                            "type": "string",
+                            "description": "Instance at a shell prompt to open the file in (default: spawn a new one)"
+                        }
+                    },
+                    "required": ["source_instance_id"],
+                    "additionalProperties": false
+                }),
+            },
+        ]
+    }
+}
+
+/// Runs tool calls. Holds only what the handlers need, so each call can run
+/// on its own task and be aborted when the client cancels it.
+#[derive(Clone)]
+struct ToolRunner {
+    manager: Arc<AlacrittyManager>,
+    /// `None` unless the client accepts notifications
+    notification_tx: Option<mpsc::UnboundedSender<String>>,
+    neovim_watches: Arc<Mutex<HashMap<String, BufferWatch>>>,
+}
+
+impl ToolRunner {
+    async fn call_tool(&self, tool_name: &str, arguments: Value, progress: Option<&ProgressNotifier>) -> Result<ToolContent> {
+        let text = match tool_name {
+            "list_instances" => self.handle_list_instances(arguments).await,
+            "list_instances_by_tag" => self.handle_list_instances_by_tag(arguments).await,
+            "list_neovim_instances" => self.handle_list_neovim_instances(arguments).await,
+            "get_instance" => self.handle_get_instance(arguments).await,
+            "get_instance_memory" => self.handle_get_instance_memory(arguments).await,
+            "spawn_instance" => self.handle_spawn_instance(arguments).await,
+            "duplicate_instance" => self.handle_duplicate_instance(arguments).await,
+            "send_keys" => self.handle_send_keys(arguments).await,
+            "send_keys_sequence" => self.handle_send_keys_sequence(arguments).await,
+            "send_keys_to_tag" => self.handle_send_keys_to_tag(arguments).await,
+            "batch_send_keys" => self.handle_batch_send_keys(arguments).await,
+            "set_clipboard" => self.handle_set_clipboard(arguments).await,
+            "get_clipboard" => self.handle_get_clipboard(arguments).await,
+            "send_text" => self.handle_send_text(arguments).await,
+            "screenshot_instance" => return self.handle_screenshot_instance(arguments).await,
+            "screenshot_diff" => self.handle_screenshot_diff(arguments).await,
+            "screenshot_region" => self.handle_screenshot_region(arguments).await,
+            "minimap_screenshot" => self.handle_minimap_screenshot(arguments).await,
+            "get_scrollback" => self.handle_get_scrollback(arguments).await,
+            "parse_terminal_content" => self.handle_parse_terminal_content(arguments),
+            "wait_for_output" => self.handle_wait_for_output(arguments, progress).await,
+            "monitor_instance" => self.handle_monitor_instance(arguments, progress).await,
+            "run_command" => self.handle_run_command(arguments).await,
+            "reload_config" => self.handle_reload_config(arguments).await,
+            "close_instance" => self.handle_close_instance(arguments).await,
+            "kill_all_instances" => self.handle_kill_all_instances(arguments).await,
+            "kill_idle_instances" => self.handle_kill_idle_instances(arguments).await,
+            "get_process_tree" => self.handle_get_process_tree(arguments).await,
+            "list_instance_environment" => self.handle_list_instance_environment(arguments).await,
+            "write_file" => self.handle_write_file(arguments).await,
+            "read_file" => self.handle_read_file(arguments).await,
+            "search_in_instance" => self.handle_search_in_instance(arguments).await,
+            "resize_instance" => self.handle_resize_instance(arguments).await,
+            "get_terminal_dimensions" => self.handle_get_terminal_dimensions(arguments).await,
+            "set_alacritty_option" => self.handle_set_alacritty_option(arguments).await,
+            "rename_instance" => self.handle_rename_instance(arguments).await,
+            "focus_instance" => self.handle_focus_instance(arguments).await,
+            "get_neovim_context" => self.handle_get_neovim_context(arguments).await,
+            "get_neovim_hover" => self.handle_get_neovim_hover(arguments).await,
+            "get_neovim_completions" => self.handle_get_neovim_completions(arguments).await,
+            "get_neovim_references" => self.handle_get_neovim_references(arguments).await,
+            "get_neovim_symbol_definition" => self.handle_get_neovim_symbol_definition(arguments).await,
+            "get_neovim_code_actions" => self.handle_get_neovim_code_actions(arguments).await,
+            "apply_code_action" => self.handle_apply_code_action(arguments).await,
+            "get_neovim_marks" => self.handle_get_neovim_marks(arguments).await,
+            "get_neovim_registers" => self.handle_get_neovim_registers(arguments).await,
+            "get_neovim_jumplist" => self.handle_get_neovim_jumplist(arguments).await,
+            "subscribe_neovim_changes" => self.handle_subscribe_neovim_changes(arguments).await,
+            "unsubscribe_neovim_changes" => self.handle_unsubscribe_neovim_changes(arguments).await,
+            "get_neovim_search_history" => self.handle_get_neovim_search_history(arguments).await,
+            "get_neovim_spell_errors" => self.handle_get_neovim_spell_errors(arguments).await,
+            "get_neovim_session_variables" => self.handle_get_neovim_session_variables(arguments).await,
+            "get_neovim_quickfix" => self.handle_get_neovim_quickfix(arguments).await,
+            "get_neovim_git_status" => self.handle_get_neovim_git_status(arguments).await,
+            "neovim_execute_command" => self.handle_neovim_execute_command(arguments).await,
+            "neovim_run_test" => self.handle_neovim_run_test(arguments).await,
+            "get_neovim_workspace_diagnostics" => self.handle_get_neovim_workspace_diagnostics(arguments).await,
+            "set_neovim_option" => self.handle_set_neovim_option(arguments).await,
+            "clone_neovim_buffer" => self.handle_clone_neovim_buffer(arguments).await,
+            _ => Err(AlacrittyMcpError::InvalidParams(format!("Unknown tool: {}", tool_name)).into()),
+        };
+        text.map(ToolContent::from)
+    }
+
+    async fn handle_list_instances(&self, arguments: Value) -> Result<String> {
+        let params: ListInstancesParams = parse_params(arguments, "list instances")?;
+
+        let instances = self.manager.list_instances(params.filter).await?;
+        let total: MemoryUsage = instances
+            .iter()
+            .filter(|instance| instance.status == InstanceStatus::Running)
+            .filter_map(|instance| read_memory_usage(instance.pid).ok())
+            .sum();
+        let json_result = serde_json::to_string_pretty(&instances)?;
+        Ok(format!(
+            "Found {} Alacritty instances ({} kB RSS, {} kB swap in total):\n{}",
+            instances.len(), total.rss_kb, total.swap_kb, json_result
+        ))
+    }
+
+    async fn handle_list_instances_by_tag(&self, arguments: Value) -> Result<String> {
+        let params: ListByTagParams = parse_params(arguments, "list by tag")?;
+
+        let tag = params.tag.clone();
+        let instances = self.manager.list_instances_by_tag(params).await?;
+        let json_result = serde_json::to_string_pretty(&instances)?;
+        Ok(format!("Found {} Alacritty instances tagged '{}':\n{}", instances.len(), tag, json_result))
+    }
+
+    async fn handle_list_neovim_instances(&self, arguments: Value) -> Result<String> {
+        let params: ListInstancesParams = parse_params(arguments, "list neovim instances")?;
+
+        let list = self.manager.list_neovim_instances(params.filter).await?;
+        let json_result = serde_json::to_string_pretty(&list)?;
+        Ok(format!("Found {} Alacritty instances running Neovim:\n{}", list.neovim_detected_count, json_result))
+    }
+
+    async fn handle_get_instance(&self, arguments: Value) -> Result<String> {
+        let params: GetInstanceParams = parse_params(arguments, "get instance")?;
+
+        let instance = self.manager.get_instance(params).await?;
+        let mut json_instance = serde_json::to_value(&instance)?;
+        json_instance["memory"] = serde_json::to_value(self.manager.get_memory_usage(&instance.id).await.ok())?;
+        let json_result = serde_json::to_string_pretty(&json_instance)?;
+        Ok(format!("Alacritty instance {}:\n{}", instance.id, json_result))
+    }
+
+    async fn handle_get_instance_memory(&self, arguments: Value) -> Result<String> {
+        let params: GetInstanceParams = parse_params(arguments, "get instance memory")?;
+
+        let usage = self.manager.get_memory_usage(&params.instance_id).await?;
+        let json_result = serde_json::to_string_pretty(&usage)?;
+        Ok(format!("Memory of instance {}:\n{}", params.instance_id, json_result))
+    }
+
+    async fn handle_spawn_instance(&self, arguments: Value) -> Result<String> {
+        let params: SpawnParams = parse_params(arguments, "spawn")?;
+        
+        let instance = self.manager.spawn_instance(params).await?;
+        let json_result = serde_json::to_string_pretty(&instance)?;
+        Ok(format!("Spawned new Alacritty instance:\n{}", json_result))
+    }
+
+    async fn handle_duplicate_instance(&self, arguments: Value) -> Result<String> {
+        let params: DuplicateParams = parse_params(arguments, "duplicate")?;
+
+        let result = self.manager.duplicate_instance(params).await?;
+        let json_result = serde_json::to_string_pretty(&result)?;
+        Ok(format!("Duplicated instance {} as {}:\n{}", result.original.id, result.duplicate.id, json_result))
+    }
+
+    async fn handle_send_keys(&self, arguments: Value) -> Result<String> {
+        let params: SendKeysParams = parse_params(arguments, "send keys")?;
+        
+        self.manager.send_keys(params.clone()).await?;
+        Ok(format!("Sent keys '{}' to instance {}", params.keys, params.instance_id))
+    }
+
+    async fn handle_send_keys_sequence(&self, arguments: Value) -> Result<String> {
+        let params: SendKeysSequenceParams = parse_params(arguments, "send keys sequence")?;
+
+        let instance_id = params.instance_id.clone();
+        let result = self.manager.send_keys_sequence(params).await?;
+        let json_result = serde_json::to_string_pretty(&result)?;
+        Ok(format!("Sent {} key events to instance {} in {}ms:\n{}", result.events_sent, instance_id, result.elapsed_ms, json_result))
+    }
+
+    async fn handle_send_keys_to_tag(&self, arguments: Value) -> Result<String> {
+        let params: SendKeysToTagParams = parse_params(arguments, "send keys to tag")?;
+
+        let instance_ids = self.manager.send_keys_to_tag(params.clone()).await?;
+        Ok(format!("Sent keys '{}' to {} instances tagged '{}': {}",
+            params.keys, instance_ids.len(), params.tag, instance_ids.join(", ")))
+    }
+
+    async fn handle_batch_send_keys(&self, arguments: Value) -> Result<String> {
+        let params: BatchSendKeysParams = parse_params(arguments, "batch send keys")?;
+
+        let total = params.targets.len();
+        let result = self.manager.batch_send_keys(params).await?;
+        let json_result = serde_json::to_string_pretty(&result)?;
+        Ok(format!("Sent keys to {} of {} instances:\n{}", result.succeeded.len(), total, json_result))
+    }
+
+    async fn handle_set_clipboard(&self, arguments: Value) -> Result<String> {
+        let params: SetClipboardParams = parse_params(arguments, "set clipboard")?;
+
+        let bytes = self.manager.set_clipboard(params).await?;
+        Ok(format!("Copied {} bytes to the clipboard", bytes))
+    }
+
+    async fn handle_get_clipboard(&self, arguments: Value) -> Result<String> {
+        let params: GetClipboardParams = parse_params(arguments, "get clipboard")?;
+
+        let clipboard = self.manager.get_clipboard(params).await?;
+        let json_result = serde_json::to_string_pretty(&clipboard)?;
+        Ok(format!("Clipboard ({} bytes):\n{}", clipboard.size_bytes, json_result))
+    }
+
+    async fn handle_send_text(&self, arguments: Value) -> Result<String> {
+        let params: SendTextParams = parse_params(arguments, "send text")?;
+        
+        self.manager.send_text(params.clone()).await?;
+        Ok(format!("Sent {} characters of text to instance {}", params.text.chars().count(), params.instance_id))
+    }
+
+    /// Images come back as an `image` content item, so clients can show
+    /// them instead of a base64 string.
+    async fn handle_screenshot_instance(&self, arguments: Value) -> Result<ToolContent> {
+        let params: ScreenshotParams = parse_params(arguments, "screenshot")?;
+        
+        let screenshot = self.manager.screenshot_instance(params.clone()).await?;
+        let format = params.format.as_deref().unwrap_or("text");
+        
+        match format {
+            "text" => {
+                let screenshot_id = self.manager.store_screenshot(&params.instance_id, &screenshot);
+                Ok(format!("Screenshot text from instance {} (screenshot_id {}):\n{}", params.instance_id, screenshot_id, screenshot).into())
+            }
+            "image" => ToolContent::from_data_url(&screenshot)
+                .ok_or_else(|| anyhow!("Screenshot of instance {} is not a base64 data URL", params.instance_id)),
+            _ => Err(anyhow!("Unsupported format: {}", format)),
+        }
+    }
+
+    async fn handle_screenshot_diff(&self, arguments: Value) -> Result<String> {
+        let params: ScreenshotDiffParams = parse_params(arguments, "screenshot diff")?;
+
+        let diff = self.manager.screenshot_diff(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&diff)?;
+        Ok(format!("Changes on instance {} since screenshot {}:\n{}", params.instance_id, params.since_screenshot_id, json_result))
+    }
+
+    async fn handle_screenshot_region(&self, arguments: Value) -> Result<String> {
+        let params: ScreenshotRegionParams = parse_params(arguments, "screenshot region")?;
+
+        let screenshot = self.manager.screenshot_region(params.clone()).await?;
+        Ok(format!("Screenshot of {}x{}+{}+{} from instance {} (base64): {}",
+            params.width, params.height, params.x, params.y, params.instance_id, screenshot))
+    }
+
+    async fn handle_minimap_screenshot(&self, arguments: Value) -> Result<String> {
+        let params: MinimapParams = parse_params(arguments, "minimap")?;
+
+        let map = self.manager.minimap_screenshot(params.clone()).await?;
+        Ok(format!("Minimap of instance {} ('#' content, '.' blank):\n{}", params.instance_id, map))
+    }
+
+    async fn handle_get_scrollback(&self, arguments: Value) -> Result<String> {
+        let params: ScrollbackParams = parse_params(arguments, "scrollback")?;
+
+        let page = self.manager.get_scrollback(params.clone()).await?;
+        Ok(format!(
+            "Scrollback lines {}-{} of {} from instance {}:\n{}",
+            page.start_line, page.end_line, page.total_lines, params.instance_id, page.lines.join("\n")
+        ))
+    }
+
+    fn handle_parse_terminal_content(&self, arguments: Value) -> Result<String> {
+        let params: ParseTerminalParams = parse_params(arguments, "parse terminal")?;
+
+        let grid = TerminalParser::new().parse(&params.text);
+        let json_result = serde_json::to_string_pretty(&grid)?;
+        Ok(format!("Parsed {} rows, {} columns wide:\n{}", grid.rows.len(), grid.cols, json_result))
+    }
+
+    async fn handle_wait_for_output(&self, arguments: Value, progress: Option<&ProgressNotifier>) -> Result<String> {
+        let params: WaitForOutputParams = parse_params(arguments, "wait for output")?;
+
+        let found = self.manager.wait_for_output_with_progress(params.clone(), |polls, max_polls| {
+            if let Some(progress) = progress {
+                let message = format!("Waiting for '{}' (poll {})", params.pattern, polls);
+                progress.notify(polls as u64, Some(max_polls as u64), Some(&message));
+            }
+        }).await?;
+        Ok(format!(
+            "Pattern '{}' matched in instance {} after {} polls:\n{}",
+            params.pattern, params.instance_id, found.polls, found.line
+        ))
+    }
+
+    async fn handle_monitor_instance(&self, arguments: Value, progress: Option<&ProgressNotifier>) -> Result<String> {
+        let params: MonitorParams = parse_params(arguments, "monitor")?;
+
+        let screenshots = self.manager.monitor_instance_with_progress(params.clone(), |taken, count| {
+            if let Some(progress) = progress {
+                let message = format!("Screenshot {} of {}", taken, count);
+                progress.notify(taken as u64, Some(count as u64), Some(&message));
+            }
+        }).await?;
+        let json_result = serde_json::to_string_pretty(&screenshots)?;
+        Ok(format!("{} screenshots of instance {}:\n{}", screenshots.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_run_command(&self, arguments: Value) -> Result<String> {
+        let params: RunCommandParams = parse_params(arguments, "run command")?;
+
+        let result = self.manager.run_command(params).await?;
+        let json_result = serde_json::to_string_pretty(&result)?;
+        Ok(format!("Command finished in {}ms:\n{}", result.elapsed_ms, json_result))
+    }
+
+    async fn handle_reload_config(&self, arguments: Value) -> Result<String> {
+        let params: ReloadConfigParams = parse_params(arguments, "reload config")?;
+
+        let result = self.manager.reload_config(params.clone()).await?;
+        Ok(format!("Reloading {} for instance {}", result.config_file, params.instance_id))
+    }
+
+    async fn handle_close_instance(&self, arguments: Value) -> Result<String> {
+        let params: CloseParams = parse_params(arguments, "close")?;
+
+        self.manager.close_instance(params.clone()).await?;
+        Ok(format!("Closed instance {}", params.instance_id))
+    }
+
+    async fn handle_kill_all_instances(&self, arguments: Value) -> Result<String> {
+        let params: KillAllParams = parse_params(arguments, "kill all")?;
+
+        let summary = self.manager.kill_all_instances(params.tag.clone()).await?;
+        let scope = match &params.tag {
+            Some(tag) => format!("instances tagged '{}'", tag),
+            None => "instances".to_string(),
+        };
+        Ok(format!("Killed {} {}, {} had already exited", summary.killed, scope, summary.already_exited))
+    }
+
+    async fn handle_kill_idle_instances(&self, arguments: Value) -> Result<String> {
+        let params: KillIdleParams = parse_params(arguments, "kill idle")?;
+
+        let summary = self.manager.kill_idle_instances(params.older_than_secs).await?;
+        Ok(format!(
+            "Killed {} instances idle for more than {}s, {} had already exited",
+            summary.killed, params.older_than_secs, summary.already_exited
+        ))
+    }
+
+    async fn handle_get_process_tree(&self, arguments: Value) -> Result<String> {
+        let params: ProcessTreeParams = parse_params(arguments, "process tree")?;
+
+        let tree = self.manager.get_process_tree(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&tree)?;
+        Ok(format!("Process tree for instance {}:\n{}", params.instance_id, json_result))
+    }
+
+    async fn handle_list_instance_environment(&self, arguments: Value) -> Result<String> {
+        let params: EnvironmentParams = parse_params(arguments, "environment")?;
+
+        let environment = self.manager.list_instance_environment(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&environment)?;
+        Ok(format!("{} environment variables of instance {}:\n{}", environment.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_write_file(&self, arguments: Value) -> Result<String> {
+        let params: WriteFileParams = parse_params(arguments, "write file")?;
+
+        let result = self.manager.write_file(params).await?;
+        let json_result = serde_json::to_string_pretty(&result)?;
+        Ok(format!("Wrote {} bytes to {}:\n{}", result.bytes_written, result.path, json_result))
+    }
+
+    async fn handle_read_file(&self, arguments: Value) -> Result<String> {
+        let params: ReadFileParams = parse_params(arguments, "read file")?;
+
+        let file = self.manager.read_file(params).await?;
+        let json_result = serde_json::to_string_pretty(&file)?;
+        Ok(format!("Contents of {} ({} lines):\n{}", file.path, file.total_lines, json_result))
+    }
+
+    async fn handle_search_in_instance(&self, arguments: Value) -> Result<String> {
+        let params: SearchParams = parse_params(arguments, "search")?;
+
+        let results = self.manager.search_in_instance(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&results)?;
+        Ok(format!("{} matches for '{}' in instance {}:\n{}", results.len(), params.pattern, params.instance_id, json_result))
+    }
+
+    async fn handle_resize_instance(&self, arguments: Value) -> Result<String> {
+        let params: ResizeParams = parse_params(arguments, "resize")?;
+
+        self.manager.resize_instance(params.clone()).await?;
+        Ok(format!("Resized instance {} to {}x{}", params.instance_id, params.cols, params.rows))
+    }
+
+    async fn handle_get_terminal_dimensions(&self, arguments: Value) -> Result<String> {
+        let params: DimensionsParams = parse_params(arguments, "terminal dimensions")?;
+
+        let dimensions = self.manager.get_terminal_dimensions(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&dimensions)?;
+        Ok(format!("Instance {} is {}x{}:\n{}", params.instance_id, dimensions.cols, dimensions.rows, json_result))
+    }
+
+    async fn handle_set_alacritty_option(&self, arguments: Value) -> Result<String> {
+        let params: SetOptionParams = parse_params(arguments, "set option")?;
+
+        self.manager.set_alacritty_option(params.clone()).await?;
+        Ok(format!("Set {} = {} on instance {}", params.key, params.value, params.instance_id))
+    }
+
+    async fn handle_rename_instance(&self, arguments: Value) -> Result<String> {
+        let params: RenameParams = parse_params(arguments, "rename")?;
+
+        self.manager.rename_instance(params.clone()).await?;
+        Ok(format!("Renamed instance {} to '{}'", params.instance_id, params.new_title))
+    }
+
+    async fn handle_focus_instance(&self, arguments: Value) -> Result<String> {
+        let params: FocusParams = parse_params(arguments, "focus")?;
+
+        self.manager.focus_instance(params.clone()).await?;
+        Ok(format!("Focused instance {}", params.instance_id))
+    }
+
+    async fn handle_get_neovim_context(&self, arguments: Value) -> Result<String> {
+        let params: NeovimContextParams = parse_params(arguments, "neovim context")?;
+        
+        let context = self.manager.get_neovim_context(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&context)?;
+        
+        Ok(format!("Neovim context for instance {}:\n{}", params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_hover(&self, arguments: Value) -> Result<String> {
+        let params: NeovimHoverParams = parse_params(arguments, "neovim hover")?;
+
+        match self.manager.get_neovim_hover(params.clone()).await? {
+            Some(hover) => {
+                let json_result = serde_json::to_string_pretty(&hover)?;
+                Ok(format!("Hover information for instance {}:\n{}", params.instance_id, json_result))
+            }
+            None => Ok(format!("No hover information available for instance {}", params.instance_id)),
+        }
+    }
+
+    async fn handle_get_neovim_completions(&self, arguments: Value) -> Result<String> {
+        let params: CompletionParams = parse_params(arguments, "neovim completion")?;
+
+        let items = self.manager.get_neovim_completions(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&items)?;
+        Ok(format!("{} completions for instance {}:\n{}", items.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_references(&self, arguments: Value) -> Result<String> {
+        let params: ReferencesParams = parse_params(arguments, "neovim references")?;
+
+        let references = self.manager.get_neovim_references(params.clone()).await?;
+        let count: usize = references.values().map(|references| references.len()).sum();
+        let json_result = serde_json::to_string_pretty(&references)?;
+        Ok(format!(
+            "{} references in {} files for instance {}:\n{}",
+            count, references.len(), params.instance_id, json_result
+        ))
+    }
+
+    async fn handle_get_neovim_symbol_definition(&self, arguments: Value) -> Result<String> {
+        let params: DefinitionParams = parse_params(arguments, "neovim definition")?;
+
+        let definitions = self.manager.get_neovim_symbol_definition(params.clone()).await?;
+        if definitions.is_empty() {
+            return Ok(format!("No definition found for instance {}", params.instance_id));
+        }
+        let json_result = serde_json::to_string_pretty(&definitions)?;
+        Ok(format!("{} definitions for instance {}:\n{}", definitions.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_code_actions(&self, arguments: Value) -> Result<String> {
+        let params: CodeActionsParams = parse_params(arguments, "neovim code actions")?;
+
+        let actions = self.manager.get_neovim_code_actions(params.clone()).await?;
+        if actions.is_empty() {
+            return Ok(format!("No code actions available at the cursor in instance {}", params.instance_id));
+        }
+        let json_result = serde_json::to_string_pretty(&actions)?;
+        Ok(format!("{} code actions for instance {}:\n{}", actions.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_apply_code_action(&self, arguments: Value) -> Result<String> {
+        let params: ApplyCodeActionParams = parse_params(arguments, "apply code action")?;
+
+        let action = self.manager.apply_code_action(params.clone()).await?;
+        Ok(format!("Applied code action '{}' in instance {}", action.title, params.instance_id))
+    }
+
+    async fn handle_get_neovim_marks(&self, arguments: Value) -> Result<String> {
+        let params: MarksParams = parse_params(arguments, "neovim marks")?;
+
+        let marks = self.manager.get_neovim_marks(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&marks)?;
+        Ok(format!("{} marks in the current buffer of instance {}:\n{}", marks.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_registers(&self, arguments: Value) -> Result<String> {
+        let params: RegistersParams = parse_params(arguments, "neovim registers")?;
+
+        let registers = self.manager.get_neovim_registers(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&registers)?;
+        Ok(format!("{} non-empty registers in instance {}:\n{}", registers.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_jumplist(&self, arguments: Value) -> Result<String> {
+        let params: JumplistParams = parse_params(arguments, "neovim jumplist")?;
+
+        let jumps = self.manager.get_neovim_jumplist(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&jumps)?;
+        Ok(format!("{} jumps in instance {}:\n{}", jumps.len(), params.instance_id, json_result))
+    }
+
+    /// Forwards the instance's buffer changes as `notifications/message`
+    /// events until `unsubscribe_neovim_changes`, or until Neovim goes away.
+    async fn handle_subscribe_neovim_changes(&self, arguments: Value) -> Result<String> {
+        let params: NeovimChangesParams = parse_params(arguments, "neovim changes")?;
+
+        let Some(notification_tx) = self.notification_tx.clone() else {
+            return Err(anyhow!("Buffer changes are sent as notifications; the client did not list `notifications` in its initialize capabilities"));
+        };
+        if self.neovim_watches.lock().unwrap().get(&params.instance_id).is_some_and(|watch| !watch.is_finished()) {
+            return Ok(format!("Already subscribed to Neovim changes in instance {}", params.instance_id));
+        }
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+        let watch = self.manager.watch_neovim_changes(params.clone(), tx).await?;
+        let watch_id = watch.watch_id().to_string();
+        self.neovim_watches.lock().unwrap().insert(params.instance_id.clone(), watch);
+
+        let watches = Arc::clone(&self.neovim_watches);
+        let instance_id = params.instance_id.clone();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let mut data = serde_json::to_value(event).unwrap_or_else(|_| json!({}));
+                data["event"] = json!("buffer_changed");
+                data["instance_id"] = json!(instance_id);
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/message",
+                    "params": {
+                        "level": "info",
+                        "logger": "alacritty-mcp",
+                        "data": data
+                    }
+                });
+                let _ = notification_tx.send(notification.to_string());
+            }
+            // The watch ended by itself; a new subscription may have taken its place
+            let mut watches = watches.lock().unwrap();
+            if watches.get(&instance_id).is_some_and(|watch| watch.watch_id() == watch_id) {
+                watches.remove(&instance_id);
+            }
+        });
+
+        Ok(format!("Subscribed to Neovim changes in instance {}", params.instance_id))
+    }
+
+    async fn handle_unsubscribe_neovim_changes(&self, arguments: Value) -> Result<String> {
+        let params: NeovimChangesParams = parse_params(arguments, "neovim changes")?;
+
+        let watch = self.neovim_watches.lock().unwrap().remove(&params.instance_id).ok_or_else(|| {
+            AlacrittyMcpError::InvalidParams(format!("Instance {} has no Neovim changes subscription", params.instance_id))
+        })?;
+        if let Err(e) = watch.cancel().await {
+            warn!("Could not remove the buffer watch from Neovim: {}", e);
+        }
+        Ok(format!("Unsubscribed from Neovim changes in instance {}", params.instance_id))
+    }
+
+    async fn handle_get_neovim_search_history(&self, arguments: Value) -> Result<String> {
+        let params: SearchHistoryParams = parse_params(arguments, "neovim search history")?;
+
+        let history = self.manager.get_neovim_search_history(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&history)?;
+        Ok(format!("{} recent searches in instance {}:\n{}", history.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_spell_errors(&self, arguments: Value) -> Result<String> {
+        let params: SpellErrorsParams = parse_params(arguments, "neovim spell errors")?;
+
+        let errors = self.manager.get_neovim_spell_errors(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&errors)?;
+        Ok(format!("{} spelling errors in instance {}:\n{}", errors.len(), params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_session_variables(&self, arguments: Value) -> Result<String> {
+        let params: SessionVariablesParams = parse_params(arguments, "neovim session variables")?;
+
+        let variables = self.manager.get_neovim_session_variables(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&variables)?;
+        Ok(format!("Session variables of instance {}:\n{}", params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_quickfix(&self, arguments: Value) -> Result<String> {
+        let params: QuickfixParams = parse_params(arguments, "neovim quickfix")?;
+
+        let entries = self.manager.get_neovim_quickfix(params.clone()).await?;
+        let list = if params.location_list { "location list" } else { "quickfix list" };
+        let json_result = serde_json::to_string_pretty(&entries)?;
+        Ok(format!("{} {} entries for instance {}:\n{}", entries.len(), list, params.instance_id, json_result))
+    }
+
+    async fn handle_get_neovim_git_status(&self, arguments: Value) -> Result<String> {
+        let params: GitStatusParams = parse_params(arguments, "neovim git status")?;
+
+        let status = self.manager.get_neovim_git_status(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&status)?;
+        Ok(format!(
+            "Git status of the Neovim working directory for instance {} ({} modified, {} staged, {} untracked):\n{}",
+            params.instance_id, status.modified.len(), status.staged.len(), status.untracked.len(), json_result
+        ))
+    }
+
+    async fn handle_neovim_execute_command(&self, arguments: Value) -> Result<String> {
+        let params: ExecuteCommandParams = parse_params(arguments, "neovim execute command")?;
+
+        let result = self.manager.neovim_execute_command(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&result)?;
+        let outcome = if result.error.is_some() { "failed" } else { "ran" };
+        Ok(format!("':{}' {} in instance {}:\n{}", params.command.trim(), outcome, params.instance_id, json_result))
+    }
+
+    async fn handle_neovim_run_test(&self, arguments: Value) -> Result<String> {
+        let params: RunTestParams = parse_params(arguments, "neovim run test")?;
+
+        let result = self.manager.neovim_run_test(params.clone()).await?;
+        let json_result = serde_json::to_string_pretty(&result)?;
+        let outcome = if result.timed_out { "still running after the timeout" } else { "finished" };
+        Ok(format!(
+            "Tests {} in instance {} ({}): {} passed, {} failed, {} pending\n{}",
+            outcome, params.instance_id, result.runner, result.passed, result.failed, result.pending, json_result
+        ))
+    }
+
+    async fn handle_get_neovim_workspace_diagnostics(&self, arguments: Value) -> Result<String> {
+        let params: WorkspaceDiagnosticsParams = parse_params(arguments, "neovim workspace diagnostics")?;
+
+        let diagnostics = self.manager.get_neovim_workspace_diagnostics(params.clone()).await?;
+        let summary = &diagnostics.summary;
+        let json_result = serde_json::to_string_pretty(&diagnostics)?;
+        Ok(format!(
+            "{} errors, {} warnings, {} info, {} hints in {} files for instance {}:\n{}",
+            summary.errors, summary.warnings, summary.info, summary.hints,
+            diagnostics.by_file.len(), params.instance_id, json_result
+        ))
+    }
+
+    async fn handle_set_neovim_option(&self, arguments: Value) -> Result<String> {
+        let params: SetNeovimOptionParams = parse_params(arguments, "set neovim option")?;
+
+        self.manager.set_neovim_option(params.clone()).await?;
+        Ok(format!("Set {}={} in instance {}", params.option, params.value, params.instance_id))
+    }
+
+    async fn handle_clone_neovim_buffer(&self, arguments: Value) -> Result<String> {
+        let params: CloneBufferParams = parse_params(arguments, "clone neovim buffer")?;
+
+        let result = self.manager.clone_neovim_buffer(params).await?;
+        let json_result = serde_json::to_string_pretty(&result)?;
+        Ok(format!(
+            "Opened {} at line {} in instance {}:\n{}",
+            result.file_path, result.line, result.target_instance_id, json_result
+        ))
+    }
+}
+
+impl Drop for McpServer {
+    fn drop(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.abort();
+        }
+        // The forwarding tasks hold the map too, so it has to be emptied
+        self.neovim_watches.lock().unwrap().clear();
+    }
+}
+
+/// Picks the version to answer `initialize` with. A supported version is
+/// echoed back; an unknown older one gets the newest version released before
+/// it, or the oldest we have. Versions are dates, so they compare as strings.
+/// `None` means the client is newer than anything supported.
+fn negotiate_version(requested: &str) -> Option<&'static str> {
+    if requested > SUPPORTED_VERSIONS[0] {
+        return None;
+    }
+
+    SUPPORTED_VERSIONS
+        .iter()
+        .find(|version| **version <= requested)
+        .or(SUPPORTED_VERSIONS.last())
+        .copied()
+}
+
+/// Deserializes a tool's arguments. Failures, such as a missing required
+/// argument, are `InvalidParams`; `what` names the tool in the message.
+fn parse_params<T: DeserializeOwned>(arguments: Value, what: &str) -> Result<T> {
+    serde_json::from_value(arguments)
+        .map_err(|e| AlacrittyMcpError::InvalidParams(format!("Invalid {} parameters: {}", what, e)).into())
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::types::ToolContent;
+
+/// What a `Middleware` hook returns. Boxed rather than an `async fn` so the
+/// server can hold its middleware as trait objects.
+pub type HookFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Hooks run around every `tools/call`, in the order they were registered.
+pub trait Middleware: Send + Sync {
+    /// Runs before the tool does. An error rejects the call: it is sent back
+    /// instead, with the code `error_code` gives it, and no `after_call`
+    /// follows.
+    fn before_call<'a>(&'a self, _tool: &'a str, _args: &'a Value) -> HookFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Runs once the tool has returned, failed or been cancelled.
+    fn after_call<'a>(&'a self, tool: &'a str, result: &'a Result<ToolContent>, elapsed: Duration) -> HookFuture<'a, ()>;
+
+    /// What this middleware adds to the `get_metrics` response, if anything.
+    fn metrics(&self) -> Option<Value> {
+        None
+    }
+}
+
+/// The middleware a server starts with.
+pub fn default_middleware() -> Vec<Box<dyn Middleware>> {
+    vec![Box::new(LoggingMiddleware), Box::new(MetricsMiddleware::new())]
+}
+
+/// Logs every finished call inside its `tool` span, with `elapsed_ms`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingMiddleware;
+
+impl Middleware for LoggingMiddleware {
+    fn after_call<'a>(&'a self, _tool: &'a str, result: &'a Result<ToolContent>, elapsed: Duration) -> HookFuture<'a, ()> {
+        Box::pin(async move {
+            let elapsed_ms = elapsed.as_millis() as u64;
+            match result {
+                Ok(_) => info!(elapsed_ms, ok = true, "Tool call finished"),
+                Err(e) => info!(elapsed_ms, ok = false, error = %e, "Tool call finished"),
+            }
+        })
+    }
+}
+
+/// Counts of one tool's calls, as reported by `get_metrics`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ToolMetrics {
+    pub calls: u64,
+    /// Calls that failed or were cancelled
+    pub errors: u64,
+    pub total_ms: u64,
+}
+
+/// Counts calls, errors and time spent per tool, reported under `tools` by
+/// `get_metrics`.
+#[derive(Debug, Default)]
+pub struct MetricsMiddleware {
+    tools: Mutex<BTreeMap<String, ToolMetrics>>,
+}
+
+impl MetricsMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, ToolMetrics> {
+        self.tools.lock().unwrap().clone()
+    }
+}
+
+impl Middleware for MetricsMiddleware {
+    fn after_call<'a>(&'a self, tool: &'a str, result: &'a Result<ToolContent>, elapsed: Duration) -> HookFuture<'a, ()> {
+        Box::pin(async move {
+            let mut tools = self.tools.lock().unwrap();
+            let metrics = tools.entry(tool.to_string()).or_default();
+            metrics.calls += 1;
+            metrics.errors += u64::from(result.is_err());
+            metrics.total_ms += elapsed.as_millis() as u64;
+        })
+    }
+
+    fn metrics(&self) -> Option<Value> {
+        Some(json!({ "tools": self.snapshot() }))
+    }
+}
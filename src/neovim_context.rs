@@ -1,6 +1,30 @@
-use std::process::Command;
+use std::collections::{BTreeMap, HashMap};
+use std::process::Output;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use anyhow::{Result, anyhow};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::alacritty_manager::{read_environ_var, read_process_tree};
+use crate::helix_context::{HelixContext, HelixContextExtractor};
+use crate::syntax::find_function_context;
+use crate::error::AlacrittyMcpError;
+use crate::types::{NeovimContextParams, ProcessNode};
+
+pub const DEFAULT_CONTEXT_LINES: u32 = 5;
+pub const MAX_CONTEXT_LINES: u32 = 50;
+
+/// How long a discovered socket path is trusted before looking it up again.
+pub const SOCKET_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// How long any `nvim`, `tmux` or `lsof` call may take before it is killed,
+/// so a hung Neovim cannot stall the server. `socket_timeout_secs` in the
+/// config file overrides it.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NeovimContext {
@@ -14,120 +38,2616 @@ pub struct NeovimContext {
     pub lsp_status: Option<LspStatus>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct NeovimInstanceInfo {
-    pub pid: u32,
-    pub socket_path: Option<String>,
-    pub version: Option<String>,
-    pub config_path: Option<String>,
-}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NeovimInstanceInfo {
+    pub pid: u32,
+    pub socket_path: Option<String>,
+    pub version: Option<String>,
+    pub config_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurrentBuffer {
+    pub file_path: String,
+    pub file_type: Option<String>,
+    pub is_modified: bool,
+    pub line_count: u32,
+    pub content_preview: String,
+    pub surrounding_context: SurroundingContext,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurroundingContext {
+    pub lines_before: Vec<String>,
+    pub current_line: String,
+    pub lines_after: Vec<String>,
+    pub function_context: Option<FunctionContext>,
+    pub class_context: Option<String>,
+}
+
+/// The function the cursor is in. Lines are 1-based and inclusive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionContext {
+    pub name: String,
+    pub signature: String,
+    pub start_line: u32,
+    pub end_line: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+    pub source: Option<String>,
+    pub code: Option<String>,
+}
+
+impl Diagnostic {
+    /// Reads one diagnostic as the Lua scripts print it, with `severity`
+    /// in `vim.diagnostic.severity` numbering.
+    fn from_json(d: &serde_json::Value) -> Self {
+        let severity = match d["severity"].as_u64().unwrap_or(1) {
+            1 => DiagnosticSeverity::Error,
+            2 => DiagnosticSeverity::Warning,
+            3 => DiagnosticSeverity::Info,
+            _ => DiagnosticSeverity::Hint,
+        };
+
+        Diagnostic {
+            file_path: d["file_path"].as_str().unwrap_or("").to_string(),
+            line: d["line"].as_u64().unwrap_or(0) as u32,
+            column: d["column"].as_u64().unwrap_or(0) as u32,
+            severity,
+            message: d["message"].as_str().unwrap_or("").to_string(),
+            source: d["source"].as_str().map(|s| s.to_string()),
+            code: d["code"].as_str().map(|s| s.to_string()),
+        }
+    }
+}
+
+/// Ordered from most to least severe, like LSP's numbering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl DiagnosticSeverity {
+    /// Parses `error`, `warning`, `info` or `hint`, ignoring case.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warning" | "warn" => Some(Self::Warning),
+            "info" | "information" => Some(Self::Info),
+            "hint" => Some(Self::Hint),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferInfo {
+    pub file_path: String,
+    pub is_modified: bool,
+    pub is_current: bool,
+    pub file_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CursorPosition {
+    pub line: u32,
+    pub column: u32,
+    pub line_content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LspStatus {
+    pub active_clients: Vec<LspClient>,
+    pub diagnostics_count: DiagnosticCounts,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LspClient {
+    pub name: String,
+    pub file_types: Vec<String>,
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnosticCounts {
+    pub errors: u32,
+    pub warnings: u32,
+    pub info: u32,
+    pub hints: u32,
+}
+
+/// Diagnostics from every buffer, grouped by file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceDiagnostics {
+    /// Each file's diagnostics, most severe first, then by position
+    pub by_file: BTreeMap<String, Vec<Diagnostic>>,
+    pub summary: DiagnosticCounts,
+}
+
+impl WorkspaceDiagnostics {
+    /// Groups `diagnostics`, keeping those at least as severe as `min_severity`.
+    pub fn new(diagnostics: Vec<Diagnostic>, min_severity: Option<DiagnosticSeverity>) -> Self {
+        let min_severity = min_severity.unwrap_or(DiagnosticSeverity::Hint);
+        let mut by_file: BTreeMap<String, Vec<Diagnostic>> = BTreeMap::new();
+        let mut summary = DiagnosticCounts::default();
+
+        for diagnostic in diagnostics.into_iter().filter(|d| d.severity <= min_severity) {
+            match diagnostic.severity {
+                DiagnosticSeverity::Error => summary.errors += 1,
+                DiagnosticSeverity::Warning => summary.warnings += 1,
+                DiagnosticSeverity::Info => summary.info += 1,
+                DiagnosticSeverity::Hint => summary.hints += 1,
+            }
+            by_file.entry(diagnostic.file_path.clone()).or_default().push(diagnostic);
+        }
+        for diagnostics in by_file.values_mut() {
+            diagnostics.sort_by_key(|d| (d.severity, d.line, d.column));
+        }
+
+        Self { by_file, summary }
+    }
+}
+
+/// LSP hover documentation for a position in the current buffer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HoverResult {
+    pub content: String,
+    pub language: Option<String>,
+    /// Start line, start column, end line, end column, all 1-based
+    pub range: Option<(u32, u32, u32, u32)>,
+}
+
+impl HoverResult {
+    /// Parses the JSON printed by `hover_lua_script`. An empty object means
+    /// no language server had anything to say.
+    pub fn parse(output: &str) -> Result<Option<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected hover output: {}", e))?;
+
+        let content = match data["content"].as_str() {
+            Some(content) if !content.trim().is_empty() => content.to_string(),
+            _ => return Ok(None),
+        };
+
+        let range = data["range"].as_array().and_then(|range| {
+            let values: Vec<u32> = range.iter().filter_map(|v| v.as_u64().map(|v| v as u32)).collect();
+            match values[..] {
+                [start_line, start_col, end_line, end_col] => Some((start_line, start_col, end_line, end_col)),
+                _ => None,
+            }
+        });
+
+        Ok(Some(Self {
+            content,
+            language: data["language"].as_str().map(|language| language.to_string()),
+            range,
+        }))
+    }
+}
+
+/// How long the hover request waits for language servers to answer.
+pub const HOVER_TIMEOUT_MS: u32 = 2000;
+
+/// Lua run over the socket to request `textDocument/hover` at the given
+/// 1-based position, or at the cursor for whichever part is `None`.
+pub fn hover_lua_script(line: Option<u32>, col: Option<u32>) -> String {
+    let position = |value: Option<u32>| value.map(|v| v.saturating_sub(1) as i64).unwrap_or(-1).to_string();
+
+    r#"
+            local line, col = {line}, {col}
+            local cursor = vim.api.nvim_win_get_cursor(0)
+            if line < 0 then line = cursor[1] - 1 end
+            if col < 0 then col = cursor[2] end
+
+            local params = vim.lsp.util.make_position_params()
+            params.position = { line = line, character = col }
+            local responses = vim.lsp.buf_request_sync(0, "textDocument/hover", params, {timeout})
+
+            local result = {}
+            for _, response in pairs(responses or {}) do
+                local hover = response.result
+                if hover and hover.contents then
+                    local contents = hover.contents
+                    if type(contents) == "string" then
+                        result.content = contents
+                    elseif contents.value then
+                        result.content = contents.value
+                        result.language = contents.language
+                    else
+                        local parts = {}
+                        for _, part in ipairs(contents) do
+                            if type(part) == "string" then
+                                table.insert(parts, part)
+                            else
+                                table.insert(parts, part.value)
+                                result.language = result.language or part.language
+                            end
+                        end
+                        result.content = table.concat(parts, "
+
+")
+                    end
+                    if hover.range then
+                        result.range = {
+                            hover.range.start.line + 1, hover.range.start.character + 1,
+                            hover.range["end"].line + 1, hover.range["end"].character + 1,
+                        }
+                    end
+                    break
+                end
+            end
+
+            print(vim.json.encode(result))
+        "#
+    .replace("{line}", &position(line))
+    .replace("{col}", &position(col))
+    .replace("{timeout}", &HOVER_TIMEOUT_MS.to_string())
+}
+
+/// Default and upper bound for the number of completion items returned.
+pub const DEFAULT_COMPLETION_ITEMS: u32 = 50;
+pub const MAX_COMPLETION_ITEMS: u32 = 500;
+
+/// One LSP completion candidate at the cursor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompletionItem {
+    pub label: String,
+    /// Name of the LSP `CompletionItemKind`, e.g. `Function`
+    pub kind: Option<String>,
+    pub detail: Option<String>,
+    pub documentation: Option<String>,
+}
+
+impl CompletionItem {
+    /// Parses the JSON array printed by `completion_lua_script`, keeping at
+    /// most `max_items`. Neovim encodes an empty list as `{}`.
+    pub fn parse_list(output: &str, max_items: usize) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected completion output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) if object.is_empty() => Vec::new(),
+            other => return Err(anyhow!("Unexpected completion output: {}", other)),
+        };
+
+        let text = |value: &serde_json::Value| value.as_str().filter(|s| !s.is_empty()).map(|s| s.to_string());
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                Some(Self {
+                    label: text(&item["label"])?,
+                    kind: text(&item["kind"]),
+                    detail: text(&item["detail"]),
+                    documentation: text(&item["documentation"]),
+                })
+            })
+            .take(max_items)
+            .collect())
+    }
+}
+
+/// Lua run over the socket to request `textDocument/completion` at the cursor
+/// from every attached language server, stopping after `max_items`.
+pub fn completion_lua_script(max_items: u32) -> String {
+    r#"
+            local params = vim.lsp.util.make_position_params()
+            local responses = vim.lsp.buf_request_sync(0, "textDocument/completion", params, {timeout})
+            local kinds = vim.lsp.protocol.CompletionItemKind
+
+            local result = {}
+            for _, response in pairs(responses or {}) do
+                local items = response.result or {}
+                if items.items then items = items.items end
+                for _, item in ipairs(items) do
+                    if #result >= {max_items} then break end
+                    local documentation = item.documentation
+                    if type(documentation) == "table" then documentation = documentation.value end
+                    table.insert(result, {
+                        label = item.label,
+                        kind = item.kind and kinds[item.kind] or nil,
+                        detail = item.detail,
+                        documentation = documentation,
+                    })
+                end
+            end
+
+            print(vim.json.encode(result))
+        "#
+    .replace("{max_items}", &max_items.to_string())
+    .replace("{timeout}", &HOVER_TIMEOUT_MS.to_string())
+}
+
+/// One place the symbol under the cursor is used. Line and column are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reference {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+    /// The referencing line, trimmed
+    pub preview: String,
+}
+
+impl Reference {
+    /// Parses the JSON array printed by `references_lua_script`. Neovim
+    /// encodes an empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected references output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) if object.is_empty() => Vec::new(),
+            other => return Err(anyhow!("Unexpected references output: {}", other)),
+        };
+
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                Some(Self {
+                    file_path: item["file_path"].as_str()?.to_string(),
+                    line: item["line"].as_u64()? as u32,
+                    column: item["column"].as_u64()? as u32,
+                    preview: item["preview"].as_str().unwrap_or("").trim().to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Groups references by file, sorted by path and then position.
+    pub fn group_by_file(references: Vec<Self>) -> BTreeMap<String, Vec<Self>> {
+        let mut grouped: BTreeMap<String, Vec<Self>> = BTreeMap::new();
+        for reference in references {
+            grouped.entry(reference.file_path.clone()).or_default().push(reference);
+        }
+        for references in grouped.values_mut() {
+            references.sort_by_key(|reference| (reference.line, reference.column));
+            references.dedup();
+        }
+        grouped
+    }
+}
+
+/// Lua run over the socket to request `textDocument/references` for the
+/// symbol under the cursor. Previews come from the loaded buffer when there is
+/// one, otherwise from the file on disk.
+pub fn references_lua_script(include_declaration: bool) -> String {
+    r#"
+            local params = vim.lsp.util.make_position_params()
+            params.context = { includeDeclaration = {include_declaration} }
+            local responses = vim.lsp.buf_request_sync(0, "textDocument/references", params, {timeout})
+
+            local function preview(path, line)
+                local buf = vim.fn.bufnr(path)
+                if buf ~= -1 and vim.api.nvim_buf_is_loaded(buf) then
+                    return vim.api.nvim_buf_get_lines(buf, line, line + 1, false)[1] or ""
+                end
+                local ok, lines = pcall(vim.fn.readfile, path, "", line + 1)
+                return ok and lines[line + 1] or ""
+            end
+
+            local result = {}
+            for _, response in pairs(responses or {}) do
+                for _, location in ipairs(response.result or {}) do
+                    local uri = location.uri or location.targetUri
+                    local range = location.range or location.targetSelectionRange
+                    if uri and range then
+                        local path = vim.uri_to_fname(uri)
+                        table.insert(result, {
+                            file_path = path,
+                            line = range.start.line + 1,
+                            column = range.start.character + 1,
+                            preview = preview(path, range.start.line),
+                        })
+                    end
+                end
+            end
+
+            print(vim.json.encode(result))
+        "#
+    .replace("{include_declaration}", &include_declaration.to_string())
+    .replace("{timeout}", &HOVER_TIMEOUT_MS.to_string())
+}
+
+/// Where the symbol at a position is defined. Line and column are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefinitionResult {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+    /// The defining line, trimmed
+    pub preview: String,
+    /// The definition is in the buffer the request was made from
+    pub is_same_file: bool,
+}
+
+impl DefinitionResult {
+    /// Parses the JSON array printed by `definition_lua_script`. Neovim
+    /// encodes an empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected definition output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) if object.is_empty() => Vec::new(),
+            other => return Err(anyhow!("Unexpected definition output: {}", other)),
+        };
+
+        let mut definitions: Vec<Self> = items
+            .iter()
+            .filter_map(|item| {
+                Some(Self {
+                    file_path: item["file_path"].as_str()?.to_string(),
+                    line: item["line"].as_u64()? as u32,
+                    column: item["column"].as_u64()? as u32,
+                    preview: item["preview"].as_str().unwrap_or("").trim().to_string(),
+                    is_same_file: item["is_same_file"].as_bool().unwrap_or(false),
+                })
+            })
+            .collect();
+        // Several language servers on one buffer often agree
+        definitions.dedup();
+        Ok(definitions)
+    }
+}
+
+/// Lua run over the socket to request `textDocument/definition` at the given
+/// 1-based position, or at the cursor for whichever part is `None`. Servers
+/// answer with a `Location`, a `Location[]` or a `LocationLink[]`.
+pub fn definition_lua_script(line: Option<u32>, col: Option<u32>) -> String {
+    let position = |value: Option<u32>| value.map(|v| v.saturating_sub(1) as i64).unwrap_or(-1).to_string();
+
+    r#"
+            local line, col = {line}, {col}
+            local cursor = vim.api.nvim_win_get_cursor(0)
+            if line < 0 then line = cursor[1] - 1 end
+            if col < 0 then col = cursor[2] end
+
+            local params = vim.lsp.util.make_position_params()
+            params.position = { line = line, character = col }
+            local responses = vim.lsp.buf_request_sync(0, "textDocument/definition", params, {timeout})
+
+            local current = vim.api.nvim_buf_get_name(0)
+            local function preview(path, line)
+                local buf = vim.fn.bufnr(path)
+                if buf ~= -1 and vim.api.nvim_buf_is_loaded(buf) then
+                    return vim.api.nvim_buf_get_lines(buf, line, line + 1, false)[1] or ""
+                end
+                local ok, lines = pcall(vim.fn.readfile, path, "", line + 1)
+                return ok and lines[line + 1] or ""
+            end
+
+            local result = {}
+            for _, response in pairs(responses or {}) do
+                local locations = response.result or {}
+                if locations.uri or locations.targetUri then
+                    locations = { locations }
+                end
+                for _, location in ipairs(locations) do
+                    local uri = location.uri or location.targetUri
+                    local range = location.range or location.targetSelectionRange or location.targetRange
+                    if uri and range then
+                        local path = vim.uri_to_fname(uri)
+                        table.insert(result, {
+                            file_path = path,
+                            line = range.start.line + 1,
+                            column = range.start.character + 1,
+                            preview = preview(path, range.start.line),
+                            is_same_file = path == current,
+                        })
+                    end
+                end
+            end
+
+            print(vim.json.encode(result))
+        "#
+    .replace("{line}", &position(line))
+    .replace("{col}", &position(col))
+    .replace("{timeout}", &HOVER_TIMEOUT_MS.to_string())
+}
+
+/// A quick fix or refactoring a language server offers at the cursor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodeAction {
+    pub title: String,
+    /// e.g. `quickfix` or `refactor.extract`
+    pub kind: Option<String>,
+    pub is_preferred: bool,
+}
+
+impl CodeAction {
+    /// Parses the JSON array printed by `code_actions_lua_script`. Neovim
+    /// encodes an empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected code actions output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) if object.is_empty() => Vec::new(),
+            other => return Err(anyhow!("Unexpected code actions output: {}", other)),
+        };
+
+        Ok(items.iter().filter_map(Self::from_json).collect())
+    }
+
+    /// Parses the single action printed when `code_actions_lua_script` was
+    /// asked to apply one.
+    pub fn parse_applied(output: &str) -> Result<Self> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected code action output: {}", e))?;
+
+        Self::from_json(&data).ok_or_else(|| anyhow!("Unexpected code action output: {}", data))
+    }
+
+    fn from_json(item: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            title: item["title"].as_str()?.to_string(),
+            kind: item["kind"].as_str().map(str::to_string),
+            is_preferred: item["is_preferred"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+/// Lua run over the socket to request `textDocument/codeAction` for the
+/// cursor line, passing the LSP diagnostics on it along. Actions are listed by
+/// client ID and then in the order each server sent them, so the same
+/// `apply_index`, 0-based, picks the same action as the listing showed. With
+/// an index the action is applied like `vim.lsp.buf.code_action()` would:
+/// resolved first if it came without an edit or command, then its workspace
+/// edit applied and its command executed.
+pub fn code_actions_lua_script(apply_index: Option<u32>) -> String {
+    r#"
+            local index = {index}
+            local params = vim.lsp.util.make_range_params()
+            local diagnostics = {}
+            for _, diagnostic in ipairs(vim.diagnostic.get(0, { lnum = params.range.start.line })) do
+                local lsp = diagnostic.user_data and diagnostic.user_data.lsp
+                if lsp then table.insert(diagnostics, lsp) end
+            end
+            params.context = { diagnostics = diagnostics }
+            local responses = vim.lsp.buf_request_sync(0, "textDocument/codeAction", params, {timeout}) or {}
+
+            local client_ids = vim.tbl_keys(responses)
+            table.sort(client_ids)
+            local actions = {}
+            for _, client_id in ipairs(client_ids) do
+                for _, action in ipairs(responses[client_id].result or {}) do
+                    table.insert(actions, { client_id = client_id, action = action })
+                end
+            end
+
+            local function describe(action)
+                return { title = action.title, kind = action.kind, is_preferred = action.isPreferred == true }
+            end
+
+            if index < 0 then
+                local result = {}
+                for _, entry in ipairs(actions) do
+                    table.insert(result, describe(entry.action))
+                end
+                print(vim.json.encode(result))
+            else
+                local entry = actions[index + 1]
+                if not entry then
+                    error("No code action " .. index .. " at the cursor; there are " .. #actions)
+                end
+                local client = vim.lsp.get_client_by_id(entry.client_id)
+                local action = entry.action
+                if not action.edit and not action.command then
+                    local resolved = client.request_sync("codeAction/resolve", action, {timeout}, 0)
+                    if resolved and resolved.result then action = resolved.result end
+                end
+                if action.edit then
+                    vim.lsp.util.apply_workspace_edit(action.edit, client.offset_encoding)
+                end
+                -- A bare `Command` carries its command name as a string
+                local command = action.command
+                if type(command) == "string" then command = action end
+                if command then
+                    client.request("workspace/executeCommand", command, nil, 0)
+                end
+                print(vim.json.encode(describe(entry.action)))
+            end
+        "#
+    .replace("{index}", &apply_index.map_or(-1, i64::from).to_string())
+    .replace("{timeout}", &HOVER_TIMEOUT_MS.to_string())
+}
+
+/// A letter mark set in the current buffer. Line and column are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mark {
+    /// `a`-`z` for buffer-local marks, `A`-`Z` for global ones
+    pub name: String,
+    pub line: u32,
+    pub column: u32,
+    /// The marked line as it is now, untrimmed
+    pub line_content: String,
+}
+
+impl Mark {
+    /// Parses the JSON array printed by `MARKS_LUA_SCRIPT`. Neovim encodes an
+    /// empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected marks output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) if object.is_empty() => Vec::new(),
+            other => return Err(anyhow!("Unexpected marks output: {}", other)),
+        };
+
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                Some(Self {
+                    name: item["name"].as_str()?.to_string(),
+                    line: item["line"].as_u64()? as u32,
+                    column: item["column"].as_u64()? as u32,
+                    line_content: item["line_content"].as_str().unwrap_or("").to_string(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Lua run over the socket to list the current buffer's marks together with
+/// the text of the lines they are on. `getmarklist()` without an argument
+/// returns the global marks, which are kept only when they point into the
+/// current buffer; digit and special marks (`'`, `.`, `^`, ...) are left out.
+pub const MARKS_LUA_SCRIPT: &str = r#"
+            local buf = vim.api.nvim_get_current_buf()
+            local result = {}
+
+            local function add(mark)
+                local name = mark.mark:sub(2)
+                local line, col = mark.pos[2], mark.pos[3]
+                if name:match("^%a$") and mark.pos[1] == buf and line > 0 then
+                    table.insert(result, {
+                        name = name,
+                        line = line,
+                        column = col,
+                        line_content = vim.api.nvim_buf_get_lines(buf, line - 1, line, false)[1] or "",
+                    })
+                end
+            end
+
+            for _, mark in ipairs(vim.fn.getmarklist(buf)) do add(mark) end
+            for _, mark in ipairs(vim.fn.getmarklist()) do add(mark) end
+
+            print(vim.json.encode(result))
+        "#;
+
+/// An entry of the quickfix or a location list. Line and column are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuickfixEntry {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+    pub message: String,
+    /// `E`, `W`, `I`, `N` or empty, as set by the compiler's `errorformat`
+    pub qf_type: String,
+}
+
+impl QuickfixEntry {
+    /// Parses the JSON array printed by `quickfix_lua_script`. Neovim
+    /// encodes an empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected quickfix output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) if object.is_empty() => Vec::new(),
+            other => return Err(anyhow!("Unexpected quickfix output: {}", other)),
+        };
+
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                Some(Self {
+                    file_path: item["file_path"].as_str()?.to_string(),
+                    line: item["line"].as_u64()? as u32,
+                    column: item["column"].as_u64().unwrap_or(0) as u32,
+                    message: item["message"].as_str().unwrap_or("").to_string(),
+                    qf_type: item["qf_type"].as_str().unwrap_or("").to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Drops entries that repeat one of `diagnostics`, as a list filled by
+    /// `vim.diagnostic.setqflist()` does, so each problem is reported once.
+    pub fn without_diagnostics(entries: Vec<Self>, diagnostics: &[Diagnostic]) -> Vec<Self> {
+        entries
+            .into_iter()
+            .filter(|entry| !diagnostics.iter().any(|diagnostic| {
+                diagnostic.file_path == entry.file_path
+                    && diagnostic.line == entry.line
+                    && diagnostic.column == entry.column
+                    && diagnostic.message.trim() == entry.message.trim()
+            }))
+            .collect()
+    }
+}
+
+/// Lua run over the socket to read the quickfix list, or with
+/// `location_list` the current window's location list. Entries that
+/// `errorformat` did not recognise (`valid` is 0) are plain compiler output
+/// and left out.
+pub fn quickfix_lua_script(location_list: bool) -> String {
+    let list = if location_list { "vim.fn.getloclist(0)" } else { "vim.fn.getqflist()" };
+    r#"
+            local result = {}
+            for _, item in ipairs({list}) do
+                if item.valid == 1 then
+                    table.insert(result, {
+                        file_path = item.bufnr > 0 and vim.api.nvim_buf_get_name(item.bufnr) or "",
+                        line = item.lnum,
+                        column = item.col,
+                        message = item.text,
+                        qf_type = item.type,
+                    })
+                end
+            end
+            print(vim.json.encode(result))
+        "#
+    .replace("{list}", list)
+}
+
+/// What a register holds. `register_type` is `c` for characterwise, `l` for
+/// linewise and `b` for blockwise text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterContent {
+    pub content: String,
+    pub register_type: String,
+}
+
+impl RegisterContent {
+    /// Parses the JSON object printed by `REGISTERS_LUA_SCRIPT`, mapping
+    /// `getregtype()`'s `v`, `V` and `<C-V>{width}` to `c`, `l` and `b`.
+    /// Empty registers are left out.
+    pub fn parse_map(output: &str) -> Result<BTreeMap<String, Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected registers output: {}", e))?;
+        let items = data.as_object().ok_or_else(|| anyhow!("Unexpected registers output: {}", data))?;
+
+        Ok(items
+            .iter()
+            .filter_map(|(name, item)| {
+                let content = item["content"].as_str().filter(|content| !content.is_empty())?;
+                let register_type = match item["type"].as_str()? {
+                    "V" => "l",
+                    regtype if regtype.starts_with('\x16') => "b",
+                    _ => "c",
+                };
+                Some((name.clone(), Self { content: content.to_string(), register_type: register_type.to_string() }))
+            })
+            .collect())
+    }
+}
+
+/// Lua run over the socket to read the named registers, the unnamed one and
+/// both clipboards in one call. Reading `+` or `*` without a clipboard
+/// provider fails, which only leaves that register out.
+pub const REGISTERS_LUA_SCRIPT: &str = r#"
+            local names = { "\"", "+", "*" }
+            for code = string.byte("a"), string.byte("z") do
+                table.insert(names, string.char(code))
+            end
+
+            local result = vim.empty_dict()
+            for _, name in ipairs(names) do
+                local ok, content = pcall(vim.fn.getreg, name)
+                if ok and type(content) == "string" and content ~= "" then
+                    result[name] = { content = content, type = vim.fn.getregtype(name) }
+                end
+            end
+
+            print(vim.json.encode(result))
+        "#;
+
+pub const DEFAULT_JUMPLIST_ENTRIES: u32 = 50;
+/// Neovim keeps at most this many jumps anyway.
+pub const MAX_JUMPLIST_ENTRIES: u32 = 100;
+
+/// A position in Neovim's jumplist. Line and column are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JumpEntry {
+    pub file_path: String,
+    pub line: u32,
+    pub column: u32,
+    /// Where `<C-o>` and `<C-i>` count from; no entry has it while the
+    /// cursor is past the newest jump
+    pub current: bool,
+}
+
+impl JumpEntry {
+    /// Parses the JSON array printed by `jumplist_lua_script`. Neovim
+    /// encodes an empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected jumplist output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) if object.is_empty() => Vec::new(),
+            other => return Err(anyhow!("Unexpected jumplist output: {}", other)),
+        };
+
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                Some(Self {
+                    file_path: item["file_path"].as_str()?.to_string(),
+                    line: item["line"].as_u64()? as u32,
+                    column: item["column"].as_u64()? as u32,
+                    current: item["current"].as_bool().unwrap_or(false),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Lua run over the socket to read the newest `max_entries` jumps of the
+/// current window with `getjumplist()`, oldest first. Its second item is the
+/// 0-based index of the current entry.
+pub fn jumplist_lua_script(max_entries: u32) -> String {
+    r#"
+            local jumps = vim.fn.getjumplist()
+            local list, position = jumps[1], jumps[2]
+            local result = {}
+            for i = math.max(1, #list - {max_entries} + 1), #list do
+                local jump = list[i]
+                table.insert(result, {
+                    file_path = jump.filename or vim.api.nvim_buf_get_name(jump.bufnr),
+                    line = jump.lnum,
+                    column = jump.col + 1,
+                    current = i - 1 == position,
+                })
+            end
+            print(vim.json.encode(result))
+        "#
+    .replace("{max_entries}", &max_entries.to_string())
+}
+
+pub const DEFAULT_SEARCH_HISTORY: u32 = 10;
+pub const MAX_SEARCH_HISTORY: u32 = 50;
+
+/// A pattern from Neovim's `/` and `?` history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHistoryEntry {
+    pub pattern: String,
+    /// Guessed with `is_vim_regex`
+    pub is_regex: bool,
+}
+
+impl SearchHistoryEntry {
+    /// Parses the JSON array of patterns printed by
+    /// `search_history_lua_script`, most recent first. Neovim encodes an
+    /// empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected search history output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) if object.is_empty() => Vec::new(),
+            other => return Err(anyhow!("Unexpected search history output: {}", other)),
+        };
+
+        Ok(items
+            .iter()
+            .filter_map(|item| item.as_str())
+            .map(|pattern| Self { pattern: pattern.to_string(), is_regex: is_vim_regex(pattern) })
+            .collect())
+    }
+}
+
+/// Whether `pattern` uses anything beyond literal text under Vim's default
+/// `magic`: an unescaped `.`, `*`, `[` or `~`, `^` at the start, `$` at the
+/// end, or a backslash item such as `\(`, `\d` or `\<`. Escaped
+/// metacharacters like `\.` are literal.
+pub fn is_vim_regex(pattern: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => match chars.get(i + 1) {
+                Some('.' | '*' | '[' | ']' | '~' | '/' | '?' | '\\' | '^' | '$') => i += 1,
+                Some(_) => return true,
+                None => {}
+            },
+            '.' | '*' | '[' | '~' => return true,
+            '^' if i == 0 => return true,
+            '$' if i == chars.len() - 1 => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Lua run over the socket to read the last `count` entries of the search
+/// history, stopping early when it runs out.
+pub fn search_history_lua_script(count: u32) -> String {
+    r#"
+            local result = {}
+            for i = 1, {count} do
+                local pattern = vim.fn.histget("search", -i)
+                if pattern == "" then break end
+                table.insert(result, pattern)
+            end
+            print(vim.json.encode(result))
+        "#
+    .replace("{count}", &count.to_string())
+}
+
+/// Node text longer than this many characters is cut short, so the root
+/// node does not repeat the whole file.
+pub const MAX_TS_NODE_TEXT_CHARS: usize = 200;
+
+/// A Tree-sitter node under or around the cursor. Rows and columns are
+/// 0-based, as Tree-sitter reports them, and the end is exclusive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TsNode {
+    #[serde(rename = "type")]
+    pub node_type: String,
+    pub text: String,
+    pub start_row: u32,
+    pub start_col: u32,
+    pub end_row: u32,
+    pub end_col: u32,
+}
+
+impl TsNode {
+    /// Parses the JSON printed by `TREESITTER_NODES_LUA_SCRIPT`: an array of
+    /// nodes, innermost first, or an object whose `error` says why there
+    /// are none.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected Tree-sitter output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) => match object.get("error").and_then(|error| error.as_str()) {
+                Some(error) => return Err(anyhow!("{}", error)),
+                None if object.is_empty() => Vec::new(),
+                None => return Err(anyhow!("Unexpected Tree-sitter output: {}", serde_json::Value::Object(object))),
+            },
+            other => return Err(anyhow!("Unexpected Tree-sitter output: {}", other)),
+        };
+
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                let text = item["text"].as_str().unwrap_or("");
+                let text = match text.char_indices().nth(MAX_TS_NODE_TEXT_CHARS) {
+                    Some((end, _)) => format!("{}...", &text[..end]),
+                    None => text.to_string(),
+                };
+                Some(Self {
+                    node_type: item["type"].as_str()?.to_string(),
+                    text,
+                    start_row: item["start_row"].as_u64()? as u32,
+                    start_col: item["start_col"].as_u64()? as u32,
+                    end_row: item["end_row"].as_u64()? as u32,
+                    end_col: item["end_col"].as_u64()? as u32,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Lua run over the socket to walk from the Tree-sitter node at the cursor
+/// up to the root of the current buffer. Only runs when a parser for the
+/// buffer's filetype is loaded.
+pub const TREESITTER_NODES_LUA_SCRIPT: &str = r#"
+            local ft = vim.bo.filetype
+            local lang = vim.treesitter.language.get_lang(ft) or ft
+            local loaded = vim.treesitter.is_parser_loaded and vim.treesitter.is_parser_loaded(lang)
+            if loaded == nil then
+                loaded = pcall(vim.treesitter.get_parser, 0, lang)
+            end
+            if ft == "" or not loaded then
+                print(vim.json.encode({ error = "No Tree-sitter parser is loaded for filetype " .. (ft == "" and "(none)" or ft) }))
+                return
+            end
+
+            vim.treesitter.get_parser(0, lang):parse()
+            local node = vim.treesitter.get_node()
+            local result = {}
+            while node do
+                local start_row, start_col, end_row, end_col = node:range()
+                table.insert(result, {
+                    type = node:type(),
+                    text = vim.treesitter.get_node_text(node, 0),
+                    start_row = start_row,
+                    start_col = start_col,
+                    end_row = end_row,
+                    end_col = end_col,
+                })
+                node = node:parent()
+            end
+            print(vim.json.encode(result))
+        "#;
+
+/// How often a buffer watch collects the changes Neovim has queued.
+pub const BUFFER_WATCH_INTERVAL: Duration = Duration::from_millis(250);
+/// Lines of new text sent with each change; the counts stay exact.
+pub const MAX_CHANGE_EVENT_LINES: u32 = 50;
+/// Changes Neovim keeps per watch between collections; older ones are
+/// dropped first.
+pub const MAX_QUEUED_CHANGES: u32 = 1000;
+
+/// An edit to a Neovim buffer, as `nvim_buf_attach` reports it: from
+/// `start_line` (1-based), `removed_lines` lines were replaced by
+/// `added_lines` new ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BufferChangeEvent {
+    pub file_path: String,
+    pub buffer: u32,
+    pub changedtick: u64,
+    pub start_line: u32,
+    pub removed_lines: u32,
+    pub added_lines: u32,
+    /// The first `MAX_CHANGE_EVENT_LINES` added lines
+    pub lines: Vec<String>,
+}
+
+impl BufferChangeEvent {
+    /// Parses the JSON array printed by `buffer_watch_drain_lua_script`, or
+    /// the `error` object it prints once the watch is gone. Neovim encodes an
+    /// empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected buffer watch output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) => match object.get("error").and_then(|error| error.as_str()) {
+                Some(error) => return Err(anyhow!("{}", error)),
+                None if object.is_empty() => Vec::new(),
+                None => return Err(anyhow!("Unexpected buffer watch output: {}", serde_json::Value::Object(object))),
+            },
+            other => return Err(anyhow!("Unexpected buffer watch output: {}", other)),
+        };
+
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                Some(Self {
+                    file_path: item["file_path"].as_str().unwrap_or("").to_string(),
+                    buffer: item["buffer"].as_u64()? as u32,
+                    changedtick: item["changedtick"].as_u64()?,
+                    start_line: item["start_line"].as_u64()? as u32,
+                    removed_lines: item["removed_lines"].as_u64()? as u32,
+                    added_lines: item["added_lines"].as_u64()? as u32,
+                    lines: item["lines"]
+                        .as_array()
+                        .map(|lines| lines.iter().filter_map(|line| line.as_str().map(|line| line.to_string())).collect())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Lua run over the socket to start watch `watch_id`: every loaded file
+/// buffer, and each one read later, gets an `nvim_buf_attach` callback that
+/// queues its changes in `_G.alacritty_mcp_watches`. A callback detaches
+/// itself once its watch is removed. Prints how many buffers it attached to.
+pub fn buffer_watch_start_lua_script(watch_id: &str) -> String {
+    r#"
+            local id = {watch_id}
+            _G.alacritty_mcp_watches = _G.alacritty_mcp_watches or {}
+            local watches = _G.alacritty_mcp_watches
+            watches[id] = { events = {}, attached = {} }
+
+            local function attach(buf)
+                local watch = watches[id]
+                if not watch or watch.attached[buf] or not vim.api.nvim_buf_is_loaded(buf) or vim.bo[buf].buftype ~= "" then
+                    return false
+                end
+                watch.attached[buf] = true
+                return vim.api.nvim_buf_attach(buf, false, {
+                    on_lines = function(_, bufnr, changedtick, first, last, new_last)
+                        local current = watches[id]
+                        if not current then
+                            return true
+                        end
+                        table.insert(current.events, {
+                            file_path = vim.api.nvim_buf_get_name(bufnr),
+                            buffer = bufnr,
+                            changedtick = changedtick,
+                            start_line = first + 1,
+                            removed_lines = last - first,
+                            added_lines = new_last - first,
+                            lines = vim.api.nvim_buf_get_lines(bufnr, first, math.min(new_last, first + {max_lines}), false),
+                        })
+                        if #current.events > {max_queued} then
+                            table.remove(current.events, 1)
+                        end
+                    end,
+                    on_detach = function(_, bufnr)
+                        local current = watches[id]
+                        if current then
+                            current.attached[bufnr] = nil
+                        end
+                    end,
+                })
+            end
+
+            local count = 0
+            for _, buf in ipairs(vim.api.nvim_list_bufs()) do
+                if attach(buf) then
+                    count = count + 1
+                end
+            end
+            local group = vim.api.nvim_create_augroup("alacritty_mcp_watch_" .. id, { clear = true })
+            vim.api.nvim_create_autocmd({ "BufReadPost", "BufNewFile" }, {
+                group = group,
+                callback = function(args)
+                    attach(args.buf)
+                end,
+            })
+            print(vim.json.encode({ attached = count }))
+        "#
+    .replace("{watch_id}", &lua_string(watch_id))
+    .replace("{max_lines}", &MAX_CHANGE_EVENT_LINES.to_string())
+    .replace("{max_queued}", &MAX_QUEUED_CHANGES.to_string())
+}
+
+/// Lua run over the socket to take the changes queued for `watch_id`.
+pub fn buffer_watch_drain_lua_script(watch_id: &str) -> String {
+    r#"
+            local watch = (_G.alacritty_mcp_watches or {})[{watch_id}]
+            if not watch then
+                print(vim.json.encode({ error = "Buffer watch is no longer registered in Neovim" }))
+                return
+            end
+            local events = watch.events
+            watch.events = {}
+            print(vim.json.encode(events))
+        "#
+    .replace("{watch_id}", &lua_string(watch_id))
+}
+
+/// Lua run over the socket to end `watch_id`. Its callbacks detach on the
+/// next change they see.
+pub fn buffer_watch_stop_lua_script(watch_id: &str) -> String {
+    r#"
+            local id = {watch_id}
+            if _G.alacritty_mcp_watches then
+                _G.alacritty_mcp_watches[id] = nil
+            end
+            pcall(vim.api.nvim_del_augroup_by_name, "alacritty_mcp_watch_" .. id)
+            print(vim.json.encode({}))
+        "#
+    .replace("{watch_id}", &lua_string(watch_id))
+}
+
+/// A running `watch_buffer_changes`. Dropping it stops the collecting;
+/// `cancel` also removes the watch from Neovim.
+pub struct BufferWatch {
+    watch_id: String,
+    socket_path: String,
+    extractor: Arc<NeovimContextExtractor>,
+    task: JoinHandle<()>,
+}
+
+impl BufferWatch {
+    pub fn watch_id(&self) -> &str {
+        &self.watch_id
+    }
+
+    /// Whether the watch has ended by itself, because Neovim went away or
+    /// the receiver was dropped.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    pub async fn cancel(self) -> Result<()> {
+        self.task.abort();
+        self.extractor.stop_buffer_watch(&self.socket_path, &self.watch_id).await
+    }
+}
+
+impl Drop for BufferWatch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+pub const DEFAULT_SPELL_ERRORS: u32 = 50;
+pub const MAX_SPELL_ERRORS: u32 = 500;
+/// Suggestions asked of `spellsuggest()` per word.
+pub const SPELL_SUGGESTIONS: u32 = 5;
+
+/// A word Neovim's spell checker flags. Line and column are 1-based, the
+/// column counted in bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpellError {
+    pub word: String,
+    pub line: u32,
+    pub column: u32,
+    /// `bad`, `rare`, `local` or `caps`, as `vim.spell.check()` reports it
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub suggestions: Vec<String>,
+}
+
+impl SpellError {
+    /// Parses the JSON array printed by `spell_errors_lua_script`, or the
+    /// `error` object it prints when spell checking is unavailable. Neovim
+    /// encodes an empty list as `{}`.
+    pub fn parse_list(output: &str) -> Result<Vec<Self>> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected spell check output: {}", e))?;
+
+        let items = match data {
+            serde_json::Value::Array(items) => items,
+            serde_json::Value::Object(object) => match object.get("error").and_then(|error| error.as_str()) {
+                Some(error) => return Err(anyhow!("{}", error)),
+                None if object.is_empty() => Vec::new(),
+                None => return Err(anyhow!("Unexpected spell check output: {}", serde_json::Value::Object(object))),
+            },
+            other => return Err(anyhow!("Unexpected spell check output: {}", other)),
+        };
+
+        Ok(items
+            .iter()
+            .filter_map(|item| {
+                Some(Self {
+                    word: item["word"].as_str()?.to_string(),
+                    line: item["line"].as_u64()? as u32,
+                    column: item["column"].as_u64()? as u32,
+                    type_: item["type"].as_str().unwrap_or("bad").to_string(),
+                    suggestions: item["suggestions"]
+                        .as_array()
+                        .map(|words| words.iter().filter_map(|word| word.as_str().map(|word| word.to_string())).collect())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Lua run over the socket to spell check the current buffer line by line
+/// with `vim.spell.check()`, which needs Neovim 0.10, stopping after
+/// `max_errors`. Only runs while `spell` is set in the current window.
+pub fn spell_errors_lua_script(max_errors: u32) -> String {
+    r#"
+            if not (vim.spell and vim.spell.check) then
+                print(vim.json.encode({ error = "Spell checking needs Neovim 0.10 or later" }))
+                return
+            end
+            if not vim.wo.spell then
+                print(vim.json.encode({ error = "Spell checking is off in the current window; enable it with :set spell" }))
+                return
+            end
+
+            local result = {}
+            for lnum, text in ipairs(vim.api.nvim_buf_get_lines(0, 0, -1, false)) do
+                for _, found in ipairs(vim.spell.check(text)) do
+                    if #result >= {max_errors} then
+                        break
+                    end
+                    table.insert(result, {
+                        word = found[1],
+                        type = found[2],
+                        line = lnum,
+                        column = found[3],
+                        suggestions = vim.fn.spellsuggest(found[1], {suggestions}),
+                    })
+                end
+            end
+            print(vim.json.encode(result))
+        "#
+    .replace("{max_errors}", &max_errors.to_string())
+    .replace("{suggestions}", &SPELL_SUGGESTIONS.to_string())
+}
+
+/// The scopes `get_session_variables` can read, with their Vim prefix.
+pub const SESSION_VARIABLE_SCOPES: &[(&str, &str)] = &[("global", "g"), ("buffer", "b"), ("window", "w"), ("tab", "t")];
+
+/// Neovim's `g:`, `b:`, `w:` and `t:` variables, the last three for the
+/// current buffer, window and tab page, each as a JSON object. Scopes that
+/// were not asked for are `null`. Names starting with `_` are left out, and
+/// values JSON cannot hold, such as functions, are given as `<function>`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionVariables {
+    pub global: serde_json::Value,
+    pub buffer: serde_json::Value,
+    pub window: serde_json::Value,
+    pub tab: serde_json::Value,
+}
+
+impl SessionVariables {
+    /// Parses the JSON object printed by `session_variables_lua_script`.
+    /// An empty scope may come back as `[]` or `{}`; both become `{}`.
+    pub fn parse(output: &str) -> Result<Self> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected session variables output: {}", e))?;
+        if !data.is_object() {
+            return Err(anyhow!("Unexpected session variables output: {}", data));
+        }
+
+        let scope = |name: &str| match &data[name] {
+            serde_json::Value::Array(items) if items.is_empty() => serde_json::json!({}),
+            value => value.clone(),
+        };
+        Ok(Self {
+            global: scope("global"),
+            buffer: scope("buffer"),
+            window: scope("window"),
+            tab: scope("tab"),
+        })
+    }
+}
+
+/// Lua run over the socket to read the variables of each scope in `scopes`,
+/// names from `SESSION_VARIABLE_SCOPES`, through the dictionaries behind
+/// `vim.g`, `vim.b`, `vim.w` and `vim.t`.
+pub fn session_variables_lua_script(scopes: &[&str]) -> String {
+    let scopes: Vec<String> = SESSION_VARIABLE_SCOPES
+        .iter()
+        .filter(|(name, _)| scopes.contains(name))
+        .map(|(name, prefix)| format!("{{ name = \"{}\", prefix = \"{}\" }}", name, prefix))
+        .collect();
+
+    r#"
+            local result = {}
+            for _, scope in ipairs({ {scopes} }) do
+                local vars = vim.empty_dict()
+                for key, value in pairs(vim.fn.eval(scope.prefix .. ":")) do
+                    if type(key) == "string" and key:sub(1, 1) ~= "_" then
+                        local ok = pcall(vim.json.encode, value)
+                        vars[key] = ok and value or ("<" .. type(value) .. ">")
+                    end
+                end
+                result[scope.name] = vars
+            end
+            print(vim.json.encode(result))
+        "#
+    .replace("{scopes}", &scopes.join(", "))
+}
+
+/// `git status` of the directory Neovim is working in. Paths are relative to
+/// the repository root, as git prints them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitStatus {
+    /// Empty on a detached HEAD
+    pub branch: String,
+    /// Changed in the working tree but not staged, conflicts included
+    pub modified: Vec<String>,
+    pub staged: Vec<String>,
+    pub untracked: Vec<String>,
+    /// Commits ahead of and behind the upstream, 0 without one
+    pub ahead: u32,
+    pub behind: u32,
+}
+
+impl GitStatus {
+    /// Parses `git status --porcelain=v1 --branch`. A file with staged and
+    /// unstaged changes is listed in both `staged` and `modified`; a rename
+    /// is listed under its new name.
+    pub fn parse(output: &str) -> Self {
+        let mut status = Self::default();
+
+        for line in output.lines() {
+            if let Some(header) = line.strip_prefix("## ") {
+                status.parse_branch(header);
+                continue;
+            }
+            if line.len() < 4 {
+                continue;
+            }
+            let (code, path) = line.split_at(3);
+            let path = path.rsplit(" -> ").next().unwrap_or(path).to_string();
+            let mut code = code.chars();
+            let (index, worktree) = (code.next().unwrap_or(' '), code.next().unwrap_or(' '));
+
+            match (index, worktree) {
+                ('?', '?') => status.untracked.push(path),
+                ('!', '!') => {}
+                // Unmerged: both sides changed, or one side is a `U`
+                ('U', _) | (_, 'U') | ('A', 'A') | ('D', 'D') => status.modified.push(path),
+                (index, worktree) => {
+                    if index != ' ' {
+                        status.staged.push(path.clone());
+                    }
+                    if worktree != ' ' {
+                        status.modified.push(path);
+                    }
+                }
+            }
+        }
+
+        status
+    }
+
+    /// `main...origin/main [ahead 1, behind 2]`, `No commits yet on main`
+    /// or `HEAD (no branch)`.
+    fn parse_branch(&mut self, header: &str) {
+        let (branch, tracking) = match header.split_once(" [") {
+            Some((branch, tracking)) => (branch, tracking.trim_end_matches(']')),
+            None => (header, ""),
+        };
+        let branch = branch.split("...").next().unwrap_or(branch);
+        self.branch = match branch {
+            "HEAD (no branch)" => String::new(),
+            branch => branch.strip_prefix("No commits yet on ").unwrap_or(branch).to_string(),
+        };
+
+        for part in tracking.split(", ") {
+            if let Some(count) = part.strip_prefix("ahead ") {
+                self.ahead = count.parse().unwrap_or(0);
+            } else if let Some(count) = part.strip_prefix("behind ") {
+                self.behind = count.parse().unwrap_or(0);
+            }
+        }
+    }
+}
+
+/// Checks for a finished test run this often.
+pub const TEST_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A run counts as finished once this many checks in a row found nothing
+/// running and the same results.
+pub const TEST_STABLE_POLLS: u32 = 3;
+
+/// The outcome of `neovim_run_test`. Dispatch only fills the quickfix list,
+/// so with it `passed` and `pending` stay 0 and `failed` counts the entries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestRunResult {
+    /// `neotest` or `dispatch`
+    pub runner: String,
+    pub passed: u32,
+    pub failed: u32,
+    pub pending: u32,
+    pub duration_ms: u64,
+    pub failures: Vec<Diagnostic>,
+    /// Whether the results were still changing when the timeout elapsed
+    pub timed_out: bool,
+}
+
+/// One check on a test run, as printed by `test_status_lua_script`.
+struct TestStatus {
+    running: u64,
+    result: TestRunResult,
+}
+
+impl TestStatus {
+    fn parse(runner: &str, output: &str) -> Result<Self> {
+        let data: serde_json::Value = serde_json::from_str(output.trim())
+            .map_err(|e| anyhow!("Unexpected test status output: {}", e))?;
+        let count = |key: &str| data[key].as_u64().unwrap_or(0);
+
+        Ok(Self {
+            running: count("running"),
+            result: TestRunResult {
+                runner: runner.to_string(),
+                passed: count("passed") as u32,
+                failed: count("failed") as u32,
+                pending: count("pending") as u32,
+                duration_ms: 0,
+                failures: data["failures"].as_array()
+                    .map(|failures| failures.iter().map(Diagnostic::from_json).collect())
+                    .unwrap_or_default(),
+                timed_out: false,
+            },
+        })
+    }
+}
+
+/// `value` as a double-quoted Lua string literal. Control characters become
+/// decimal escapes, which LuaJIT understands too.
+pub fn lua_string(value: &str) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                literal.push('\\');
+                literal.push(c);
+            }
+            c if c.is_ascii_control() => literal.push_str(&format!("\\{:03}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Lua run over the socket to start a test run with Neotest, or with
+/// `:Dispatch make test` where Neotest is not installed, and print which one
+/// it used (`null` for neither). Neotest runs `test_name` in `test_file`, or
+/// in the current file without one, as position `<file>::<name>`; the whole
+/// of `test_file`; or otherwise the test nearest the cursor, like
+/// `:Neotest run`. Dispatch always runs the whole suite.
+pub fn test_start_lua_script(test_file: Option<&str>, test_name: Option<&str>) -> String {
+    let literal = |value: Option<&str>| value.map_or_else(|| "nil".to_string(), lua_string);
+
+    r#"
+            local file, name = {file}, {name}
+            if file ~= nil then file = vim.fn.fnamemodify(file, ":p") end
+            local ok, neotest = pcall(require, "neotest")
+            if ok then
+                local target = nil
+                if name ~= nil then
+                    target = (file or vim.api.nvim_buf_get_name(0)) .. "::" .. name
+                elseif file ~= nil then
+                    target = file
+                end
+                neotest.run.run(target)
+                print(vim.json.encode({ runner = "neotest" }))
+            elseif vim.fn.exists(":Dispatch") == 2 then
+                local tick = vim.fn.getqflist({ changedtick = 0 }).changedtick
+                vim.cmd("Dispatch make test")
+                print(vim.json.encode({ runner = "dispatch", quickfix_tick = tick }))
+            else
+                print(vim.json.encode({ runner = vim.NIL }))
+            end
+        "#
+    .replace("{file}", &literal(test_file))
+    .replace("{name}", &literal(test_name))
+}
+
+/// Lua run over the socket to check on a run `test_start_lua_script`
+/// started. For Neotest it adds up the status counts of every adapter and
+/// takes the failures from Neotest's diagnostics. Dispatch fills the
+/// quickfix list once the run is over, so the run counts as going on until
+/// the list's `changedtick` moves past `quickfix_tick`, and the failures are
+/// its valid entries.
+pub fn test_status_lua_script(runner: &str, quickfix_tick: u64) -> String {
+    let script = match runner {
+        "neotest" => r#"
+            local neotest = require("neotest")
+            local result = { running = 0, passed = 0, failed = 0, pending = 0, failures = {} }
+            for _, adapter_id in ipairs(neotest.state.adapter_ids()) do
+                local counts = neotest.state.status_counts(adapter_id) or {}
+                result.running = result.running + (counts.running or 0)
+                result.passed = result.passed + (counts.passed or 0)
+                result.failed = result.failed + (counts.failed or 0)
+                result.pending = result.pending + (counts.skipped or 0)
+            end
+            for name, namespace in pairs(vim.api.nvim_get_namespaces()) do
+                if name:find("neotest", 1, true) then
+                    for _, diag in ipairs(vim.diagnostic.get(nil, { namespace = namespace })) do
+                        table.insert(result.failures, {
+                            file_path = vim.api.nvim_buf_get_name(diag.bufnr),
+                            line = diag.lnum + 1,
+                            column = diag.col + 1,
+                            severity = diag.severity,
+                            message = diag.message,
+                            source = diag.source or "neotest",
+                        })
+                    end
+                end
+            end
+            print(vim.json.encode(result))
+        "#,
+        _ => r#"
+            local tick = vim.fn.getqflist({ changedtick = 0 }).changedtick
+            local result = { running = tick == {quickfix_tick} and 1 or 0, failures = {} }
+            for _, item in ipairs(vim.fn.getqflist()) do
+                if item.valid == 1 then
+                    table.insert(result.failures, {
+                        file_path = item.bufnr > 0 and vim.api.nvim_buf_get_name(item.bufnr) or "",
+                        line = item.lnum,
+                        column = item.col,
+                        severity = 1,
+                        message = item.text,
+                        source = "dispatch",
+                    })
+                end
+            end
+            result.failed = #result.failures
+            print(vim.json.encode(result))
+        "#,
+    };
+    script.replace("{quickfix_tick}", &quickfix_tick.to_string())
+}
+
+/// What an Ex command printed, or the error Neovim reported for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecResult {
+    pub output: String,
+    pub error: Option<String>,
+}
+
+/// What the terminal turned out to be running when context was requested.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TerminalContext {
+    Neovim(Box<NeovimContext>),
+    Helix(HelixContext),
+    Tmux(TmuxContext),
+    PlainTerminal,
+}
+
+/// Which modal editor the screen looks like it is showing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EditorType {
+    Neovim,
+    Helix,
+    Unknown,
+}
+
+/// The active tmux pane, as reported by `tmux display-message -p`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TmuxContext {
+    pub socket_path: String,
+    pub pane_current_path: String,
+    pub pane_title: String,
+    pub current_command: String,
+    pub pane_width: u32,
+    pub pane_height: u32,
+}
+
+/// Fields are tab separated since paths and titles may contain spaces.
+pub const TMUX_PANE_FORMAT: &str =
+    "#{pane_current_path}\t#{pane_title}\t#{pane_current_command}\t#{pane_width}\t#{pane_height}";
+
+impl TmuxContext {
+    /// Parses the output of `display-message -p` with `TMUX_PANE_FORMAT`.
+    pub fn parse(socket_path: &str, output: &str) -> Option<Self> {
+        let fields: Vec<&str> = output.trim_end_matches('\n').split('\t').collect();
+        let [path, title, command, width, height] = fields[..] else {
+            return None;
+        };
+
+        Some(Self {
+            socket_path: socket_path.to_string(),
+            pane_current_path: path.to_string(),
+            pane_title: title.to_string(),
+            current_command: command.to_string(),
+            pane_width: width.trim().parse().ok()?,
+            pane_height: height.trim().parse().ok()?,
+        })
+    }
+}
+
+/// What can be read off a Neovim status line such as
+/// `src/main.rs [+] [rust] 42,17`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusLine {
+    pub file_path: String,
+    pub is_modified: bool,
+    pub file_type: Option<String>,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl StatusLine {
+    pub fn parse(line: &str) -> Option<Self> {
+        static STATUS_LINE: OnceLock<Regex> = OnceLock::new();
+        let re = STATUS_LINE.get_or_init(|| {
+            Regex::new(concat!(
+                r"^\s*(?P<file>\S.*?)",
+                r"(?P<modified>\s+\[\+\])?",
+                r"(?:\s+\[(?P<filetype>[^\]]+)\])?",
+                r"\s+(?P<line>\d+),(?P<column>\d+)(?:-\d+)?",
+                r"(?:\s+(?:All|Top|Bot|\d+%))?\s*$",
+            ))
+            .unwrap()
+        });
+
+        let caps = re.captures(line)?;
+        Some(Self {
+            file_path: caps["file"].to_string(),
+            is_modified: caps.name("modified").is_some(),
+            file_type: caps.name("filetype").map(|m| m.as_str().to_string()),
+            line: caps["line"].parse().ok()?,
+            column: caps["column"].parse().ok()?,
+        })
+    }
+}
+
+/// Lua run over the socket to read the current buffer along with
+/// `context_lines` lines either side of the cursor (clamped to
+/// `MAX_CONTEXT_LINES`).
+pub fn current_buffer_lua_script(context_lines: u32) -> String {
+    let context_lines = context_lines.min(MAX_CONTEXT_LINES);
+    r#"
+            local buf = vim.api.nvim_get_current_buf()
+            local file_path = vim.api.nvim_buf_get_name(buf)
+            local file_type = vim.bo.filetype
+            local is_modified = vim.bo.modified
+            local line_count = vim.api.nvim_buf_line_count(buf)
+            local cursor = vim.api.nvim_win_get_cursor(0)
+            local current_line_nr = cursor[1]
+            
+            -- Get surrounding context
+            local start_line = math.max(1, current_line_nr - {context_lines})
+            local end_line = math.min(line_count, current_line_nr + {context_lines})
+            local lines = vim.api.nvim_buf_get_lines(buf, start_line - 1, end_line, false)
+            
+            local context = {
+                file_path = file_path,
+                file_type = file_type,
+                is_modified = is_modified,
+                line_count = line_count,
+                current_line_nr = current_line_nr,
+                lines_before = {},
+                current_line = "",
+                lines_after = {},
+            }
+            
+            for i, line in ipairs(lines) do
+                local line_nr = start_line + i - 1
+                if line_nr < current_line_nr then
+                    table.insert(context.lines_before, line)
+                elseif line_nr == current_line_nr then
+                    context.current_line = line
+                else
+                    table.insert(context.lines_after, line)
+                end
+            end
+            
+            print(vim.json.encode(context))
+        "#
+    .replace("{context_lines}", &context_lines.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OptionKind {
+    Boolean,
+    Number,
+    Text,
+}
+
+/// The options `set_neovim_option` may change. Anything that names a
+/// program or a path (`shell`, `makeprg`, `runtimepath`, ...) is left out,
+/// since the values come from MCP callers.
+const SETTABLE_OPTIONS: &[(&str, OptionKind)] = &[
+    ("autoindent", OptionKind::Boolean),
+    ("cursorcolumn", OptionKind::Boolean),
+    ("cursorline", OptionKind::Boolean),
+    ("expandtab", OptionKind::Boolean),
+    ("hlsearch", OptionKind::Boolean),
+    ("ignorecase", OptionKind::Boolean),
+    ("incsearch", OptionKind::Boolean),
+    ("list", OptionKind::Boolean),
+    ("number", OptionKind::Boolean),
+    ("relativenumber", OptionKind::Boolean),
+    ("smartcase", OptionKind::Boolean),
+    ("smartindent", OptionKind::Boolean),
+    ("spell", OptionKind::Boolean),
+    ("wrap", OptionKind::Boolean),
+    ("cmdheight", OptionKind::Number),
+    ("conceallevel", OptionKind::Number),
+    ("laststatus", OptionKind::Number),
+    ("numberwidth", OptionKind::Number),
+    ("scrolloff", OptionKind::Number),
+    ("shiftwidth", OptionKind::Number),
+    ("sidescrolloff", OptionKind::Number),
+    ("softtabstop", OptionKind::Number),
+    ("tabstop", OptionKind::Number),
+    ("textwidth", OptionKind::Number),
+    ("background", OptionKind::Text),
+    ("colorcolumn", OptionKind::Text),
+    ("fileformat", OptionKind::Text),
+    ("filetype", OptionKind::Text),
+    ("foldmethod", OptionKind::Text),
+    ("mouse", OptionKind::Text),
+    ("signcolumn", OptionKind::Text),
+    ("spelllang", OptionKind::Text),
+    ("syntax", OptionKind::Text),
+    ("virtualedit", OptionKind::Text),
+];
+
+/// The keys `set_neovim_option` sends for `:set <option>=<value>`. Booleans
+/// take `true`/`false` (or `on`/`off`) and become `:set <option>` or
+/// `:set no<option>`. Text values are limited to characters that cannot end
+/// the command or start a key notation like `<CR>`. The keys begin with
+/// `<C-\><C-N>` so they work from insert mode too.
+pub fn set_option_keys(option: &str, value: &str) -> Result<String> {
+    let kind = SETTABLE_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == option)
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| AlacrittyMcpError::InvalidParams(format!("Option '{}' cannot be set remotely", option)))?;
+    let invalid = |expected: &str| AlacrittyMcpError::InvalidParams(format!("Invalid value '{}' for {}: expected {}", value, option, expected));
+
+    let command = match kind {
+        OptionKind::Boolean => match value {
+            "true" | "on" => option.to_string(),
+            "false" | "off" => format!("no{}", option),
+            _ => return Err(invalid("true or false").into()),
+        },
+        OptionKind::Number => {
+            if value.is_empty() || value.len() > 9 || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid("a non-negative number").into());
+            }
+            format!("{}={}", option, value)
+        }
+        OptionKind::Text => {
+            if !value.chars().all(|c| c.is_ascii_alphanumeric() || "_-.,:+".contains(c)) {
+                return Err(invalid("letters, digits and _-.,:+ only").into());
+            }
+            format!("{}={}", option, value)
+        }
+    };
+
+    Ok(format!("<C-\\><C-N>:set {}<CR>", command))
+}
+
+/// Ex commands `neovim_execute_command` runs, in the spellings Vim accepts
+/// for them. All of them only report state; anything that can run a shell,
+/// Lua or Python, write files or define mappings and autocommands is left out.
+pub const EXECUTABLE_COMMANDS: &[&str] = &[
+    "buffers", "ls", "files",
+    "changes",
+    "display", "di", "registers", "reg",
+    "echo", "ec",
+    "history", "his",
+    "jumps", "ju",
+    "marks",
+    "messages", "mes",
+    "nohlsearch", "noh",
+    "oldfiles", "ol",
+    "pwd", "pw",
+    "scriptnames", "scr",
+    "set", "se", "setlocal", "setl", "setglobal", "setg",
+    "tabs",
+    "undolist", "undol",
+    "version", "ve",
+];
+
+/// Checks an Ex command (without the leading `:`) before it is run with
+/// `execute()`, returning it trimmed. On top of `EXECUTABLE_COMMANDS`:
+/// no `|` to chain another command, no backticks (which expand to shell
+/// output in file arguments), no `!` forms, `echo` without function calls
+/// and `set` only to query, e.g. `set tabstop?`.
+pub fn validate_ex_command(command: &str) -> Result<&str> {
+    let command = command.trim().trim_start_matches(':').trim_start();
+    let rejected = |reason: &str| AlacrittyMcpError::InvalidParams(format!("Command '{}' is not allowed: {}", command, reason));
+
+    if command.is_empty() {
+        return Err(AlacrittyMcpError::InvalidParams("command must not be empty".to_string()).into());
+    }
+    if let Some(c) = command.chars().find(|&c| c == '|' || c == '`' || c.is_control()) {
+        return Err(rejected(&format!("it contains {:?}", c)).into());
+    }
+
+    let name_len = command.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(command.len());
+    let (name, args) = command.split_at(name_len);
+    if !EXECUTABLE_COMMANDS.contains(&name) {
+        return Err(rejected(&format!("only {} can be run", EXECUTABLE_COMMANDS.join(", "))).into());
+    }
+    if args.starts_with('!') {
+        return Err(rejected("! forms are not accepted").into());
+    }
+
+    match name {
+        "echo" | "ec" if args.contains('(') => {
+            return Err(rejected("echo cannot call functions").into());
+        }
+        "set" | "se" | "setlocal" | "setl" | "setglobal" | "setg"
+            if !args.split_whitespace().all(is_option_query) =>
+        {
+            return Err(rejected("options can only be queried (e.g. set tabstop?); use set_neovim_option to change them").into());
+        }
+        _ => {}
+    }
+
+    Ok(command)
+}
+
+/// Whether a `set` argument only shows a value: `all`, or an option name
+/// followed by `?`. Vim reads everything after `=`, `:` and the like as the
+/// value, `?` included, so anything else could change an option; a bare
+/// name would switch a boolean option on.
+fn is_option_query(arg: &str) -> bool {
+    arg == "all" || arg.strip_suffix('?').is_some_and(|name| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Maps the mode message Neovim shows in the command line area to the value
+/// `mode()` would return.
+fn parse_mode_message(line: &str) -> Option<String> {
+    let mode = match line.trim() {
+        "-- INSERT --" => "i",
+        "-- REPLACE --" => "R",
+        "-- VISUAL --" => "v",
+        "-- VISUAL LINE --" => "V",
+        "-- VISUAL BLOCK --" => "\u{16}",
+        "-- SELECT --" => "s",
+        "-- TERMINAL --" => "t",
+        _ => return None,
+    };
+    Some(mode.to_string())
+}
+
+/// Neovim's listen address from `$NVIM`, which Neovim sets for everything it
+/// starts: language servers, `:terminal` shells, jobs. Any process below an
+/// `nvim` in the tree under `pid` carries it, so the first one that does is
+/// taken. A value `pid` itself inherited is skipped, as it belongs to a Neovim
+/// the terminal was started from rather than one running inside it.
+fn socket_from_nvim_env(pid: u32) -> Option<String> {
+    fn find(node: &ProcessNode, inherited: Option<&str>) -> Option<String> {
+        node.children.iter().find_map(|child| {
+            read_environ_var(child.pid, "NVIM")
+                .filter(|address| Some(address.as_str()) != inherited)
+                // A path must still exist; `host:port` addresses are taken as is
+                .filter(|address| !address.starts_with('/') || std::path::Path::new(address).exists())
+                .or_else(|| find(child, inherited))
+        })
+    }
+
+    let inherited = read_environ_var(pid, "NVIM");
+    find(&read_process_tree(pid).ok()?, inherited.as_deref())
+}
+
+pub struct NeovimContextExtractor {
+    nvim_command: String,
+    command_timeout: Duration,
+    /// Socket path per Neovim PID and when it was found, so `lsof` does not
+    /// run on every request. Behind a mutex to keep the extractor `Sync`.
+    socket_cache: Mutex<HashMap<u32, (String, Instant)>>,
+}
+
+impl NeovimContextExtractor {
+    pub fn new() -> Self {
+        Self {
+            nvim_command: "nvim".to_string(),
+            command_timeout: COMMAND_TIMEOUT,
+            socket_cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replaces `COMMAND_TIMEOUT` as the limit for every external command.
+    pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// Runs `program` without blocking the runtime. The child is killed if it
+    /// outlives the command timeout.
+    async fn run_command(&self, program: &str, args: &[&str]) -> Result<Output> {
+        let output = tokio::process::Command::new(program)
+            .args(args)
+            .kill_on_drop(true)
+            .output();
+
+        match tokio::time::timeout(self.command_timeout, output).await {
+            Ok(output) => Ok(output?),
+            Err(_) => Err(AlacrittyMcpError::NeovimRpcTimeout { command: program.to_string(), timeout: self.command_timeout }.into()),
+        }
+    }
+
+    /// `terminal_content` is the current text of the terminal, used to scrape
+    /// what it can when the Neovim socket is not reachable and to spot tmux.
+    /// Neovim wins over tmux, so an editor running inside tmux is still
+    /// reported as Neovim.
+    pub async fn extract_context_from_instance(
+        &self,
+        pid: u32,
+        params: &NeovimContextParams,
+        terminal_content: Option<&str>,
+    ) -> Result<TerminalContext> {
+        // Try multiple methods to connect to Neovim
+        match self.extract_via_nvim_listen(pid, params).await {
+            Ok(ctx) => return Ok(TerminalContext::Neovim(Box::new(ctx))),
+            Err(e) if matches!(e.downcast_ref(), Some(AlacrittyMcpError::NeovimRpcTimeout { .. })) => {
+                warn!("Neovim for PID {} is not responding, falling back to the screen: {}", pid, e);
+            }
+            Err(_) => {}
+        }
+
+        let Some(content) = terminal_content else {
+            return Ok(TerminalContext::PlainTerminal);
+        };
+
+        // Helix support is opt-in while the status line parsing matures. It
+        // goes first because Neovim's screen indicators are looser.
+        if HelixContextExtractor::enabled() {
+            if let Some(ctx) = HelixContextExtractor::new().extract_from_screen(content) {
+                return Ok(TerminalContext::Helix(ctx));
+            }
+        }
+
+        if let Ok(ctx) = self.extract_via_terminal_scraping(pid, content) {
+            return Ok(TerminalContext::Neovim(Box::new(ctx)));
+        }
+
+        if self.detect_tmux_in_terminal(content) {
+            if let Ok(ctx) = self.extract_tmux_context().await {
+                return Ok(TerminalContext::Tmux(ctx));
+            }
+        }
+
+        Ok(TerminalContext::PlainTerminal)
+    }
+
+    /// Asks the Neovim instance under `pid` for LSP hover documentation.
+    /// Needs the Neovim socket; nothing useful can be scraped off the screen.
+    pub async fn get_hover(&self, pid: u32, line: Option<u32>, col: Option<u32>) -> Result<Option<HoverResult>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        let lua_script = hover_lua_script(line, col);
+
+        let output = self.run_command("nvim", &[
+            "--server", &socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Hover request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        HoverResult::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Asks the Neovim instance under `pid` for LSP completions at the cursor.
+    pub async fn get_completions(&self, pid: u32, max_items: Option<u32>) -> Result<Vec<CompletionItem>> {
+        let max_items = max_items.unwrap_or(DEFAULT_COMPLETION_ITEMS).min(MAX_COMPLETION_ITEMS);
+        let socket_path = self.find_neovim_socket(pid).await?;
+        let lua_script = completion_lua_script(max_items);
+
+        let output = self.run_command("nvim", &[
+            "--server", &socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Completion request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        CompletionItem::parse_list(&String::from_utf8_lossy(&output.stdout), max_items as usize)
+    }
+
+    /// Asks the Neovim instance under `pid` where the symbol under the cursor
+    /// is used, grouped by file.
+    pub async fn get_references(&self, pid: u32, include_declaration: bool) -> Result<BTreeMap<String, Vec<Reference>>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        let lua_script = references_lua_script(include_declaration);
+
+        let output = self.run_command("nvim", &[
+            "--server", &socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("References request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        let references = Reference::parse_list(&String::from_utf8_lossy(&output.stdout))?;
+        Ok(Reference::group_by_file(references))
+    }
+
+    /// Asks the Neovim instance under `pid` where the symbol at the cursor,
+    /// or at the given position, is defined.
+    pub async fn get_definition(&self, pid: u32, line: Option<u32>, col: Option<u32>) -> Result<Vec<DefinitionResult>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        let lua_script = definition_lua_script(line, col);
+
+        let output = self.run_command("nvim", &[
+            "--server", &socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Definition request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        DefinitionResult::parse_list(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Lists the code actions the language servers of the Neovim instance
+    /// under `pid` offer on the cursor line.
+    pub async fn get_lsp_code_actions(&self, pid: u32) -> Result<Vec<CodeAction>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.get_lsp_code_actions_via_socket(&socket_path).await
+    }
+
+    pub async fn get_lsp_code_actions_via_socket(&self, socket_path: &str) -> Result<Vec<CodeAction>> {
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", code_actions_lua_script(None)),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Code actions request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        CodeAction::parse_list(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Applies the code action at `index` in the list `get_lsp_code_actions`
+    /// returns, asking the servers again so the action is current.
+    pub async fn apply_lsp_code_action(&self, pid: u32, index: u32) -> Result<CodeAction> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.apply_lsp_code_action_via_socket(&socket_path, index).await
+    }
+
+    pub async fn apply_lsp_code_action_via_socket(&self, socket_path: &str, index: u32) -> Result<CodeAction> {
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", code_actions_lua_script(Some(index))),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Applying code action {} failed: {}",
+                index, String::from_utf8_lossy(&output.stderr)));
+        }
+
+        CodeAction::parse_applied(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Lists the letter marks in the current buffer of the Neovim instance
+    /// under `pid`.
+    pub async fn get_marks(&self, pid: u32) -> Result<Vec<Mark>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+
+        let output = self.run_command("nvim", &[
+            "--server", &socket_path, "--remote-expr", &format!("luaeval('{}')", MARKS_LUA_SCRIPT),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Marks request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        Mark::parse_list(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// The quickfix list, or the location list of the current window, of the
+    /// Neovim instance under `pid`. Entries duplicating a diagnostic are
+    /// dropped; those are available from `get_workspace_diagnostics`.
+    pub async fn get_quickfix_list(&self, pid: u32, location_list: bool) -> Result<Vec<QuickfixEntry>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        let entries = self.get_quickfix_list_via_socket(&socket_path, location_list).await?;
+        let diagnostics = self.get_diagnostics_via_socket(&socket_path).await?;
+        Ok(QuickfixEntry::without_diagnostics(entries, &diagnostics))
+    }
+
+    pub async fn get_quickfix_list_via_socket(&self, socket_path: &str, location_list: bool) -> Result<Vec<QuickfixEntry>> {
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", quickfix_lua_script(location_list)),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Quickfix request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        QuickfixEntry::parse_list(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// The non-empty registers of the Neovim instance under `pid`, keyed by
+    /// register name.
+    pub async fn get_registers(&self, pid: u32) -> Result<BTreeMap<String, RegisterContent>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.get_registers_via_socket(&socket_path).await
+    }
+
+    pub async fn get_registers_via_socket(&self, socket_path: &str) -> Result<BTreeMap<String, RegisterContent>> {
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr", &format!("luaeval('{}')", REGISTERS_LUA_SCRIPT),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Registers request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        RegisterContent::parse_map(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// The jumplist of the Neovim instance under `pid`, see `jumplist_lua_script`.
+    pub async fn get_jumplist(&self, pid: u32, max_entries: Option<u32>) -> Result<Vec<JumpEntry>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.get_jumplist_via_socket(&socket_path, max_entries).await
+    }
+
+    pub async fn get_jumplist_via_socket(&self, socket_path: &str, max_entries: Option<u32>) -> Result<Vec<JumpEntry>> {
+        let max_entries = max_entries.unwrap_or(DEFAULT_JUMPLIST_ENTRIES).clamp(1, MAX_JUMPLIST_ENTRIES);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CurrentBuffer {
-    pub file_path: String,
-    pub file_type: Option<String>,
-    pub is_modified: bool,
-    pub line_count: u32,
-    pub content_preview: String,
-    pub surrounding_context: SurroundingContext,
-}
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", jumplist_lua_script(max_entries)),
+        ]).await?;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SurroundingContext {
-    pub lines_before: Vec<String>,
-    pub current_line: String,
-    pub lines_after: Vec<String>,
-    pub function_context: Option<String>,
-    pub class_context: Option<String>,
-}
+        if !output.status.success() {
+            return Err(anyhow!("Jumplist request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Diagnostic {
-    pub file_path: String,
-    pub line: u32,
-    pub column: u32,
-    pub severity: DiagnosticSeverity,
-    pub message: String,
-    pub source: Option<String>,
-    pub code: Option<String>,
-}
+        JumpEntry::parse_list(&String::from_utf8_lossy(&output.stdout))
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum DiagnosticSeverity {
-    Error,
-    Warning,
-    Info,
-    Hint,
-}
+    /// The last `count` search patterns of the Neovim instance under `pid`,
+    /// most recent first.
+    pub async fn get_search_history(&self, pid: u32, count: Option<u32>) -> Result<Vec<SearchHistoryEntry>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.get_search_history_via_socket(&socket_path, count).await
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct BufferInfo {
-    pub file_path: String,
-    pub is_modified: bool,
-    pub is_current: bool,
-    pub file_type: Option<String>,
-}
+    pub async fn get_search_history_via_socket(&self, socket_path: &str, count: Option<u32>) -> Result<Vec<SearchHistoryEntry>> {
+        let count = count.unwrap_or(DEFAULT_SEARCH_HISTORY).clamp(1, MAX_SEARCH_HISTORY);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CursorPosition {
-    pub line: u32,
-    pub column: u32,
-    pub line_content: String,
-}
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", search_history_lua_script(count)),
+        ]).await?;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LspStatus {
-    pub active_clients: Vec<LspClient>,
-    pub diagnostics_count: DiagnosticCounts,
-}
+        if !output.status.success() {
+            return Err(anyhow!("Search history request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LspClient {
-    pub name: String,
-    pub file_types: Vec<String>,
-    pub status: String,
-}
+        SearchHistoryEntry::parse_list(&String::from_utf8_lossy(&output.stdout))
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DiagnosticCounts {
-    pub errors: u32,
-    pub warnings: u32,
-    pub info: u32,
-    pub hints: u32,
-}
+    /// The Tree-sitter nodes at the cursor of the Neovim instance under `pid`,
+    /// from the innermost out to the root.
+    pub async fn get_treesitter_nodes(&self, pid: u32) -> Result<Vec<TsNode>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.get_treesitter_nodes_via_socket(&socket_path).await
+    }
 
-pub struct NeovimContextExtractor {
-    nvim_command: String,
-}
+    pub async fn get_treesitter_nodes_via_socket(&self, socket_path: &str) -> Result<Vec<TsNode>> {
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", TREESITTER_NODES_LUA_SCRIPT),
+        ]).await?;
 
-impl NeovimContextExtractor {
-    pub fn new() -> Self {
-        Self {
-            nvim_command: "nvim".to_string(),
+        if !output.status.success() {
+            return Err(anyhow!("Tree-sitter request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
         }
+
+        TsNode::parse_list(&String::from_utf8_lossy(&output.stdout))
     }
 
-    pub async fn extract_context_from_instance(&self, instance_id: &str, pid: u32) -> Result<NeovimContext> {
-        // Try multiple methods to connect to Neovim
-        let context = if let Ok(ctx) = self.extract_via_nvim_listen(pid).await {
-            ctx
-        } else if let Ok(ctx) = self.extract_via_terminal_scraping(instance_id).await {
-            ctx
+    /// Sends every change made to the buffers of the Neovim at `socket_path`
+    /// to `tx`. `--remote-expr` cannot keep a connection open, so the
+    /// changes are queued inside Neovim by `nvim_buf_attach` callbacks and
+    /// collected every `BUFFER_WATCH_INTERVAL`; none are missed in between.
+    /// The watch ends when Neovim stops answering or `tx`'s receiver is
+    /// dropped, closing the channel from this side.
+    pub async fn watch_buffer_changes(&self, socket_path: &str, tx: mpsc::Sender<BufferChangeEvent>) -> Result<BufferWatch> {
+        let watch_id = uuid::Uuid::new_v4().to_string();
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", buffer_watch_start_lua_script(&watch_id)),
+        ]).await?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let attached = serde_json::from_str::<serde_json::Value>(stdout.trim()).ok()
+            .and_then(|data| data["attached"].as_u64());
+        if !output.status.success() || attached.is_none() {
+            return Err(anyhow!("Buffer watch request failed: {}{}",
+                String::from_utf8_lossy(&output.stderr), stdout));
+        }
+        debug!(watch_id = %watch_id, buffers = attached, "Watching Neovim buffers");
+
+        let extractor = Arc::new(NeovimContextExtractor::new().with_command_timeout(self.command_timeout));
+        let task = tokio::spawn({
+            let extractor = Arc::clone(&extractor);
+            let socket_path = socket_path.to_string();
+            let watch_id = watch_id.clone();
+            async move {
+                let mut interval = tokio::time::interval(BUFFER_WATCH_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    let events = match extractor.drain_buffer_changes(&socket_path, &watch_id).await {
+                        Ok(events) => events,
+                        Err(e) => {
+                            debug!(watch_id = %watch_id, "Buffer watch ended: {}", e);
+                            return;
+                        }
+                    };
+                    for event in events {
+                        if tx.send(event).await.is_err() {
+                            let _ = extractor.stop_buffer_watch(&socket_path, &watch_id).await;
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(BufferWatch { watch_id, socket_path: socket_path.to_string(), extractor, task })
+    }
+
+    async fn drain_buffer_changes(&self, socket_path: &str, watch_id: &str) -> Result<Vec<BufferChangeEvent>> {
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", buffer_watch_drain_lua_script(watch_id)),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Buffer watch request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        BufferChangeEvent::parse_list(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    async fn stop_buffer_watch(&self, socket_path: &str, watch_id: &str) -> Result<()> {
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", buffer_watch_stop_lua_script(watch_id)),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Stopping the buffer watch failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+        Ok(())
+    }
+
+    /// The words flagged by the spell checker in the current buffer of the
+    /// Neovim instance under `pid`, in buffer order.
+    pub async fn get_spell_errors(&self, pid: u32, max_errors: Option<u32>) -> Result<Vec<SpellError>> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.get_spell_errors_via_socket(&socket_path, max_errors).await
+    }
+
+    pub async fn get_spell_errors_via_socket(&self, socket_path: &str, max_errors: Option<u32>) -> Result<Vec<SpellError>> {
+        let max_errors = max_errors.unwrap_or(DEFAULT_SPELL_ERRORS).clamp(1, MAX_SPELL_ERRORS);
+
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", spell_errors_lua_script(max_errors)),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Spell check request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        SpellError::parse_list(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// The session variables of the Neovim instance under `pid`, see
+    /// `SessionVariables`.
+    pub async fn get_session_variables(&self, pid: u32, scope: Option<&str>) -> Result<SessionVariables> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.get_session_variables_via_socket(&socket_path, scope).await
+    }
+
+    /// Reads only `scope`, one of `SESSION_VARIABLE_SCOPES`, when given.
+    pub async fn get_session_variables_via_socket(&self, socket_path: &str, scope: Option<&str>) -> Result<SessionVariables> {
+        let scopes: Vec<&str> = match scope {
+            None => SESSION_VARIABLE_SCOPES.iter().map(|(name, _)| *name).collect(),
+            Some(scope) if SESSION_VARIABLE_SCOPES.iter().any(|(name, _)| *name == scope) => vec![scope],
+            Some(scope) => {
+                let names: Vec<&str> = SESSION_VARIABLE_SCOPES.iter().map(|(name, _)| *name).collect();
+                return Err(AlacrittyMcpError::InvalidParams(format!(
+                    "Unknown scope '{}'; expected one of: {}", scope, names.join(", ")
+                )).into());
+            }
+        };
+
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr",
+            &format!("luaeval('{}')", session_variables_lua_script(&scopes)),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Session variables request failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        SessionVariables::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// `git status` of the Neovim's current working directory, as reported
+    /// over its socket.
+    pub async fn get_git_status(&self, pid: u32) -> Result<GitStatus> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        let working_directory = self.get_working_directory_via_socket(&socket_path).await?
+            .filter(|wd| !wd.is_empty())
+            .ok_or_else(|| anyhow!("Could not get the working directory of Neovim for PID {}", pid))?;
+
+        let output = self.run_command("git", &[
+            "-C", &working_directory, "status", "--porcelain=v1", "--branch",
+        ]).await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("not a git repository") {
+                return Err(anyhow!("{} is not a git repository", working_directory));
+            }
+            return Err(anyhow!("git status failed in {}: {}", working_directory, stderr.trim()));
+        }
+
+        Ok(GitStatus::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Runs an Ex command accepted by `validate_ex_command` in the Neovim
+    /// listening on `socket_path` and captures what it prints. An error from
+    /// Neovim itself, e.g. `E492`, is returned in the result rather than as
+    /// a failure.
+    pub async fn execute_command(&self, socket_path: &str, command: &str) -> Result<ExecResult> {
+        let command = validate_ex_command(command)?;
+        // Doubling quotes is how a single-quoted Vim string escapes them
+        let expr = format!("execute('{}')", command.replace('\'', "''"));
+
+        let output = self.run_command("nvim", &["--server", socket_path, "--remote-expr", &expr]).await?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        let error = if !output.status.success() || !stderr.is_empty() {
+            Some(if stderr.is_empty() { format!("nvim exited with {}", output.status) } else { stderr })
         } else {
-            self.extract_basic_context(pid).await?
+            None
         };
+        // `execute()` starts its output with the newline Vim prints first
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let output = stdout.strip_prefix('\n').unwrap_or(&stdout).trim_end().to_string();
+
+        Ok(ExecResult { output, error })
+    }
+
+    /// Sets a Vim option in the Neovim listening on `socket_path`. Only
+    /// options in `SETTABLE_OPTIONS` are accepted, see `set_option_keys`.
+    pub async fn set_neovim_option(&self, socket_path: &str, option: &str, value: &str) -> Result<()> {
+        let keys = set_option_keys(option, value)?;
+
+        let output = self.run_command("nvim", &["--server", socket_path, "--remote-send", &keys]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Setting {} failed: {}", option,
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        Ok(())
+    }
+
+    /// Starts a test run in the Neovim instance under `pid` and waits for it
+    /// to finish, see `run_tests_via_socket`.
+    pub async fn run_tests(
+        &self,
+        pid: u32,
+        test_file: Option<&str>,
+        test_name: Option<&str>,
+        timeout: Duration,
+    ) -> Result<TestRunResult> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        self.run_tests_via_socket(&socket_path, test_file, test_name, timeout).await
+    }
+
+    /// Starts a test run with `test_start_lua_script`, then checks on it every
+    /// `TEST_POLL_INTERVAL` until the results stop changing or `timeout`
+    /// elapses. A run that times out is returned with what it has so far.
+    pub async fn run_tests_via_socket(
+        &self,
+        socket_path: &str,
+        test_file: Option<&str>,
+        test_name: Option<&str>,
+        timeout: Duration,
+    ) -> Result<TestRunResult> {
+        let started = Instant::now();
+        let script = test_start_lua_script(test_file, test_name);
+        // Test names may contain quotes, which a single-quoted Vim string doubles
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr", &format!("luaeval('{}')", script.replace('\'', "''")),
+        ]).await?;
+
+        if !output.status.success() {
+            return Err(anyhow!("Starting tests failed: {}",
+                String::from_utf8_lossy(&output.stderr)));
+        }
+
+        let started_with: serde_json::Value = serde_json::from_slice(&output.stdout)
+            .map_err(|e| anyhow!("Unexpected test start output: {}", e))?;
+        let runner = started_with["runner"].as_str()
+            .ok_or_else(|| anyhow!("Neither Neotest nor vim-dispatch is installed in this Neovim"))?
+            .to_string();
+
+        let quickfix_tick = started_with["quickfix_tick"].as_u64().unwrap_or(0);
+        let status_script = format!("luaeval('{}')", test_status_lua_script(&runner, quickfix_tick));
+        let mut last_output = None;
+        let mut stable_polls = 0;
+        loop {
+            tokio::time::sleep(TEST_POLL_INTERVAL).await;
+
+            let output = self.run_command("nvim", &["--server", socket_path, "--remote-expr", &status_script]).await?;
+            if !output.status.success() {
+                return Err(anyhow!("Checking on tests failed: {}",
+                    String::from_utf8_lossy(&output.stderr)));
+            }
+            let output = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let status = TestStatus::parse(&runner, &output)?;
+
+            stable_polls = if status.running == 0 && last_output.as_ref() == Some(&output) { stable_polls + 1 } else { 1 };
+            last_output = Some(output);
+
+            let timed_out = started.elapsed() >= timeout;
+            if (status.running == 0 && stable_polls >= TEST_STABLE_POLLS) || timed_out {
+                return Ok(TestRunResult {
+                    duration_ms: started.elapsed().as_millis() as u64,
+                    timed_out: timed_out && stable_polls < TEST_STABLE_POLLS,
+                    ..status.result
+                });
+            }
+        }
+    }
+
+    /// Collects the diagnostics of every buffer in the Neovim instance under
+    /// `pid`, grouped by file.
+    pub async fn get_workspace_diagnostics(
+        &self,
+        pid: u32,
+        min_severity: Option<DiagnosticSeverity>,
+    ) -> Result<WorkspaceDiagnostics> {
+        let socket_path = self.find_neovim_socket(pid).await?;
+        let diagnostics = self.get_diagnostics_via_socket(&socket_path).await?;
+        Ok(WorkspaceDiagnostics::new(diagnostics, min_severity))
+    }
+
+    /// Asks each tmux server under `$TMUX_TMPDIR/tmux-<uid>/` (`/tmp` by
+    /// default) for its active pane and returns the first answer.
+    pub async fn extract_tmux_context(&self) -> Result<TmuxContext> {
+        let tmpdir = std::env::var("TMUX_TMPDIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or_else(|| "/tmp".to_string());
+        let socket_dir = std::path::Path::new(&tmpdir).join(format!("tmux-{}", self.get_user_id().await?));
+
+        let mut sockets: Vec<_> = std::fs::read_dir(&socket_dir)
+            .map_err(|e| anyhow!("No tmux sockets in {}: {}", socket_dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        sockets.sort();
+
+        for socket in sockets {
+            let socket_path = socket.to_string_lossy().to_string();
+            let args = ["-S", &socket_path, "display-message", "-p", TMUX_PANE_FORMAT];
+            let output = match self.run_command("tmux", &args).await {
+                Ok(output) if output.status.success() => output,
+                _ => continue,
+            };
+
+            if let Some(ctx) = TmuxContext::parse(&socket_path, &String::from_utf8_lossy(&output.stdout)) {
+                return Ok(ctx);
+            }
+        }
 
-        Ok(context)
+        Err(anyhow!("No tmux server answered in {}", socket_dir.display()))
     }
 
-    async fn extract_via_nvim_listen(&self, pid: u32) -> Result<NeovimContext> {
+    async fn extract_via_nvim_listen(&self, pid: u32, params: &NeovimContextParams) -> Result<NeovimContext> {
         // Try to find Neovim socket
         let socket_path = self.find_neovim_socket(pid).await?;
+        let context_lines = params.context_lines.unwrap_or(DEFAULT_CONTEXT_LINES);
         
         // Use nvim --server to communicate with the instance
-        let current_buffer = self.get_current_buffer_via_socket(&socket_path).await?;
-        let diagnostics = self.get_diagnostics_via_socket(&socket_path).await?;
-        let open_buffers = self.get_open_buffers_via_socket(&socket_path).await?;
+        let current_buffer = self.get_current_buffer_via_socket(&socket_path, context_lines).await?;
+
+        // Every query is its own `nvim --remote-expr` process, ten per context
+        // in all, so each section skipped saves one client startup plus a
+        // round trip: about a tenth of the call each, more in workspaces with
+        // thousands of diagnostics to encode
+        let diagnostics = if params.include_diagnostics.unwrap_or(true) {
+            self.get_diagnostics_via_socket(&socket_path).await?
+        } else {
+            Vec::new()
+        };
+        let open_buffers = if params.include_buffers.unwrap_or(true) {
+            self.get_open_buffers_via_socket(&socket_path).await?
+        } else {
+            Vec::new()
+        };
         let cursor_position = self.get_cursor_position_via_socket(&socket_path).await?;
         let vim_mode = self.get_vim_mode_via_socket(&socket_path).await?;
         let lsp_status = self.get_lsp_status_via_socket(&socket_path).await?;
@@ -150,13 +2670,108 @@ impl NeovimContextExtractor {
         })
     }
 
-    async fn extract_via_terminal_scraping(&self, _instance_id: &str) -> Result<NeovimContext> {
-        // This would use the existing screenshot functionality to parse terminal content
-        // and extract Neovim state from the visual output
-        Err(anyhow!("Terminal scraping not yet implemented"))
+    /// Builds a partial context from what is visible on screen: the status
+    /// line gives the file and cursor position, the mode message gives the
+    /// mode. Anything that cannot be seen is left empty.
+    pub fn extract_via_terminal_scraping(&self, pid: u32, terminal_content: &str) -> Result<NeovimContext> {
+        if !self.detect_neovim_in_terminal(terminal_content) {
+            return Err(anyhow!("Terminal content does not look like Neovim"));
+        }
+
+        let lines: Vec<&str> = terminal_content.lines().collect();
+        let vim_mode = lines.iter().rev().find_map(|line| parse_mode_message(line));
+
+        // The status line sits at the bottom, above the command line
+        let status = lines.iter().enumerate().rev()
+            .find_map(|(i, line)| StatusLine::parse(line).map(|status| (i, status)));
+
+        let (status_index, status) = match status {
+            Some(found) => found,
+            None => {
+                return Ok(NeovimContext {
+                    vim_mode,
+                    ..self.empty_context(pid)
+                });
+            }
+        };
+
+        // Buffer text is everything above the status line except `~` filler rows
+        let visible: Vec<&str> = lines[..status_index]
+            .iter()
+            .copied()
+            .filter(|line| line.trim() != "~")
+            .collect();
+
+        // With `number` set each row starts with its line number, which lets us
+        // find the cursor line and its neighbours
+        let numbered: Vec<(u32, &str)> = visible.iter()
+            .filter_map(|line| {
+                let trimmed = line.trim_start();
+                let digits = trimmed.len() - trimmed.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let number = trimmed[..digits].parse().ok()?;
+                let rest = &trimmed[digits..];
+                if !rest.is_empty() && !rest.starts_with(' ') {
+                    return None;
+                }
+                Some((number, rest.strip_prefix(' ').unwrap_or(rest)))
+            })
+            .collect();
+        let text_at = |line: u32| numbered.iter().find(|(n, _)| *n == line).map(|(_, text)| text.to_string());
+        let current_line = text_at(status.line).unwrap_or_default();
+
+        let current_buffer = CurrentBuffer {
+            file_path: status.file_path.clone(),
+            file_type: status.file_type.clone(),
+            is_modified: status.is_modified,
+            line_count: 0, // Not visible on screen
+            content_preview: visible.join("\n"),
+            surrounding_context: SurroundingContext {
+                lines_before: numbered.iter()
+                    .filter(|(n, _)| *n < status.line)
+                    .map(|(_, text)| text.to_string())
+                    .collect(),
+                current_line: current_line.clone(),
+                lines_after: numbered.iter()
+                    .filter(|(n, _)| *n > status.line)
+                    .map(|(_, text)| text.to_string())
+                    .collect(),
+                function_context: None,
+                class_context: None,
+            },
+        };
+
+        Ok(NeovimContext {
+            current_buffer: Some(current_buffer),
+            cursor_position: Some(CursorPosition {
+                line: status.line,
+                column: status.column,
+                line_content: current_line,
+            }),
+            vim_mode,
+            ..self.empty_context(pid)
+        })
+    }
+
+    fn empty_context(&self, pid: u32) -> NeovimContext {
+        NeovimContext {
+            instance_info: NeovimInstanceInfo {
+                pid,
+                socket_path: None,
+                version: None,
+                config_path: None,
+            },
+            current_buffer: None,
+            diagnostics: Vec::new(),
+            open_buffers: Vec::new(),
+            cursor_position: None,
+            vim_mode: None,
+            working_directory: None,
+            lsp_status: None,
+        }
     }
 
-    async fn extract_basic_context(&self, pid: u32) -> Result<NeovimContext> {
+    /// Fallback when nothing but the process itself can be inspected.
+    pub async fn extract_basic_context(&self, pid: u32) -> Result<NeovimContext> {
         // Fallback: basic process information
         Ok(NeovimContext {
             instance_info: NeovimInstanceInfo {
@@ -175,12 +2790,39 @@ impl NeovimContextExtractor {
         })
     }
 
-    async fn find_neovim_socket(&self, pid: u32) -> Result<String> {
+    /// Neovim's RPC socket for the process `pid`, cached for `SOCKET_CACHE_TTL`.
+    pub async fn find_neovim_socket(&self, pid: u32) -> Result<String> {
+        if let Ok(mut cache) = self.socket_cache.lock() {
+            match cache.get(&pid) {
+                Some((socket, found_at))
+                    if found_at.elapsed() < SOCKET_CACHE_TTL && std::path::Path::new(socket).exists() =>
+                {
+                    return Ok(socket.clone());
+                }
+                Some(_) => {
+                    cache.remove(&pid);
+                }
+                None => {}
+            }
+        }
+
+        let socket = self.discover_neovim_socket(pid).await?;
+        if let Ok(mut cache) = self.socket_cache.lock() {
+            cache.insert(pid, (socket.clone(), Instant::now()));
+        }
+        Ok(socket)
+    }
+
+    async fn discover_neovim_socket(&self, pid: u32) -> Result<String> {
+        if let Some(socket) = socket_from_nvim_env(pid) {
+            return Ok(socket);
+        }
+
         // Check common socket locations
         let possible_sockets = vec![
             format!("/tmp/nvim.{}.0", pid),
             format!("/tmp/nvim{}/0", pid),
-            format!("/run/user/{}/nvim.{}.0", self.get_user_id()?, pid),
+            format!("/run/user/{}/nvim.{}.0", self.get_user_id().await?, pid),
         ];
 
         for socket in possible_sockets {
@@ -190,9 +2832,7 @@ impl NeovimContextExtractor {
         }
 
         // Try to find via lsof
-        let output = Command::new("lsof")
-            .args(&["-p", &pid.to_string(), "-a", "-U"])
-            .output()?;
+        let output = self.run_command("lsof", &["-p", &pid.to_string(), "-a", "-U"]).await?;
 
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
@@ -205,56 +2845,23 @@ impl NeovimContextExtractor {
             }
         }
 
-        Err(anyhow!("Could not find Neovim socket for PID {}", pid))
+        Err(AlacrittyMcpError::NeovimSocketNotFound(pid).into())
     }
 
-    async fn get_current_buffer_via_socket(&self, socket_path: &str) -> Result<Option<CurrentBuffer>> {
-        let lua_script = r#"
-            local buf = vim.api.nvim_get_current_buf()
-            local file_path = vim.api.nvim_buf_get_name(buf)
-            local file_type = vim.bo.filetype
-            local is_modified = vim.bo.modified
-            local line_count = vim.api.nvim_buf_line_count(buf)
-            local cursor = vim.api.nvim_win_get_cursor(0)
-            local current_line_nr = cursor[1]
-            
-            -- Get surrounding context
-            local start_line = math.max(1, current_line_nr - 5)
-            local end_line = math.min(line_count, current_line_nr + 5)
-            local lines = vim.api.nvim_buf_get_lines(buf, start_line - 1, end_line, false)
-            
-            local context = {
-                file_path = file_path,
-                file_type = file_type,
-                is_modified = is_modified,
-                line_count = line_count,
-                current_line_nr = current_line_nr,
-                lines_before = {},
-                current_line = "",
-                lines_after = {},
-            }
-            
-            for i, line in ipairs(lines) do
-                local line_nr = start_line + i - 1
-                if line_nr < current_line_nr then
-                    table.insert(context.lines_before, line)
-                elseif line_nr == current_line_nr then
-                    context.current_line = line
-                else
-                    table.insert(context.lines_after, line)
-                end
-            end
-            
-            print(vim.json.encode(context))
-        "#;
+    async fn get_current_buffer_via_socket(&self, socket_path: &str, context_lines: u32) -> Result<Option<CurrentBuffer>> {
+        let lua_script = current_buffer_lua_script(context_lines);
 
-        let output = Command::new("nvim")
-            .args(&["--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script)])
-            .output()?;
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
 
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
             if let Ok(data) = serde_json::from_str::<serde_json::Value>(&output_str) {
+                let file_type = data["file_type"].as_str().filter(|ft| !ft.is_empty());
+                let line = data["current_line_nr"].as_u64().unwrap_or(0) as u32;
+                let function_context = self.extract_function_context(socket_path, file_type, line).await;
+
                 let current_buffer = CurrentBuffer {
                     file_path: data["file_path"].as_str().unwrap_or("").to_string(),
                     file_type: data["file_type"].as_str().map(|s| s.to_string()),
@@ -269,7 +2876,7 @@ impl NeovimContextExtractor {
                         lines_after: data["lines_after"].as_array()
                             .map(|arr| arr.iter().map(|v| v.as_str().unwrap_or("").to_string()).collect())
                             .unwrap_or_default(),
-                        function_context: None, // TODO: Parse function context
+                        function_context,
                         class_context: None,    // TODO: Parse class context
                     },
                 };
@@ -280,6 +2887,30 @@ impl NeovimContextExtractor {
         Ok(None)
     }
 
+    /// Reads the whole current buffer and finds the function around `line`
+    /// with Tree-sitter, or by counting braces for languages without a grammar.
+    pub async fn extract_function_context(
+        &self,
+        socket_path: &str,
+        file_type: Option<&str>,
+        line: u32,
+    ) -> Option<FunctionContext> {
+        let lua_script = r#"
+            local lines = vim.api.nvim_buf_get_lines(0, 0, -1, false)
+            print(vim.json.encode(table.concat(lines, "\n")))
+        "#;
+
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await.ok()?;
+        if !output.status.success() {
+            return None;
+        }
+
+        let source: String = serde_json::from_str(&String::from_utf8_lossy(&output.stdout)).ok()?;
+        find_function_context(&source, file_type, line)
+    }
+
     async fn get_diagnostics_via_socket(&self, socket_path: &str) -> Result<Vec<Diagnostic>> {
         let lua_script = r#"
             local diagnostics = vim.diagnostic.get()
@@ -298,31 +2929,14 @@ impl NeovimContextExtractor {
             print(vim.json.encode(result))
         "#;
 
-        let output = Command::new("nvim")
-            .args(&["--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script)])
-            .output()?;
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
 
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
             if let Ok(data) = serde_json::from_str::<Vec<serde_json::Value>>(&output_str) {
-                let diagnostics = data.into_iter().map(|d| {
-                    let severity = match d["severity"].as_u64().unwrap_or(1) {
-                        1 => DiagnosticSeverity::Error,
-                        2 => DiagnosticSeverity::Warning,
-                        3 => DiagnosticSeverity::Info,
-                        _ => DiagnosticSeverity::Hint,
-                    };
-
-                    Diagnostic {
-                        file_path: d["file_path"].as_str().unwrap_or("").to_string(),
-                        line: d["line"].as_u64().unwrap_or(0) as u32,
-                        column: d["column"].as_u64().unwrap_or(0) as u32,
-                        severity,
-                        message: d["message"].as_str().unwrap_or("").to_string(),
-                        source: d["source"].as_str().map(|s| s.to_string()),
-                        code: d["code"].as_str().map(|s| s.to_string()),
-                    }
-                }).collect();
+                let diagnostics = data.iter().map(Diagnostic::from_json).collect();
 
                 return Ok(diagnostics);
             }
@@ -351,9 +2965,9 @@ impl NeovimContextExtractor {
             print(vim.json.encode(buffers))
         "#;
 
-        let output = Command::new("nvim")
-            .args(&["--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script)])
-            .output()?;
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
 
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
@@ -384,9 +2998,9 @@ impl NeovimContextExtractor {
             print(vim.json.encode(result))
         "#;
 
-        let output = Command::new("nvim")
-            .args(&["--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script)])
-            .output()?;
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
 
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
@@ -403,9 +3017,7 @@ impl NeovimContextExtractor {
     }
 
     async fn get_vim_mode_via_socket(&self, socket_path: &str) -> Result<Option<String>> {
-        let output = Command::new("nvim")
-            .args(&["--server", socket_path, "--remote-expr", "mode()"])
-            .output()?;
+        let output = self.run_command("nvim", &["--server", socket_path, "--remote-expr", "mode()"]).await?;
 
         if output.status.success() {
             let mode = String::from_utf8_lossy(&output.stdout).trim().to_string();
@@ -447,9 +3059,9 @@ impl NeovimContextExtractor {
             print(vim.json.encode(result))
         "#;
 
-        let output = Command::new("nvim")
-            .args(&["--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script)])
-            .output()?;
+        let output = self.run_command("nvim", &[
+            "--server", socket_path, "--remote-expr", &format!("luaeval('{}')", lua_script),
+        ]).await?;
 
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
@@ -484,9 +3096,7 @@ impl NeovimContextExtractor {
     }
 
     async fn get_working_directory_via_socket(&self, socket_path: &str) -> Result<Option<String>> {
-        let output = Command::new("nvim")
-            .args(&["--server", socket_path, "--remote-expr", "getcwd()"])
-            .output()?;
+        let output = self.run_command("nvim", &["--server", socket_path, "--remote-expr", "getcwd()"]).await?;
 
         if output.status.success() {
             let wd = String::from_utf8_lossy(&output.stdout).trim().to_string();
@@ -497,9 +3107,7 @@ impl NeovimContextExtractor {
     }
 
     async fn get_neovim_version(&self) -> Result<String> {
-        let output = Command::new(&self.nvim_command)
-            .args(&["--version"])
-            .output()?;
+        let output = self.run_command(&self.nvim_command, &["--version"]).await?;
 
         if output.status.success() {
             let version_output = String::from_utf8_lossy(&output.stdout);
@@ -512,9 +3120,7 @@ impl NeovimContextExtractor {
     }
 
     async fn get_config_path(&self) -> Result<String> {
-        let output = Command::new(&self.nvim_command)
-            .args(&["--headless", "-c", "echo stdpath('config')", "-c", "quit"])
-            .output()?;
+        let output = self.run_command(&self.nvim_command, &["--headless", "-c", "echo stdpath('config')", "-c", "quit"]).await?;
 
         if output.status.success() {
             let config_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
@@ -530,9 +3136,7 @@ impl NeovimContextExtractor {
             Ok(path) => Ok(path.to_string_lossy().to_string()),
             Err(_) => {
                 // Fallback: use lsof
-                let output = Command::new("lsof")
-                    .args(&["-p", &pid.to_string(), "-a", "-d", "cwd"])
-                    .output()?;
+                let output = self.run_command("lsof", &["-p", &pid.to_string(), "-a", "-d", "cwd"]).await?;
 
                 if output.status.success() {
                     let output_str = String::from_utf8_lossy(&output.stdout);
@@ -550,10 +3154,8 @@ impl NeovimContextExtractor {
         }
     }
 
-    fn get_user_id(&self) -> Result<u32> {
-        let output = Command::new("id")
-            .args(&["-u"])
-            .output()?;
+    async fn get_user_id(&self) -> Result<u32> {
+        let output = self.run_command("id", &["-u"]).await?;
 
         if output.status.success() {
             let uid_string = String::from_utf8_lossy(&output.stdout);
@@ -564,6 +3166,18 @@ impl NeovimContextExtractor {
         Err(anyhow!("Could not get user ID"))
     }
 
+    /// Tells Neovim and Helix apart. Helix's status line is checked first,
+    /// since Neovim's indicators such as `:q` can show up on any screen.
+    pub fn detect_editor_in_terminal(&self, terminal_content: &str) -> EditorType {
+        if HelixContextExtractor::new().extract_from_screen(terminal_content).is_some() {
+            EditorType::Helix
+        } else if self.detect_neovim_in_terminal(terminal_content) {
+            EditorType::Neovim
+        } else {
+            EditorType::Unknown
+        }
+    }
+
     pub fn detect_neovim_in_terminal(&self, terminal_content: &str) -> bool {
         // Look for common Neovim indicators in terminal content
         let nvim_indicators = [
@@ -580,6 +3194,19 @@ impl NeovimContextExtractor {
 
         nvim_indicators.iter().any(|indicator| terminal_content.contains(indicator))
     }
+
+    /// Looks for tmux's default status bar, e.g. `[main] 0:bash* 1:vim-`,
+    /// on the last non-empty line.
+    pub fn detect_tmux_in_terminal(&self, terminal_content: &str) -> bool {
+        static TMUX_STATUS: OnceLock<Regex> = OnceLock::new();
+        let re = TMUX_STATUS.get_or_init(|| Regex::new(r"^\[[^\]]+\] \d+:\S+").unwrap());
+
+        terminal_content
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .is_some_and(|line| re.is_match(line))
+    }
 }
 
 impl Default for NeovimContextExtractor {
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// Display server the terminals are running under. This decides which
+/// external tools are used for key injection, clipboard access and screenshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Platform {
+    X11,
+    Wayland,
+}
+
+impl Platform {
+    /// Detects the platform from the environment. A non-empty `$WAYLAND_DISPLAY`
+    /// means Wayland, anything else falls back to X11.
+    pub fn detect() -> Self {
+        match std::env::var("WAYLAND_DISPLAY") {
+            Ok(display) if !display.is_empty() => Platform::Wayland,
+            _ => Platform::X11,
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+/// Sends `notifications/progress` for one request that carried
+/// `_meta.progressToken`. The notifications share the output channel with
+/// every other server-initiated message, so they end up on stdout in order.
+#[derive(Debug, Clone)]
+pub struct ProgressNotifier {
+    token: Value,
+    tx: mpsc::UnboundedSender<String>,
+}
+
+impl ProgressNotifier {
+    pub fn new(token: Value, tx: mpsc::UnboundedSender<String>) -> Self {
+        Self { token, tx }
+    }
+
+    pub fn token(&self) -> &Value {
+        &self.token
+    }
+
+    /// Emits one progress notification. Fails silently once the output side
+    /// has gone away, as there is nobody left to tell.
+    pub fn notify(&self, progress: u64, total: Option<u64>, message: Option<&str>) {
+        let mut params = json!({
+            "progressToken": self.token,
+            "progress": progress,
+        });
+        if let Some(total) = total {
+            params["total"] = json!(total);
+        }
+        if let Some(message) = message {
+            params["message"] = json!(message);
+        }
+
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": params
+        });
+        let _ = self.tx.send(notification.to_string());
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::error::AlacrittyMcpError;
+
+/// A reusable prompt template. `{{name}}` placeholders in `template` are
+/// replaced with the argument of that name when the prompt is requested.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prompt {
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub arguments: Vec<PromptArgument>,
+    #[serde(skip_serializing)]
+    pub template: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptArgument {
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: PromptContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptContent {
+    #[serde(rename = "type")]
+    pub content_type: String,
+    pub text: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PromptsFile {
+    #[serde(default)]
+    prompts: Vec<Prompt>,
+}
+
+/// The built-in prompts plus any the user defines in `prompts.toml`.
+#[derive(Debug, Clone)]
+pub struct PromptLibrary {
+    prompts: Vec<Prompt>,
+}
+
+impl PromptLibrary {
+    pub fn builtin() -> Self {
+        let argument = |name: &str, description: &str| PromptArgument {
+            name: name.to_string(),
+            description: description.to_string(),
+            required: true,
+        };
+        let instance_id = argument("instance_id", "ID of the Alacritty instance to use");
+
+        Self {
+            prompts: vec![
+                Prompt {
+                    name: "run_command_and_wait".to_string(),
+                    description: "Run a command in a terminal and wait for it to finish".to_string(),
+                    arguments: vec![instance_id.clone(), argument("command", "Command line to run")],
+                    template: concat!(
+                        "In Alacritty instance {{instance_id}}, run `{{command}}`: type it with send_text, ",
+                        "press Return with send_keys, then use wait_for_output until the shell prompt comes back. ",
+                        "Report whether the command succeeded and summarise its output.",
+                    ).to_string(),
+                },
+                Prompt {
+                    name: "open_file_in_neovim".to_string(),
+                    description: "Open a file in the Neovim running in a terminal".to_string(),
+                    arguments: vec![instance_id.clone(), argument("file", "Path of the file to open")],
+                    template: concat!(
+                        "In Alacritty instance {{instance_id}}, open {{file}} in Neovim: press Escape with send_keys, ",
+                        "type `:edit {{file}}` with send_text and press Return. ",
+                        "Then call get_neovim_context to confirm the file is the current buffer.",
+                    ).to_string(),
+                },
+                Prompt {
+                    name: "build_project".to_string(),
+                    description: "Build a project in a terminal and report any errors".to_string(),
+                    arguments: vec![instance_id, argument("command", "Build command, e.g. `cargo build`")],
+                    template: concat!(
+                        "In Alacritty instance {{instance_id}}, build the project with `{{command}}`. ",
+                        "Wait for the build to finish with wait_for_output, then read the result with get_scrollback. ",
+                        "List every error and warning with its file and line.",
+                    ).to_string(),
+                },
+            ],
+        }
+    }
+
+    /// Built-in prompts merged with the user's file at `default_path()`. A
+    /// user prompt with the same name as a built-in one replaces it.
+    pub fn load() -> Self {
+        let mut library = Self::builtin();
+        if let Some(path) = Self::default_path() {
+            if let Err(e) = library.load_file(&path) {
+                warn!("Ignoring prompts file {}: {}", path.display(), e);
+            }
+        }
+        library
+    }
+
+    /// `$XDG_CONFIG_HOME/alacritty-mcp/prompts.toml`, falling back to
+    /// `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("alacritty-mcp").join("prompts.toml"))
+    }
+
+    /// Adds the prompts defined in a TOML file. A missing file is not an error.
+    pub fn load_file(&mut self, path: &Path) -> Result<()> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let file: PromptsFile = toml::from_str(&contents)?;
+        for prompt in file.prompts {
+            self.prompts.retain(|existing| existing.name != prompt.name);
+            self.prompts.push(prompt);
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> &[Prompt] {
+        &self.prompts
+    }
+
+    /// Fills in the named prompt. Unknown prompts and missing required
+    /// arguments are reported as `AlacrittyMcpError::InvalidParams`.
+    pub fn get(&self, name: &str, arguments: &HashMap<String, String>) -> Result<(&Prompt, Vec<PromptMessage>)> {
+        let prompt = self.prompts.iter()
+            .find(|prompt| prompt.name == name)
+            .ok_or_else(|| anyhow!(AlacrittyMcpError::InvalidParams(format!("Unknown prompt: {}", name))))?;
+
+        let mut text = prompt.template.clone();
+        for argument in &prompt.arguments {
+            let value = match arguments.get(&argument.name) {
+                Some(value) => value.as_str(),
+                None if argument.required => {
+                    return Err(AlacrittyMcpError::InvalidParams(format!(
+                        "Prompt {} requires argument '{}'", name, argument.name
+                    )).into());
+                }
+                None => "",
+            };
+            text = text.replace(&format!("{{{{{}}}}}", argument.name), value);
+        }
+
+        let message = PromptMessage {
+            role: "user".to_string(),
+            content: PromptContent {
+                content_type: "text".to_string(),
+                text,
+            },
+        };
+        Ok((prompt, vec![message]))
+    }
+}
+
+impl Default for PromptLibrary {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
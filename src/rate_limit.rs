@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Environment variable holding the allowed requests per second per method.
+pub const RATE_LIMIT_ENV: &str = "ALACRITTY_MCP_RATE_LIMIT";
+
+/// Token bucket per key. Each bucket holds up to `rate` tokens (at least one)
+/// and refills at `rate` tokens per second, so short bursts are allowed but
+/// the sustained rate is capped.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    buckets: HashMap<String, Bucket>,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    /// `rate` is in requests per second and must be positive.
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            capacity: rate.max(1.0),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Reads `ALACRITTY_MCP_RATE_LIMIT`. Unset means no limit; values that
+    /// are not a positive number are ignored with a warning.
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(RATE_LIMIT_ENV).ok()?;
+        match value.trim().parse::<f64>() {
+            Ok(rate) if rate.is_finite() && rate > 0.0 => Some(Self::new(rate)),
+            _ => {
+                warn!("Ignoring invalid {}={:?}, expected requests per second", RATE_LIMIT_ENV, value);
+                None
+            }
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Takes a token for `key`, or returns how long until one is available.
+    pub fn check(&mut self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    /// `check` with an explicit clock, for callers that need deterministic timing.
+    pub fn check_at(&mut self, key: &str, now: Instant) -> Result<(), Duration> {
+        let bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::Path;
+use std::process::Output;
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use regex::Regex;
+
+use crate::types::SearchResult;
+
+/// Lines of context kept on each side of a match.
+pub const SEARCH_CONTEXT_LINES: u32 = 2;
+
+/// How long `rg` or `grep` may run before the search is abandoned.
+pub const SEARCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One line `rg` or `grep` printed: a match, with its first match's 1-based
+/// column and text, or a line of context around one.
+struct OutputLine {
+    file_path: String,
+    line: u32,
+    text: String,
+    submatch: Option<(u32, String)>,
+}
+
+/// Searches `root`, or `path` beneath it, for `pattern` with ripgrep, or
+/// with `grep -r` where `rg` is not installed. `regex` is `pattern` compiled,
+/// used to find the column of each match `grep` reports. File paths in the
+/// results are relative to `root`.
+pub async fn search_directory(
+    root: &Path,
+    pattern: &str,
+    regex: &Regex,
+    path: Option<&str>,
+    file_glob: Option<&str>,
+    max_results: usize,
+) -> Result<Vec<SearchResult>> {
+    let context = SEARCH_CONTEXT_LINES.to_string();
+    // `--max-count` is per file, so it only bounds the output
+    let max_count = max_results.to_string();
+
+    let mut rg_args = vec!["--json", "--context", &context, "--max-count", &max_count];
+    if let Some(glob) = file_glob {
+        rg_args.extend(["--glob", glob]);
+    }
+    rg_args.extend(["--regexp", pattern, "--"]);
+    rg_args.extend(path);
+
+    match run_search_command(root, "rg", &rg_args).await {
+        Ok(output) => return Ok(parse_rg_json(&output, SEARCH_CONTEXT_LINES, max_results)),
+        Err(e) if !is_not_found(&e) => return Err(e),
+        Err(_) => {}
+    }
+
+    let context = format!("--context={}", SEARCH_CONTEXT_LINES);
+    let max_count = format!("--max-count={}", max_results);
+    let include = file_glob.map(|glob| format!("--include={}", glob));
+    let mut grep_args = vec!["-rnIEZ", &context, &max_count];
+    grep_args.extend(include.as_deref());
+    grep_args.extend(["-e", pattern, "--", path.unwrap_or(".")]);
+
+    let output = run_search_command(root, "grep", &grep_args).await
+        .map_err(|e| if is_not_found(&e) { anyhow!("Neither rg nor grep is installed") } else { e })?;
+    Ok(parse_grep_output(&output, regex, SEARCH_CONTEXT_LINES, max_results))
+}
+
+/// Runs a search tool in `root` and returns its stdout. Exit code 1 only
+/// means nothing matched; other failures are errors unless something was
+/// found regardless, e.g. next to an unreadable directory.
+async fn run_search_command(root: &Path, program: &str, args: &[&str]) -> Result<String> {
+    let child = tokio::process::Command::new(program)
+        .args(args)
+        .current_dir(root)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+
+    let output: Output = tokio::time::timeout(SEARCH_TIMEOUT, child).await
+        .map_err(|_| anyhow!("{} did not finish within {}s", program, SEARCH_TIMEOUT.as_secs()))??;
+
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    match output.status.code() {
+        Some(0 | 1) => Ok(stdout),
+        _ if !stdout.is_empty() => Ok(stdout),
+        _ => Err(anyhow!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim())),
+    }
+}
+
+fn is_not_found(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// Turns `rg --json` output into results. `begin`, `end` and `summary`
+/// messages are skipped, as are lines that are not valid UTF-8, which rg
+/// sends base64-encoded.
+pub fn parse_rg_json(output: &str, context_lines: u32, max_results: usize) -> Vec<SearchResult> {
+    let lines = output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|message| {
+            let kind = message["type"].as_str()?;
+            if kind != "match" && kind != "context" {
+                return None;
+            }
+            let data = &message["data"];
+            let text = data["lines"]["text"].as_str()?;
+            let submatch = data["submatches"].get(0).and_then(|submatch| {
+                let start = submatch["start"].as_u64()? as usize;
+                let column = text.get(..start)?.chars().count() as u32 + 1;
+                Some((column, submatch["match"]["text"].as_str()?.to_string()))
+            });
+            Some(OutputLine {
+                file_path: data["path"]["text"].as_str()?.to_string(),
+                line: data["line_number"].as_u64()? as u32,
+                text: trim_line_ending(text),
+                submatch: submatch.filter(|_| kind == "match"),
+            })
+        })
+        .collect();
+
+    collect_results(lines, context_lines, max_results)
+}
+
+/// Turns the output of `grep -rnZ` into results. `-Z` ends the file name
+/// with a null byte, after which come the line number and `:` for a match
+/// or `-` for context. A match `regex` cannot find again, e.g. because POSIX
+/// and Rust regexes differ, is reported at column 1 with the whole line.
+pub fn parse_grep_output(output: &str, regex: &Regex, context_lines: u32, max_results: usize) -> Vec<SearchResult> {
+    let lines = output
+        .lines()
+        .filter_map(|line| {
+            let (file_path, rest) = line.split_once('\0')?;
+            let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+            let line_number = rest[..digits].parse().ok()?;
+            let text = trim_line_ending(&rest[digits + 1..]);
+            let submatch = rest[digits..].starts_with(':').then(|| match regex.find(&text) {
+                Some(found) => (text[..found.start()].chars().count() as u32 + 1, found.as_str().to_string()),
+                None => (1, text.clone()),
+            });
+            Some(OutputLine {
+                file_path: file_path.strip_prefix("./").unwrap_or(file_path).to_string(),
+                line: line_number,
+                text,
+                submatch,
+            })
+        })
+        .collect();
+
+    collect_results(lines, context_lines, max_results)
+}
+
+/// One result per matching line, with up to `context_lines` lines on each
+/// side taken from everything printed for the same file, other matches
+/// included.
+fn collect_results(lines: Vec<OutputLine>, context_lines: u32, max_results: usize) -> Vec<SearchResult> {
+    let context = |file_path: &str, range: std::ops::RangeInclusive<u32>| -> Vec<String> {
+        lines
+            .iter()
+            .filter(|line| line.file_path == file_path && range.contains(&line.line))
+            .map(|line| line.text.clone())
+            .collect()
+    };
+
+    lines
+        .iter()
+        .filter_map(|line| {
+            let (column, match_text) = line.submatch.clone()?;
+            Some(SearchResult {
+                file_path: line.file_path.clone(),
+                line: line.line,
+                column,
+                match_text,
+                // Line numbers start at 1, so nothing matches line 0
+                context_before: context(&line.file_path, line.line.saturating_sub(context_lines)..=line.line.saturating_sub(1)),
+                context_after: context(&line.file_path, line.line + 1..=line.line + context_lines),
+            })
+        })
+        .take(max_results)
+        .collect()
+}
+
+fn trim_line_ending(text: &str) -> String {
+    text.trim_end_matches(['\n', '\r']).to_string()
+}
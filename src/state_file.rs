@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+
+use crate::types::AlacrittyInstance;
+
+/// On-disk copy of the instance registry, so terminals spawned by a previous
+/// server process can still be addressed after a restart.
+#[derive(Debug, Clone)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `$XDG_RUNTIME_DIR/alacritty-mcp/state.json`, or `None` when the runtime
+    /// directory is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| PathBuf::from(dir).join("alacritty-mcp").join("state.json"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the stored instances, dropping any whose process has exited or
+    /// whose PID now belongs to something other than `binary`, as after a
+    /// reboot or once PIDs wrap around. A missing file is treated as an
+    /// empty registry.
+    pub fn load(&self, binary: &str) -> Result<HashMap<String, AlacrittyInstance>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let instances: HashMap<String, AlacrittyInstance> = serde_json::from_str(&contents)?;
+        Ok(instances
+            .into_iter()
+            .filter(|(_, instance)| {
+                is_pid_alive(instance.pid) && is_same_process(instance.pid, binary, instance.process_started_at)
+            })
+            .collect())
+    }
+
+    pub fn save(&self, instances: &HashMap<String, AlacrittyInstance>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Write to a sibling file and rename so a crash never leaves a torn file
+        let temp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(instances)?)?;
+        std::fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Checks `/proc/<pid>/status`, treating zombies as dead.
+pub(crate) fn is_pid_alive(pid: u32) -> bool {
+    let status = match std::fs::read_to_string(format!("/proc/{}/status", pid)) {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("State:"))
+        .map(|state| !state.trim_start().starts_with('Z'))
+        .unwrap_or(false)
+}
+
+/// Whether `pid` runs `binary`, going by its command name, which the kernel
+/// cuts to 15 bytes, or the program in its command line, and when
+/// `started_at` is known, whether it started then.
+fn is_same_process(pid: u32, binary: &str, started_at: Option<u64>) -> bool {
+    let Some(name) = Path::new(binary).file_name().map(|name| name.to_string_lossy().to_string()) else {
+        return false;
+    };
+
+    let comm = std::fs::read(format!("/proc/{}/comm", pid)).unwrap_or_default();
+    let comm_matches = comm.strip_suffix(b"\n") == Some(&name.as_bytes()[..name.len().min(15)]);
+    let program_matches = std::fs::read(format!("/proc/{}/cmdline", pid))
+        .ok()
+        .and_then(|cmdline| {
+            let program = String::from_utf8_lossy(cmdline.split(|byte| *byte == 0).next()?).to_string();
+            Some(Path::new(&program).file_name()? == name.as_str())
+        })
+        .unwrap_or(false);
+
+    (comm_matches || program_matches)
+        && started_at.is_none_or(|started_at| process_start_time(pid) == Some(started_at))
+}
+
+/// Unix time the process started at: its start time in clock ticks since
+/// boot from `/proc/<pid>/stat`, plus the boot time from `/proc/stat`.
+pub(crate) fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+    let start_ticks: u64 = after_comm.split_whitespace().nth(19)?.parse().ok()?;
+
+    let boot_time: u64 = std::fs::read_to_string("/proc/stat").ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_second <= 0 {
+        return None;
+    }
+    Some(boot_time + start_ticks / ticks_per_second as u64)
+}
@@ -0,0 +1,170 @@
+use tree_sitter::{Language, Node, Parser, Query, QueryCursor};
+
+use crate::neovim_context::FunctionContext;
+
+const RUST_FUNCTIONS: &str = "(function_item name: (identifier) @name) @function";
+
+const PYTHON_FUNCTIONS: &str = "(function_definition name: (identifier) @name) @function";
+
+const TYPESCRIPT_FUNCTIONS: &str = r#"
+    (function_declaration name: (identifier) @name) @function
+    (generator_function_declaration name: (identifier) @name) @function
+    (method_definition name: (property_identifier) @name) @function
+    (variable_declarator
+        name: (identifier) @name
+        value: [(arrow_function) (function_expression)]) @function
+"#;
+
+/// Keywords that open a brace block and take parentheses but are not functions.
+const CONTROL_KEYWORDS: &[&str] = &["if", "for", "while", "switch", "catch", "match", "else"];
+
+/// Grammar and function query for a Neovim `filetype`, or `None` when there
+/// is no grammar for it.
+fn grammar_for(file_type: &str) -> Option<(Language, &'static str)> {
+    match file_type {
+        "rust" => Some((tree_sitter_rust::language(), RUST_FUNCTIONS)),
+        "python" => Some((tree_sitter_python::language(), PYTHON_FUNCTIONS)),
+        "typescript" | "javascript" => Some((tree_sitter_typescript::language_typescript(), TYPESCRIPT_FUNCTIONS)),
+        "typescriptreact" | "javascriptreact" => Some((tree_sitter_typescript::language_tsx(), TYPESCRIPT_FUNCTIONS)),
+        _ => None,
+    }
+}
+
+/// Finds the innermost function around `line` (1-based) in `source`.
+/// Languages without a grammar fall back to counting braces.
+pub fn find_function_context(source: &str, file_type: Option<&str>, line: u32) -> Option<FunctionContext> {
+    match file_type.and_then(grammar_for) {
+        Some((language, query)) => find_with_tree_sitter(source, &language, query, line),
+        None => find_with_braces(source, line),
+    }
+}
+
+fn find_with_tree_sitter(source: &str, language: &Language, query: &str, line: u32) -> Option<FunctionContext> {
+    let mut parser = Parser::new();
+    parser.set_language(language).ok()?;
+    let tree = parser.parse(source, None)?;
+    let query = Query::new(language, query).ok()?;
+    let function_index = query.capture_index_for_name("function")?;
+    let name_index = query.capture_index_for_name("name")?;
+    let row = line.checked_sub(1)? as usize;
+
+    let mut cursor = QueryCursor::new();
+    let mut innermost: Option<(Node, Node)> = None;
+    for found in cursor.matches(&query, tree.root_node(), source.as_bytes()) {
+        let capture = |index| found.captures.iter().find(|c| c.index == index).map(|c| c.node);
+        let (Some(function), Some(name)) = (capture(function_index), capture(name_index)) else {
+            continue;
+        };
+        if function.start_position().row > row || function.end_position().row < row {
+            continue;
+        }
+        // The smallest enclosing match is the innermost function
+        let is_inner = match innermost {
+            Some((current, _)) => function.byte_range().len() < current.byte_range().len(),
+            None => true,
+        };
+        if is_inner {
+            innermost = Some((function, name));
+        }
+    }
+
+    let (function, name) = innermost?;
+    Some(FunctionContext {
+        name: source[name.byte_range()].to_string(),
+        signature: signature_of(source, function),
+        start_line: function.start_position().row as u32 + 1,
+        end_line: function.end_position().row as u32 + 1,
+    })
+}
+
+/// The function's text up to its body, on one line.
+fn signature_of(source: &str, function: Node) -> String {
+    // Arrow functions assigned to a variable keep their body under `value`
+    let body = function
+        .child_by_field_name("body")
+        .or_else(|| function.child_by_field_name("value").and_then(|value| value.child_by_field_name("body")));
+    let end = body.map_or(function.end_byte(), |body| body.start_byte());
+    let header = source[function.start_byte()..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    let header = header.trim_end_matches([':', '{']).trim_end();
+    header.strip_suffix("=>").unwrap_or(header).trim_end().to_string()
+}
+
+/// Walks outwards from `line` to the nearest unclosed `{` whose header looks
+/// like a call signature, then forwards to its matching `}`.
+fn find_with_braces(source: &str, line: u32) -> Option<FunctionContext> {
+    let lines: Vec<&str> = source.lines().collect();
+    let cursor = (line as usize).checked_sub(1).filter(|&row| row < lines.len())?;
+
+    let mut depth = 0i32;
+    for start in (0..=cursor).rev() {
+        let text = lines[start];
+        for ch in text.chars().rev() {
+            match ch {
+                '}' => depth += 1,
+                '{' => depth -= 1,
+                _ => {}
+            }
+        }
+        if depth >= 0 {
+            continue;
+        }
+        depth = 0;
+
+        let Some(name) = function_name(text) else {
+            continue;
+        };
+        let end = matching_close(&lines, start)?;
+        if end < cursor {
+            return None;
+        }
+        let signature = text[..text.find('{').unwrap_or(text.len())].trim().to_string();
+        return Some(FunctionContext {
+            name,
+            signature,
+            start_line: start as u32 + 1,
+            end_line: end as u32 + 1,
+        });
+    }
+
+    None
+}
+
+/// The identifier right before the first `(` of a block header, unless the
+/// header is a control-flow statement.
+fn function_name(header: &str) -> Option<String> {
+    let before_paren = &header[..header.find('(')?];
+    let name: String = before_paren
+        .trim_end()
+        .chars()
+        .rev()
+        .take_while(|ch| ch.is_alphanumeric() || *ch == '_')
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+
+    let first_word = header.split_whitespace().next().unwrap_or("");
+    if name.is_empty() || CONTROL_KEYWORDS.contains(&name.as_str()) || CONTROL_KEYWORDS.contains(&first_word) {
+        return None;
+    }
+    Some(name)
+}
+
+fn matching_close(lines: &[&str], start: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    for (row, text) in lines.iter().enumerate().skip(start) {
+        for ch in text.chars() {
+            match ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(row);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,191 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
+use crate::types::DiffResult;
+
+/// Upper bound on the size of the map `minimap` returns, in characters.
+pub const MINIMAP_COLS: usize = 40;
+pub const MINIMAP_ROWS: usize = 20;
+
+/// Characters a shell prompt commonly ends with: `$` for sh/bash, `#` for
+/// root, `>` for fish/PowerShell and `%` for zsh.
+const PROMPT_SUFFIXES: &[char] = &['$', '#', '>', '%'];
+
+/// `run_command`'s prompt pattern when none is given: a line ending in one of
+/// `PROMPT_SUFFIXES`.
+pub const DEFAULT_PROMPT_PATTERN: &str = r"[$#>%]\s*$";
+
+/// Screen text split into rows, with escape sequences removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TerminalGrid {
+    pub rows: Vec<TerminalRow>,
+    /// Width of the widest row, in characters
+    pub cols: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TerminalRow {
+    /// The line as captured, escape sequences included
+    pub raw: String,
+    /// The line with ANSI escape sequences stripped
+    pub text: String,
+    /// Heuristic: the line ends with a shell prompt character
+    pub has_prompt: bool,
+}
+
+/// Turns the text of a screenshot (`screenshot_instance` with `text` format)
+/// into a `TerminalGrid`.
+#[derive(Debug, Clone, Default)]
+pub struct TerminalParser;
+
+impl TerminalParser {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn parse(&self, content: &str) -> TerminalGrid {
+        let rows: Vec<TerminalRow> = content
+            .lines()
+            .map(|line| {
+                let text = strip_ansi(line);
+                let has_prompt = text
+                    .trim_end()
+                    .chars()
+                    .last()
+                    .map(|last| PROMPT_SUFFIXES.contains(&last))
+                    .unwrap_or(false);
+                TerminalRow { raw: line.to_string(), text, has_prompt }
+            })
+            .collect();
+
+        let cols = rows
+            .iter()
+            .map(|row| row.text.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(u16::MAX as usize) as u16;
+
+        TerminalGrid { rows, cols }
+    }
+}
+
+/// Removes CSI (`ESC [ ... final`), OSC (`ESC ] ... BEL` or `ESC ] ... ESC \`)
+/// and other `ESC`-introduced sequences, plus carriage returns.
+pub fn strip_ansi(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    // Parameters and intermediates, up to the final byte
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                Some(c) if ('\x20'..='\x2f').contains(&c) => {
+                    // e.g. `ESC ( B`: intermediates followed by one final character
+                    while chars.next_if(|c| ('\x20'..='\x2f').contains(c)).is_some() {}
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\r' => {}
+            c => text.push(c),
+        }
+    }
+
+    text
+}
+
+/// Shrinks screen text to at most `MINIMAP_COLS` x `MINIMAP_ROWS`. Each
+/// output cell covers a block of the screen and is `#` when at least half of
+/// the block is non-whitespace, `.` otherwise.
+pub fn minimap(content: &str) -> String {
+    let lines: Vec<Vec<char>> = content.lines().map(|line| strip_ansi(line).chars().collect()).collect();
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+    let height = lines.len();
+    if width == 0 {
+        return String::new();
+    }
+
+    let block_width = width.div_ceil(MINIMAP_COLS);
+    let block_height = height.div_ceil(MINIMAP_ROWS);
+
+    let mut map = String::new();
+    for block_top in (0..height).step_by(block_height) {
+        let block_lines = &lines[block_top..(block_top + block_height).min(height)];
+        for block_left in (0..width).step_by(block_width) {
+            let block_right = (block_left + block_width).min(width);
+            // Short lines count as whitespace past their end
+            let filled: usize = block_lines
+                .iter()
+                .map(|line| {
+                    line.iter()
+                        .take(block_right)
+                        .skip(block_left)
+                        .filter(|c| !c.is_whitespace())
+                        .count()
+                })
+                .sum();
+            let cells = block_lines.len() * (block_right - block_left);
+            map.push(if filled * 2 >= cells { '#' } else { '.' });
+        }
+        map.push('\n');
+    }
+    map
+}
+
+/// Finds `command` at or after line `from_line` of the screen text and
+/// returns what was printed between it and the next line matching `prompt`,
+/// plus the prompt's `exit_code` capture group if it has one. `None` until
+/// that prompt has appeared.
+pub fn command_output(content: &str, from_line: usize, command: &str, prompt: &Regex) -> Option<(String, Option<i32>)> {
+    let lines: Vec<String> = content.lines().map(strip_ansi).collect();
+    let first_line = command.lines().next().unwrap_or("").trim();
+
+    let command_row = (from_line.min(lines.len())..lines.len()).find(|&i| lines[i].contains(first_line))?;
+    // A multi-line command echoes one row per line, continuation prompts
+    // included, so those are skipped rather than taken for the next prompt
+    let output_start = command_row + command.lines().count().max(1);
+
+    let prompt_row = (output_start..lines.len()).find(|&i| prompt.is_match(&lines[i]))?;
+    let exit_code = prompt
+        .captures(&lines[prompt_row])
+        .and_then(|captures| captures.name("exit_code"))
+        .and_then(|code| code.as_str().parse().ok());
+
+    Some((lines[output_start..prompt_row].join("\n"), exit_code))
+}
+
+/// Diffs two screenshots line by line. A line that moved, e.g. scrolled up,
+/// counts as unchanged as long as the lines around it kept their order.
+pub fn diff_screens(old: &str, new: &str) -> DiffResult {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    let mut result = DiffResult { added: Vec::new(), removed: Vec::new(), unchanged_count: 0 };
+    for change in TextDiff::from_slices(&old_lines, &new_lines).iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => result.added.push(change.value().to_string()),
+            ChangeTag::Delete => result.removed.push(change.value().to_string()),
+            ChangeTag::Equal => result.unchanged_count += 1,
+        }
+    }
+    result
+}
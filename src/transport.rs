@@ -0,0 +1,385 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs, UnixListener};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::alacritty_manager::AlacrittyManager;
+use crate::cancellation::cancellation_target;
+use crate::mcp_server::McpServer;
+
+/// Runs `server` over one newline-delimited JSON-RPC stream until the reader
+/// hits end of file. Responses and notifications share one writer task, so
+/// they never interleave mid-line.
+pub async fn serve_connection<R, W>(mut server: McpServer, reader: R, writer: W) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<String>();
+    let writer_task = tokio::spawn(async move {
+        let mut writer = writer;
+        while let Some(message) = out_rx.recv().await {
+            writer.write_all(message.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await?;
+        }
+        Ok::<(), std::io::Error>(())
+    });
+
+    // Notifications can arrive at any time, not just in reply to a request
+    if let Some(mut notifications) = server.take_notifications() {
+        let notification_tx = out_tx.clone();
+        tokio::spawn(async move {
+            while let Some(notification) = notifications.recv().await {
+                if notification_tx.send(notification).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let in_flight = server.in_flight_requests();
+    let mut lines = BufReader::new(reader).lines();
+    // Lines read while a request was being served, waiting their turn
+    let mut pending = VecDeque::new();
+    // IDs of queued requests cancelled before they started
+    let mut cancelled = Vec::new();
+    let mut end_of_input = false;
+    loop {
+        let line = match pending.pop_front() {
+            Some(line) => line,
+            None if end_of_input => break,
+            None => match lines.next_line().await? {
+                Some(line) => line,
+                None => break,
+            },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(id) = take_cancelled(&mut cancelled, &line) {
+            let _ = out_tx.send(cancelled_response(&id));
+            continue;
+        }
+
+        // Keep reading while the request runs, so a cancellation for it, or
+        // for one queued behind it, can get through. Everything else is
+        // queued and served in order.
+        let request = server.handle_request(&line);
+        tokio::pin!(request);
+        let result = loop {
+            // Polling the request first lets a tool call register itself as
+            // in flight before a cancellation already waiting is read
+            tokio::select! {
+                biased;
+                result = &mut request => break result,
+                next = lines.next_line(), if !end_of_input => match next? {
+                    Some(next) => match cancellation_target(&next) {
+                        Some(id) if is_queued(&pending, &id) => cancelled.push(id),
+                        Some(id) => {
+                            in_flight.cancel(&id);
+                        }
+                        None => pending.push_back(next),
+                    },
+                    None => end_of_input = true,
+                },
+            }
+        };
+
+        match result {
+            Ok(Some(response)) => {
+                let _ = out_tx.send(response);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("Error handling request: {}", e);
+                let error_response = serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "error": {
+                        "code": -32603,
+                        "message": e.to_string()
+                    },
+                    "id": null
+                });
+                let _ = out_tx.send(error_response.to_string());
+            }
+        }
+    }
+
+    // Dropping the server ends its notification stream, then the writer
+    // drains what is left
+    drop(server);
+    drop(out_tx);
+    writer_task.await??;
+    Ok(())
+}
+
+fn request_id(line: &str) -> Option<serde_json::Value> {
+    serde_json::from_str::<serde_json::Value>(line).ok()?.get("id").cloned()
+}
+
+fn is_queued(pending: &VecDeque<String>, id: &serde_json::Value) -> bool {
+    pending.iter().any(|line| request_id(line).as_ref() == Some(id))
+}
+
+/// The ID of `line` if it was cancelled while queued, so it is answered in
+/// its turn without being started.
+fn take_cancelled(cancelled: &mut Vec<serde_json::Value>, line: &str) -> Option<serde_json::Value> {
+    if cancelled.is_empty() {
+        return None;
+    }
+    let id = request_id(line)?;
+    let position = cancelled.iter().position(|cancelled_id| *cancelled_id == id)?;
+    Some(cancelled.swap_remove(position))
+}
+
+/// What a cancelled call gets back, matching `McpServer`'s answer for one
+/// that was already running.
+fn cancelled_response(id: &serde_json::Value) -> String {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "error": {
+            "code": -32800,
+            "message": "Request cancelled"
+        },
+        "id": id
+    }).to_string()
+}
+
+/// Removes the socket file when the listener goes away, however that happens.
+struct SocketGuard {
+    path: PathBuf,
+}
+
+impl Drop for SocketGuard {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove socket {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Listens on a Unix domain socket and runs one `McpServer` per connection,
+/// all sharing the same manager and so the same instance registry.
+pub struct UnixSocketServer {
+    listener: UnixListener,
+    guard: SocketGuard,
+}
+
+impl UnixSocketServer {
+    /// Binds `path`. A leftover socket file nobody is listening on is
+    /// replaced; a live one is an error.
+    pub fn bind(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(anyhow!("Socket {} is already in use", path.display()));
+            }
+            std::fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        Ok(Self { listener, guard: SocketGuard { path } })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.guard.path
+    }
+
+    /// Accepts connections until the returned future is dropped, which also
+    /// removes the socket file.
+    pub async fn serve(self, manager: Arc<AlacrittyManager>) -> Result<()> {
+        info!("Listening on {}", self.path().display());
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            spawn_session(McpServer::new(Arc::clone(&manager)), stream);
+        }
+    }
+}
+
+/// Listens on a TCP address, optionally behind TLS, and runs one `McpServer`
+/// per connection like `UnixSocketServer`.
+pub struct TcpServer {
+    listener: TcpListener,
+    #[cfg(feature = "tls")]
+    tls: Option<tokio_rustls::TlsAcceptor>,
+}
+
+impl TcpServer {
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self {
+            listener,
+            #[cfg(feature = "tls")]
+            tls: None,
+        })
+    }
+
+    /// Serves TLS with the PEM certificate chain and private key at the given
+    /// paths.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, cert: &Path, key: &Path) -> Result<Self> {
+        use tokio_rustls::rustls::ServerConfig;
+
+        let read_pem = |path: &Path| {
+            std::fs::File::open(path)
+                .map(std::io::BufReader::new)
+                .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))
+        };
+        let certs = rustls_pemfile::certs(&mut read_pem(cert)?).collect::<Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            return Err(anyhow!("No certificates found in {}", cert.display()));
+        }
+        let key = rustls_pemfile::private_key(&mut read_pem(key)?)?
+            .ok_or_else(|| anyhow!("No private key found in {}", key.display()))?;
+
+        let config = ServerConfig::builder().with_no_client_auth().with_single_cert(certs, key)?;
+        self.tls = Some(tokio_rustls::TlsAcceptor::from(Arc::new(config)));
+        Ok(self)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Whether anyone on the network could connect and read or send the
+    /// traffic in the clear: the listener is not on a loopback address and
+    /// does not use TLS. There is no authentication either way.
+    pub fn is_exposed(&self) -> bool {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return false;
+        }
+        !self.listener.local_addr().is_ok_and(|addr| addr.ip().is_loopback())
+    }
+
+    /// Accepts connections until the returned future is dropped.
+    pub async fn serve(self, manager: Arc<AlacrittyManager>) -> Result<()> {
+        #[cfg(feature = "tls")]
+        let scheme = if self.tls.is_some() { "tls" } else { "tcp" };
+        #[cfg(not(feature = "tls"))]
+        let scheme = "tcp";
+        info!("Listening on {}://{}", scheme, self.local_addr()?);
+        if self.is_exposed() {
+            warn!(
+                "{} is reachable beyond this machine without TLS or authentication; anyone who connects can spawn terminals and type into them",
+                self.local_addr()?
+            );
+        }
+
+        loop {
+            let (stream, peer) = self.listener.accept().await?;
+            info!("Accepted connection from {}", peer);
+            let server = McpServer::new(Arc::clone(&manager));
+
+            #[cfg(feature = "tls")]
+            if let Some(acceptor) = &self.tls {
+                // The handshake runs on the connection's task so a slow
+                // client cannot hold up the accept loop
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => spawn_session(server, stream),
+                        Err(e) => warn!("TLS handshake with {} failed: {}", peer, e),
+                    }
+                });
+                continue;
+            }
+
+            spawn_session(server, stream);
+        }
+    }
+}
+
+/// Longest HTTP request head `HealthServer` reads before giving up.
+const MAX_HEALTH_REQUEST_BYTES: usize = 8 * 1024;
+const HEALTH_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Plain HTTP listener answering `GET /health` with the same JSON as the
+/// `health` method, for monitors that do not speak JSON-RPC. Every response
+/// closes the connection.
+pub struct HealthServer {
+    listener: TcpListener,
+}
+
+impl HealthServer {
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(Self { listener })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until the returned future is dropped.
+    pub async fn serve(self, manager: Arc<AlacrittyManager>) -> Result<()> {
+        info!("Serving health checks on http://{}/health", self.local_addr()?);
+        loop {
+            let (stream, peer) = self.listener.accept().await?;
+            let manager = Arc::clone(&manager);
+            tokio::spawn(async move {
+                if let Err(e) = answer_health_check(stream, &manager).await {
+                    warn!("Health check from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn answer_health_check(mut stream: TcpStream, manager: &AlacrittyManager) -> Result<()> {
+    // Only the request line matters; headers and any body are ignored
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    tokio::time::timeout(HEALTH_REQUEST_TIMEOUT, async {
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+            if head.len() > MAX_HEALTH_REQUEST_BYTES {
+                return Err(anyhow!("Request head longer than {} bytes", MAX_HEALTH_REQUEST_BYTES));
+            }
+        }
+        Ok(())
+    }).await.map_err(|_| anyhow!("Timed out reading the request"))??;
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/health")) => {
+            ("200 OK", serde_json::to_string(&manager.health().await)?)
+        }
+        (Some("GET"), Some(_)) => ("404 Not Found", serde_json::json!({"error": "Not found"}).to_string()),
+        _ => ("405 Method Not Allowed", serde_json::json!({"error": "Method not allowed"}).to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn spawn_session<S>(server: McpServer, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    tokio::spawn(async move {
+        let (reader, writer) = tokio::io::split(stream);
+        if let Err(e) = serve_connection(server, reader, writer).await {
+            warn!("Connection closed with an error: {}", e);
+        }
+    });
+}
@@ -1,14 +1,131 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Serialized with extra, computed `uptime_secs` and `idle_secs` fields (see
+/// the `Serialize` impl below); they are ignored when deserializing.
+#[derive(Debug, Clone, Deserialize)]
 pub struct AlacrittyInstance {
     pub id: String,
     pub pid: u32,
     pub window_id: Option<u32>,
     pub title: String,
     pub command: String,
+    /// The arguments passed to `command`
+    #[serde(default)]
+    pub args: Vec<String>,
     pub created_at: u64,
+    pub status: InstanceStatus,
+    #[serde(default)]
+    pub last_focused_at: Option<u64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub working_directory: Option<String>,
+    /// Set once a spawned instance has been reaped; 128 + N for signal N
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// The `--class` the server gave the window, which finds it faster than
+    /// its PID. `None` for terminals opened some other way.
+    #[serde(default)]
+    pub window_class: Option<String>,
+    /// The socket `alacritty msg` talks to, once one has been found
+    #[serde(default)]
+    pub ipc_socket_path: Option<String>,
+    /// When keys were last sent, a screenshot taken or a status change
+    /// seen; starts out as `created_at`
+    #[serde(default)]
+    pub last_activity_at: u64,
+    /// When the process started, by the kernel's account, so that a restored
+    /// entry is not mistaken for a later process given the same PID
+    #[serde(default)]
+    pub process_started_at: Option<u64>,
+}
+
+impl AlacrittyInstance {
+    /// Seconds since the instance was created, or `None` when its start time
+    /// is unknown (`created_at` of 0).
+    pub fn uptime_secs(&self) -> Option<u64> {
+        if self.created_at == 0 {
+            return None;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(now.saturating_sub(self.created_at))
+    }
+
+    /// Seconds since the last activity, or `None` when neither that nor the
+    /// start time is known.
+    pub fn idle_secs(&self) -> Option<u64> {
+        let since = self.last_activity_at.max(self.created_at);
+        if since == 0 {
+            return None;
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(now.saturating_sub(since))
+    }
+}
+
+impl Serialize for AlacrittyInstance {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Mirrors the struct field for field, plus `uptime_secs` and `idle_secs`
+        #[derive(Serialize)]
+        struct Fields<'a> {
+            id: &'a str,
+            pid: u32,
+            window_id: Option<u32>,
+            title: &'a str,
+            command: &'a str,
+            args: &'a [String],
+            created_at: u64,
+            status: InstanceStatus,
+            last_focused_at: Option<u64>,
+            tags: &'a [String],
+            working_directory: Option<&'a str>,
+            exit_code: Option<i32>,
+            window_class: Option<&'a str>,
+            ipc_socket_path: Option<&'a str>,
+            last_activity_at: u64,
+            process_started_at: Option<u64>,
+            uptime_secs: Option<u64>,
+            idle_secs: Option<u64>,
+        }
+
+        Fields {
+            id: &self.id,
+            pid: self.pid,
+            window_id: self.window_id,
+            title: &self.title,
+            command: &self.command,
+            args: &self.args,
+            created_at: self.created_at,
+            status: self.status,
+            last_focused_at: self.last_focused_at,
+            tags: &self.tags,
+            working_directory: self.working_directory.as_deref(),
+            exit_code: self.exit_code,
+            window_class: self.window_class.as_deref(),
+            ipc_socket_path: self.ipc_socket_path.as_deref(),
+            last_activity_at: self.last_activity_at,
+            process_started_at: self.process_started_at,
+            uptime_secs: self.uptime_secs(),
+            idle_secs: self.idle_secs(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InstanceStatus {
+    Running,
+    Exited,
+}
+
+/// Reported by `AlacrittyManager::watch_instances`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WatchEvent {
+    Exited,
+    NewWindow { window_id: u32 },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +136,23 @@ pub struct JsonRpcRequest {
     pub id: Option<serde_json::Value>,
 }
 
+impl JsonRpcRequest {
+    /// JSON-RPC 2.0 IDs are strings, numbers without a fractional part, or
+    /// null. Returns why the ID is unusable otherwise.
+    pub fn validate_id(&self) -> Result<(), String> {
+        match &self.id {
+            None | Some(serde_json::Value::Null) | Some(serde_json::Value::String(_)) => Ok(()),
+            Some(serde_json::Value::Number(number)) => match number.as_f64() {
+                Some(value) if number.is_f64() && value.fract() != 0.0 => {
+                    Err(format!("id must not have a fractional part, got {}", number))
+                }
+                _ => Ok(()),
+            },
+            Some(other) => Err(format!("id must be a string, number or null, got {}", other)),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JsonRpcResponse {
     pub jsonrpc: String,
@@ -41,9 +175,96 @@ pub struct Tool {
     pub input_schema: serde_json::Value,
 }
 
+/// One item of the `content` a `tools/call` result carries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ToolContent {
+    Text { text: String },
+    /// `data` is base64, without a `data:` URL prefix
+    Image {
+        data: String,
+        #[serde(rename = "mimeType")]
+        mime_type: String,
+    },
+}
+
+impl ToolContent {
+    /// An image item from a `data:<mime type>;base64,<data>` URL, as the
+    /// screenshot tools produce.
+    pub fn from_data_url(url: &str) -> Option<Self> {
+        let (mime_type, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+        Some(ToolContent::Image { data: data.to_string(), mime_type: mime_type.to_string() })
+    }
+}
+
+impl From<String> for ToolContent {
+    fn from(text: String) -> Self {
+        ToolContent::Text { text }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerCapabilities {
     pub tools: Vec<Tool>,
+    pub resources: ResourcesCapability,
+    pub prompts: PromptsCapability,
+    pub logging: LoggingCapability,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoggingCapability {
+    /// Current level, as last set by `logging/setLevel`
+    pub level: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetLevelParams {
+    pub level: String,
+}
+
+/// Answer to the `health` method and to `GET /health`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthStatus {
+    pub status: String,
+    /// Tracked instances still running
+    pub instances: usize,
+    /// Since the manager was created, i.e. server start
+    pub uptime_secs: u64,
+    pub version: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PromptsCapability {
+    #[serde(rename = "listChanged")]
+    pub list_changed: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ResourcesCapability {
+    pub subscribe: bool,
+    #[serde(rename = "listChanged")]
+    pub list_changed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resource {
+    pub uri: String,
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(rename = "mimeType")]
+    pub mime_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceReadParams {
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptGetParams {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: HashMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,12 +282,77 @@ pub struct ClientInfo {
     pub version: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SpawnParams {
     pub command: Option<String>,
     pub args: Option<Vec<String>>,
     pub working_directory: Option<String>,
     pub title: Option<String>,
+    pub env: Option<HashMap<String, String>>, // Added to the inherited environment
+    pub tags: Option<Vec<String>>,
+    pub config_file: Option<String>, // Passed as `--config-file`, must be a .toml file
+    pub geometry: Option<WindowGeometry>,
+    pub font_size: Option<f32>,      // Passed as `--option font.size`, 4-72
+    pub font_family: Option<String>, // Passed as `--option font.normal.family`
+}
+
+/// Initial window size in cells and, optionally, position on screen in
+/// pixels. Passed to Alacritty as `--option window.*` overrides.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub cols: u16,
+    pub rows: u16,
+    /// Both or neither; window managers may ignore them
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListInstancesParams {
+    pub filter: Option<InstanceFilter>,
+}
+
+/// The running instances with Neovim in them, from `list_neovim_instances`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NeovimInstanceList {
+    pub instances: Vec<AlacrittyInstance>,
+    pub neovim_detected_count: u32,
+    /// Neovim's socket per instance ID, for the instances where it was found
+    pub socket_paths: BTreeMap<String, String>,
+}
+
+/// Every condition that is set must hold for an instance to be listed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InstanceFilter {
+    pub title_contains: Option<String>,
+    pub command_eq: Option<String>,
+    pub has_window: Option<bool>,
+}
+
+impl InstanceFilter {
+    pub fn matches(&self, instance: &AlacrittyInstance) -> bool {
+        if let Some(needle) = &self.title_contains {
+            if !instance.title.contains(needle.as_str()) {
+                return false;
+            }
+        }
+        if let Some(command) = &self.command_eq {
+            if &instance.command != command {
+                return false;
+            }
+        }
+        if let Some(has_window) = self.has_window {
+            if instance.window_id.is_some() != has_window {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetInstanceParams {
+    pub instance_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,16 +361,557 @@ pub struct SendKeysParams {
     pub keys: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendKeysSequenceParams {
+    pub instance_id: String,
+    pub sequence: Vec<KeyEvent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyEvent {
+    /// Same format as `send_keys`
+    pub keys: String,
+    /// Pause before the next event, or before returning after the last one
+    pub delay_after_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySequenceResult {
+    pub events_sent: u32,
+    /// Including the delays
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListByTagParams {
+    pub tag: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendKeysToTagParams {
+    pub tag: String,
+    pub keys: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchSendKeysParams {
+    pub targets: Vec<BatchTarget>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchTarget {
+    pub instance_id: String,
+    /// Same format as `send_keys`
+    pub keys: String,
+}
+
+/// Per-instance outcome of `batch_send_keys`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchResult {
+    /// In the order the targets were given
+    pub succeeded: Vec<String>,
+    /// The error for each instance the keys could not be sent to
+    pub failed: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetClipboardParams {
+    pub content: String,
+    pub instance_id: Option<String>, // Use this instance's display instead of the server's
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetClipboardParams {
+    pub instance_id: Option<String>, // Use this instance's display instead of the server's
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardContent {
+    pub text: String,
+    pub size_bytes: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SendTextParams {
+    pub instance_id: String,
+    pub text: String,
+    pub delay_ms: Option<u32>, // Delay between typed characters
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScreenshotParams {
     pub instance_id: String,
     pub format: Option<String>, // "text" or "image"
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenshotDiffParams {
+    pub instance_id: String,
+    /// ID from an earlier text `screenshot_instance` of the same instance
+    pub since_screenshot_id: String,
+}
+
+/// Line-level difference between a stored screenshot and the screen now.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffResult {
+    /// Lines on the screen now that were not there before, top to bottom
+    pub added: Vec<String>,
+    /// Lines that were there before and are gone now
+    pub removed: Vec<String>,
+    pub unchanged_count: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenshotRegionParams {
+    pub instance_id: String,
+    pub x: u32, // Pixels from the window's left edge
+    pub y: u32, // Pixels from the window's top edge
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrollbackParams {
+    pub instance_id: String,
+    pub start_line: Option<u32>, // First line to return, 0-based and inclusive
+    pub end_line: Option<u32>,   // Line to stop at, exclusive
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinimapParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParseTerminalParams {
+    pub text: String, // Raw screen text, e.g. from `screenshot_instance`
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrollbackPage {
+    pub total_lines: u32,
+    pub start_line: u32,
+    pub end_line: u32,
+    pub lines: Vec<String>,
+}
+
+impl ScrollbackPage {
+    /// Cuts `[start_line, end_line)` out of `text`, clamping both ends to the
+    /// available lines. Missing bounds default to the start and end of the text.
+    pub fn slice(text: &str, start_line: Option<u32>, end_line: Option<u32>) -> Self {
+        let all_lines: Vec<&str> = text.lines().collect();
+        let total_lines = all_lines.len() as u32;
+        let end_line = end_line.unwrap_or(total_lines).min(total_lines);
+        let start_line = start_line.unwrap_or(0).min(end_line);
+
+        Self {
+            total_lines,
+            start_line,
+            end_line,
+            lines: all_lines[start_line as usize..end_line as usize]
+                .iter()
+                .map(|line| line.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WaitForOutputParams {
+    pub instance_id: String,
+    pub pattern: String, // Regex matched against each line of output
+    pub timeout_ms: Option<u64>,
+    pub poll_interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorParams {
+    pub instance_id: String,
+    pub duration_secs: u32,
+    pub interval_ms: u32,       // Between the starts of two screenshots
+    pub format: Option<String>, // "text" or "image", as for `screenshot_instance`
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedScreenshot {
+    pub timestamp_ms: u64, // Since monitoring started
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputMatch {
+    pub line: String,
+    pub polls: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunCommandParams {
+    pub instance_id: String,
+    pub command: String,
+    pub timeout_ms: Option<u64>,
+    pub prompt_pattern: Option<String>, // Regex for the shell prompt; an `exit_code` group is read if present
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunCommandResult {
+    /// Text between the command line and the next prompt. Stderr is mixed
+    /// in, the terminal does not keep them apart
+    pub stdout: String,
+    /// Only known when `prompt_pattern` captures it
+    pub exit_code: Option<i32>,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloseParams {
+    pub instance_id: String,
+    pub timeout_ms: Option<u64>, // How long to wait after SIGTERM before SIGKILL
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReloadConfigParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReloadResult {
+    /// The file Alacritty was made to re-read
+    pub config_file: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessTreeParams {
+    pub instance_id: String,
+}
+
+/// Memory of one process, from `/proc/<pid>/status`, in kB.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryUsage {
+    pub rss_kb: u64,
+    pub virtual_kb: u64,
+    pub swap_kb: u64,
+}
+
+impl MemoryUsage {
+    /// Reads `VmRSS`, `VmSize` and `VmSwap` out of the contents of
+    /// `/proc/<pid>/status`. A field the kernel left out, as it does for
+    /// zombies, counts as 0.
+    pub fn from_proc_status(status: &str) -> Self {
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Self { rss_kb: field("VmRSS"), virtual_kb: field("VmSize"), swap_kb: field("VmSwap") }
+    }
+}
+
+impl std::iter::Sum for MemoryUsage {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |total, usage| Self {
+            rss_kb: total.rss_kb + usage.rss_kb,
+            virtual_kb: total.virtual_kb + usage.virtual_kb,
+            swap_kb: total.swap_kb + usage.swap_kb,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessNode {
+    pub pid: u32,
+    pub name: String,
+    pub args: Vec<String>,
+    pub children: Vec<ProcessNode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvironmentParams {
+    pub instance_id: String,
+    pub filter_prefix: Option<String>, // Only variables whose name starts with this
+    pub show_secrets: Option<bool>,    // Defaults to false
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KillAllParams {
+    pub tag: Option<String>, // Only kill instances carrying this tag
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillIdleParams {
+    pub older_than_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KillSummary {
+    pub killed: u32,
+    pub already_exited: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenameParams {
+    pub instance_id: String,
+    pub new_title: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateResult {
+    pub original: AlacrittyInstance,
+    pub duplicate: AlacrittyInstance,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FocusParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DimensionsParams {
+    pub instance_id: String,
+}
+
+/// A terminal's size in character cells and in pixels.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dimensions {
+    pub cols: u16,
+    pub rows: u16,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResizeParams {
+    pub instance_id: String,
+    pub cols: u16,
+    pub rows: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetOptionParams {
+    pub instance_id: String,
+    pub key: String,   // Dotted config path, e.g. `font.size`
+    pub value: String, // TOML value, e.g. `14` or `"#1d1f21"`
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteFileParams {
+    pub instance_id: String,
+    pub relative_path: String, // Resolved against the shell's working directory
+    pub content: String,
+    pub mode: Option<String>,   // Octal permissions, e.g. "755"
+    pub overwrite: Option<bool>, // Defaults to false
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteFileResult {
+    pub path: String,
+    pub bytes_written: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadFileParams {
+    pub instance_id: String,
+    pub relative_path: String,
+    pub start_line: Option<u32>, // 1-based, inclusive
+    pub end_line: Option<u32>,   // 1-based, inclusive
+    pub max_bytes: Option<usize>, // Defaults to 1 MB
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileContent {
+    pub path: String,
+    pub content: String,
+    pub total_lines: u32,
+    pub encoding: String, // "utf-8", or "iso-8859-1" for other text
+    pub truncated: bool,  // Content was cut at `max_bytes`
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchParams {
+    pub instance_id: String,
+    pub pattern: String,           // Regex
+    pub path: Option<String>,      // Relative to the working directory, which is the default
+    pub file_glob: Option<String>, // e.g. "*.rs"
+    pub max_results: Option<u32>,  // Defaults to 100, capped at 1000
+}
+
+/// A matching line. Line and column are 1-based; the column counts
+/// characters up to the first match on the line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub file_path: String, // Relative to the working directory
+    pub line: u32,
+    pub column: u32,
+    pub match_text: String,
+    pub context_before: Vec<String>,
+    pub context_after: Vec<String>,
+}
+
+/// File tool failures that carry details for the client. The server puts
+/// the serialized error in the JSON-RPC error's `data`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FileError {
+    Binary { path: String },
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileError::Binary { path } => write!(f, "{} is a binary file", path),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NeovimContextParams {
     pub instance_id: String,
     pub include_diagnostics: Option<bool>,
     pub include_buffers: Option<bool>,
     pub context_lines: Option<u32>, // Number of lines around cursor
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NeovimHoverParams {
+    pub instance_id: String,
+    pub line: Option<u32>, // 1-based, defaults to the cursor line
+    pub col: Option<u32>,  // 1-based, defaults to the cursor column
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetNeovimOptionParams {
+    pub instance_id: String,
+    pub option: String, // Must be one Neovim allows to be set remotely
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloneBufferParams {
+    pub source_instance_id: String,
+    pub target_instance_id: Option<String>, // Terminal at a shell prompt to open the file in, instead of a new one
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloneBufferResult {
+    pub source_instance_id: String,
+    pub target_instance_id: String,
+    pub file_path: String,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DefinitionParams {
+    pub instance_id: String,
+    pub line: Option<u32>, // 1-based, defaults to the cursor line
+    pub col: Option<u32>,  // 1-based, defaults to the cursor column
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeActionsParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyCodeActionParams {
+    pub instance_id: String,
+    pub index: u32, // 0-based, into the list `get_neovim_code_actions` returned
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceDiagnosticsParams {
+    pub instance_id: String,
+    pub min_severity: Option<String>, // "error", "warning", "info" or "hint"
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionParams {
+    pub instance_id: String,
+    pub max_items: Option<u32>, // Defaults to 50, capped at 500
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarksParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistersParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JumplistParams {
+    pub instance_id: String,
+    pub max_entries: Option<u32>, // Defaults to 50, capped at 100
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionVariablesParams {
+    pub instance_id: String,
+    pub scope: Option<String>, // global, buffer, window or tab; all of them by default
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpellErrorsParams {
+    pub instance_id: String,
+    pub max_errors: Option<u32>, // Defaults to 50, capped at 500
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NeovimChangesParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHistoryParams {
+    pub instance_id: String,
+    pub count: Option<u32>, // Defaults to 10, capped at 50
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuickfixParams {
+    pub instance_id: String,
+    /// Read the current window's location list instead of the quickfix list
+    #[serde(default)]
+    pub location_list: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitStatusParams {
+    pub instance_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecuteCommandParams {
+    pub instance_id: String,
+    /// Ex command without the leading `:`
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunTestParams {
+    pub instance_id: String,
+    pub test_file: Option<String>, // Relative to Neovim's working directory
+    pub test_name: Option<String>,
+    pub timeout_secs: Option<u64>, // Defaults to 60, capped at 600
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferencesParams {
+    pub instance_id: String,
+    pub include_declaration: Option<bool>, // Defaults to true, like `vim.lsp.buf.references()`
+}
@@ -3,18 +3,25 @@ use std::time::Duration;
 use tokio::time::{timeout, sleep};
 use serde_json::{json, Value};
 use serial_test::serial;
-use alacritty_mcp::{AlacrittyManager, McpServer};
+use alacritty_mcp::{AlacrittyManager, Config, McpServer};
 use std::collections::HashSet;
 use std::os::unix::process::ExitStatusExt;
 
+/// A manager on the default config with no state file, so tests never read
+/// the developer's config or write to their registry.
+fn test_manager() -> AlacrittyManager {
+    AlacrittyManager::with_config_and_state_file(Config::default(), None)
+}
+
 async fn create_test_server() -> McpServer {
-    let manager = AlacrittyManager::new();
+    let manager = test_manager();
     McpServer::new(manager)
 }
 
 async fn send_request(server: &mut McpServer, request: Value) -> Result<Value, Box<dyn std::error::Error>> {
     let request_str = serde_json::to_string(&request)?;
-    let response_str = server.handle_request(&request_str).await?;
+    let response_str = server.handle_request(&request_str).await?
+        .ok_or("no response for request")?;
     let response: Value = serde_json::from_str(&response_str)?;
     Ok(response)
 }
@@ -39,7 +46,7 @@ async fn initialize_server(server: &mut McpServer) -> Result<(), Box<dyn std::er
 
 async fn get_alacritty_pids() -> Vec<u32> {
     let output = Command::new("pgrep")
-        .args(&["-f", "alacritty"])
+        .args(["-f", "alacritty"])
         .output()
         .unwrap_or_else(|_| std::process::Output {
             status: std::process::ExitStatus::from_raw(1),
@@ -3,16 +3,28 @@ use std::time::Duration;
 use tokio::time::timeout;
 use serde_json::{json, Value};
 use serial_test::serial;
-use alacritty_mcp::{AlacrittyManager, McpServer};
+use alacritty_mcp::{AlacrittyManager, AlacrittyMcpError, Config, McpServer, Middleware, RateLimiter, ToolContent};
+use alacritty_mcp::middleware::HookFuture;
+use alacritty_mcp::mcp_server::{PRE_INIT_WARNING, SUPPORTED_VERSIONS};
+
+/// Well-formed, but never handed out by the manager.
+const UNKNOWN_INSTANCE_ID: &str = "00000000-0000-4000-8000-000000000000";
+
+/// A manager on the default config with no state file, so tests never read
+/// the developer's config or write to their registry.
+fn test_manager() -> AlacrittyManager {
+    AlacrittyManager::with_config_and_state_file(Config::default(), None)
+}
 
 async fn create_test_server() -> McpServer {
-    let manager = AlacrittyManager::new();
+    let manager = test_manager();
     McpServer::new(manager)
 }
 
 async fn send_request(server: &mut McpServer, request: Value) -> Result<Value, Box<dyn std::error::Error>> {
     let request_str = serde_json::to_string(&request)?;
-    let response_str = server.handle_request(&request_str).await?;
+    let response_str = server.handle_request(&request_str).await?
+        .ok_or("no response for request")?;
     let response: Value = serde_json::from_str(&response_str)?;
     Ok(response)
 }
@@ -42,9 +54,129 @@ async fn test_initialize() {
     assert_eq!(response["id"], 1);
     assert!(response["result"]["protocolVersion"].is_string());
     assert!(response["result"]["capabilities"]["tools"].is_array());
+    assert!(response["result"]["capabilities"]["resources"].is_object());
     assert!(response["error"].is_null());
 }
 
+#[tokio::test]
+#[serial]
+async fn test_initialize_reports_config_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, "default_title_prefix = \"agent\"\n").unwrap();
+
+    let manager = AlacrittyManager::with_config_and_state_file(Config::load(&path).unwrap(), None);
+    let mut server = McpServer::new(manager);
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 1
+    });
+    let response = send_request(&mut server, init_request).await.unwrap();
+    assert_eq!(response["result"]["serverInfo"]["configPath"], path.display().to_string());
+
+    // No file in use
+    let mut server = McpServer::new(test_manager());
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 2
+    });
+    let response = send_request(&mut server, init_request).await.unwrap();
+    assert!(response["result"]["serverInfo"]["configPath"].is_null());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_logging_set_level() {
+    let mut server = create_test_server().await;
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    for (id, level) in ["debug", "info", "warning", "error"].into_iter().enumerate() {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "logging/setLevel",
+            "params": {"level": level},
+            "id": id + 2
+        });
+        let response = send_request(&mut server, request).await.unwrap();
+        assert!(response["error"].is_null(), "{}: {}", level, response);
+        assert_eq!(response["result"], json!({}));
+    }
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "logging/setLevel",
+        "params": {"level": "verbose"},
+        "id": 10
+    });
+    let response = send_request(&mut server, request).await.unwrap();
+    assert_eq!(response["error"]["code"], -32602);
+
+    // The last valid level is reported on the next handshake
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 11
+    });
+    let response = send_request(&mut server, init_request).await.unwrap();
+    assert_eq!(response["result"]["capabilities"]["logging"]["level"], "error");
+
+    let request = json!({"jsonrpc": "2.0", "method": "logging/setLevel", "params": {"level": "info"}, "id": 12});
+    send_request(&mut server, request).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_protocol_version_negotiation() {
+    let initialize = |version: &str| json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": version,
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+
+    let mut server = create_test_server().await;
+    let response = send_request(&mut server, initialize("2099-01-01")).await.unwrap();
+    assert_eq!(response["error"]["code"], -32002);
+    assert!(response["error"]["message"].as_str().unwrap().contains(SUPPORTED_VERSIONS[0]));
+    assert_eq!(response["error"]["data"]["supported"], json!(SUPPORTED_VERSIONS));
+
+    // A rejected handshake leaves the server uninitialized
+    let spawn_request = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": "spawn_instance", "arguments": {}},
+        "id": 2
+    });
+    let response = send_request(&mut server, spawn_request).await.unwrap();
+    assert_eq!(response["error"]["code"], -32002);
+
+    for (requested, answered) in [
+        ("2025-03-26", "2025-03-26"),
+        ("2024-11-05", "2024-11-05"),
+        ("2025-01-01", "2024-11-05"),
+        ("2024-10-07", "2024-11-05"),
+    ] {
+        let mut server = create_test_server().await;
+        let response = send_request(&mut server, initialize(requested)).await.unwrap();
+        assert_eq!(response["result"]["protocolVersion"], answered, "requested {}", requested);
+    }
+}
+
 #[tokio::test]
 #[serial]
 async fn test_tools_list() {
@@ -88,9 +220,37 @@ async fn test_tools_list() {
         .collect();
     
     assert!(tool_names.contains(&"list_instances".to_string()));
+    assert!(tool_names.contains(&"list_instances_by_tag".to_string()));
+    assert!(tool_names.contains(&"get_instance".to_string()));
     assert!(tool_names.contains(&"spawn_instance".to_string()));
     assert!(tool_names.contains(&"send_keys".to_string()));
+    assert!(tool_names.contains(&"send_keys_to_tag".to_string()));
+    assert!(tool_names.contains(&"send_text".to_string()));
     assert!(tool_names.contains(&"screenshot_instance".to_string()));
+    assert!(tool_names.contains(&"screenshot_region".to_string()));
+    assert!(tool_names.contains(&"close_instance".to_string()));
+    assert!(tool_names.contains(&"kill_all_instances".to_string()));
+    assert!(tool_names.contains(&"get_process_tree".to_string()));
+    assert!(tool_names.contains(&"resize_instance".to_string()));
+    assert!(tool_names.contains(&"focus_instance".to_string()));
+    assert!(tool_names.contains(&"rename_instance".to_string()));
+    assert!(tool_names.contains(&"duplicate_instance".to_string()));
+    assert!(tool_names.contains(&"get_neovim_completions".to_string()));
+    assert!(tool_names.contains(&"set_alacritty_option".to_string()));
+    assert!(tool_names.contains(&"write_file".to_string()));
+    assert!(tool_names.contains(&"read_file".to_string()));
+    assert!(tool_names.contains(&"parse_terminal_content".to_string()));
+    assert!(tool_names.contains(&"minimap_screenshot".to_string()));
+    assert!(tool_names.contains(&"list_neovim_instances".to_string()));
+    assert!(tool_names.contains(&"run_command".to_string()));
+    assert!(tool_names.contains(&"get_neovim_references".to_string()));
+    assert!(tool_names.contains(&"get_neovim_symbol_definition".to_string()));
+    assert!(tool_names.contains(&"get_neovim_workspace_diagnostics".to_string()));
+    assert!(tool_names.contains(&"clone_neovim_buffer".to_string()));
+    assert!(tool_names.contains(&"set_neovim_option".to_string()));
+    assert!(tool_names.contains(&"get_scrollback".to_string()));
+    assert!(tool_names.contains(&"wait_for_output".to_string()));
+    assert!(tool_names.contains(&"get_neovim_hover".to_string()));
 }
 
 #[tokio::test]
@@ -138,7 +298,7 @@ async fn test_list_instances_empty() {
 #[serial]
 async fn test_spawn_instance() {
     // Skip this test if alacritty is not available
-    if Command::new("which").arg("alacritty").output().unwrap().status.success() == false {
+    if !Command::new("which").arg("alacritty").output().unwrap().status.success() {
         return;
     }
 
@@ -250,7 +410,7 @@ async fn test_send_keys_invalid_instance() {
         "params": {
             "name": "send_keys",
             "arguments": {
-                "instance_id": "invalid-id",
+                "instance_id": UNKNOWN_INSTANCE_ID,
                 "keys": "ctrl+c"
             }
         },
@@ -261,7 +421,7 @@ async fn test_send_keys_invalid_instance() {
     
     assert_eq!(response["jsonrpc"], "2.0");
     assert_eq!(response["id"], 2);
-    assert!(!response["error"].is_null());
+    assert_eq!(response["error"]["code"], -32001);
     assert!(response["error"]["message"].as_str().unwrap().contains("Instance not found"));
 }
 
@@ -292,7 +452,7 @@ async fn test_screenshot_invalid_instance() {
         "params": {
             "name": "screenshot_instance",
             "arguments": {
-                "instance_id": "invalid-id",
+                "instance_id": UNKNOWN_INSTANCE_ID,
                 "format": "text"
             }
         },
@@ -307,6 +467,375 @@ async fn test_screenshot_invalid_instance() {
     assert!(response["error"]["message"].as_str().unwrap().contains("Instance not found"));
 }
 
+#[tokio::test]
+#[serial]
+async fn test_close_invalid_instance() {
+    let mut server = create_test_server().await;
+    
+    // Initialize
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {
+                "name": "test-client",
+                "version": "1.0.0"
+            }
+        },
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    let close_request = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {
+            "name": "close_instance",
+            "arguments": {
+                "instance_id": UNKNOWN_INSTANCE_ID
+            }
+        },
+        "id": 2
+    });
+
+    let response = send_request(&mut server, close_request).await.unwrap();
+    
+    assert_eq!(response["jsonrpc"], "2.0");
+    assert_eq!(response["id"], 2);
+    assert!(!response["error"].is_null());
+    assert!(response["error"]["message"].as_str().unwrap().contains("Instance not found"));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_resize_out_of_bounds() {
+    let mut server = create_test_server().await;
+    
+    // Initialize
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {
+                "name": "test-client",
+                "version": "1.0.0"
+            }
+        },
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    for (cols, rows) in [(5, 24), (80, 501)] {
+        let resize_request = json!({
+            "jsonrpc": "2.0",
+            "method": "tools/call",
+            "params": {
+                "name": "resize_instance",
+                "arguments": {
+                    "instance_id": "invalid-id",
+                    "cols": cols,
+                    "rows": rows
+                }
+            },
+            "id": 2
+        });
+
+        let response = send_request(&mut server, resize_request).await.unwrap();
+        
+        assert_eq!(response["id"], 2);
+        assert_eq!(response["error"]["code"], -32602);
+        assert!(response["error"]["message"].as_str().unwrap().contains("between 10 and 500"));
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_wait_for_output_invalid_pattern() {
+    let mut server = create_test_server().await;
+    
+    // Initialize
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {
+                "name": "test-client",
+                "version": "1.0.0"
+            }
+        },
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    let wait_request = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {
+            "name": "wait_for_output",
+            "arguments": {
+                "instance_id": "invalid-id",
+                "pattern": "unclosed ("
+            }
+        },
+        "id": 2
+    });
+
+    let response = send_request(&mut server, wait_request).await.unwrap();
+    
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["error"]["code"], -32602);
+    assert!(response["error"]["message"].as_str().unwrap().contains("Invalid pattern"));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_batch_request() {
+    let mut server = create_test_server().await;
+    
+    let batch_request = json!([
+        {
+            "jsonrpc": "2.0",
+            "method": "initialize",
+            "params": {
+                "protocolVersion": "2024-11-05",
+                "capabilities": {},
+                "clientInfo": {
+                    "name": "test-client",
+                    "version": "1.0.0"
+                }
+            },
+            "id": 1
+        },
+        {
+            "jsonrpc": "2.0",
+            "method": "tools/list",
+            "id": 2
+        }
+    ]);
+
+    let response = send_request(&mut server, batch_request).await.unwrap();
+    
+    let responses = response.as_array().unwrap();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0]["id"], 1);
+    assert!(responses[0]["result"]["protocolVersion"].is_string());
+    assert_eq!(responses[1]["id"], 2);
+    assert!(responses[1]["result"]["tools"].is_array());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_batch_with_notifications() {
+    let mut server = create_test_server().await;
+    
+    // The notification gets no entry, the invalid element gets an error entry
+    let batch_request = json!([
+        {
+            "jsonrpc": "2.0",
+            "method": "invalid_method"
+        },
+        42
+    ]);
+
+    let response = send_request(&mut server, batch_request).await.unwrap();
+    
+    let responses = response.as_array().unwrap();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0]["error"]["code"], -32600);
+    assert!(responses[0]["id"].is_null());
+
+    // A batch of only notifications produces no output at all
+    let request_str = json!([{"jsonrpc": "2.0", "method": "invalid_method"}]).to_string();
+    assert!(server.handle_request(&request_str).await.unwrap().is_none());
+
+    // An empty batch is itself an invalid request
+    let response = send_request(&mut server, json!([])).await.unwrap();
+    assert_eq!(response["error"]["code"], -32600);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_initialized_notification_and_ping() {
+    let mut server = create_test_server().await;
+    assert!(!server.client_initialized());
+
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "notifications/initialized"
+    }).to_string();
+    assert!(server.handle_request(&notification).await.unwrap().is_none());
+    assert!(server.client_initialized());
+
+    // Unknown notifications are dropped silently too
+    let notification = json!({"jsonrpc": "2.0", "method": "invalid_method"}).to_string();
+    assert!(server.handle_request(&notification).await.unwrap().is_none());
+
+    // So are notifications for tools, and ones too malformed to dispatch
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": "list_instances", "arguments": {}}
+    }).to_string();
+    assert!(server.handle_request(&notification).await.unwrap().is_none());
+    let notification = json!({"jsonrpc": "2.0", "params": {}}).to_string();
+    assert!(server.handle_request(&notification).await.unwrap().is_none());
+
+    let ping_request = json!({
+        "jsonrpc": "2.0",
+        "method": "$/ping",
+        "id": 7
+    });
+    let response = send_request(&mut server, ping_request).await.unwrap();
+    assert_eq!(response["result"], json!({}));
+    assert_eq!(response["id"], 7);
+    assert!(response["error"].is_null());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_rate_limit_rejects_rapid_requests() {
+    let mut server = McpServer::with_rate_limiter(test_manager(), Some(RateLimiter::new(1.0)));
+
+    let mut rejected = Vec::new();
+    for id in 0..100 {
+        let ping_request = json!({"jsonrpc": "2.0", "method": "$/ping", "id": id});
+        let response = send_request(&mut server, ping_request).await.unwrap();
+        if !response["error"].is_null() {
+            rejected.push(response);
+        }
+    }
+
+    // One request per second with a burst of one: only the first gets through
+    assert_eq!(rejected.len(), 99);
+    assert_eq!(rejected[0]["error"]["code"], -32029);
+    assert!(rejected[0]["error"]["data"]["retry_after_ms"].as_u64().unwrap() > 0);
+
+    // Other methods have their own budget
+    let tools_request = json!({"jsonrpc": "2.0", "method": "tools/list", "id": 100});
+    let response = send_request(&mut server, tools_request).await.unwrap();
+    assert_ne!(response["error"]["code"], -32029);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_resources_read_errors() {
+    let mut server = create_test_server().await;
+
+    let read_request = json!({
+        "jsonrpc": "2.0",
+        "method": "resources/read",
+        "params": {"uri": "alacritty://instances/missing"},
+        "id": 1
+    });
+    let response = send_request(&mut server, read_request.clone()).await.unwrap();
+    assert_eq!(response["error"]["code"], -32002);
+    assert_eq!(response["error"]["message"], "Server not initialized");
+
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 2
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    let response = send_request(&mut server, read_request).await.unwrap();
+    assert_eq!(response["error"]["code"], -32002);
+    assert!(response["error"]["message"].as_str().unwrap().contains("Resource not found"));
+
+    let bad_uri_request = json!({
+        "jsonrpc": "2.0",
+        "method": "resources/read",
+        "params": {"uri": "file:///etc/passwd"},
+        "id": 3
+    });
+    let response = send_request(&mut server, bad_uri_request).await.unwrap();
+    assert_eq!(response["error"]["code"], -32602);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_prompts_list_and_get() {
+    let mut server = create_test_server().await;
+
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    let response = send_request(&mut server, init_request).await.unwrap();
+    assert!(response["result"]["capabilities"]["prompts"].is_object());
+
+    let list_request = json!({"jsonrpc": "2.0", "method": "prompts/list", "id": 2});
+    let response = send_request(&mut server, list_request).await.unwrap();
+    let prompts = response["result"]["prompts"].as_array().unwrap();
+    assert!(prompts.iter().any(|prompt| prompt["name"] == "run_command_and_wait"));
+    assert!(prompts.iter().all(|prompt| prompt.get("template").is_none()));
+
+    let get_request = json!({
+        "jsonrpc": "2.0",
+        "method": "prompts/get",
+        "params": {
+            "name": "open_file_in_neovim",
+            "arguments": {"instance_id": "abc", "file": "src/main.rs"}
+        },
+        "id": 3
+    });
+    let response = send_request(&mut server, get_request).await.unwrap();
+    let messages = response["result"]["messages"].as_array().unwrap();
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(messages[0]["content"]["type"], "text");
+    assert!(messages[0]["content"]["text"].as_str().unwrap().contains(":edit src/main.rs"));
+
+    let missing_request = json!({
+        "jsonrpc": "2.0",
+        "method": "prompts/get",
+        "params": {"name": "open_file_in_neovim", "arguments": {"instance_id": "abc"}},
+        "id": 4
+    });
+    let response = send_request(&mut server, missing_request).await.unwrap();
+    assert_eq!(response["error"]["code"], -32602);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_invalid_request_ids() {
+    let mut server = create_test_server().await;
+
+    for id in [json!(1.5), json!({"a": 1}), json!([1, 2])] {
+        let request = json!({"jsonrpc": "2.0", "method": "ping", "id": id});
+        let response = send_request(&mut server, request).await.unwrap();
+        assert_eq!(response["error"]["code"], -32600, "id {}", id);
+        assert!(response["id"].is_null());
+    }
+
+    // Each bad ID in a batch is rejected on its own
+    let batch = json!([
+        {"jsonrpc": "2.0", "method": "ping", "id": 2.5},
+        {"jsonrpc": "2.0", "method": "ping", "id": 3}
+    ]);
+    let response = server.handle_request(&batch.to_string()).await.unwrap().unwrap();
+    let responses: Value = serde_json::from_str(&response).unwrap();
+    assert_eq!(responses[0]["error"]["code"], -32600);
+    assert_eq!(responses[1]["id"], 3);
+    assert!(responses[1]["error"].is_null());
+}
+
 #[tokio::test]
 #[serial]
 async fn test_malformed_json_request() {
@@ -314,4 +843,384 @@ async fn test_malformed_json_request() {
     
     let result = server.handle_request("invalid json").await;
     assert!(result.is_err());
-}
\ No newline at end of file
+}
+#[tokio::test]
+#[serial]
+async fn test_unix_socket_transport() {
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixStream;
+    use alacritty_mcp::UnixSocketServer;
+
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("mcp.sock");
+
+    let listener = UnixSocketServer::bind(&socket_path).unwrap();
+    assert!(UnixSocketServer::bind(&socket_path).is_err(), "live socket must not be replaced");
+    let serve = tokio::spawn(listener.serve(Arc::new(test_manager())));
+
+    // Two clients at once, each with its own session
+    let mut clients = Vec::new();
+    for id in 1..=2 {
+        let stream = UnixStream::connect(&socket_path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "initialize",
+            "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test", "version": "1.0"}},
+            "id": id
+        });
+        writer.write_all(format!("{}\n", request).as_bytes()).await.unwrap();
+        clients.push((BufReader::new(reader).lines(), writer, id));
+    }
+
+    for (lines, _, id) in clients.iter_mut() {
+        let line = timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+        let response: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response["id"], *id);
+        assert!(response["result"]["serverInfo"].is_object());
+    }
+
+    // Dropping the listener removes the socket file
+    serve.abort();
+    let _ = serve.await;
+    assert!(!socket_path.exists());
+
+    // A leftover file nobody listens on is replaced
+    drop(clients);
+    std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+    let listener = UnixSocketServer::bind(&socket_path).unwrap();
+    assert_eq!(listener.path(), socket_path);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_tcp_transport() {
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpStream;
+    use alacritty_mcp::TcpServer;
+
+    // Any address but loopback is reachable by others, in plain text
+    assert!(TcpServer::bind("0.0.0.0:0").await.unwrap().is_exposed());
+
+    let listener = TcpServer::bind("127.0.0.1:0").await.unwrap();
+    assert!(!listener.is_exposed());
+    let addr = listener.local_addr().unwrap();
+    let serve = tokio::spawn(listener.serve(Arc::new(test_manager())));
+
+    for id in 1..=2 {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let request = json!({"jsonrpc": "2.0", "method": "ping", "id": id});
+        writer.write_all(format!("{}\n", request).as_bytes()).await.unwrap();
+
+        let mut lines = BufReader::new(reader).lines();
+        let line = timeout(Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+        let response: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(response["id"], id);
+        assert!(response["result"].is_object());
+    }
+
+    serve.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_health() {
+    let mut server = create_test_server().await;
+
+    // Answered before the handshake, unlike tools/call
+    let request = json!({"jsonrpc": "2.0", "method": "health", "id": 1});
+    let before = send_request(&mut server, request).await.unwrap();
+    assert!(before["error"].is_null(), "{}", before);
+    assert_eq!(before["result"]["status"], "ok");
+    assert_eq!(before["result"]["version"], env!("CARGO_PKG_VERSION"));
+    assert!(before["result"]["instances"].is_u64());
+    assert!(before["result"]["uptime_secs"].is_u64());
+
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 2
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    let request = json!({"jsonrpc": "2.0", "method": "health", "id": 3});
+    let after = send_request(&mut server, request).await.unwrap();
+    assert_eq!(after["result"]["status"], "ok");
+    assert_eq!(after["result"]["instances"], before["result"]["instances"]);
+    assert!(after["result"]["uptime_secs"].as_u64() >= before["result"]["uptime_secs"].as_u64());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_health_http() {
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use alacritty_mcp::HealthServer;
+
+    let listener = HealthServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let serve = tokio::spawn(listener.serve(Arc::new(test_manager())));
+
+    let get = |path: &'static str| async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+        response
+    };
+
+    let response = get("/health").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Content-Type: application/json\r\n"));
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let health: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["version"], env!("CARGO_PKG_VERSION"));
+
+    assert!(get("/metrics").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    serve.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_malformed_instance_id() {
+    let mut server = create_test_server().await;
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    // Not UUIDs, not v4, or not in the form the manager hands out
+    for instance_id in ["invalid-id", "", "6ba7b810-9dad-11d1-80b4-00c04fd430c8", "00000000-0000-4000-8000-00000000000A", "00000000000040008000000000000000"] {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "tools/call",
+            "params": {"name": "get_instance", "arguments": {"instance_id": instance_id}},
+            "id": 2
+        });
+        let response = send_request(&mut server, request).await.unwrap();
+        assert_eq!(response["error"]["code"], -32602, "{}", instance_id);
+        assert!(response["error"]["message"].as_str().unwrap().contains("Invalid instance_id format"));
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_invalid_tool_arguments() {
+    let mut server = create_test_server().await;
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    // Missing instance_id, a mistyped one, and a tool that does not exist
+    for (id, (name, arguments)) in [
+        ("get_instance", json!({})),
+        ("send_keys", json!({"instance_id": 5, "keys": "Return"})),
+        ("no_such_tool", json!({})),
+    ].into_iter().enumerate() {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "tools/call",
+            "params": {"name": name, "arguments": arguments},
+            "id": id + 2
+        });
+        let response = send_request(&mut server, request).await.unwrap();
+        assert_eq!(response["error"]["code"], -32602, "{}: {}", name, response);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_metrics_middleware() {
+    let mut server = create_test_server().await;
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    for (id, instance_id) in [(2, UNKNOWN_INSTANCE_ID), (3, UNKNOWN_INSTANCE_ID)] {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "tools/call",
+            "params": {"name": "get_instance", "arguments": {"instance_id": instance_id}},
+            "id": id
+        });
+        send_request(&mut server, request).await.unwrap();
+    }
+    let request = json!({"jsonrpc": "2.0", "method": "tools/call", "params": {"name": "list_instances", "arguments": {}}, "id": 4});
+    send_request(&mut server, request).await.unwrap();
+
+    let response = send_request(&mut server, json!({"jsonrpc": "2.0", "method": "get_metrics", "id": 5})).await.unwrap();
+    let tools = &response["result"]["tools"];
+    assert_eq!((tools["get_instance"]["calls"].as_u64(), tools["get_instance"]["errors"].as_u64()), (Some(2), Some(2)));
+    assert_eq!((tools["list_instances"]["calls"].as_u64(), tools["list_instances"]["errors"].as_u64()), (Some(1), Some(0)));
+    assert!(tools["list_instances"]["total_ms"].is_u64());
+}
+
+/// Rejects every call to one tool and records the calls it sees finish.
+struct DenyTool {
+    denied: &'static str,
+    finished: std::sync::Arc<std::sync::Mutex<Vec<(String, bool)>>>,
+}
+
+impl Middleware for DenyTool {
+    fn before_call<'a>(&'a self, tool: &'a str, _args: &'a Value) -> HookFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            if tool == self.denied {
+                return Err(AlacrittyMcpError::InvalidParams(format!("{} is not allowed", tool)).into());
+            }
+            Ok(())
+        })
+    }
+
+    fn after_call<'a>(&'a self, tool: &'a str, result: &'a anyhow::Result<ToolContent>, _elapsed: Duration) -> HookFuture<'a, ()> {
+        Box::pin(async move {
+            self.finished.lock().unwrap().push((tool.to_string(), result.is_ok()));
+        })
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_custom_middleware() {
+    let finished = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut server = McpServer::new(test_manager()).with_middleware(vec![
+        Box::new(DenyTool { denied: "spawn_instance", finished: std::sync::Arc::clone(&finished) }),
+    ]);
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test-client", "version": "1.0.0"}},
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    let request = json!({"jsonrpc": "2.0", "method": "tools/call", "params": {"name": "spawn_instance", "arguments": {}}, "id": 2});
+    let denied = send_request(&mut server, request).await.unwrap();
+    assert_eq!(denied["error"]["code"], -32602);
+    assert_eq!(denied["error"]["message"], "spawn_instance is not allowed");
+
+    let request = json!({"jsonrpc": "2.0", "method": "tools/call", "params": {"name": "list_instances", "arguments": {}}, "id": 3});
+    let allowed = send_request(&mut server, request).await.unwrap();
+    assert!(allowed["error"].is_null(), "{}", allowed);
+
+    // The rejected call never ran, so only the other one finished
+    assert_eq!(finished.lock().unwrap().as_slice(), &[("list_instances".to_string(), true)]);
+
+    // Without a metrics middleware there is nothing to report
+    let response = send_request(&mut server, json!({"jsonrpc": "2.0", "method": "get_metrics", "id": 4})).await.unwrap();
+    assert_eq!(response["result"], json!({}));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_tools_before_initialize() {
+    let mut server = create_test_server().await;
+    let call = |id: u64, name: &str| json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": name, "arguments": {}},
+        "id": id
+    });
+
+    let list_request = json!({"jsonrpc": "2.0", "method": "tools/list", "id": 1});
+    let response = send_request(&mut server, list_request.clone()).await.unwrap();
+    assert!(response["result"]["tools"].as_array().unwrap().len() > 10);
+    assert_eq!(response["result"]["_meta"]["_warning"], PRE_INIT_WARNING);
+
+    let response = send_request(&mut server, call(2, "list_instances")).await.unwrap();
+    assert!(response["result"]["content"][0]["text"].is_string());
+    assert_eq!(response["result"]["_meta"]["_warning"], PRE_INIT_WARNING);
+
+    for gated in ["spawn_instance", "kill_all_instances"] {
+        let response = send_request(&mut server, call(3, gated)).await.unwrap();
+        assert_eq!(response["error"]["code"], -32002);
+        assert_eq!(response["error"]["message"], "Server not initialized");
+    }
+
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 4
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    let response = send_request(&mut server, list_request).await.unwrap();
+    assert!(response["result"].get("_meta").is_none());
+    let response = send_request(&mut server, call(5, "list_instances")).await.unwrap();
+    assert!(response["result"].get("_meta").is_none());
+
+    // The set can be narrowed
+    let mut server = create_test_server().await.with_pre_init_tools(Vec::new());
+    let response = send_request(&mut server, call(6, "list_instances")).await.unwrap();
+    assert_eq!(response["error"]["code"], -32002);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_subscribe_neovim_changes_needs_notifications() {
+    let mut server = create_test_server().await;
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+
+    let call = |id: u64, name: &str| json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": name, "arguments": {"instance_id": UNKNOWN_INSTANCE_ID}},
+        "id": id
+    });
+
+    let response = send_request(&mut server, call(2, "subscribe_neovim_changes")).await.unwrap();
+    assert!(response["error"]["message"].as_str().unwrap().contains("notifications"));
+
+    let response = send_request(&mut server, call(3, "unsubscribe_neovim_changes")).await.unwrap();
+    assert_eq!(response["error"]["code"], -32602);
+    assert!(response["error"]["message"].as_str().unwrap().contains("no Neovim changes subscription"));
+
+    // With notifications the instance is looked up
+    let mut server = create_test_server().await;
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {"notifications": {}},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    send_request(&mut server, init_request).await.unwrap();
+    let response = send_request(&mut server, call(2, "subscribe_neovim_changes")).await.unwrap();
+    assert_eq!(response["error"]["code"], -32001);
+}
@@ -3,18 +3,30 @@ use std::time::Duration;
 use tokio::time::{timeout, sleep};
 use serde_json::{json, Value};
 use serial_test::serial;
-use alacritty_mcp::{AlacrittyManager, McpServer, NeovimContextExtractor};
+use alacritty_mcp::{
+    current_buffer_lua_script, find_function_context, AlacrittyManager, AlacrittyMcpError, Config, FunctionContext, McpServer, NeovimContextExtractor, StatusLine,
+    hover_lua_script, HoverResult, completion_lua_script, CompletionItem, references_lua_script, Reference, definition_lua_script, DefinitionResult, CodeAction, code_actions_lua_script, TerminalContext, EditorType,
+    HelixContextExtractor, DiagnosticSeverity, TmuxContext, types::NeovimContextParams,
+    MAX_CONTEXT_LINES, set_option_keys, Mark, MARKS_LUA_SCRIPT, RegisterContent, REGISTERS_LUA_SCRIPT, JumpEntry, jumplist_lua_script, SearchHistoryEntry, search_history_lua_script, is_vim_regex, TsNode, TREESITTER_NODES_LUA_SCRIPT, MAX_TS_NODE_TEXT_CHARS, SpellError, spell_errors_lua_script, SessionVariables, session_variables_lua_script, BufferChangeEvent, buffer_watch_start_lua_script, buffer_watch_drain_lua_script, buffer_watch_stop_lua_script, QuickfixEntry, quickfix_lua_script, GitStatus, validate_ex_command, ExecResult, lua_string, test_start_lua_script, test_status_lua_script,
+};
 use std::collections::HashSet;
 use std::os::unix::process::ExitStatusExt;
 
+/// A manager on the default config with no state file, so tests never read
+/// the developer's config or write to their registry.
+fn test_manager() -> AlacrittyManager {
+    AlacrittyManager::with_config_and_state_file(Config::default(), None)
+}
+
 async fn create_test_server() -> McpServer {
-    let manager = AlacrittyManager::new();
+    let manager = test_manager();
     McpServer::new(manager)
 }
 
 async fn send_request(server: &mut McpServer, request: Value) -> Result<Value, Box<dyn std::error::Error>> {
     let request_str = serde_json::to_string(&request)?;
-    let response_str = server.handle_request(&request_str).await?;
+    let response_str = server.handle_request(&request_str).await?
+        .ok_or("no response for request")?;
     let response: Value = serde_json::from_str(&response_str)?;
     Ok(response)
 }
@@ -37,9 +49,73 @@ async fn initialize_server(server: &mut McpServer) -> Result<(), Box<dyn std::er
     Ok(())
 }
 
+/// A fake `nvim` first on `PATH` that appends its arguments to `nvim.log`, plus the
+/// `/tmp/nvim.<pid>.0` socket `find_neovim_socket` looks for. `PATH` is restored and
+/// the socket removed on drop, so a failing test does not leak either.
+struct FakeNvim {
+    dir: tempfile::TempDir,
+    original_path: String,
+    socket: String,
+}
+
+impl FakeNvim {
+    /// Answers every call with `stdout`.
+    fn new(stdout: &str) -> Self {
+        let nvim = Self::with_script("cat 'DIR/stdout'");
+        std::fs::write(nvim.dir().join("stdout"), stdout).unwrap();
+        nvim
+    }
+
+    /// Runs `body` after logging the call; `DIR` in it stands for the tempdir.
+    fn with_script(body: &str) -> Self {
+        let dir = tempfile::TempDir::new().unwrap();
+        let socket = format!("/tmp/nvim.{}.0", std::process::id());
+        std::fs::write(&socket, "").unwrap();
+        let original_path = std::env::var("PATH").unwrap_or_default();
+        std::env::set_var("PATH", format!("{}:{}", dir.path().display(), original_path));
+
+        let nvim = Self { dir, original_path, socket };
+        nvim.write_tool("nvim", body);
+        nvim
+    }
+
+    /// Puts another fake tool next to `nvim`, logging to `<name>.log`.
+    fn write_tool(&self, name: &str, body: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = self.dir().display().to_string();
+        let path = self.dir().join(name);
+        std::fs::write(&path, format!("#!/bin/sh\necho \"$*\" >> '{}/{}.log'\n{}\n", dir, name, body.replace("DIR", &dir))).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn dir(&self) -> &std::path::Path {
+        self.dir.path()
+    }
+
+    fn socket(&self) -> &str {
+        &self.socket
+    }
+
+    fn log(&self) -> String {
+        self.tool_log("nvim")
+    }
+
+    fn tool_log(&self, name: &str) -> String {
+        std::fs::read_to_string(self.dir().join(format!("{}.log", name))).unwrap_or_default()
+    }
+}
+
+impl Drop for FakeNvim {
+    fn drop(&mut self) {
+        std::env::set_var("PATH", &self.original_path);
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
 async fn get_alacritty_pids() -> Vec<u32> {
     let output = Command::new("pgrep")
-        .args(&["-f", "alacritty"])
+        .args(["-f", "alacritty"])
         .output()
         .unwrap_or_else(|_| std::process::Output {
             status: std::process::ExitStatus::from_raw(1),
@@ -113,7 +189,7 @@ async fn test_neovim_context_invalid_instance() {
         "params": {
             "name": "get_neovim_context",
             "arguments": {
-                "instance_id": "invalid-id"
+                "instance_id": "00000000-0000-4000-8000-000000000000"
             }
         },
         "id": 2
@@ -312,4 +388,1285 @@ async fn test_neovim_detection_patterns() {
     }
     
     println!("✅ Neovim detection patterns work correctly");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_status_line_parsing() {
+    let status = StatusLine::parse("src/main.rs [+] [rust] 42,17").unwrap();
+    assert_eq!(status.file_path, "src/main.rs");
+    assert!(status.is_modified);
+    assert_eq!(status.file_type, Some("rust".to_string()));
+    assert_eq!((status.line, status.column), (42, 17));
+
+    // Default ruler layout: padding, virtual column and scroll position
+    let status = StatusLine::parse("/tmp/test.txt                      3,5-8          All").unwrap();
+    assert_eq!(status.file_path, "/tmp/test.txt");
+    assert!(!status.is_modified);
+    assert_eq!(status.file_type, None);
+    assert_eq!((status.line, status.column), (3, 5));
+
+    let status = StatusLine::parse("[No Name] [+]  1,1  Top").unwrap();
+    assert_eq!(status.file_path, "[No Name]");
+    assert!(status.is_modified);
+
+    assert!(StatusLine::parse("bash-5.1$ ls").is_none());
+    assert!(StatusLine::parse("").is_none());
+}
+
+#[test]
+fn test_extract_via_terminal_scraping() {
+    let extractor = NeovimContextExtractor::new();
+    let screen = [
+        "  1 fn main() {",
+        "  2     let x = 1;",
+        "  3     println!(\"{}\", x);",
+        "  4 }",
+        "~",
+        "~",
+        "src/main.rs [+] [rust] 2,9",
+        "-- INSERT --",
+    ].join("\n");
+
+    let context = extractor.extract_via_terminal_scraping(4242, &screen).unwrap();
+    
+    assert_eq!(context.instance_info.pid, 4242);
+    assert!(context.instance_info.socket_path.is_none());
+    assert_eq!(context.vim_mode, Some("i".to_string()));
+
+    let cursor = context.cursor_position.unwrap();
+    assert_eq!((cursor.line, cursor.column), (2, 9));
+    assert_eq!(cursor.line_content, "    let x = 1;");
+
+    let buffer = context.current_buffer.unwrap();
+    assert_eq!(buffer.file_path, "src/main.rs");
+    assert_eq!(buffer.file_type, Some("rust".to_string()));
+    assert!(buffer.is_modified);
+    assert!(buffer.content_preview.contains("println!"));
+    assert!(!buffer.content_preview.contains('~'));
+    assert_eq!(buffer.surrounding_context.lines_before, vec!["fn main() {"]);
+    assert_eq!(buffer.surrounding_context.lines_after.len(), 2);
+
+    // Plain shell output is rejected so the caller can fall back further
+    assert!(extractor.extract_via_terminal_scraping(4242, "bash-5.1$ ls").is_err());
+}
+
+#[test]
+fn test_current_buffer_lua_script_context_lines() {
+    let script = current_buffer_lua_script(12);
+    assert!(script.contains("current_line_nr - 12"));
+    assert!(script.contains("current_line_nr + 12"));
+    assert!(!script.contains("{context_lines}"));
+
+    let clamped = current_buffer_lua_script(80);
+    assert!(clamped.contains(&format!("current_line_nr - {}", MAX_CONTEXT_LINES)));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_context_lines_reaches_socket_query() {
+    // A fake `nvim` that records what it was asked, plus a socket path for it to "connect" to
+    let nvim = FakeNvim::new("");
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let params = NeovimContextParams {
+        instance_id: "unused".to_string(),
+        include_diagnostics: None,
+        include_buffers: None,
+        context_lines: Some(12),
+    };
+    let _ = extractor.extract_context_from_instance(pid, &params, None).await;
+
+    let calls = nvim.log();
+    assert!(calls.contains(&format!("--server {}", nvim.socket())));
+    assert!(calls.contains("current_line_nr - 12"));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_excluded_sections_skip_socket_queries() {
+    let nvim = FakeNvim::new("");
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let mut params = NeovimContextParams {
+        instance_id: "unused".to_string(),
+        include_diagnostics: Some(false),
+        include_buffers: Some(false),
+        context_lines: None,
+    };
+    let _ = extractor.extract_context_from_instance(pid, &params, None).await;
+    let skipped = nvim.log();
+    std::fs::remove_file(nvim.dir().join("nvim.log")).unwrap();
+
+    params.include_diagnostics = None;
+    params.include_buffers = None;
+    let _ = extractor.extract_context_from_instance(pid, &params, None).await;
+    let full = nvim.log();
+
+    assert!(skipped.contains("nvim_win_get_cursor"), "other sections are still queried");
+    assert!(!skipped.contains("diag.bufnr"));
+    assert!(!skipped.contains("nvim_list_bufs"));
+    assert!(full.contains("diag.bufnr"));
+    assert!(full.contains("nvim_list_bufs"));
+}
+
+#[test]
+fn test_tmux_detection_and_parsing() {
+    let extractor = NeovimContextExtractor::new();
+
+    let screen = "user@host:~$ cargo build\n   Compiling foo\n\n[main] 0:bash* 1:vim-  \"host\" 12:01 14-Oct-26\n";
+    assert!(extractor.detect_tmux_in_terminal(screen));
+    assert!(!extractor.detect_tmux_in_terminal("bash-5.1$ ls\n[main] is a branch"));
+    assert!(!extractor.detect_tmux_in_terminal(""));
+
+    let context = TmuxContext::parse("/tmp/tmux-1000/default", "/home/user/src\tbuild pane\tcargo\t120\t40\n").unwrap();
+    assert_eq!(context.socket_path, "/tmp/tmux-1000/default");
+    assert_eq!(context.pane_current_path, "/home/user/src");
+    assert_eq!(context.pane_title, "build pane");
+    assert_eq!(context.current_command, "cargo");
+    assert_eq!((context.pane_width, context.pane_height), (120, 40));
+
+    assert!(TmuxContext::parse("sock", "only\ttwo").is_none());
+    assert!(TmuxContext::parse("sock", "a\tb\tc\twide\t40").is_none());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_extract_tmux_context_from_instance() {
+    // A fake `tmux` answering for a socket under a private TMUX_TMPDIR
+    let fake = FakeNvim::new("");
+    fake.write_tool("tmux", r"printf '/srv/app\tlogs\ttail\t80\t24\n'");
+
+    let uid = String::from_utf8(Command::new("id").arg("-u").output().unwrap().stdout).unwrap();
+    let socket_dir = fake.dir().join(format!("tmux-{}", uid.trim()));
+    std::fs::create_dir(&socket_dir).unwrap();
+    std::fs::write(socket_dir.join("default"), "").unwrap();
+
+    std::env::set_var("TMUX_TMPDIR", fake.dir());
+
+    let extractor = NeovimContextExtractor::new();
+    let params = NeovimContextParams {
+        instance_id: "unused".to_string(),
+        include_diagnostics: None,
+        include_buffers: None,
+        context_lines: None,
+    };
+    let tmux_screen = "$ tail -f app.log\n[logs] 0:tail*";
+    let with_tmux = extractor.extract_context_from_instance(u32::MAX, &params, Some(tmux_screen)).await.unwrap();
+    let plain = extractor.extract_context_from_instance(u32::MAX, &params, Some("$ ls")).await.unwrap();
+
+    std::env::remove_var("TMUX_TMPDIR");
+
+    match with_tmux {
+        TerminalContext::Tmux(context) => {
+            assert_eq!(context.socket_path, socket_dir.join("default").to_string_lossy());
+            assert_eq!(context.pane_current_path, "/srv/app");
+            assert_eq!(context.current_command, "tail");
+            assert_eq!((context.pane_width, context.pane_height), (80, 24));
+        }
+        other => panic!("expected tmux context, got {:?}", other),
+    }
+    assert!(matches!(plain, TerminalContext::PlainTerminal));
+}
+
+#[test]
+fn test_hover_script_and_parsing() {
+    let script = hover_lua_script(Some(12), Some(5));
+    assert!(script.contains("local line, col = 11, 4"));
+    assert!(script.contains("\"textDocument/hover\", params, 2000"));
+
+    // Missing parts fall back to the cursor
+    assert!(hover_lua_script(None, Some(1)).contains("local line, col = -1, 0"));
+
+    let hover = HoverResult::parse(
+        r#"{"content": "```rust\nfn main()\n```", "language": "rust", "range": [3, 4, 3, 8]}"#
+    ).unwrap().unwrap();
+    assert_eq!(hover.content, "```rust\nfn main()\n```");
+    assert_eq!(hover.language, Some("rust".to_string()));
+    assert_eq!(hover.range, Some((3, 4, 3, 8)));
+
+    let hover = HoverResult::parse(r#"{"content": "docs"}"#).unwrap().unwrap();
+    assert_eq!((hover.language, hover.range), (None, None));
+
+    // An empty object means no language server answered
+    assert!(HoverResult::parse("{}").unwrap().is_none());
+    assert!(HoverResult::parse("E5108: Error executing lua").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_hover_through_socket() {
+    // A fake `nvim` that answers every remote expression with a hover result
+    let _nvim = FakeNvim::new(r#"{"content": "pub fn new() -> Self", "range": [1, 1, 1, 4]}"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let hover = extractor.get_hover(pid, None, None).await;
+
+    let hover = hover.unwrap().unwrap();
+    assert_eq!(hover.content, "pub fn new() -> Self");
+    assert_eq!(hover.range, Some((1, 1, 1, 4)));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_socket_from_nvim_env() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let socket = dir.path().join("nvim-listen");
+    std::fs::write(&socket, "").unwrap();
+
+    // Stand-ins for processes Neovim started, e.g. a language server. A
+    // socket that no longer exists is passed over.
+    let mut children = Vec::new();
+    for address in [dir.path().join("gone"), socket.clone()] {
+        children.push(Command::new("sleep").arg("10").env("NVIM", &address).spawn().unwrap());
+    }
+
+    let extractor = NeovimContextExtractor::new();
+    let found = extractor.find_neovim_socket(std::process::id()).await;
+
+    for child in children.iter_mut() {
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+    assert_eq!(found.unwrap(), socket.display().to_string());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_socket_lookup_is_cached() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<NeovimContextExtractor>();
+
+    // Only the fake `lsof` knows where this socket is, and it logs each call
+    let nvim = FakeNvim::new(r#"{"content": "docs"}"#);
+    nvim.write_tool("lsof", "echo 'nvim 1 user 9u unix 0x0 0t0 1 socket DIR/nvim-socket'");
+    let socket = nvim.dir().join("nvim-socket");
+    std::fs::write(&socket, "").unwrap();
+
+    let extractor = NeovimContextExtractor::new();
+    let lsof_calls = || nvim.tool_log("lsof").lines().count();
+
+    let first = extractor.get_hover(u32::MAX, None, None).await;
+    let second = extractor.get_hover(u32::MAX, None, None).await;
+    let calls_while_cached = lsof_calls();
+
+    // A socket that vanished is looked up again rather than trusted
+    std::fs::remove_file(&socket).unwrap();
+    let third = extractor.get_hover(u32::MAX, None, None).await;
+
+    assert!(first.unwrap().is_some());
+    assert!(second.unwrap().is_some());
+    assert_eq!(calls_while_cached, 1);
+    assert!(third.unwrap().is_some());
+    assert_eq!(lsof_calls(), 2);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_hung_nvim_times_out() {
+    // A fake `nvim` that never answers within the command timeout
+    let _nvim = FakeNvim::with_script("exec sleep 6");
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let started = std::time::Instant::now();
+    let hover = timeout(Duration::from_secs(10), extractor.get_hover(pid, None, None)).await;
+    let elapsed = started.elapsed();
+
+    let err = hover.expect("get_hover hung past the command timeout").unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::NeovimRpcTimeout { .. })));
+    assert!(elapsed < Duration::from_secs(6), "took {:?}", elapsed);
+}
+
+#[test]
+fn test_find_function_context() {
+    let rust = "struct Server;\n\nimpl Server {\n    pub fn handle(&self, id: u32) -> bool {\n        let ok = id > 0;\n        ok\n    }\n}\n";
+    assert_eq!(find_function_context(rust, Some("rust"), 5), Some(FunctionContext {
+        name: "handle".to_string(),
+        signature: "pub fn handle(&self, id: u32) -> bool".to_string(),
+        start_line: 4,
+        end_line: 7,
+    }));
+    assert_eq!(find_function_context(rust, Some("rust"), 1), None);
+
+    let python = "def outer(x):\n    def inner(y: int) -> int:\n        return y * 2\n    return inner(x)\n";
+    let inner = find_function_context(python, Some("python"), 3).unwrap();
+    assert_eq!((inner.name.as_str(), inner.signature.as_str()), ("inner", "def inner(y: int) -> int"));
+    assert_eq!((inner.start_line, inner.end_line), (2, 3));
+    assert_eq!(find_function_context(python, Some("python"), 4).unwrap().name, "outer");
+
+    let typescript = "class Api {\n  fetch(url: string): Promise<void> {\n    return get(url);\n  }\n}\nconst add = (a: number, b: number) => {\n  return a + b;\n};\n";
+    let method = find_function_context(typescript, Some("typescript"), 3).unwrap();
+    assert_eq!((method.name.as_str(), method.signature.as_str()), ("fetch", "fetch(url: string): Promise<void>"));
+    let arrow = find_function_context(typescript, Some("typescript"), 7).unwrap();
+    assert_eq!((arrow.name.as_str(), arrow.signature.as_str()), ("add", "add = (a: number, b: number)"));
+
+    // No grammar for C, so braces are counted; control-flow blocks are skipped
+    let c = "int main(int argc, char **argv) {\n    if (argc > 1) {\n        return 1;\n    }\n    return 0;\n}\n";
+    assert_eq!(find_function_context(c, Some("c"), 3), Some(FunctionContext {
+        name: "main".to_string(),
+        signature: "int main(int argc, char **argv)".to_string(),
+        start_line: 1,
+        end_line: 6,
+    }));
+    assert_eq!(find_function_context(c, None, 7), None);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_function_context_through_socket() {
+    // A fake `nvim` answering the current buffer query and the full buffer read
+    let _nvim = FakeNvim::with_script(r#"case "$*" in
+  *current_line_nr*) printf '{"file_path": "/src/lib.rs", "file_type": "rust", "current_line_nr": 2, "current_line": "    1"}' ;;
+  *table.concat*) printf '"fn one() -> u32 {\\n    1\\n}\\n"' ;;
+esac"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let params = NeovimContextParams {
+        instance_id: "unused".to_string(),
+        include_diagnostics: None,
+        include_buffers: None,
+        context_lines: None,
+    };
+    let context = extractor.extract_context_from_instance(pid, &params, None).await;
+
+    let TerminalContext::Neovim(context) = context.unwrap() else {
+        panic!("expected Neovim context");
+    };
+    let function = context.current_buffer.unwrap().surrounding_context.function_context.unwrap();
+    assert_eq!(function.name, "one");
+    assert_eq!(function.signature, "fn one() -> u32");
+    assert_eq!((function.start_line, function.end_line), (1, 3));
+}
+
+#[test]
+fn test_completion_script_and_parsing() {
+    let script = completion_lua_script(7);
+    assert!(script.contains("\"textDocument/completion\", params, 2000"));
+    assert!(script.contains("#result >= 7"));
+
+    let items = CompletionItem::parse_list(r#"[
+        {"label": "push", "kind": "Method", "detail": "fn(&mut self, T)", "documentation": "Appends an element"},
+        {"label": "pop", "kind": "Method"},
+        {"kind": "Text"},
+        {"label": "len", "detail": ""}
+    ]"#, 10).unwrap();
+    assert_eq!(items.len(), 3);
+    assert_eq!(items[0], CompletionItem {
+        label: "push".to_string(),
+        kind: Some("Method".to_string()),
+        detail: Some("fn(&mut self, T)".to_string()),
+        documentation: Some("Appends an element".to_string()),
+    });
+    assert_eq!((items[2].kind.as_deref(), items[2].detail.as_deref()), (None, None));
+
+    assert_eq!(CompletionItem::parse_list(r#"[{"label": "a"}, {"label": "b"}]"#, 1).unwrap().len(), 1);
+    assert!(CompletionItem::parse_list("{}", 10).unwrap().is_empty());
+    assert!(CompletionItem::parse_list("E5108: Error executing lua", 10).is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_completions_through_socket() {
+    // A fake `nvim` that logs its arguments and answers with three items
+    let nvim = FakeNvim::new(r#"[{"label": "a"}, {"label": "b"}, {"label": "c"}]"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let capped = extractor.get_completions(pid, Some(2)).await;
+    let clamped = extractor.get_completions(pid, Some(10_000)).await;
+
+    let labels: Vec<String> = capped.unwrap().into_iter().map(|item| item.label).collect();
+    assert_eq!(labels, vec!["a", "b"]);
+    assert_eq!(clamped.unwrap().len(), 3);
+
+    let calls = nvim.log();
+    assert!(calls.contains("#result >= 2"));
+    assert!(calls.contains("#result >= 500"));
+}
+
+#[test]
+fn test_references_script_and_grouping() {
+    assert!(references_lua_script(true).contains("includeDeclaration = true"));
+    assert!(references_lua_script(false).contains("includeDeclaration = false"));
+    assert!(references_lua_script(false).contains("\"textDocument/references\", params, 2000"));
+
+    let references = Reference::parse_list(r#"[
+        {"file_path": "/src/main.rs", "line": 12, "column": 5, "preview": "    run(config);"},
+        {"file_path": "/src/lib.rs", "line": 3, "column": 8, "preview": "pub fn run(config: Config) {"},
+        {"file_path": "/src/main.rs", "line": 4, "column": 1, "preview": "run(default());"},
+        {"file_path": "/src/main.rs", "line": 12, "column": 5, "preview": "    run(config);"},
+        {"line": 1, "column": 1}
+    ]"#).unwrap();
+    assert_eq!(references.len(), 4);
+
+    let grouped = Reference::group_by_file(references);
+    assert_eq!(grouped.keys().collect::<Vec<_>>(), vec!["/src/lib.rs", "/src/main.rs"]);
+    let main = &grouped["/src/main.rs"];
+    assert_eq!(main.iter().map(|r| r.line).collect::<Vec<_>>(), vec![4, 12]);
+    assert_eq!(main[1].preview, "run(config);");
+
+    assert!(Reference::parse_list("{}").unwrap().is_empty());
+    assert!(Reference::parse_list("E5108: Error executing lua").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_references_through_socket() {
+    let nvim = FakeNvim::new(r#"[{"file_path": "/a.rs", "line": 2, "column": 3, "preview": "x"}]"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let references = extractor.get_references(pid, false).await;
+
+    let references = references.unwrap();
+    assert_eq!(references["/a.rs"][0].line, 2);
+    assert!(nvim.log().contains("includeDeclaration = false"));
+}
+
+#[test]
+fn test_definition_script_and_parsing() {
+    let script = definition_lua_script(Some(10), Some(4));
+    assert!(script.contains("local line, col = 9, 3"));
+    assert!(script.contains("\"textDocument/definition\", params, 2000"));
+    assert!(script.contains("location.targetSelectionRange"));
+    assert!(definition_lua_script(None, None).contains("local line, col = -1, -1"));
+
+    let definitions = DefinitionResult::parse_list(r#"[
+        {"file_path": "/src/lib.rs", "line": 3, "column": 8, "preview": "  pub fn run() {", "is_same_file": false},
+        {"file_path": "/src/lib.rs", "line": 3, "column": 8, "preview": "  pub fn run() {", "is_same_file": false},
+        {"file_path": "/src/main.rs", "line": 1, "column": 1, "is_same_file": true},
+        {"line": 1, "column": 1}
+    ]"#).unwrap();
+    assert_eq!(definitions.len(), 2);
+    assert_eq!(definitions[0].preview, "pub fn run() {");
+    assert!(!definitions[0].is_same_file);
+    assert!(definitions[1].is_same_file);
+
+    assert!(DefinitionResult::parse_list("{}").unwrap().is_empty());
+    assert!(DefinitionResult::parse_list("E5108: Error executing lua").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_definition_through_socket() {
+    let nvim = FakeNvim::new(r#"[{"file_path": "/a.rs", "line": 7, "column": 4, "preview": "fn a()", "is_same_file": true}]"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let definitions = extractor.get_definition(pid, Some(2), None).await;
+
+    let definitions = definitions.unwrap();
+    assert_eq!(definitions.len(), 1);
+    assert_eq!((definitions[0].line, definitions[0].column), (7, 4));
+    assert!(definitions[0].is_same_file);
+    assert!(nvim.log().contains("local line, col = 1, -1"));
+}
+
+#[test]
+fn test_marks_script_and_parsing() {
+    assert!(MARKS_LUA_SCRIPT.contains("vim.fn.getmarklist(buf)"));
+    assert!(MARKS_LUA_SCRIPT.contains("mark.pos[1] == buf"));
+    // The script is sent inside luaeval('...')
+    assert!(!MARKS_LUA_SCRIPT.contains('\''));
+
+    let marks = Mark::parse_list(r#"[
+        {"name": "a", "line": 3, "column": 1, "line_content": "    let x = 1;"},
+        {"name": "B", "line": 10, "column": 5, "line_content": "fn main() {"},
+        {"line": 1, "column": 1}
+    ]"#).unwrap();
+    assert_eq!(marks.len(), 2);
+    assert_eq!(marks[0].line_content, "    let x = 1;");
+    assert_eq!((marks[1].name.as_str(), marks[1].line, marks[1].column), ("B", 10, 5));
+
+    assert!(Mark::parse_list("{}").unwrap().is_empty());
+    assert!(Mark::parse_list("E5108: Error executing lua").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_marks_through_socket() {
+    let nvim = FakeNvim::new(r#"[{"name": "m", "line": 4, "column": 2, "line_content": "todo"}]"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let marks = extractor.get_marks(pid).await;
+
+    let marks = marks.unwrap();
+    assert_eq!(marks, vec![Mark { name: "m".to_string(), line: 4, column: 2, line_content: "todo".to_string() }]);
+    assert!(nvim.log().contains("getmarklist"));
+}
+
+#[test]
+fn test_code_actions_script_and_parsing() {
+    let list = code_actions_lua_script(None);
+    assert!(list.contains("vim.lsp.buf_request_sync(0, \"textDocument/codeAction\", params, 2000)"));
+    assert!(list.contains("local index = -1"));
+    let apply = code_actions_lua_script(Some(2));
+    assert!(apply.contains("local index = 2"));
+    assert!(apply.contains("vim.lsp.util.apply_workspace_edit"));
+    // The script is sent inside luaeval('...')
+    assert!(!list.contains('\''));
+
+    let actions = CodeAction::parse_list(r#"[
+        {"title": "Import `HashMap`", "kind": "quickfix", "is_preferred": true},
+        {"title": "Extract into function"},
+        {"kind": "refactor"}
+    ]"#).unwrap();
+    assert_eq!(actions.len(), 2);
+    assert_eq!(actions[0], CodeAction { title: "Import `HashMap`".to_string(), kind: Some("quickfix".to_string()), is_preferred: true });
+    assert_eq!((actions[1].kind.as_deref(), actions[1].is_preferred), (None, false));
+
+    assert!(CodeAction::parse_list("{}").unwrap().is_empty());
+    assert_eq!(CodeAction::parse_applied(r#"{"title": "Remove unused import"}"#).unwrap().title, "Remove unused import");
+    assert!(CodeAction::parse_applied("{}").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_code_actions_through_socket() {
+    // Lists two actions, or applies one when asked for an index; index 5
+    // does not exist and fails like Lua's `error()` would
+    let nvim = FakeNvim::with_script(r#"case "$*" in
+  *'local index = -1'*) printf '[{"title": "Add missing import", "kind": "quickfix", "is_preferred": true}, {"title": "Inline variable"}]' ;;
+  *'local index = 5'*) echo 'No code action 5 at the cursor; there are 2' >&2; exit 1 ;;
+  *) printf '{"title": "Inline variable"}' ;;
+esac"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let actions = extractor.get_lsp_code_actions(pid).await;
+    let applied = extractor.apply_lsp_code_action(pid, 1).await;
+    let missing = extractor.apply_lsp_code_action(pid, 5).await;
+
+    let actions = actions.unwrap();
+    assert_eq!(actions.len(), 2);
+    assert!(actions[0].is_preferred);
+    assert_eq!(applied.unwrap().title, actions[1].title);
+    assert!(missing.unwrap_err().to_string().contains("there are 2"));
+    assert!(nvim.log().contains("local index = 1"));
+}
+
+#[test]
+fn test_registers_script_and_parsing() {
+    assert!(REGISTERS_LUA_SCRIPT.contains("pcall(vim.fn.getreg, name)"));
+    assert!(REGISTERS_LUA_SCRIPT.contains("vim.fn.getregtype(name)"));
+    // The script is sent inside luaeval('...')
+    assert!(!REGISTERS_LUA_SCRIPT.contains('\''));
+
+    let registers = RegisterContent::parse_map(r#"{
+        "a": {"content": "let x = 1;", "type": "v"},
+        "\"": {"content": "fn main() {\n", "type": "V"},
+        "b": {"content": "ab\ncd", "type": "\u00162"},
+        "c": {"content": "", "type": "v"}
+    }"#).unwrap();
+    assert_eq!(registers.keys().collect::<Vec<_>>(), vec!["\"", "a", "b"]);
+    assert_eq!(registers["a"].register_type, "c");
+    assert_eq!(registers["\""], RegisterContent { content: "fn main() {\n".to_string(), register_type: "l".to_string() });
+    assert_eq!(registers["b"].register_type, "b");
+
+    assert!(RegisterContent::parse_map("{}").unwrap().is_empty());
+    assert!(RegisterContent::parse_map("[]").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_registers_through_socket() {
+    let nvim = FakeNvim::new(r#"{"q": {"content": "0dwj", "type": "v"}}"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let registers = extractor.get_registers(pid).await;
+
+    let registers = registers.unwrap();
+    assert_eq!(registers.len(), 1);
+    assert_eq!(registers["q"].content, "0dwj");
+    assert!(nvim.log().contains("getregtype"));
+}
+
+#[test]
+fn test_jumplist_script_and_parsing() {
+    let script = jumplist_lua_script(20);
+    assert!(script.contains("vim.fn.getjumplist()"));
+    assert!(script.contains("#list - 20 + 1"));
+    // The script is sent inside luaeval('...')
+    assert!(!script.contains('\''));
+
+    let jumps = JumpEntry::parse_list(r#"[
+        {"file_path": "/src/main.rs", "line": 10, "column": 1, "current": false},
+        {"file_path": "/src/lib.rs", "line": 42, "column": 5, "current": true},
+        {"file_path": "/src/lib.rs", "column": 1}
+    ]"#).unwrap();
+    assert_eq!(jumps.len(), 2);
+    assert_eq!(jumps[1], JumpEntry { file_path: "/src/lib.rs".to_string(), line: 42, column: 5, current: true });
+
+    assert!(JumpEntry::parse_list("{}").unwrap().is_empty());
+    assert!(JumpEntry::parse_list("null").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_jumplist_through_socket() {
+    let nvim = FakeNvim::new(r#"[{"file_path": "/src/app.py", "line": 3, "column": 8, "current": false}]"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let capped = extractor.get_jumplist(pid, Some(1000)).await;
+    let default = extractor.get_jumplist(pid, None).await;
+
+    let jumps = capped.unwrap();
+    assert_eq!((jumps[0].file_path.as_str(), jumps[0].line, jumps[0].column), ("/src/app.py", 3, 8));
+    assert_eq!(default.unwrap(), jumps);
+
+    let log = nvim.log();
+    assert!(log.contains("#list - 100 + 1"));
+    assert!(log.contains("#list - 50 + 1"));
+}
+
+#[test]
+fn test_treesitter_nodes_script_and_parsing() {
+    assert!(TREESITTER_NODES_LUA_SCRIPT.contains("vim.treesitter.get_node()"));
+    assert!(TREESITTER_NODES_LUA_SCRIPT.contains("vim.treesitter.is_parser_loaded"));
+    // The script is sent inside luaeval('...')
+    assert!(!TREESITTER_NODES_LUA_SCRIPT.contains('\''));
+
+    let long_text = "x".repeat(MAX_TS_NODE_TEXT_CHARS + 10);
+    let nodes = TsNode::parse_list(&json!([
+        {"type": "identifier", "text": "total", "start_row": 4, "start_col": 8, "end_row": 4, "end_col": 13},
+        {"type": "function_item", "text": long_text, "start_row": 2, "start_col": 0, "end_row": 9, "end_col": 1},
+        {"text": "missing type", "start_row": 0, "start_col": 0, "end_row": 0, "end_col": 0}
+    ]).to_string()).unwrap();
+    assert_eq!(nodes.len(), 2);
+    assert_eq!(nodes[0], TsNode {
+        node_type: "identifier".to_string(),
+        text: "total".to_string(),
+        start_row: 4,
+        start_col: 8,
+        end_row: 4,
+        end_col: 13,
+    });
+    assert_eq!(nodes[1].text.chars().count(), MAX_TS_NODE_TEXT_CHARS + 3);
+    assert!(nodes[1].text.ends_with("..."));
+    assert_eq!(serde_json::to_value(&nodes[0]).unwrap()["type"], "identifier");
+
+    assert!(TsNode::parse_list("{}").unwrap().is_empty());
+    let err = TsNode::parse_list(r#"{"error": "No Tree-sitter parser is loaded for filetype text"}"#).unwrap_err();
+    assert!(err.to_string().contains("filetype text"));
+    assert!(TsNode::parse_list("null").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_treesitter_nodes_through_socket() {
+    let _nvim = FakeNvim::new(r#"[{"type": "identifier", "text": "x", "start_row": 0, "start_col": 0, "end_row": 0, "end_col": 1}, {"type": "chunk", "text": "x = 1", "start_row": 0, "start_col": 0, "end_row": 1, "end_col": 0}]"#);
+    let pid = std::process::id();
+
+    let result = NeovimContextExtractor::new().get_treesitter_nodes(pid).await;
+
+    let nodes = result.unwrap();
+    let types: Vec<&str> = nodes.iter().map(|node| node.node_type.as_str()).collect();
+    assert_eq!(types, ["identifier", "chunk"]);
+    assert_eq!(nodes[1].text, "x = 1");
+    assert_eq!((nodes[1].end_row, nodes[1].end_col), (1, 0));
+}
+
+#[test]
+fn test_buffer_watch_scripts_and_parsing() {
+    let start = buffer_watch_start_lua_script("watch-1");
+    assert!(start.contains("local id = \"watch-1\""));
+    assert!(start.contains("vim.api.nvim_buf_attach(buf, false"));
+    let drain = buffer_watch_drain_lua_script("watch-1");
+    assert!(drain.contains("watch.events = {}"));
+    let stop = buffer_watch_stop_lua_script("watch-1");
+    assert!(stop.contains("nvim_del_augroup_by_name"));
+    // The scripts are sent inside luaeval('...')
+    for script in [&start, &drain, &stop] {
+        assert!(!script.contains('\''));
+    }
+
+    let events = BufferChangeEvent::parse_list(r#"[
+        {"file_path": "/src/main.rs", "buffer": 1, "changedtick": 7, "start_line": 3, "removed_lines": 1, "added_lines": 2, "lines": ["let a = 1;", "let b = 2;"]},
+        {"file_path": "/src/main.rs", "buffer": 1, "changedtick": 8, "start_line": 5, "removed_lines": 1, "added_lines": 0, "lines": {}},
+        {"file_path": "/src/main.rs", "changedtick": 9}
+    ]"#).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].lines, ["let a = 1;", "let b = 2;"]);
+    assert_eq!((events[1].start_line, events[1].removed_lines, events[1].added_lines), (5, 1, 0));
+    assert!(events[1].lines.is_empty());
+
+    assert!(BufferChangeEvent::parse_list("{}").unwrap().is_empty());
+    assert!(BufferChangeEvent::parse_list(r#"{"error": "Buffer watch is no longer registered in Neovim"}"#).is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_watch_buffer_changes() {
+    let nvim = FakeNvim::with_script(r#"case "$*" in
+    *nvim_buf_attach*) printf '{"attached": 1}' ;;
+    *"watch.events = {}"*)
+        if [ -e DIR/gone ]; then
+            printf '{"error": "Buffer watch is no longer registered in Neovim"}'
+        elif [ -e DIR/drained ]; then
+            printf '{}'
+        else
+            touch DIR/drained
+            printf '[{"file_path": "/src/main.rs", "buffer": 1, "changedtick": 7, "start_line": 3, "removed_lines": 1, "added_lines": 2, "lines": ["a", "b"]}]'
+        fi ;;
+    *) printf '{}' ;;
+esac"#);
+
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    let extractor = NeovimContextExtractor::new();
+    let watch = extractor.watch_buffer_changes("/tmp/nvim-watch.sock", tx).await.unwrap();
+    let event = timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+
+    // Neovim forgetting the watch ends it and closes the channel
+    std::fs::write(nvim.dir().join("gone"), "").unwrap();
+    let closed = timeout(Duration::from_secs(5), rx.recv()).await.unwrap();
+    assert!(watch.is_finished());
+    let cancelled = watch.cancel().await;
+
+    assert_eq!(event.file_path, "/src/main.rs");
+    assert_eq!((event.changedtick, event.start_line, event.added_lines), (7, 3, 2));
+    assert!(closed.is_none());
+    cancelled.unwrap();
+
+    let log = nvim.log();
+    assert!(log.contains("--server /tmp/nvim-watch.sock"));
+    assert!(log.contains("nvim_del_augroup_by_name"));
+}
+
+#[test]
+fn test_spell_errors_script_and_parsing() {
+    let script = spell_errors_lua_script(20);
+    assert!(script.contains("vim.spell.check(text)"));
+    assert!(script.contains("#result >= 20"));
+    assert!(script.contains("vim.fn.spellsuggest(found[1], 5)"));
+    // The script is sent inside luaeval('...')
+    assert!(!script.contains('\''));
+
+    let errors = SpellError::parse_list(r#"[
+        {"word": "teh", "type": "bad", "line": 3, "column": 9, "suggestions": ["the", "ten"]},
+        {"word": "colour", "type": "local", "line": 7, "column": 1, "suggestions": {}},
+        {"type": "bad", "line": 8, "column": 1}
+    ]"#).unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0], SpellError {
+        word: "teh".to_string(),
+        line: 3,
+        column: 9,
+        type_: "bad".to_string(),
+        suggestions: vec!["the".to_string(), "ten".to_string()],
+    });
+    assert!(errors[1].suggestions.is_empty());
+    assert_eq!(serde_json::to_value(&errors[1]).unwrap()["type"], "local");
+
+    assert!(SpellError::parse_list("{}").unwrap().is_empty());
+    let err = SpellError::parse_list(r#"{"error": "Spell checking is off in the current window; enable it with :set spell"}"#).unwrap_err();
+    assert!(err.to_string().contains(":set spell"));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spell_errors_through_socket() {
+    let nvim = FakeNvim::new(r#"[{"word": "recieve", "type": "bad", "line": 2, "column": 5, "suggestions": ["receive"]}]"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let capped = extractor.get_spell_errors(pid, Some(10_000)).await;
+    let default = extractor.get_spell_errors(pid, None).await;
+
+    let errors = capped.unwrap();
+    assert_eq!((errors[0].word.as_str(), errors[0].line, errors[0].column), ("recieve", 2, 5));
+    assert_eq!(errors[0].suggestions, ["receive"]);
+    assert_eq!(default.unwrap(), errors);
+
+    let log = nvim.log();
+    assert!(log.contains("#result >= 500"));
+    assert!(log.contains("#result >= 50 "));
+}
+
+#[test]
+fn test_session_variables_script_and_parsing() {
+    let script = session_variables_lua_script(&["global", "buffer", "window", "tab"]);
+    assert!(script.contains(r#"{ name = "global", prefix = "g" }, { name = "buffer", prefix = "b" }, { name = "window", prefix = "w" }, { name = "tab", prefix = "t" }"#));
+    assert!(script.contains("key:sub(1, 1) ~= \"_\""));
+    assert!(script.contains("pcall(vim.json.encode, value)"));
+    // The script is sent inside luaeval('...')
+    assert!(!script.contains('\''));
+
+    let script = session_variables_lua_script(&["tab"]);
+    assert!(script.contains(r#"ipairs({ { name = "tab", prefix = "t" } })"#));
+    assert!(!script.contains("prefix = \"g\""));
+
+    let variables = SessionVariables::parse(r#"{
+        "global": {"mapleader": " ", "loaded_netrw": 1},
+        "buffer": {"changedtick": 12, "gitsigns_status": "+1"},
+        "window": [],
+        "tab": {}
+    }"#).unwrap();
+    assert_eq!(variables.global, serde_json::json!({"mapleader": " ", "loaded_netrw": 1}));
+    assert_eq!(variables.buffer["gitsigns_status"], "+1");
+    assert_eq!(variables.window, serde_json::json!({}));
+    assert_eq!(variables.tab, serde_json::json!({}));
+
+    let only_buffer = SessionVariables::parse(r#"{"buffer": {"changedtick": 3}}"#).unwrap();
+    assert!(only_buffer.global.is_null());
+    assert_eq!(only_buffer.buffer["changedtick"], 3);
+
+    assert!(SessionVariables::parse("[1, 2]").is_err());
+    assert!(SessionVariables::parse("not json").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_session_variables_through_socket() {
+    let nvim = FakeNvim::new(r#"{"global": {"colors_name": "habamax"}}"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let global = extractor.get_session_variables(pid, Some("global")).await;
+    let unknown = extractor.get_session_variables(pid, Some("g")).await;
+
+    let variables = global.unwrap();
+    assert_eq!(variables.global["colors_name"], "habamax");
+    assert!(variables.buffer.is_null());
+
+    let err = unknown.unwrap_err();
+    assert_eq!(err.downcast_ref::<AlacrittyMcpError>().map(AlacrittyMcpError::code), Some(-32602));
+    assert!(err.to_string().contains("global, buffer, window, tab"));
+
+    // The unknown scope never reaches Neovim
+    let log = nvim.log();
+    assert_eq!(log.matches("--remote-expr").count(), 1);
+    assert!(log.contains("--server /tmp/nvim."));
+    assert!(log.contains("prefix = \"g\""));
+}
+
+#[test]
+fn test_search_history_script_and_parsing() {
+    let script = search_history_lua_script(5);
+    assert!(script.contains("for i = 1, 5 do"));
+    assert!(script.contains("vim.fn.histget(\"search\", -i)"));
+    // The script is sent inside luaeval('...')
+    assert!(!script.contains('\''));
+
+    let entries = SearchHistoryEntry::parse_list(r#"["fn main", "^use ", "foo\\.bar", 42]"#).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0], SearchHistoryEntry { pattern: "fn main".to_string(), is_regex: false });
+    assert!(entries[1].is_regex);
+    assert!(!entries[2].is_regex);
+
+    assert!(SearchHistoryEntry::parse_list("{}").unwrap().is_empty());
+    assert!(SearchHistoryEntry::parse_list("\"fn\"").is_err());
+
+    for pattern in ["a.b", "x*", "[abc]", "^start", "end$", "\\(group\\)", "\\d\\+", "\\<word\\>", "~"] {
+        assert!(is_vim_regex(pattern), "{} should be a regex", pattern);
+    }
+    for pattern in ["", "plain text", "a\\.b", "x\\*", "cost $5", "a^b", "path\\/to", "trailing\\"] {
+        assert!(!is_vim_regex(pattern), "{} should be literal", pattern);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_search_history_through_socket() {
+    let nvim = FakeNvim::new(r#"["TODO", "fn .*("]"#);
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let capped = extractor.get_search_history(pid, Some(500)).await;
+    let default = extractor.get_search_history(pid, None).await;
+
+    let history = capped.unwrap();
+    assert_eq!(history.iter().map(|entry| entry.is_regex).collect::<Vec<_>>(), vec![false, true]);
+    assert_eq!(default.unwrap(), history);
+
+    let log = nvim.log();
+    assert!(log.contains("for i = 1, 50 do"));
+    assert!(log.contains("for i = 1, 10 do"));
+}
+
+#[test]
+fn test_run_test_scripts() {
+    assert_eq!(lua_string("plain"), "\"plain\"");
+    assert_eq!(lua_string("say \"hi\"\\n\tnow"), "\"say \\\"hi\\\"\\\\n\\009now\"");
+
+    let nearest = test_start_lua_script(None, None);
+    assert!(nearest.contains("local file, name = nil, nil"));
+    assert!(nearest.contains("Dispatch make test"));
+    // Only the arguments may bring in quotes, which the caller doubles
+    assert!(!nearest.contains('\''));
+    let scoped = test_start_lua_script(Some("tests/parser_test.py"), Some("TestParser::test_empty"));
+    assert!(scoped.contains("local file, name = \"tests/parser_test.py\", \"TestParser::test_empty\""));
+
+    assert!(test_status_lua_script("neotest", 0).contains("neotest.state.status_counts(adapter_id)"));
+    let dispatch = test_status_lua_script("dispatch", 7);
+    assert!(dispatch.contains("running = tick == 7 and 1 or 0"));
+    assert!(!dispatch.contains('\''));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_run_tests_through_socket() {
+    // Reports two running checks, then the same results for good. With
+    // `stuck` it never finishes; with `bare` there is no test runner.
+    let nvim = FakeNvim::with_script(r#"case "$*" in
+  *neotest.run.run*)
+    if [ -e 'DIR/bare' ]; then printf '{"runner": null}'; else printf '{"runner": "neotest"}'; fi ;;
+  *status_counts*)
+    n=$(( $(cat 'DIR/polls' 2>/dev/null || echo 0) + 1 )); echo $n > 'DIR/polls'
+    if [ -e 'DIR/stuck' ] || [ $n -lt 3 ]; then
+      printf '{"running": 2, "passed": %d, "failures": {}}' $n
+    else
+      printf '{"running": 0, "passed": 4, "failed": 1, "pending": 1, "failures": [{"file_path": "/src/lib.rs", "line": 12, "column": 5, "severity": 1, "message": "assertion failed", "source": "neotest"}]}'
+    fi ;;
+esac"#);
+
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let finished = extractor.run_tests(pid, None, Some("it's empty"), Duration::from_secs(30)).await;
+    std::fs::write(nvim.dir().join("stuck"), "").unwrap();
+    let stuck = extractor.run_tests(pid, Some("tests/lib.rs"), None, Duration::from_secs(1)).await;
+    std::fs::write(nvim.dir().join("bare"), "").unwrap();
+    let bare = extractor.run_tests(pid, None, None, Duration::from_secs(1)).await;
+
+    let finished = finished.unwrap();
+    assert_eq!(finished.runner, "neotest");
+    assert_eq!((finished.passed, finished.failed, finished.pending), (4, 1, 1));
+    assert_eq!(finished.failures.len(), 1);
+    assert_eq!(finished.failures[0].line, 12);
+    assert!(!finished.timed_out);
+    // Two running checks, then three that agree
+    assert!(finished.duration_ms >= 2500, "{}", finished.duration_ms);
+
+    let stuck = stuck.unwrap();
+    assert!(stuck.timed_out);
+    assert!(stuck.failures.is_empty());
+
+    assert!(bare.unwrap_err().to_string().contains("Neither Neotest nor vim-dispatch"));
+
+    let log = nvim.log();
+    assert!(log.contains("local file, name = nil, \"it''s empty\""));
+}
+
+#[test]
+fn test_quickfix_script_and_parsing() {
+    assert!(quickfix_lua_script(false).contains("vim.fn.getqflist()"));
+    assert!(quickfix_lua_script(true).contains("vim.fn.getloclist(0)"));
+    // The script is sent inside luaeval('...')
+    assert!(!quickfix_lua_script(false).contains('\''));
+
+    let entries = QuickfixEntry::parse_list(r#"[
+        {"file_path": "/src/main.rs", "line": 3, "column": 9, "message": "expected `;`", "qf_type": "E"},
+        {"file_path": "/src/lib.rs", "line": 7, "message": "unused import"},
+        {"message": "no location"}
+    ]"#).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].qf_type, "E");
+    assert_eq!((entries[1].column, entries[1].qf_type.as_str()), (0, ""));
+
+    assert!(QuickfixEntry::parse_list("{}").unwrap().is_empty());
+    assert!(QuickfixEntry::parse_list("E5108: Error executing lua").is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_quickfix_through_socket() {
+    // The second quickfix entry repeats the diagnostic, as
+    // `vim.diagnostic.setqflist()` would have put it there
+    let nvim = FakeNvim::with_script(r#"case "$*" in
+  *getqflist*) printf '[{"file_path": "/a.rs", "line": 2, "column": 1, "message": "make error", "qf_type": "E"}, {"file_path": "/a.rs", "line": 5, "column": 3, "message": "lsp error", "qf_type": "E"}]' ;;
+  *getloclist*) printf '[{"file_path": "/b.rs", "line": 1, "column": 1, "message": "match", "qf_type": ""}]' ;;
+  *) printf '[{"file_path": "/a.rs", "line": 5, "column": 3, "severity": 1, "message": "lsp error"}]' ;;
+esac"#);
+
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let quickfix = extractor.get_quickfix_list(pid, false).await;
+    let location_list = extractor.get_quickfix_list(pid, true).await;
+
+    let quickfix = quickfix.unwrap();
+    assert_eq!(quickfix, vec![QuickfixEntry {
+        file_path: "/a.rs".to_string(),
+        line: 2,
+        column: 1,
+        message: "make error".to_string(),
+        qf_type: "E".to_string(),
+    }]);
+    let location_list = location_list.unwrap();
+    assert_eq!(location_list.len(), 1);
+    assert_eq!(location_list[0].file_path, "/b.rs");
+    assert!(nvim.log().contains("vim.diagnostic.get()"));
+}
+
+#[test]
+fn test_git_status_parsing() {
+    let output = concat!(
+        "## main...origin/main [ahead 2, behind 1]\n",
+        " M src/lib.rs\n",
+        "M  README.md\n",
+        "MM src/main.rs\n",
+        "A  src/new.rs\n",
+        "R  old.rs -> renamed.rs\n",
+        " D gone.rs\n",
+        "UU conflict.rs\n",
+        "?? notes.txt\n",
+    );
+    let status = GitStatus::parse(output);
+    assert_eq!(status.branch, "main");
+    assert_eq!((status.ahead, status.behind), (2, 1));
+    assert_eq!(status.modified, vec!["src/lib.rs", "src/main.rs", "gone.rs", "conflict.rs"]);
+    assert_eq!(status.staged, vec!["README.md", "src/main.rs", "src/new.rs", "renamed.rs"]);
+    assert_eq!(status.untracked, vec!["notes.txt"]);
+
+    // No upstream, a fresh repository, a detached HEAD
+    assert_eq!(GitStatus::parse("## feature\n"), GitStatus { branch: "feature".to_string(), ..Default::default() });
+    assert_eq!(GitStatus::parse("## No commits yet on main\n").branch, "main");
+    assert_eq!(GitStatus::parse("## HEAD (no branch)\n").branch, "");
+    assert_eq!(GitStatus::parse("## main...origin/main [behind 3]\n").behind, 3);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_git_status_through_socket() {
+    // The fake reports `cwd` as Neovim's working directory
+    let nvim = FakeNvim::with_script("cat 'DIR/cwd'");
+    let repo = nvim.dir().join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git").arg("-C").arg(&repo).args(args).output().unwrap().status;
+        assert!(status.success(), "git {:?}", args);
+    };
+    git(&["init", "-q", "-b", "main"]);
+    git(&["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "--allow-empty", "-m", "init"]);
+    std::fs::write(repo.join("tracked.txt"), "one\n").unwrap();
+    git(&["add", "tracked.txt"]);
+    std::fs::write(repo.join("untracked.txt"), "two\n").unwrap();
+
+    let cwd = nvim.dir().join("cwd");
+    std::fs::write(&cwd, repo.display().to_string()).unwrap();
+
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let status = extractor.get_git_status(pid).await;
+    let outside = nvim.dir().join("outside");
+    std::fs::create_dir(&outside).unwrap();
+    std::fs::write(&cwd, outside.display().to_string()).unwrap();
+    let not_a_repo = extractor.get_git_status(pid).await;
+
+    let status = status.unwrap();
+    assert_eq!(status.branch, "main");
+    assert_eq!(status.staged, vec!["tracked.txt"]);
+    assert!(status.modified.is_empty());
+    assert_eq!(status.untracked, vec!["untracked.txt"]);
+    assert_eq!((status.ahead, status.behind), (0, 0));
+
+    let err = not_a_repo.unwrap_err();
+    assert!(err.to_string().contains("is not a git repository"), "{}", err);
+}
+
+#[test]
+fn test_set_option_keys() {
+    assert_eq!(set_option_keys("relativenumber", "true").unwrap(), "<C-\\><C-N>:set relativenumber<CR>");
+    assert_eq!(set_option_keys("wrap", "off").unwrap(), "<C-\\><C-N>:set nowrap<CR>");
+    assert_eq!(set_option_keys("tabstop", "4").unwrap(), "<C-\\><C-N>:set tabstop=4<CR>");
+    assert_eq!(set_option_keys("colorcolumn", "80,120").unwrap(), "<C-\\><C-N>:set colorcolumn=80,120<CR>");
+    assert_eq!(set_option_keys("colorcolumn", "").unwrap(), "<C-\\><C-N>:set colorcolumn=<CR>");
+
+    // Options outside the list, and values that could smuggle in commands
+    for (option, value) in [
+        ("shell", "/bin/sh"),
+        ("makeprg", "make"),
+        ("number<CR>:!rm", "true"),
+        ("number", "yes"),
+        ("tabstop", "-1"),
+        ("tabstop", "4|!id"),
+        ("filetype", "rust<CR>:!id"),
+        ("filetype", "rust | !id"),
+        ("spelllang", "en\\"),
+    ] {
+        let err = set_option_keys(option, value).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))), "{}={}", option, value);
+    }
+}
+
+#[test]
+fn test_validate_ex_command() {
+    assert_eq!(validate_ex_command("ls").unwrap(), "ls");
+    assert_eq!(validate_ex_command("  :messages ").unwrap(), "messages");
+    assert_eq!(validate_ex_command("set tabstop? filetype?").unwrap(), "set tabstop? filetype?");
+    assert_eq!(validate_ex_command("set").unwrap(), "set");
+    assert_eq!(validate_ex_command("setl all t_Co?").unwrap(), "setl all t_Co?");
+    assert_eq!(validate_ex_command("echo &filetype").unwrap(), "echo &filetype");
+    assert_eq!(validate_ex_command("marks abc").unwrap(), "marks abc");
+
+    for command in [
+        "",
+        "!rm -rf ~",
+        ":!id",
+        "ls | !id",
+        "echo system('id')",
+        "lua os.execute('id')",
+        "python3 import os",
+        "w !sh",
+        "write",
+        "set shell=/bin/sh",
+        "set tabstop=2",
+        "set shell=/tmp/evil?",
+        "set makeprg=foo?",
+        "setl runtimepath+=/tmp/x?",
+        "set path-=/usr?",
+        "set path^=/tmp?",
+        "set shell:/bin/sh?",
+        "set shell&",
+        "set shell<",
+        "set number",
+        "set nonumber?x",
+        "setg ?",
+        "terminal",
+        "ls!",
+        "e `id`",
+        "ls\n!id",
+        "5d",
+        "MyUserCommand",
+    ] {
+        let err = validate_ex_command(command).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))), "{:?} was allowed", command);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_execute_command_through_socket() {
+    // Answers like `execute()` does, and fails for anything mentioning `history`
+    let nvim = FakeNvim::with_script(r#"case "$*" in *history*) echo 'E492: Not an editor command' >&2; exit 1 ;; esac
+printf '\n  filetype=rust\n'"#);
+
+
+    let extractor = NeovimContextExtractor::new();
+    let ok = extractor.execute_command("/tmp/nvim.sock", "set filetype?").await;
+    let failed = extractor.execute_command("/tmp/nvim.sock", "history").await;
+    let quoted = extractor.execute_command("/tmp/nvim.sock", "echo 'it''s'").await;
+    let rejected = extractor.execute_command("/tmp/nvim.sock", "!id").await;
+
+    assert_eq!(ok.unwrap(), ExecResult { output: "  filetype=rust".to_string(), error: None });
+    assert_eq!(failed.unwrap().error.as_deref(), Some("E492: Not an editor command"));
+    assert!(quoted.unwrap().error.is_none());
+    assert!(rejected.is_err());
+
+    let log = nvim.log();
+    assert!(log.contains("--server /tmp/nvim.sock --remote-expr execute('set filetype?')"), "{}", log);
+    // Quotes are doubled inside the Vim string, and rejected commands never reach nvim
+    assert!(log.contains("execute('echo ''it''''s''')"), "{}", log);
+    assert!(!log.contains("!id"));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_set_neovim_option_through_socket() {
+    let nvim = FakeNvim::new("");
+
+
+    let extractor = NeovimContextExtractor::new();
+    let set = extractor.set_neovim_option("/tmp/nvim.test.0", "shiftwidth", "2").await;
+    let rejected = extractor.set_neovim_option("/tmp/nvim.test.0", "shell", "/bin/sh").await;
+
+    set.unwrap();
+    assert!(rejected.is_err());
+    let calls = nvim.log();
+    assert_eq!(calls.trim(), "--server /tmp/nvim.test.0 --remote-send <C-\\><C-N>:set shiftwidth=2<CR>");
+}
+
+#[test]
+fn test_helix_detection_and_status_line() {
+    let extractor = NeovimContextExtractor::new();
+    let helix_screen = concat!(
+        "  1 fn main() {\n",
+        "  2     run();\n",
+        " NOR   src/main.rs [+]                ● 2  ● 1   3 sels  12:5 \n",
+        ":q\n",
+    );
+    assert_eq!(extractor.detect_editor_in_terminal(helix_screen), EditorType::Helix);
+    assert_eq!(extractor.detect_editor_in_terminal("-- INSERT --"), EditorType::Neovim);
+    assert_eq!(extractor.detect_editor_in_terminal("bash-5.1$ ls"), EditorType::Unknown);
+
+    let helix = HelixContextExtractor::new();
+    let context = helix.extract_from_screen(helix_screen).unwrap();
+    assert_eq!(context.file_path.as_deref(), Some("src/main.rs"));
+    assert_eq!(context.mode, "normal");
+    assert_eq!(context.selections, 3);
+    assert_eq!(context.diagnostics, 3);
+    assert_eq!((context.line, context.column), (12, 5));
+
+    let scratch = helix.parse_status_line(" INS   [scratch]   1 sel  1:1").unwrap();
+    assert_eq!(scratch.file_path, None);
+    assert_eq!(scratch.mode, "insert");
+    assert_eq!((scratch.selections, scratch.diagnostics), (1, 0));
+    assert_eq!(helix.parse_status_line("SEL  notes.md  4:2 ISO-8859-1").unwrap().mode, "select");
+    assert!(helix.parse_status_line("NORMAL people use vim").is_none());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_helix_context_needs_env() {
+    let extractor = NeovimContextExtractor::new();
+    let params = NeovimContextParams {
+        instance_id: "unused".to_string(),
+        include_diagnostics: None,
+        include_buffers: None,
+        context_lines: None,
+    };
+    let screen = " NOR   lib.rs   1 sel  3:1";
+
+    std::env::remove_var("ALACRITTY_MCP_HELIX");
+    let disabled = extractor.extract_context_from_instance(u32::MAX, &params, Some(screen)).await.unwrap();
+    std::env::set_var("ALACRITTY_MCP_HELIX", "1");
+    let enabled = extractor.extract_context_from_instance(u32::MAX, &params, Some(screen)).await.unwrap();
+    std::env::remove_var("ALACRITTY_MCP_HELIX");
+
+    assert!(matches!(disabled, TerminalContext::PlainTerminal));
+    let TerminalContext::Helix(context) = enabled else {
+        panic!("expected Helix context, got {:?}", enabled);
+    };
+    assert_eq!(context.file_path.as_deref(), Some("lib.rs"));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_workspace_diagnostics() {
+    assert_eq!(DiagnosticSeverity::parse("Warning"), Some(DiagnosticSeverity::Warning));
+    assert_eq!(DiagnosticSeverity::parse("fatal"), None);
+
+    let _nvim = FakeNvim::new(concat!(
+        "[",
+        r#"{"file_path": "/b.rs", "line": 9, "column": 1, "severity": 4, "message": "hint"},"#,
+        r#"{"file_path": "/b.rs", "line": 20, "column": 2, "severity": 1, "message": "late error"},"#,
+        r#"{"file_path": "/b.rs", "line": 3, "column": 5, "severity": 2, "message": "warning"},"#,
+        r#"{"file_path": "/a.rs", "line": 1, "column": 1, "severity": 3, "message": "info"}"#,
+        "]",
+    ));
+    let pid = std::process::id();
+
+    let extractor = NeovimContextExtractor::new();
+    let all = extractor.get_workspace_diagnostics(pid, None).await;
+    let warnings = extractor.get_workspace_diagnostics(pid, Some(DiagnosticSeverity::Warning)).await;
+
+    let all = all.unwrap();
+    assert_eq!(all.by_file.keys().collect::<Vec<_>>(), vec!["/a.rs", "/b.rs"]);
+    let messages: Vec<&str> = all.by_file["/b.rs"].iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(messages, vec!["late error", "warning", "hint"]);
+    assert_eq!((all.summary.errors, all.summary.warnings, all.summary.info, all.summary.hints), (1, 1, 1, 1));
+
+    let warnings = warnings.unwrap();
+    assert!(!warnings.by_file.contains_key("/a.rs"));
+    assert_eq!(warnings.by_file["/b.rs"].len(), 2);
+    assert_eq!((warnings.summary.errors, warnings.summary.warnings, warnings.summary.hints), (1, 1, 0));
+}
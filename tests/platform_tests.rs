@@ -0,0 +1,2118 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use serial_test::serial;
+use tempfile::TempDir;
+use serde_json::{json, Value};
+use alacritty_mcp::{AlacrittyManager, AlacrittyMcpError, Config, McpServer, Platform, types::*};
+
+/// Puts fake versions of the external tools first on `PATH` so the manager can
+/// be exercised without a display server. Every fake appends its arguments to
+/// `calls.log` in the temp directory.
+struct MockTools {
+    dir: TempDir,
+    original_path: String,
+    original_wayland: Option<String>,
+}
+
+impl MockTools {
+    fn install(wayland: bool) -> Self {
+        let dir = TempDir::new().unwrap();
+
+        // Not exec'd, so `pgrep -f alacritty` still finds the fake terminal.
+        // `--version` reports 0.13.2 unless the test wrote `alacritty_version`.
+        // `--working-directory` is honoured so file tools see that directory.
+        write_script(dir.path(), "alacritty", &format!(concat!(
+            "case \"$1\" in\n",
+            "  --version) cat '{}' 2>/dev/null || echo 'alacritty 0.13.2 (mock)'; exit 0 ;;\n",
+            "  msg) exit 0 ;;\n",
+            "esac\n",
+            "while [ $# -gt 0 ]; do [ \"$1\" = --working-directory ] && cd \"$2\"; shift; done\n",
+            "trap 'kill $!; exit 0' TERM; sleep 30 & wait",
+        ), dir.path().join("alacritty_version").display()));
+        write_script(dir.path(), "ydotool", "");
+        // `search` finds window 4242 unless the test created `no_window`
+        write_script(dir.path(), "xdotool", &format!(concat!(
+            "if [ \"$1\" = search ] && [ ! -e '{}' ]; then echo 4242; fi\n",
+            "if [ \"$2\" = --shell ]; then printf 'WINDOW=4242\\nX=0\\nY=0\\nWIDTH=800\\nHEIGHT=600\\n'; fi",
+        ), dir.path().join("no_window").display()));
+        write_script(dir.path(), "xwininfo", "");
+        // Copying saves stdin to `clipboard` and the display to
+        // `clipboard_display`; pasting prints `clipboard` once there is one
+        let clipboard = dir.path().join("clipboard");
+        let copy = format!(
+            "cat > '{}'; printf '%s' \"$DISPLAY$WAYLAND_DISPLAY\" > '{}'",
+            clipboard.display(), dir.path().join("clipboard_display").display(),
+        );
+        write_script(dir.path(), "xclip", &format!(
+            "case \" $* \" in *' -o '*) cat '{}' 2>/dev/null || printf 'mock x11 clipboard'; exit ;; esac\n{}",
+            clipboard.display(), copy,
+        ));
+        write_script(dir.path(), "wl-copy", &copy);
+        write_script(dir.path(), "wl-paste", &format!(
+            "cat '{}' 2>/dev/null || printf 'mock wayland clipboard'", clipboard.display(),
+        ));
+        // `get_tree` answers with whatever the test wrote to sway_tree.json
+        write_script(dir.path(), "swaymsg", &format!(
+            "if [ \"$1\" = -t ]; then cat '{}' 2>/dev/null; fi",
+            dir.path().join("sway_tree.json").display(),
+        ));
+        // The screenshot tools write a fake PNG to the path given last
+        for tool in ["import", "grim"] {
+            write_script(dir.path(), tool, "for last; do :; done; printf 'PNG' > \"$last\"");
+        }
+
+        let original_path = std::env::var("PATH").unwrap_or_default();
+        let original_wayland = std::env::var("WAYLAND_DISPLAY").ok();
+        std::env::set_var("PATH", format!("{}:{}", dir.path().display(), original_path));
+        if wayland {
+            std::env::set_var("WAYLAND_DISPLAY", "wayland-mock");
+        } else {
+            std::env::remove_var("WAYLAND_DISPLAY");
+        }
+
+        Self { dir, original_path, original_wayland }
+    }
+
+    fn calls(&self) -> String {
+        fs::read_to_string(self.log_path()).unwrap_or_default()
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.path().join("calls.log")
+    }
+}
+
+impl Drop for MockTools {
+    fn drop(&mut self) {
+        std::env::set_var("PATH", &self.original_path);
+        match &self.original_wayland {
+            Some(display) => std::env::set_var("WAYLAND_DISPLAY", display),
+            None => std::env::remove_var("WAYLAND_DISPLAY"),
+        }
+    }
+}
+
+fn write_script(dir: &Path, name: &str, body: &str) {
+    let path = dir.join(name);
+    let log = dir.join("calls.log");
+    fs::write(&path, format!("#!/bin/sh\necho \"{} $*\" >> '{}'\n{}\n", name, log.display(), body)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// A manager on the default config with no state file, so tests never read
+/// the developer's config or write to their registry.
+fn test_manager() -> AlacrittyManager {
+    AlacrittyManager::with_config_and_state_file(Config::default(), None)
+}
+
+async fn spawn_mock_instance(manager: &AlacrittyManager) -> AlacrittyInstance {
+    manager.spawn_instance(SpawnParams {
+        title: Some("platform-test".to_string()),
+        ..Default::default()
+    }).await.unwrap()
+}
+
+/// The manager reaps its own children, so tests wait for the `/proc` entry
+/// to disappear instead of calling `waitpid` themselves.
+async fn wait_until_reaped(pid: u32) {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    for _ in 0..200 {
+        if !proc_dir.exists() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("PID {} was never reaped", pid);
+}
+
+async fn close_mock_instance(manager: &AlacrittyManager, instance: &AlacrittyInstance) {
+    manager.close_instance(CloseParams {
+        instance_id: instance.id.clone(),
+        timeout_ms: Some(1000),
+    }).await.unwrap();
+}
+
+#[test]
+#[serial]
+fn test_platform_detection() {
+    let _mock = MockTools::install(true);
+    assert_eq!(Platform::detect(), Platform::Wayland);
+
+    std::env::set_var("WAYLAND_DISPLAY", "");
+    assert_eq!(Platform::detect(), Platform::X11);
+
+    std::env::remove_var("WAYLAND_DISPLAY");
+    assert_eq!(Platform::detect(), Platform::X11);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_wayland_send_keys_and_screenshot() {
+    let mock = MockTools::install(true);
+    let manager = test_manager();
+    assert_eq!(manager.platform(), Platform::Wayland);
+
+    let instance = spawn_mock_instance(&manager).await;
+    assert_eq!(instance.window_id, None);
+
+    manager.send_keys(SendKeysParams {
+        instance_id: instance.id.clone(),
+        keys: "ctrl+c Return".to_string(),
+    }).await.unwrap();
+
+    let text = manager.screenshot_instance(ScreenshotParams {
+        instance_id: instance.id.clone(),
+        format: Some("text".to_string()),
+    }).await.unwrap();
+    assert_eq!(text, "mock wayland clipboard");
+
+    manager.resize_instance(ResizeParams {
+        instance_id: instance.id.clone(),
+        cols: 100,
+        rows: 30,
+    }).await.unwrap();
+
+    manager.focus_instance(FocusParams { instance_id: instance.id.clone() }).await.unwrap();
+
+    manager.send_text(SendTextParams {
+        instance_id: instance.id.clone(),
+        text: "ls -la".to_string(),
+        delay_ms: None,
+    }).await.unwrap();
+
+    let calls = mock.calls();
+    // ydotool 1.x takes keycodes: ctrl+c, then Return
+    assert!(calls.contains("ydotool key 29:1 46:1 46:0 29:0 28:1 28:0"));
+    assert!(calls.contains("ydotool type --key-delay 0 -- ls -la"));
+    assert!(calls.contains(&format!("swaymsg [pid={}] focus", instance.pid)));
+    assert!(calls.contains(&format!("swaymsg [pid={}] resize set 900 px 540 px", instance.pid)));
+    assert!(calls.contains("wl-paste"));
+    assert!(!calls.contains("xdotool"));
+    assert!(!calls.contains("xclip"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_run_command() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+
+    // The screen shows the command's output once Return has been pressed
+    write_script(mock.dir.path(), "xclip", &format!(
+        "if grep -q 'key --window 4242 Return' '{}'; then printf 'old\\n$ make\\nok\\n$ '; else printf 'old\\n$ '; fi",
+        mock.log_path().display(),
+    ));
+
+    let result = manager.run_command(RunCommandParams {
+        instance_id: instance.id.clone(),
+        command: "make".to_string(),
+        timeout_ms: Some(5000),
+        prompt_pattern: None,
+    }).await.unwrap();
+    assert_eq!(result.stdout, "ok");
+    assert_eq!(result.exit_code, None);
+
+    let calls = mock.calls();
+    assert!(calls.contains("xdotool windowactivate --sync 4242"));
+    assert!(calls.contains("-- make"));
+
+    let invalid = manager.run_command(RunCommandParams {
+        instance_id: instance.id.clone(),
+        command: "make".to_string(),
+        timeout_ms: Some(100),
+        prompt_pattern: Some("(".to_string()),
+    }).await.unwrap_err();
+    assert!(matches!(invalid.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    // A prompt that never comes back
+    let timed_out = manager.run_command(RunCommandParams {
+        instance_id: instance.id.clone(),
+        command: "sleep 100".to_string(),
+        timeout_ms: Some(300),
+        prompt_pattern: None,
+    }).await.unwrap_err();
+    assert!(timed_out.to_string().contains("Timed out after 300ms"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_clone_neovim_buffer() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let source = spawn_mock_instance(&manager).await;
+    let shell = spawn_mock_instance(&manager).await;
+
+    // The source shows Neovim on screen and answers on its socket
+    write_script(mock.dir.path(), "xclip", "printf 'fn main() {}\\n-- NORMAL --'");
+    write_script(mock.dir.path(), "nvim", concat!(
+        "case \"$*\" in\n",
+        r#"  *current_line_nr*) echo '{"file_path": "/src/my lib.rs", "current_line_nr": 12}' ;;"#, "\n",
+        r#"  *nvim_win_get_cursor*) echo '{"line": 12, "column": 3, "line_content": ""}' ;;"#, "\n",
+        "esac",
+    ));
+    let socket = format!("/tmp/nvim.{}.0", source.pid);
+    fs::write(&socket, "").unwrap();
+
+    let spawned = manager.clone_neovim_buffer(CloneBufferParams {
+        source_instance_id: source.id.clone(),
+        target_instance_id: None,
+    }).await.unwrap();
+
+    let reused = manager.clone_neovim_buffer(CloneBufferParams {
+        source_instance_id: source.id.clone(),
+        target_instance_id: Some(shell.id.clone()),
+    }).await.unwrap();
+    fs::remove_file(&socket).unwrap();
+
+    assert_eq!(spawned.source_instance_id, source.id);
+    assert_eq!(spawned.file_path, "/src/my lib.rs");
+    assert_eq!(spawned.line, 12);
+    assert!(spawned.target_instance_id != source.id && spawned.target_instance_id != shell.id);
+    assert_eq!(reused.target_instance_id, shell.id);
+
+    let calls = mock.calls();
+    assert!(calls.contains("--title nvim my lib.rs"));
+    assert!(calls.contains("--command nvim +12 /src/my lib.rs"));
+    assert!(calls.contains("-- nvim +12 '/src/my lib.rs'"));
+
+    let clone = manager.get_instance(GetInstanceParams { instance_id: spawned.target_instance_id }).await.unwrap();
+    for instance in [&source, &shell, &clone] {
+        close_mock_instance(&manager, instance).await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_uses_config_defaults() {
+    let mock = MockTools::install(false);
+    let work_dir = TempDir::new().unwrap();
+
+    // A differently named binary, to show the configured one is what runs
+    let binary = mock.dir.path().join("alacritty-nightly");
+    let script = fs::read_to_string(mock.dir.path().join("alacritty")).unwrap();
+    fs::write(&binary, script.replacen("echo \"alacritty ", "echo \"alacritty-nightly ", 1)).unwrap();
+    fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut config = Config::default();
+    config.alacritty_binary = binary.display().to_string();
+    config.default_working_directory = Some(work_dir.path().display().to_string());
+    config.default_title_prefix = Some("agent".to_string());
+    let manager = AlacrittyManager::with_config_and_state_file(config, None);
+
+    let instance = manager.spawn_instance(SpawnParams::default()).await.unwrap();
+    assert!(instance.title.starts_with("agent-"));
+    assert_eq!(instance.working_directory, Some(work_dir.path().display().to_string()));
+
+    // An explicit directory still wins over the default
+    let explicit = manager.spawn_instance(SpawnParams {
+        working_directory: Some("/".to_string()),
+        title: Some("explicit".to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    assert_eq!(explicit.working_directory.as_deref(), Some("/"));
+
+    let calls = mock.calls();
+    assert!(calls.contains(&format!(
+        "alacritty-nightly --title {} --class alacritty-mcp-{} --working-directory {}",
+        instance.title, instance.id, work_dir.path().display()
+    )));
+    assert!(calls.contains(&format!("alacritty-nightly --title explicit --class alacritty-mcp-{} --working-directory /", explicit.id)));
+
+    for instance in [&instance, &explicit] {
+        close_mock_instance(&manager, instance).await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_list_neovim_instances() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+
+    let shell = spawn_mock_instance(&manager).await;
+    let editor = manager.spawn_instance(SpawnParams {
+        command: Some("nvim".to_string()),
+        args: Some(vec!["src/main.rs".to_string()]),
+        ..Default::default()
+    }).await.unwrap();
+    // Not started as nvim, but running it: found through the command line
+    let wrapped = manager.spawn_instance(SpawnParams {
+        command: Some("bash".to_string()),
+        args: Some(vec!["-c".to_string(), "/usr/bin/nvim".to_string()]),
+        ..Default::default()
+    }).await.unwrap();
+
+    let socket = format!("/tmp/nvim.{}.0", editor.pid);
+    fs::write(&socket, "").unwrap();
+    let list = manager.list_neovim_instances(None).await.unwrap();
+    fs::remove_file(&socket).unwrap();
+
+    let mut ids: Vec<&str> = list.instances.iter().map(|instance| instance.id.as_str()).collect();
+    ids.sort();
+    let mut expected = vec![editor.id.as_str(), wrapped.id.as_str()];
+    expected.sort();
+    assert_eq!(ids, expected);
+    assert_eq!(list.neovim_detected_count, 2);
+    assert_eq!(list.socket_paths.get(&editor.id), Some(&socket));
+    assert!(!list.socket_paths.contains_key(&wrapped.id));
+
+    for instance in [&shell, &editor, &wrapped] {
+        close_mock_instance(&manager, instance).await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_screenshot_diff() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let other = spawn_mock_instance(&manager).await;
+
+    write_script(mock.dir.path(), "xclip", "printf '$ make\\nbuilding\\n'");
+    let text = manager.screenshot_instance(ScreenshotParams {
+        instance_id: instance.id.clone(),
+        format: Some("text".to_string()),
+    }).await.unwrap();
+    let screenshot_id = manager.store_screenshot(&instance.id, &text);
+
+    write_script(mock.dir.path(), "xclip", "printf '$ make\\nok\\n$ \\n'");
+    let diff = manager.screenshot_diff(ScreenshotDiffParams {
+        instance_id: instance.id.clone(),
+        since_screenshot_id: screenshot_id.clone(),
+    }).await.unwrap();
+    assert_eq!(diff, DiffResult {
+        added: vec!["ok".to_string(), "$ ".to_string()],
+        removed: vec!["building".to_string()],
+        unchanged_count: 1,
+    });
+
+    let unknown = manager.screenshot_diff(ScreenshotDiffParams {
+        instance_id: instance.id.clone(),
+        since_screenshot_id: uuid::Uuid::new_v4().to_string(),
+    }).await.unwrap_err();
+    assert!(matches!(unknown.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    // A screenshot only diffs against the instance it was taken of
+    let mismatched = manager.screenshot_diff(ScreenshotDiffParams {
+        instance_id: other.id.clone(),
+        since_screenshot_id: screenshot_id,
+    }).await.unwrap_err();
+    assert!(matches!(mismatched.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    close_mock_instance(&manager, &instance).await;
+    close_mock_instance(&manager, &other).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_x11_send_keys_and_screenshot() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    assert_eq!(manager.platform(), Platform::X11);
+
+    let instance = spawn_mock_instance(&manager).await;
+
+    manager.send_keys(SendKeysParams {
+        instance_id: instance.id.clone(),
+        keys: "Return".to_string(),
+    }).await.unwrap();
+
+    let text = manager.screenshot_instance(ScreenshotParams {
+        instance_id: instance.id.clone(),
+        format: Some("text".to_string()),
+    }).await.unwrap();
+    assert_eq!(text, "mock x11 clipboard");
+
+    let page = manager.get_scrollback(ScrollbackParams {
+        instance_id: instance.id.clone(),
+        start_line: Some(0),
+        end_line: Some(1),
+    }).await.unwrap();
+    assert_eq!(page.lines, vec!["mock x11 clipboard"]);
+
+    let found = manager.wait_for_output(WaitForOutputParams {
+        instance_id: instance.id.clone(),
+        pattern: r"x11\s+clip".to_string(),
+        timeout_ms: Some(5000),
+        poll_interval_ms: Some(10),
+    }).await.unwrap();
+    assert_eq!(found.line, "mock x11 clipboard");
+    assert_eq!(found.polls, 1);
+
+    let timed_out = manager.wait_for_output(WaitForOutputParams {
+        instance_id: instance.id.clone(),
+        pattern: "never printed".to_string(),
+        timeout_ms: Some(200),
+        poll_interval_ms: Some(10),
+    }).await.unwrap_err();
+    assert!(timed_out.to_string().contains("Timed out after 200ms"));
+
+    manager.resize_instance(ResizeParams {
+        instance_id: instance.id.clone(),
+        cols: 120,
+        rows: 40,
+    }).await.unwrap();
+
+    manager.focus_instance(FocusParams { instance_id: instance.id.clone() }).await.unwrap();
+
+    manager.send_text(SendTextParams {
+        instance_id: instance.id.clone(),
+        text: "ls -la".to_string(),
+        delay_ms: Some(5),
+    }).await.unwrap();
+
+    let calls = mock.calls();
+    assert!(calls.contains("xdotool key --window 4242 Return"));
+    assert!(calls.contains("xdotool type --clearmodifiers --delay 5 --window 4242 -- ls -la"));
+    assert!(calls.contains("xdotool windowactivate --sync 4242"));
+    assert!(calls.contains("xdotool windowsize --usehints 4242 120 40"));
+    assert!(calls.contains("xclip"));
+    assert!(!calls.contains("ydotool"));
+    assert!(!calls.contains("wl-paste"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_with_env() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    std::env::set_var("ALACRITTY_MCP_INHERITED", "from-server");
+
+    let mut env = HashMap::new();
+    env.insert("MY_VAR".to_string(), "hello from mcp".to_string());
+    env.insert("ALACRITTY_MCP_OVERRIDDEN".to_string(), "from-caller".to_string());
+    std::env::set_var("ALACRITTY_MCP_OVERRIDDEN", "from-server");
+
+    let instance = manager.spawn_instance(SpawnParams {
+        title: Some("env-test".to_string()),
+        env: Some(env),
+        ..Default::default()
+    }).await.unwrap();
+
+    let environ = fs::read(format!("/proc/{}/environ", instance.pid)).unwrap();
+    let vars: Vec<String> = environ
+        .split(|b| *b == 0)
+        .map(|var| String::from_utf8_lossy(var).to_string())
+        .collect();
+
+    assert!(vars.contains(&"MY_VAR=hello from mcp".to_string()));
+    assert!(vars.contains(&"ALACRITTY_MCP_INHERITED=from-server".to_string()));
+    assert!(vars.contains(&"ALACRITTY_MCP_OVERRIDDEN=from-caller".to_string()));
+
+    std::env::remove_var("ALACRITTY_MCP_INHERITED");
+    std::env::remove_var("ALACRITTY_MCP_OVERRIDDEN");
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_instance() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+
+    let instance = spawn_mock_instance(&manager).await;
+
+    let fetched = manager.get_instance(GetInstanceParams { instance_id: instance.id.clone() }).await.unwrap();
+    assert_eq!(fetched.pid, instance.pid);
+    assert_eq!(fetched.window_id, Some(4242));
+    assert_eq!(fetched.status, InstanceStatus::Running);
+
+    // Once the process is gone the stale entry is dropped
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(instance.pid as i32), nix::sys::signal::Signal::SIGKILL).unwrap();
+    wait_until_reaped(instance.pid).await;
+
+    let err = manager.get_instance(GetInstanceParams { instance_id: instance.id.clone() }).await.unwrap_err();
+    assert!(err.to_string().contains("has exited"));
+
+    let err = manager.get_instance(GetInstanceParams { instance_id: instance.id.clone() }).await.unwrap_err();
+    assert!(err.to_string().contains("Instance not found"));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_batch_send_keys() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let first = spawn_mock_instance(&manager).await;
+    let second = spawn_mock_instance(&manager).await;
+
+    // Well-formed, but never handed out
+    let unknown = "00000000-0000-4000-8000-000000000000";
+    let target = |instance_id: &str, keys: &str| BatchTarget { instance_id: instance_id.to_string(), keys: keys.to_string() };
+    let result = manager.batch_send_keys(BatchSendKeysParams {
+        targets: vec![target(&second.id, "ctrl+c"), target(unknown, "Return"), target(&first.id, "q")],
+    }).await.unwrap();
+
+    // The unknown instance fails on its own
+    assert_eq!(result.succeeded, vec![second.id.clone(), first.id.clone()]);
+    assert_eq!(result.failed.len(), 1);
+    assert!(result.failed[unknown].contains("Instance not found"));
+    let calls = mock.calls();
+    assert!(calls.contains("xdotool key --window 4242 ctrl+c"));
+    assert!(calls.contains("xdotool key --window 4242 q"));
+    assert!(!calls.contains("Return"));
+
+    for targets in [vec![], vec![target(&first.id, "a"), target(&first.id, "b")]] {
+        let err = manager.batch_send_keys(BatchSendKeysParams { targets }).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    }
+
+    for instance in [&first, &second] {
+        close_mock_instance(&manager, instance).await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_tags() {
+    let mock = MockTools::install(true);
+    let manager = test_manager();
+
+    let mut build = Vec::new();
+    for title in ["build-1", "build-2"] {
+        build.push(manager.spawn_instance(SpawnParams {
+            title: Some(title.to_string()),
+            tags: Some(vec!["build".to_string()]),
+            ..Default::default()
+        }).await.unwrap());
+    }
+    let untagged = spawn_mock_instance(&manager).await;
+
+    let tagged = manager.list_instances_by_tag(ListByTagParams { tag: "build".to_string() }).await.unwrap();
+    let mut tagged_ids: Vec<String> = tagged.iter().map(|instance| instance.id.clone()).collect();
+    tagged_ids.sort();
+    let mut build_ids: Vec<String> = build.iter().map(|instance| instance.id.clone()).collect();
+    build_ids.sort();
+    assert_eq!(tagged_ids, build_ids);
+
+    let sent_to = manager.send_keys_to_tag(SendKeysToTagParams {
+        tag: "build".to_string(),
+        keys: "ctrl+c".to_string(),
+    }).await.unwrap();
+    assert_eq!(sent_to, build_ids);
+
+    // Each tagged window is focused before ydotool sends to it
+    let calls = mock.calls();
+    assert_eq!(calls.matches("ydotool key 29:1 46:1 46:0 29:0").count(), 2);
+    for instance in &build {
+        assert!(calls.contains(&format!("swaymsg [pid={}] focus", instance.pid)));
+    }
+    assert!(!calls.contains(&format!("swaymsg [pid={}] focus", untagged.pid)));
+
+    let err = manager.send_keys_to_tag(SendKeysToTagParams {
+        tag: "missing".to_string(),
+        keys: "Return".to_string(),
+    }).await.unwrap_err();
+    assert!(err.to_string().contains("No instances tagged 'missing'"));
+
+    for instance in build.iter().chain([&untagged]) {
+        close_mock_instance(&manager, instance).await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_send_keys_sequence() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+
+    let result = manager.send_keys_sequence(SendKeysSequenceParams {
+        instance_id: instance.id.clone(),
+        sequence: vec![
+            KeyEvent { keys: "Escape".to_string(), delay_after_ms: Some(50) },
+            KeyEvent { keys: "colon".to_string(), delay_after_ms: None },
+            KeyEvent { keys: "Return".to_string(), delay_after_ms: Some(30) },
+        ],
+    }).await.unwrap();
+    assert_eq!(result.events_sent, 3);
+    assert!(result.elapsed_ms >= 80);
+
+    // Sent one at a time, in order
+    let calls = mock.calls();
+    let escape = calls.find("xdotool key --window 4242 Escape").unwrap();
+    let colon = calls.find("xdotool key --window 4242 colon").unwrap();
+    let enter = calls.find("xdotool key --window 4242 Return").unwrap();
+    assert!(escape < colon && colon < enter);
+
+    let err = manager.send_keys_sequence(SendKeysSequenceParams {
+        instance_id: instance.id.clone(),
+        sequence: Vec::new(),
+    }).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    let err = manager.send_keys_sequence(SendKeysSequenceParams {
+        instance_id: instance.id.clone(),
+        sequence: vec![KeyEvent { keys: "a".to_string(), delay_after_ms: Some(60_000) }],
+    }).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    assert!(err.to_string().contains("delay_after_ms"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_screenshot_content_types() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let mut server = McpServer::new(manager);
+
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    server.handle_request(&init_request.to_string()).await.unwrap();
+
+    let screenshot = |id: u64, format: &str| json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": "screenshot_instance", "arguments": {"instance_id": instance.id, "format": format}},
+        "id": id
+    });
+
+    let response: Value = serde_json::from_str(
+        &server.handle_request(&screenshot(2, "image").to_string()).await.unwrap().unwrap()
+    ).unwrap();
+    let content = &response["result"]["content"][0];
+    assert_eq!(content["type"], "image");
+    assert_eq!(content["mimeType"], "image/png");
+    assert_eq!(content["data"], "UE5H");
+    assert!(content.get("text").is_none());
+
+    let response: Value = serde_json::from_str(
+        &server.handle_request(&screenshot(3, "text").to_string()).await.unwrap().unwrap()
+    ).unwrap();
+    let content = &response["result"]["content"][0];
+    assert_eq!(content["type"], "text");
+    assert!(content["text"].as_str().unwrap().contains("mock x11 clipboard"));
+
+    let close_request = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": "close_instance", "arguments": {"instance_id": instance.id, "timeout_ms": 1000}},
+        "id": 4
+    });
+    server.handle_request(&close_request.to_string()).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_instance_resources() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let mut server = McpServer::new(manager);
+
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    server.handle_request(&init_request.to_string()).await.unwrap();
+
+    let uri = format!("alacritty://instances/{}", instance.id);
+    let list_request = json!({"jsonrpc": "2.0", "method": "resources/list", "id": 2});
+    let response: Value = serde_json::from_str(
+        &server.handle_request(&list_request.to_string()).await.unwrap().unwrap()
+    ).unwrap();
+    let resource = response["result"]["resources"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|resource| resource["uri"] == uri.as_str())
+        .unwrap();
+    assert_eq!(resource["name"], "platform-test");
+    assert_eq!(resource["mimeType"], "text/plain");
+
+    let read_request = json!({
+        "jsonrpc": "2.0",
+        "method": "resources/read",
+        "params": {"uri": uri},
+        "id": 3
+    });
+    let response: Value = serde_json::from_str(
+        &server.handle_request(&read_request.to_string()).await.unwrap().unwrap()
+    ).unwrap();
+    let contents = &response["result"]["contents"][0];
+    assert_eq!(contents["uri"], uri.as_str());
+    assert_eq!(contents["mimeType"], "text/plain");
+    assert_eq!(contents["text"], "mock x11 clipboard");
+
+    let close_request = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": "close_instance", "arguments": {"instance_id": instance.id, "timeout_ms": 1000}},
+        "id": 4
+    });
+    server.handle_request(&close_request.to_string()).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_kill_all_instances() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+
+    let tagged = manager.spawn_instance(SpawnParams {
+        tags: Some(vec!["build".to_string()]),
+        ..Default::default()
+    }).await.unwrap();
+    let exited = manager.spawn_instance(SpawnParams {
+        tags: Some(vec!["build".to_string()]),
+        ..Default::default()
+    }).await.unwrap();
+    let untagged = spawn_mock_instance(&manager).await;
+
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(exited.pid as i32), nix::sys::signal::Signal::SIGKILL).unwrap();
+    wait_until_reaped(exited.pid).await;
+
+    let summary = manager.kill_all_instances(Some("build".to_string())).await.unwrap();
+    assert_eq!(summary, KillSummary { killed: 1, already_exited: 1 });
+    assert!(!std::path::Path::new(&format!("/proc/{}", tagged.pid)).exists());
+
+    // The untagged instance is still managed and running
+    let fetched = manager.get_instance(GetInstanceParams { instance_id: untagged.id.clone() }).await.unwrap();
+    assert_eq!(fetched.status, InstanceStatus::Running);
+    assert!(manager.get_instance(GetInstanceParams { instance_id: tagged.id.clone() }).await.is_err());
+
+    let summary = manager.kill_all_instances(None).await.unwrap();
+    assert_eq!(summary, KillSummary { killed: 1, already_exited: 0 });
+    assert!(manager.get_instance(GetInstanceParams { instance_id: untagged.id.clone() }).await.is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_kill_idle_instances() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+
+    let idle = spawn_mock_instance(&manager).await;
+    let active = spawn_mock_instance(&manager).await;
+    assert_eq!(idle.last_activity_at, idle.created_at);
+
+    tokio::time::sleep(std::time::Duration::from_millis(2100)).await;
+    manager.send_keys(SendKeysParams { instance_id: active.id.clone(), keys: "Return".to_string() }).await.unwrap();
+    let fetched = manager.get_instance(GetInstanceParams { instance_id: active.id.clone() }).await.unwrap();
+    assert!(fetched.last_activity_at > active.created_at);
+
+    let err = manager.kill_idle_instances(0).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    let summary = manager.kill_idle_instances(1).await.unwrap();
+    assert_eq!(summary, KillSummary { killed: 1, already_exited: 0 });
+    assert!(manager.get_instance(GetInstanceParams { instance_id: idle.id.clone() }).await.is_err());
+    assert!(manager.get_instance(GetInstanceParams { instance_id: active.id.clone() }).await.is_ok());
+
+    close_mock_instance(&manager, &active).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_with_config_file() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+
+    let config_file = mock.dir.path().join("high-contrast.toml");
+    fs::write(&config_file, "[colors.primary]\nbackground = '#000000'\n").unwrap();
+
+    let instance = manager.spawn_instance(SpawnParams {
+        config_file: Some(config_file.display().to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    assert!(mock.calls().contains(&format!("--config-file {}", config_file.display())));
+
+    let err = manager.spawn_instance(SpawnParams {
+        config_file: Some(mock.dir.path().join("missing.toml").display().to_string()),
+        ..Default::default()
+    }).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    assert!(err.to_string().contains("does not exist"));
+
+    let yaml_file = mock.dir.path().join("legacy.yml");
+    fs::write(&yaml_file, "").unwrap();
+    let err = manager.spawn_instance(SpawnParams {
+        config_file: Some(yaml_file.display().to_string()),
+        ..Default::default()
+    }).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    assert!(err.to_string().contains(".toml"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_with_geometry() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+
+    let geometry = |cols, rows, x, y| Some(WindowGeometry { cols, rows, x, y });
+    let instance = manager.spawn_instance(SpawnParams {
+        command: Some("htop".to_string()),
+        geometry: geometry(120, 40, Some(10), Some(-20)),
+        ..Default::default()
+    }).await.unwrap();
+    let calls = mock.calls();
+    assert!(calls.contains(concat!(
+        "--option window.dimensions.columns=120 --option window.dimensions.lines=40 ",
+        "--option window.position.x=10 --option window.position.y=-20 --command htop",
+    )));
+
+    for bad in [geometry(9, 40, None, None), geometry(120, 501, None, None), geometry(120, 40, Some(0), None)] {
+        let err = manager.spawn_instance(SpawnParams { geometry: bad, ..Default::default() }).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    }
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_in_allowed_dirs() {
+    use alacritty_mcp::alacritty_manager::{check_allowed_dir, ALLOWED_DIRS_ENV};
+
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let allowed = mock.dir.path().join("projects");
+    let project = allowed.join("app");
+    fs::create_dir_all(&project).unwrap();
+    std::os::unix::fs::symlink("/etc", project.join("etc")).unwrap();
+
+    let spawn = |dir: String| manager.spawn_instance(SpawnParams {
+        working_directory: Some(dir),
+        ..Default::default()
+    });
+
+    std::env::set_var(ALLOWED_DIRS_ENV, format!("/nonexistent:{}", allowed.display()));
+    let inside = spawn(project.display().to_string()).await;
+    let outside = spawn("/etc".to_string()).await;
+    let escaped = spawn(format!("{}/../..", project.display())).await;
+    let symlinked = spawn(project.join("etc").display().to_string()).await;
+    let missing = spawn(project.join("missing").display().to_string()).await;
+    std::env::set_var(ALLOWED_DIRS_ENV, "");
+    let unrestricted = spawn("/etc".to_string()).await;
+    std::env::remove_var(ALLOWED_DIRS_ENV);
+
+    let inside = inside.unwrap();
+    assert!(mock.calls().contains(&format!("--working-directory {}", project.display())));
+    for err in [outside, escaped, symlinked].map(Result::unwrap_err) {
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+        assert!(err.to_string().contains(ALLOWED_DIRS_ENV));
+    }
+    let err = missing.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    assert!(err.to_string().contains("cannot be resolved"));
+    let unrestricted = unrestricted.unwrap();
+
+    // A prefix only matches whole path components
+    let sibling = mock.dir.path().join("projects-old");
+    fs::create_dir_all(&sibling).unwrap();
+    assert!(check_allowed_dir(&sibling.display().to_string(), std::slice::from_ref(&allowed)).is_err());
+    assert!(check_allowed_dir(&allowed.display().to_string(), &[allowed]).is_ok());
+
+    close_mock_instance(&manager, &inside).await;
+    close_mock_instance(&manager, &unrestricted).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_without_directory_in_allowed_dirs() {
+    use alacritty_mcp::alacritty_manager::ALLOWED_DIRS_ENV;
+
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let allowed = mock.dir.path().join("projects");
+    fs::create_dir_all(&allowed).unwrap();
+
+    // Without a working directory the terminal would start in the server's
+    let original_dir = std::env::current_dir().unwrap();
+    std::env::set_var(ALLOWED_DIRS_ENV, allowed.display().to_string());
+    std::env::set_current_dir(&allowed).unwrap();
+    let inside_dir = std::env::current_dir().unwrap();
+    let inside = manager.spawn_instance(SpawnParams::default()).await;
+    std::env::set_current_dir("/").unwrap();
+    let outside = manager.spawn_instance(SpawnParams::default()).await;
+    std::env::set_current_dir(original_dir).unwrap();
+    std::env::remove_var(ALLOWED_DIRS_ENV);
+
+    let inside = inside.unwrap();
+    assert!(mock.calls().contains(&format!("--working-directory {}", inside_dir.display())));
+    let err = outside.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    assert!(err.to_string().contains(ALLOWED_DIRS_ENV));
+
+    close_mock_instance(&manager, &inside).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_with_font() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+
+    let instance = manager.spawn_instance(SpawnParams {
+        command: Some("htop".to_string()),
+        font_size: Some(18.5),
+        font_family: Some("Fira Code".to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    assert!(mock.calls().contains(
+        "--option font.size=18.5 --option font.normal.family=\"Fira Code\" --command htop"
+    ));
+
+    for (font_size, font_family) in [(Some(3.5), None), (Some(72.5), None), (Some(f32::NAN), None), (None, Some(" "))] {
+        let err = manager.spawn_instance(SpawnParams {
+            font_size,
+            font_family: font_family.map(str::to_string),
+            ..Default::default()
+        }).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    }
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_class_before_command() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+
+    let instance = manager.spawn_instance(SpawnParams {
+        command: Some("bash".to_string()),
+        args: Some(vec!["-c".to_string(), "npm run dev".to_string()]),
+        ..Default::default()
+    }).await.unwrap();
+
+    // `--command` takes everything after it, so the class has to come first
+    let calls = mock.calls();
+    let spawn = calls.lines().find(|line| line.starts_with("alacritty --title")).unwrap();
+    let class = spawn.find(&format!("--class alacritty-mcp-{}", instance.id)).unwrap();
+    let command = spawn.find("--command").unwrap();
+    assert!(class < command, "{}", spawn);
+    assert!(spawn.ends_with("--command bash -c npm run dev"), "{}", spawn);
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_list_instance_environment() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+
+    // Inherited by the mock terminal
+    std::env::set_var("MOCKENV_PATH_EXTRA", "/opt/bin");
+    std::env::set_var("MOCKENV_API_token", "hunter2");
+    let instance = spawn_mock_instance(&manager).await;
+    std::env::remove_var("MOCKENV_PATH_EXTRA");
+    std::env::remove_var("MOCKENV_API_token");
+
+    let params = |show_secrets| EnvironmentParams {
+        instance_id: instance.id.clone(),
+        filter_prefix: Some("MOCKENV_".to_string()),
+        show_secrets,
+    };
+    let environment = manager.list_instance_environment(params(None)).await.unwrap();
+    assert_eq!(environment, [
+        EnvVar { key: "MOCKENV_API_token".to_string(), value: "<redacted>".to_string() },
+        EnvVar { key: "MOCKENV_PATH_EXTRA".to_string(), value: "/opt/bin".to_string() },
+    ]);
+
+    let environment = manager.list_instance_environment(params(Some(true))).await.unwrap();
+    assert_eq!(environment[0].value, "hunter2");
+
+    let everything = manager.list_instance_environment(EnvironmentParams {
+        instance_id: instance.id.clone(),
+        filter_prefix: None,
+        show_secrets: None,
+    }).await.unwrap();
+    assert!(everything.len() > environment.len());
+    assert!(everything.windows(2).all(|pair| pair[0].key <= pair[1].key));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_process_tree() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+
+    // The fake terminal is a shell script waiting on `sleep 30`
+    let tree = manager.get_process_tree(ProcessTreeParams { instance_id: instance.id.clone() }).await.unwrap();
+    assert_eq!(tree.pid, instance.pid);
+    assert_eq!(tree.name, "alacritty");
+    assert!(tree.args.iter().any(|arg| arg == "platform-test"));
+    assert_eq!(tree.children.len(), 1);
+    assert_eq!(tree.children[0].name, "sleep");
+    assert_eq!(tree.children[0].args, vec!["sleep", "30"]);
+
+    let err = manager.get_process_tree(ProcessTreeParams { instance_id: uuid::Uuid::new_v4().to_string() }).await.unwrap_err();
+    assert!(err.to_string().contains("Instance not found"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_screenshot_region() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+
+    let region = |x, y, width, height| ScreenshotRegionParams {
+        instance_id: instance.id.clone(),
+        x,
+        y,
+        width,
+        height,
+    };
+
+    let image = manager.screenshot_region(region(10, 20, 300, 200)).await.unwrap();
+    assert_eq!(image, "data:image/png;base64,UE5H");
+    assert!(mock.calls().contains("import -window 4242 -crop 300x200+10+20 +repage"));
+
+    // The mock window is 800x600
+    for bad in [region(600, 0, 300, 100), region(0, 500, 100, 200), region(0, 0, 0, 10)] {
+        let err = manager.screenshot_region(bad).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+        assert!(err.to_string().contains("does not fit inside the 800x600 window"));
+    }
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_terminal_dimensions() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let params = || DimensionsParams { instance_id: instance.id.clone() };
+
+    // The mock window is 800x600; without xprop a 9x18 cell is assumed
+    let dimensions = manager.get_terminal_dimensions(params()).await.unwrap();
+    assert_eq!(dimensions, Dimensions { cols: 88, rows: 33, pixel_width: 800, pixel_height: 600 });
+
+    write_script(mock.dir.path(), "xprop", concat!(
+        "printf 'WM_NORMAL_HINTS(WM_SIZE_HINTS):\\n",
+        "\\t\\tprogram specified minimum size: 30 by 40\\n",
+        "\\t\\tprogram specified resize increment: 10 by 20\\n",
+        "\\t\\tprogram specified base size: 20 by 0\\n'",
+    ));
+    let dimensions = manager.get_terminal_dimensions(params()).await.unwrap();
+    assert_eq!((dimensions.cols, dimensions.rows), (78, 30));
+    assert!(mock.calls().contains("xprop -id 4242 WM_NORMAL_HINTS"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_wayland_screenshot_region() {
+    let mock = MockTools::install(true);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+
+    let region = ScreenshotRegionParams {
+        instance_id: instance.id.clone(),
+        x: 10,
+        y: 20,
+        width: 300,
+        height: 200,
+    };
+
+    // Without a sway tree the window position is unknown
+    assert!(manager.screenshot_region(region.clone()).await.is_err());
+
+    let tree = json!({
+        "nodes": [{
+            "pid": instance.pid,
+            "rect": {"x": 100, "y": 50, "width": 900, "height": 540}
+        }]
+    });
+    fs::write(mock.dir.path().join("sway_tree.json"), tree.to_string()).unwrap();
+
+    let image = manager.screenshot_region(region).await.unwrap();
+    assert_eq!(image, "data:image/png;base64,UE5H");
+    assert!(mock.calls().contains("grim -g 110,70 300x200"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_watch_instances() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+
+    // Spawn while the window cannot be found yet
+    fs::write(mock.dir.path().join("no_window"), "").unwrap();
+    let instance = spawn_mock_instance(&manager).await;
+    assert_eq!(instance.window_id, None);
+
+    let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = std::sync::Arc::clone(&events);
+    let watcher = manager.watch_instances(move |instance_id, event| {
+        recorded.lock().unwrap().push((instance_id, event));
+    });
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(events.lock().unwrap().is_empty());
+
+    fs::remove_file(mock.dir.path().join("no_window")).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    assert_eq!(
+        events.lock().unwrap().as_slice(),
+        &[(instance.id.clone(), WatchEvent::NewWindow { window_id: 4242 })]
+    );
+
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(instance.pid as i32), nix::sys::signal::Signal::SIGKILL).unwrap();
+    wait_until_reaped(instance.pid).await;
+    tokio::time::sleep(std::time::Duration::from_millis(2200)).await;
+
+    // Exits are reported once, however long the entry lingers
+    let events = events.lock().unwrap().clone();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1], (instance.id.clone(), WatchEvent::Exited));
+    watcher.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_exit_notifications() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let mut server = McpServer::new(manager);
+    let mut notifications = server.take_notifications().unwrap();
+    assert!(server.take_notifications().is_none());
+
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {"notifications": {}},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    server.handle_request(&init_request.to_string()).await.unwrap();
+
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(instance.pid as i32), nix::sys::signal::Signal::SIGKILL).unwrap();
+    wait_until_reaped(instance.pid).await;
+
+    let notification = tokio::time::timeout(std::time::Duration::from_secs(5), notifications.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let notification: Value = serde_json::from_str(&notification).unwrap();
+    assert_eq!(notification["method"], "notifications/message");
+    assert!(notification.get("id").is_none());
+    assert_eq!(notification["params"]["data"]["event"], "exited");
+    assert_eq!(notification["params"]["data"]["instance_id"], instance.id.as_str());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_rename_instance() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+
+    manager.rename_instance(RenameParams {
+        instance_id: instance.id.clone(),
+        new_title: "tests: watching".to_string(),
+    }).await.unwrap();
+    assert!(mock.calls().contains("xdotool set_window --name tests: watching 4242"));
+
+    let fetched = manager.get_instance(GetInstanceParams { instance_id: instance.id.clone() }).await.unwrap();
+    assert_eq!(fetched.title, "tests: watching");
+
+    for bad_title in [String::new(), "x".repeat(256)] {
+        let err = manager.rename_instance(RenameParams {
+            instance_id: instance.id.clone(),
+            new_title: bad_title,
+        }).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    }
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_wait_for_output_progress() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let mut server = McpServer::new(manager);
+    let mut notifications = server.take_notifications().unwrap();
+
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    server.handle_request(&init_request.to_string()).await.unwrap();
+
+    let wait_request = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {
+            "name": "wait_for_output",
+            "arguments": {
+                "instance_id": instance.id,
+                "pattern": "clipboard",
+                "timeout_ms": 5000,
+                "poll_interval_ms": 1000
+            },
+            "_meta": {"progressToken": "wait-1"}
+        },
+        "id": 2
+    });
+
+    // Nothing is sent before `notifications/initialized`
+    server.handle_request(&wait_request.to_string()).await.unwrap();
+    assert!(notifications.try_recv().is_err());
+
+    let initialized = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+    server.handle_request(&initialized.to_string()).await.unwrap();
+    let response = server.handle_request(&wait_request.to_string()).await.unwrap().unwrap();
+    let response: Value = serde_json::from_str(&response).unwrap();
+    assert!(response["result"].is_object());
+
+    let notification: Value = serde_json::from_str(&notifications.try_recv().unwrap()).unwrap();
+    assert_eq!(notification["method"], "notifications/progress");
+    assert!(notification.get("id").is_none());
+    assert_eq!(notification["params"]["progressToken"], "wait-1");
+    assert_eq!(notification["params"]["progress"], 1);
+    assert_eq!(notification["params"]["total"], 5);
+    assert!(notification["params"]["message"].as_str().unwrap().contains("clipboard"));
+    assert!(notifications.try_recv().is_err());
+
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(instance.pid as i32), nix::sys::signal::Signal::SIGTERM).unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_batch_tool_calls_run_concurrently() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let mut server = McpServer::new(manager);
+
+    let wait = |id: u64| json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {
+            "name": "wait_for_output",
+            "arguments": {"instance_id": instance.id, "pattern": "never", "timeout_ms": 1500, "poll_interval_ms": 50}
+        },
+        "id": id
+    });
+    // `initialize` still takes effect before the tool calls run
+    let batch = json!([
+        wait(2),
+        {
+            "jsonrpc": "2.0",
+            "method": "initialize",
+            "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test", "version": "1.0"}},
+            "id": 1
+        },
+        wait(3),
+        {"jsonrpc": "2.0", "method": "tools/call", "params": {"name": "list_instances", "arguments": {}}, "id": 4}
+    ]);
+
+    let started = std::time::Instant::now();
+    let response = server.handle_request(&batch.to_string()).await.unwrap().unwrap();
+    let elapsed = started.elapsed();
+
+    let responses: Vec<Value> = serde_json::from_str(&response).unwrap();
+    let ids: Vec<u64> = responses.iter().map(|response| response["id"].as_u64().unwrap()).collect();
+    assert_eq!(ids, [2, 1, 3, 4]);
+    for response in &responses {
+        assert_ne!(response["error"]["code"], -32002, "{}", response);
+    }
+    assert!(responses[3]["result"]["content"][0]["text"].as_str().unwrap().contains(&instance.id));
+    // The two waits overlap instead of taking 3s one after the other
+    assert!(elapsed < std::time::Duration::from_millis(2500), "{:?}", elapsed);
+
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(instance.pid as i32), nix::sys::signal::Signal::SIGTERM).unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_cancel_tool_call() {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+
+    let (client, server_side) = tokio::io::duplex(64 * 1024);
+    let (server_reader, server_writer) = tokio::io::split(server_side);
+    let serve = tokio::spawn(alacritty_mcp::serve_connection(McpServer::new(manager), server_reader, server_writer));
+    let (client_reader, mut client_writer) = tokio::io::split(client);
+    let mut lines = BufReader::new(client_reader).lines();
+
+    let requests = [
+        json!({
+            "jsonrpc": "2.0",
+            "method": "initialize",
+            "params": {"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test", "version": "1.0"}},
+            "id": 1
+        }),
+        // Would wait the full minute for a pattern that never shows up
+        json!({
+            "jsonrpc": "2.0",
+            "method": "tools/call",
+            "params": {
+                "name": "wait_for_output",
+                "arguments": {"instance_id": instance.id, "pattern": "never", "timeout_ms": 60000, "poll_interval_ms": 50}
+            },
+            "id": "slow"
+        }),
+        // Still queued behind the slow call when cancelled, so never started
+        json!({
+            "jsonrpc": "2.0",
+            "method": "tools/call",
+            "params": {
+                "name": "wait_for_output",
+                "arguments": {"instance_id": instance.id, "pattern": "never", "timeout_ms": 60000, "poll_interval_ms": 50}
+            },
+            "id": "queued"
+        }),
+        json!({"jsonrpc": "2.0", "method": "ping", "id": 3}),
+        // Unknown IDs are ignored
+        json!({"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": 99}}),
+        json!({"jsonrpc": "2.0", "method": "notifications/cancelled", "params": {"requestId": "queued"}}),
+        json!({"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": "slow"}}),
+    ];
+    for request in requests {
+        client_writer.write_all(format!("{}\n", request).as_bytes()).await.unwrap();
+    }
+
+    let mut responses = Vec::new();
+    for _ in 0..4 {
+        let line = tokio::time::timeout(std::time::Duration::from_secs(5), lines.next_line()).await.unwrap().unwrap().unwrap();
+        responses.push(serde_json::from_str::<Value>(&line).unwrap());
+    }
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(responses[1]["id"], "slow");
+    assert_eq!(responses[1]["error"]["code"], -32800);
+    // Answered in its turn, like the one that was running
+    assert_eq!(responses[2]["id"], "queued");
+    assert_eq!(responses[2]["error"]["code"], -32800);
+    // Requests queued behind the cancelled ones are still answered, in order
+    assert_eq!(responses[3]["id"], 3);
+    assert_eq!(responses[3]["result"], json!({}));
+
+    // The duplex stream only closes once both client halves are gone
+    drop(client_writer);
+    drop(lines);
+    tokio::time::timeout(std::time::Duration::from_secs(5), serve).await.unwrap().unwrap().unwrap();
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(instance.pid as i32), nix::sys::signal::Signal::SIGTERM).unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_duplicate_instance() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let original = manager.spawn_instance(SpawnParams {
+        command: Some("htop".to_string()),
+        args: Some(vec!["-d".to_string(), "10".to_string()]),
+        title: Some("monitor".to_string()),
+        tags: Some(vec!["ops".to_string()]),
+        ..Default::default()
+    }).await.unwrap();
+
+    let result = manager.duplicate_instance(DuplicateParams { instance_id: original.id.clone() }).await.unwrap();
+    assert_eq!(result.original.id, original.id);
+    assert_ne!(result.duplicate.id, original.id);
+    assert_eq!(result.duplicate.title, "monitor (2)");
+    assert_eq!(result.duplicate.command, "htop");
+    assert_eq!(result.duplicate.args, vec!["-d", "10"]);
+    assert_eq!(result.duplicate.tags, vec!["ops".to_string()]);
+
+    // The fake terminal and its child run wherever the test runs
+    let cwd = std::env::current_dir().unwrap();
+    let calls = mock.calls();
+    let spawn = calls.lines().filter(|line| line.starts_with("alacritty ")).nth(1).unwrap();
+    assert!(spawn.contains("--title monitor (2)"));
+    assert!(spawn.contains(&format!("--working-directory {}", cwd.display())));
+    assert!(spawn.ends_with("--command htop -d 10"), "{}", spawn);
+
+    let err = manager.duplicate_instance(DuplicateParams { instance_id: uuid::Uuid::new_v4().to_string() }).await.unwrap_err();
+    assert!(err.to_string().contains("Instance not found"));
+
+    close_mock_instance(&manager, &result.duplicate).await;
+    close_mock_instance(&manager, &original).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+#[serial]
+async fn test_concurrent_list_and_spawn() {
+    let _mock = MockTools::install(false);
+    let manager = std::sync::Arc::new(test_manager());
+
+    let mut tasks = Vec::new();
+    for i in 0..3 {
+        let spawner = std::sync::Arc::clone(&manager);
+        tasks.push(tokio::spawn(async move {
+            spawner.spawn_instance(SpawnParams {
+                title: Some(format!("concurrent-{}", i)),
+                ..Default::default()
+            }).await.map(|_| ())
+        }));
+        let lister = std::sync::Arc::clone(&manager);
+        tasks.push(tokio::spawn(async move {
+            lister.list_instances(None).await.map(|_| ())
+        }));
+    }
+
+    let all = async {
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(10), all)
+        .await
+        .expect("concurrent list_instances and spawn_instance deadlocked");
+
+    let spawned: Vec<AlacrittyInstance> = manager.list_instances(None).await.unwrap()
+        .into_iter()
+        .filter(|instance| instance.title.starts_with("concurrent-"))
+        .collect();
+    assert_eq!(spawned.len(), 3);
+    for instance in &spawned {
+        close_mock_instance(&manager, instance).await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_set_alacritty_option() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let set = |key: &str, value: &str| SetOptionParams {
+        instance_id: instance.id.clone(),
+        key: key.to_string(),
+        value: value.to_string(),
+    };
+
+    let err = manager.set_alacritty_option(set("font.size", "14")).await.unwrap_err();
+    assert!(err.to_string().contains("No IPC socket"));
+
+    let socket = format!("/tmp/alacritty-{}.sock", instance.pid);
+    fs::write(&socket, "").unwrap();
+    let result = manager.set_alacritty_option(set("font.size", "14")).await;
+    let bad_key = manager.set_alacritty_option(set("font size", "14")).await;
+
+    fs::write(mock.dir.path().join("alacritty_version"), "alacritty 0.12.3\n").unwrap();
+    let too_old = manager.set_alacritty_option(set("font.size", "14")).await;
+    fs::remove_file(&socket).unwrap();
+
+    result.unwrap();
+    assert!(mock.calls().contains(&format!("alacritty msg --socket {} config -w 4242 font.size=14", socket)));
+    assert!(matches!(bad_key.unwrap_err().downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    let too_old = too_old.unwrap_err().to_string();
+    assert!(too_old.contains("0.12.3") && too_old.contains("upgrade"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_window_lookup_by_class() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    assert_eq!(instance.window_class, Some(format!("alacritty-mcp-{}", instance.id)));
+
+    // Found through the class the server set, not the PID
+    let calls = mock.calls();
+    assert!(calls.contains(&format!("xdotool search --class ^alacritty-mcp-{}$", instance.id)), "{}", calls);
+    assert!(!calls.contains("xdotool search --pid"));
+
+    // Looked up once, then taken from the registry
+    for keys in ["a", "b"] {
+        manager.send_keys(SendKeysParams { instance_id: instance.id.clone(), keys: keys.to_string() }).await.unwrap();
+    }
+    assert_eq!(mock.calls().matches("xdotool search").count(), 1);
+    close_mock_instance(&manager, &instance).await;
+
+    // A terminal opened by hand has no class of ours, so its PID is used
+    let mut child = std::process::Command::new("alacritty").args(["--title", "external"]).spawn().unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let imported = manager.import_existing_instances().await.unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let external = imported.iter().find(|instance| instance.pid == child.id()).unwrap();
+    assert_eq!(external.window_class, None);
+    assert_eq!(external.window_id, Some(4242));
+    assert!(mock.calls().contains(&format!("xdotool search --pid {} --class Alacritty", child.id())));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_spawn_retries_window_lookup() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+
+    // The window shows up between the second and third attempt, 600ms and
+    // 1200ms in
+    let no_window = mock.dir.path().join("no_window");
+    fs::write(&no_window, "").unwrap();
+    let (instance, _) = tokio::join!(spawn_mock_instance(&manager), async {
+        tokio::time::sleep(std::time::Duration::from_millis(900)).await;
+        fs::remove_file(&no_window).unwrap();
+    });
+
+    assert_eq!(instance.window_id, Some(4242));
+    assert_eq!(mock.calls().matches("xdotool search --class").count(), 3);
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_import_existing_instances() {
+    let _mock = MockTools::install(false);
+    let cwd = TempDir::new().unwrap();
+    let id = uuid::Uuid::new_v4().to_string();
+    let mut child = std::process::Command::new("alacritty")
+        .args(["--title", "opened-by-hand", "--class", &format!("alacritty-mcp-{}", id), "--command", "htop", "-d", "10"])
+        .current_dir(cwd.path())
+        .spawn()
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let manager = test_manager();
+    let imported = manager.import_existing_instances().await.unwrap();
+    let again = manager.import_existing_instances().await.unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let instance = imported.iter().find(|instance| instance.pid == child.id()).unwrap();
+    assert_eq!(instance.id, id);
+    assert_eq!(instance.title, "opened-by-hand");
+    assert_eq!(instance.command, "htop");
+    assert_eq!(instance.args, vec!["-d", "10"]);
+    assert_eq!(instance.window_id, Some(4242));
+    assert_eq!(instance.window_class, Some(format!("alacritty-mcp-{}", id)));
+    assert_eq!(instance.working_directory.as_deref(), cwd.path().canonicalize().unwrap().to_str());
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    assert!(instance.created_at > 0 && instance.created_at <= now + 1);
+    assert!(again.iter().all(|instance| instance.pid != child.id()));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_write_file() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let dir = TempDir::new().unwrap();
+    let instance = manager.spawn_instance(SpawnParams {
+        working_directory: Some(dir.path().to_string_lossy().to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    let write = |relative_path: &str, overwrite: Option<bool>| WriteFileParams {
+        instance_id: instance.id.clone(),
+        relative_path: relative_path.to_string(),
+        content: "fn main() {}\n".to_string(),
+        mode: None,
+        overwrite,
+    };
+
+    let result = manager.write_file(WriteFileParams {
+        mode: Some("755".to_string()),
+        ..write("src/main.rs", None)
+    }).await.unwrap();
+    let path = dir.path().canonicalize().unwrap().join("src/main.rs");
+    assert_eq!(result.path, path.to_string_lossy());
+    assert_eq!(result.bytes_written, 13);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "fn main() {}\n");
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o755);
+
+    let exists = manager.write_file(write("src/main.rs", None)).await.unwrap_err();
+    assert!(exists.to_string().contains("already exists"));
+    manager.write_file(write("./src/../src/main.rs", Some(true))).await.unwrap();
+
+    for outside in ["../escape.rs", "/etc/passwd", "src/../../escape.rs"] {
+        let err = manager.write_file(write(outside, Some(true))).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))), "{} was not rejected", outside);
+    }
+    std::os::unix::fs::symlink("/tmp", dir.path().join("link")).unwrap();
+    assert!(matches!(manager.write_file(write("link/escape.rs", None)).await.unwrap_err().downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    let bad_mode = manager.write_file(WriteFileParams { mode: Some("9".to_string()), ..write("x", None) }).await;
+    assert!(matches!(bad_mode.unwrap_err().downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_read_file() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("notes.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+    fs::write(dir.path().join("latin1.txt"), b"caf\xe9\n").unwrap();
+    fs::write(dir.path().join("utf8.txt"), "abcé").unwrap();
+    fs::write(dir.path().join("app.bin"), b"\x7fELF\x00\x01").unwrap();
+    // Longer than one read, with characters split between reads
+    fs::write(dir.path().join("long.txt"), format!("x{}\nend", "é".repeat(70_000))).unwrap();
+    assert!(std::process::Command::new("mkfifo").arg(dir.path().join("pipe")).status().unwrap().success());
+    let instance = manager.spawn_instance(SpawnParams {
+        working_directory: Some(dir.path().to_string_lossy().to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    let read = |relative_path: &str| ReadFileParams {
+        instance_id: instance.id.clone(),
+        relative_path: relative_path.to_string(),
+        start_line: None,
+        end_line: None,
+        max_bytes: None,
+    };
+
+    let whole = manager.read_file(read("notes.txt")).await.unwrap();
+    assert_eq!(whole.path, dir.path().canonicalize().unwrap().join("notes.txt").to_string_lossy());
+    assert_eq!(whole.content, "one\ntwo\nthree\nfour\n");
+    assert_eq!(whole.total_lines, 4);
+    assert_eq!(whole.encoding, "utf-8");
+    assert!(!whole.truncated);
+
+    let range = manager.read_file(ReadFileParams { start_line: Some(2), end_line: Some(3), ..read("notes.txt") }).await.unwrap();
+    assert_eq!(range.content, "two\nthree\n");
+    assert_eq!(range.total_lines, 4);
+    let tail = manager.read_file(ReadFileParams { start_line: Some(4), ..read("notes.txt") }).await.unwrap();
+    assert_eq!(tail.content, "four\n");
+    let limited = manager.read_file(ReadFileParams { max_bytes: Some(5), ..read("notes.txt") }).await.unwrap();
+    assert_eq!(limited.content, "one\nt");
+    assert!(limited.truncated);
+
+    let exact = manager.read_file(ReadFileParams { max_bytes: Some(19), ..read("notes.txt") }).await.unwrap();
+    assert_eq!((exact.content.as_str(), exact.truncated), ("one\ntwo\nthree\nfour\n", false));
+    let short = manager.read_file(ReadFileParams { max_bytes: Some(18), ..read("notes.txt") }).await.unwrap();
+    assert_eq!((short.content.as_str(), short.truncated), ("one\ntwo\nthree\nfour", true));
+    // The byte past the limit starts a character, which is left out whole
+    let straddling = manager.read_file(ReadFileParams { max_bytes: Some(3), ..read("utf8.txt") }).await.unwrap();
+    assert_eq!((straddling.content.as_str(), straddling.truncated), ("abc", true));
+    let split = manager.read_file(ReadFileParams { max_bytes: Some(4), ..read("utf8.txt") }).await.unwrap();
+    assert_eq!((split.content.as_str(), split.truncated), ("abc", true));
+
+    let latin1 = manager.read_file(read("latin1.txt")).await.unwrap();
+    assert_eq!(latin1.content, "café\n");
+    assert_eq!(latin1.encoding, "iso-8859-1");
+    // `max_bytes` counts the file's bytes, not the two é takes as UTF-8
+    let fits = manager.read_file(ReadFileParams { max_bytes: Some(5), ..read("latin1.txt") }).await.unwrap();
+    assert_eq!((fits.content.as_str(), fits.truncated), ("café\n", false));
+    let cut = manager.read_file(ReadFileParams { max_bytes: Some(4), ..read("latin1.txt") }).await.unwrap();
+    assert_eq!((cut.content.as_str(), cut.truncated), ("café", true));
+
+    let long = manager.read_file(ReadFileParams { max_bytes: Some(8), ..read("long.txt") }).await.unwrap();
+    assert_eq!(long.content, "xééé");
+    assert_eq!((long.total_lines, long.encoding.as_str(), long.truncated), (2, "utf-8", true));
+    let last = manager.read_file(ReadFileParams { start_line: Some(2), ..read("long.txt") }).await.unwrap();
+    assert_eq!(last.content, "end");
+    assert!(!last.truncated);
+
+    // Nobody writes to the FIFO, so reading it would never finish
+    let fifo = tokio::time::timeout(std::time::Duration::from_secs(5), manager.read_file(read("pipe"))).await.unwrap();
+    assert!(matches!(fifo.unwrap_err().downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    let backwards = manager.read_file(ReadFileParams { start_line: Some(3), end_line: Some(2), ..read("notes.txt") }).await;
+    assert!(matches!(backwards.unwrap_err().downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    assert!(matches!(manager.read_file(read("../notes.txt")).await.unwrap_err().downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    let mut server = McpServer::new(manager);
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    server.handle_request(&init_request.to_string()).await.unwrap();
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": "read_file", "arguments": {"instance_id": instance.id, "relative_path": "app.bin"}},
+        "id": 2
+    });
+    let response: Value = serde_json::from_str(
+        &server.handle_request(&request.to_string()).await.unwrap().unwrap()
+    ).unwrap();
+    assert_eq!(response["error"]["data"]["kind"], "binary");
+    assert!(response["error"]["data"]["path"].as_str().unwrap().ends_with("app.bin"));
+
+    let close_request = json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": "close_instance", "arguments": {"instance_id": instance.id, "timeout_ms": 1000}},
+        "id": 3
+    });
+    server.handle_request(&close_request.to_string()).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_exited_instances_keep_exit_code() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let terminated = spawn_mock_instance(&manager).await;
+    let killed = spawn_mock_instance(&manager).await;
+
+    // The mock exits 0 on SIGTERM; SIGKILL is reported shell-style as 128 + 9
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(terminated.pid as i32), nix::sys::signal::Signal::SIGTERM).unwrap();
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(killed.pid as i32), nix::sys::signal::Signal::SIGKILL).unwrap();
+    wait_until_reaped(terminated.pid).await;
+    wait_until_reaped(killed.pid).await;
+
+    let instances = manager.list_instances(None).await.unwrap();
+    let find = |id: &str| instances.iter().find(|instance| instance.id == id).unwrap().clone();
+    assert_eq!(find(&terminated.id).status, InstanceStatus::Exited);
+    assert_eq!(find(&terminated.id).exit_code, Some(0));
+    assert_eq!(find(&killed.id).exit_code, Some(137));
+
+    // Listing again keeps them until they are closed
+    assert_eq!(manager.list_instances(None).await.unwrap().len(), instances.len());
+    close_mock_instance(&manager, &terminated).await;
+    let err = manager.get_instance(GetInstanceParams { instance_id: killed.id.clone() }).await.unwrap_err();
+    assert!(err.to_string().contains("exit code 137"));
+    assert_eq!(manager.list_instances(None).await.unwrap().len(), instances.len() - 2);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_reload_config() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let config_home = TempDir::new().unwrap();
+    let config_file = config_home.path().join("alacritty").join("alacritty.toml");
+    fs::create_dir_all(config_file.parent().unwrap()).unwrap();
+    fs::write(&config_file, "[font]\nsize = 12\n").unwrap();
+    let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    fs::File::options().append(true).open(&config_file).unwrap().set_modified(old).unwrap();
+
+    let spawn_with_env = |env: HashMap<String, String>| manager.spawn_instance(SpawnParams {
+        title: Some("platform-test".to_string()),
+        env: Some(env),
+        ..Default::default()
+    });
+    let instance = spawn_with_env(HashMap::from([
+        ("XDG_CONFIG_HOME".to_string(), config_home.path().display().to_string()),
+    ])).await.unwrap();
+
+    // The file Alacritty watches is touched, its contents left alone
+    let result = manager.reload_config(ReloadConfigParams { instance_id: instance.id.clone() }).await.unwrap();
+    assert_eq!(result.config_file, config_file.display().to_string());
+    assert!(fs::metadata(&config_file).unwrap().modified().unwrap() > old);
+    assert_eq!(fs::read_to_string(&config_file).unwrap(), "[font]\nsize = 12\n");
+
+    // Without a config file there is nothing Alacritty would reload
+    let empty_home = TempDir::new().unwrap();
+    let unconfigured = spawn_with_env(HashMap::from([
+        ("HOME".to_string(), empty_home.path().display().to_string()),
+        ("XDG_CONFIG_HOME".to_string(), empty_home.path().display().to_string()),
+    ])).await.unwrap();
+    let err = manager.reload_config(ReloadConfigParams { instance_id: unconfigured.id.clone() }).await.unwrap_err();
+    assert!(err.to_string().contains("no config file"), "{}", err);
+    close_mock_instance(&manager, &unconfigured).await;
+
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(instance.pid as i32), nix::sys::signal::Signal::SIGTERM).unwrap();
+    wait_until_reaped(instance.pid).await;
+    let err = manager.reload_config(ReloadConfigParams { instance_id: instance.id.clone() }).await.unwrap_err();
+    assert!(err.to_string().contains("has exited"), "{}", err);
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_search_in_instance() {
+    let mock = MockTools::install(false);
+    let manager = test_manager();
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "use std::fmt;\n\npub fn todo_list() {}\n// TODO: tests\n").unwrap();
+    fs::write(dir.path().join("notes.txt"), "TODO: release\n").unwrap();
+    let instance = manager.spawn_instance(SpawnParams {
+        working_directory: Some(dir.path().to_string_lossy().to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    let search = |pattern: &str| SearchParams {
+        instance_id: instance.id.clone(),
+        pattern: pattern.to_string(),
+        path: None,
+        file_glob: None,
+        max_results: None,
+    };
+
+    // No rg on the mock PATH here, so this goes through `grep -r`
+    let mut results = manager.search_in_instance(search("TODO")).await.unwrap();
+    results.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].file_path, "notes.txt");
+    assert_eq!((results[1].file_path.as_str(), results[1].line, results[1].column), ("src/lib.rs", 4, 4));
+    assert_eq!(results[1].context_before, vec!["", "pub fn todo_list() {}"]);
+
+    let rust_only = manager.search_in_instance(SearchParams { file_glob: Some("*.rs".to_string()), ..search("TODO") }).await.unwrap();
+    assert_eq!(rust_only.len(), 1);
+    let in_src = manager.search_in_instance(SearchParams { path: Some("src".to_string()), ..search("fn \\w+") }).await.unwrap();
+    assert_eq!(in_src[0].match_text, "fn todo_list");
+    let limited = manager.search_in_instance(SearchParams { max_results: Some(1), ..search("TODO") }).await.unwrap();
+    assert_eq!(limited.len(), 1);
+    assert!(manager.search_in_instance(search("nothing matches this")).await.unwrap().is_empty());
+
+    let err = manager.search_in_instance(search("(unclosed")).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    let err = manager.search_in_instance(SearchParams { path: Some("../".to_string()), ..search("TODO") }).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    // ripgrep is preferred once it is installed
+    write_script(mock.dir.path(), "rg", concat!(
+        "printf '%s\\n' '{\"type\":\"match\",\"data\":{\"path\":{\"text\":\"src/lib.rs\"},",
+        "\"lines\":{\"text\":\"// TODO: tests\\n\"},\"line_number\":4,",
+        "\"submatches\":[{\"match\":{\"text\":\"TODO\"},\"start\":3,\"end\":7}]}}'",
+    ));
+    let results = manager.search_in_instance(SearchParams { file_glob: Some("*.rs".to_string()), ..search("TODO") }).await.unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].column, 4);
+    assert!(mock.calls().lines().any(|call| call.starts_with("rg --json") && call.contains("--glob *.rs --regexp TODO")));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_instance_memory() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+
+    let usage = manager.get_memory_usage(&instance.id).await.unwrap();
+    assert!(usage.rss_kb > 0);
+    assert!(usage.virtual_kb >= usage.rss_kb);
+
+    let mut server = McpServer::new(manager);
+    let init_request = json!({
+        "jsonrpc": "2.0",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": {"name": "test-client", "version": "1.0.0"}
+        },
+        "id": 1
+    });
+    server.handle_request(&init_request.to_string()).await.unwrap();
+    let call = |id: u32, name: &str, arguments: Value| json!({
+        "jsonrpc": "2.0",
+        "method": "tools/call",
+        "params": {"name": name, "arguments": arguments},
+        "id": id
+    }).to_string();
+    let text = |response: &str| -> String {
+        let response: Value = serde_json::from_str(response).unwrap();
+        response["result"]["content"][0]["text"].as_str().unwrap().to_string()
+    };
+
+    let response = server.handle_request(&call(2, "get_instance", json!({"instance_id": instance.id}))).await.unwrap().unwrap();
+    let details: Value = serde_json::from_str(text(&response).split_once('\n').unwrap().1).unwrap();
+    assert!(details["memory"]["rss_kb"].as_u64().unwrap() > 0);
+
+    let response = server.handle_request(&call(3, "list_instances", json!({}))).await.unwrap().unwrap();
+    assert!(text(&response).lines().next().unwrap().contains("kB RSS"));
+
+    nix::sys::signal::kill(nix::unistd::Pid::from_raw(instance.pid as i32), nix::sys::signal::Signal::SIGTERM).unwrap();
+    wait_until_reaped(instance.pid).await;
+    let response: Value = serde_json::from_str(
+        &server.handle_request(&call(4, "get_instance_memory", json!({"instance_id": instance.id}))).await.unwrap().unwrap()
+    ).unwrap();
+    assert!(response["error"]["message"].as_str().unwrap().contains("has exited"));
+
+    server.handle_request(&call(5, "close_instance", json!({"instance_id": instance.id, "timeout_ms": 1000}))).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_set_clipboard() {
+    for wayland in [false, true] {
+        let mock = MockTools::install(wayland);
+        let manager = test_manager();
+        let display = if wayland { "wayland-7" } else { ":7" };
+        let instance = manager.spawn_instance(SpawnParams {
+            env: Some(HashMap::from([
+                ("DISPLAY".to_string(), display.to_string()),
+                ("WAYLAND_DISPLAY".to_string(), display.to_string()),
+            ])),
+            ..Default::default()
+        }).await.unwrap();
+        let tool = if wayland { "wl-copy" } else { "xclip -selection clipboard" };
+
+        let bytes = manager.set_clipboard(SetClipboardParams { content: "héllo\n".to_string(), instance_id: None }).await.unwrap();
+        assert_eq!(bytes, 7);
+        assert_eq!(fs::read_to_string(mock.dir.path().join("clipboard")).unwrap(), "héllo\n");
+        assert!(mock.calls().lines().any(|call| call.trim_end() == tool), "{}", mock.calls());
+
+        // The instance's display wins over the server's
+        manager.set_clipboard(SetClipboardParams { content: "x".to_string(), instance_id: Some(instance.id.clone()) }).await.unwrap();
+        let used = fs::read_to_string(mock.dir.path().join("clipboard_display")).unwrap();
+        assert!(used.contains(display), "{}", used);
+
+        let huge = "a".repeat(10 * 1024 * 1024 + 1);
+        let err = manager.set_clipboard(SetClipboardParams { content: huge, instance_id: None }).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+        close_mock_instance(&manager, &instance).await;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_clipboard() {
+    for wayland in [false, true] {
+        let mock = MockTools::install(wayland);
+        let manager = test_manager();
+        let tool = if wayland { "wl-paste --no-newline" } else { "xclip -selection clipboard -o" };
+
+        manager.set_clipboard(SetClipboardParams { content: "pasted text".to_string(), instance_id: None }).await.unwrap();
+        let clipboard = manager.get_clipboard(GetClipboardParams { instance_id: None }).await.unwrap();
+        assert_eq!(clipboard.text, "pasted text");
+        assert_eq!(clipboard.size_bytes, 11);
+        assert!(mock.calls().lines().any(|call| call.trim_end() == tool), "{}", mock.calls());
+
+        fs::write(mock.dir.path().join("clipboard"), b"\x89PNG\r\n\x1a\n\x00\x00").unwrap();
+        let err = manager.get_clipboard(GetClipboardParams { instance_id: None }).await.unwrap_err();
+        assert!(err.to_string().contains("binary"), "{}", err);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_monitor_instance() {
+    let mock = MockTools::install(true);
+    let manager = test_manager();
+    let instance = spawn_mock_instance(&manager).await;
+    let monitor = |duration_secs: u32, interval_ms: u32| MonitorParams {
+        instance_id: instance.id.clone(),
+        duration_secs,
+        interval_ms,
+        format: None,
+    };
+
+    let mut captures = Vec::new();
+    let screenshots = manager.monitor_instance_with_progress(monitor(1, 400), |taken, count| captures.push((taken, count))).await.unwrap();
+    assert_eq!(screenshots.len(), 2);
+    assert!(screenshots.iter().all(|screenshot| screenshot.content == "mock wayland clipboard"));
+    // The first is taken right away, the second an interval later
+    assert!(screenshots[0].timestamp_ms < 100, "{:?}", screenshots);
+    assert!(screenshots[1].timestamp_ms >= 400, "{:?}", screenshots);
+    assert_eq!(captures, vec![(1, 2), (2, 2)]);
+    assert_eq!(mock.calls().lines().filter(|call| call.starts_with("wl-paste")).count(), 2);
+
+    let err = manager.monitor_instance(monitor(1, 0)).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    let err = manager.monitor_instance(MonitorParams { format: Some("svg".to_string()), ..monitor(1, 500) }).await.unwrap_err();
+    assert!(err.to_string().contains("Unsupported format"));
+
+    close_mock_instance(&manager, &instance).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ipc_socket_discovery() {
+    let _mock = MockTools::install(false);
+    let manager = test_manager();
+    let dir = TempDir::new().unwrap();
+    let socket = dir.path().join("ipc.sock");
+    let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+
+    // Alacritty hands its socket to the shell as $ALACRITTY_SOCKET
+    let instance = manager.spawn_instance(SpawnParams {
+        env: Some(HashMap::from([("ALACRITTY_SOCKET".to_string(), socket.display().to_string())])),
+        ..Default::default()
+    }).await.unwrap();
+    assert_eq!(instance.ipc_socket_path, Some(socket.display().to_string()));
+
+    let server = tokio::spawn(async move {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut message = String::new();
+        stream.read_to_string(&mut message).await.unwrap();
+        stream.write_all(b"{\"ok\": true}").await.unwrap();
+        message
+    });
+    let reply = manager.send_ipc_message(&instance.id, "{\n  \"Config\": {\"options\": [\"font.size=14\"]}\n}").await.unwrap();
+    assert_eq!(reply, "{\"ok\": true}");
+    // Sent compacted onto one line, as Alacritty reads line by line
+    assert_eq!(server.await.unwrap(), "{\"Config\":{\"options\":[\"font.size=14\"]}}\n");
+
+    let err = manager.send_ipc_message(&instance.id, "not json").await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+
+    let without_socket = spawn_mock_instance(&manager).await;
+    assert_eq!(without_socket.ipc_socket_path, None);
+    let err = manager.send_ipc_message(&without_socket.id, "{}").await.unwrap_err();
+    assert!(err.to_string().contains("No IPC socket found"), "{}", err);
+
+    close_mock_instance(&manager, &instance).await;
+    close_mock_instance(&manager, &without_socket).await;
+}
+
+#[test]
+fn test_ydotool_key_args() {
+    use alacritty_mcp::alacritty_manager::ydotool_key_args;
+
+    let args = |keys: &str| ydotool_key_args(keys).unwrap().join(" ");
+    assert_eq!(args("ctrl+shift+v"), "29:1 42:1 47:1 47:0 42:0 29:0");
+    assert_eq!(args("Return"), "28:1 28:0");
+    assert_eq!(args("  Escape "), "1:1 1:0");
+    assert_eq!(args("alt+F4 Page_Down"), "56:1 62:1 62:0 56:0 109:1 109:0");
+    assert_eq!(args("q m 1 0"), "16:1 16:0 50:1 50:0 2:1 2:0 11:1 11:0");
+    // An uppercase letter is typed with shift, pressed only once
+    assert_eq!(args("A shift+Z"), "42:1 30:1 30:0 42:0 42:1 44:1 44:0 42:0");
+
+    for keys in ["", "   ", "ctrl+", "Hello", "ctrl+bogus"] {
+        let err = ydotool_key_args(keys).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))), "{:?} was accepted", keys);
+    }
+}
@@ -1,9 +1,17 @@
-use alacritty_mcp::{AlacrittyManager, types::*};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use alacritty_mcp::{AlacrittyManager, AlacrittyMcpError, Config, InFlightRequests, PromptLibrary, RateLimiter, StateFile, TerminalParser, types::*};
+use alacritty_mcp::alacritty_manager::{is_secret_env_key, monitor_screenshot_count, read_environment, MAX_MONITOR_SCREENSHOTS};
+use alacritty_mcp::cancellation::{cancellation_target, cancelled_request_id};
+use alacritty_mcp::error::error_code;
+use alacritty_mcp::search::{parse_grep_output, parse_rg_json};
+use alacritty_mcp::terminal_parser::{command_output, diff_screens, minimap, strip_ansi, DEFAULT_PROMPT_PATTERN, MINIMAP_COLS, MINIMAP_ROWS};
+use regex::Regex;
 use serde_json::json;
 
 #[tokio::test]
 async fn test_alacritty_manager_creation() {
-    let _manager = AlacrittyManager::new();
+    let _manager = AlacrittyManager::with_config_and_state_file(Config::default(), None);
     // Test passes if no panic occurs
 }
 
@@ -15,7 +23,17 @@ async fn test_alacritty_instance_serialization() {
         window_id: Some(67890),
         title: "test-title".to_string(),
         command: "test-command".to_string(),
+        args: vec!["--verbose".to_string()],
         created_at: 1234567890,
+        status: InstanceStatus::Running,
+        last_focused_at: None,
+        tags: vec!["build".to_string()],
+        working_directory: Some("/tmp".to_string()),
+        exit_code: Some(0),
+        window_class: Some("alacritty-mcp-test-id".to_string()),
+        ipc_socket_path: Some("/tmp/alacritty-12345.sock".to_string()),
+        last_activity_at: 1234567900,
+        process_started_at: Some(1234567880),
     };
 
     let json_str = serde_json::to_string(&instance).unwrap();
@@ -26,7 +44,172 @@ async fn test_alacritty_instance_serialization() {
     assert_eq!(instance.window_id, deserialized.window_id);
     assert_eq!(instance.title, deserialized.title);
     assert_eq!(instance.command, deserialized.command);
+    assert_eq!(instance.args, deserialized.args);
     assert_eq!(instance.created_at, deserialized.created_at);
+    assert_eq!(instance.status, deserialized.status);
+    assert_eq!(instance.last_focused_at, deserialized.last_focused_at);
+    assert_eq!(instance.tags, deserialized.tags);
+    assert_eq!(instance.working_directory, deserialized.working_directory);
+    assert_eq!(instance.exit_code, deserialized.exit_code);
+    assert_eq!(instance.window_class, deserialized.window_class);
+    assert_eq!(instance.ipc_socket_path, deserialized.ipc_socket_path);
+    assert_eq!(instance.last_activity_at, deserialized.last_activity_at);
+    assert_eq!(instance.process_started_at, deserialized.process_started_at);
+}
+
+fn test_instance(id: &str, pid: u32) -> AlacrittyInstance {
+    AlacrittyInstance {
+        id: id.to_string(),
+        pid,
+        window_id: None,
+        title: format!("title-{}", id),
+        command: "shell".to_string(),
+        args: Vec::new(),
+        created_at: 1234567890,
+        status: InstanceStatus::Running,
+        last_focused_at: None,
+        tags: vec!["build".to_string()],
+        working_directory: None,
+        exit_code: None,
+        window_class: None,
+        ipc_socket_path: None,
+        last_activity_at: 1234567890,
+        process_started_at: None,
+    }
+}
+
+#[test]
+fn test_instance_uptime() {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let mut instance = test_instance("uptime", 1);
+    instance.created_at = now - 90;
+    let uptime = instance.uptime_secs().unwrap();
+    assert!((90..=91).contains(&uptime));
+
+    let json = serde_json::to_value(&instance).unwrap();
+    assert!((90..=91).contains(&json["uptime_secs"].as_u64().unwrap()));
+    // Read-only: ignored on the way back in
+    let deserialized: AlacrittyInstance = serde_json::from_value(json).unwrap();
+    assert_eq!(deserialized.created_at, instance.created_at);
+
+    // A creation time in the future (clock skew) is not negative uptime
+    instance.created_at = now + 3600;
+    assert_eq!(instance.uptime_secs(), Some(0));
+
+    instance.created_at = 0;
+    assert_eq!(instance.uptime_secs(), None);
+    assert!(serde_json::to_value(&instance).unwrap()["uptime_secs"].is_null());
+}
+
+#[test]
+fn test_instance_idle() {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let mut instance = test_instance("idle", 1);
+    instance.created_at = now - 600;
+    instance.last_activity_at = now - 30;
+    assert!((30..=31).contains(&instance.idle_secs().unwrap()));
+    assert!((30..=31).contains(&serde_json::to_value(&instance).unwrap()["idle_secs"].as_u64().unwrap()));
+
+    // Registries saved before activity was tracked count from creation
+    instance.last_activity_at = 0;
+    assert!((600..=601).contains(&instance.idle_secs().unwrap()));
+
+    instance.created_at = 0;
+    assert_eq!(instance.idle_secs(), None);
+}
+
+#[test]
+fn test_state_file_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = StateFile::new(dir.path().join("nested").join("state.json"));
+
+    // Our own PID is alive; PIDs above the kernel's pid_max never are
+    let mut instances = HashMap::new();
+    instances.insert("alive".to_string(), test_instance("alive", std::process::id()));
+    instances.insert("dead".to_string(), test_instance("dead", u32::MAX));
+    // Our PID, but recorded for a process that started long ago
+    let mut reused = test_instance("reused", std::process::id());
+    reused.process_started_at = Some(1);
+    instances.insert("reused".to_string(), reused);
+
+    state_file.save(&instances).unwrap();
+    let test_binary = std::env::current_exe().unwrap();
+    let loaded = state_file.load(&test_binary.to_string_lossy()).unwrap();
+
+    assert_eq!(loaded.len(), 1);
+    let restored = &loaded["alive"];
+    assert_eq!(restored.pid, std::process::id());
+    assert_eq!(restored.title, "title-alive");
+    assert_eq!(restored.created_at, 1234567890);
+    assert_eq!(restored.tags, vec!["build"]);
+
+    // The PID is alive, but it is not running Alacritty
+    assert!(state_file.load("/usr/bin/alacritty").unwrap().is_empty());
+}
+
+#[test]
+fn test_state_file_missing_is_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = StateFile::new(dir.path().join("state.json"));
+
+    assert!(state_file.load("alacritty").unwrap().is_empty());
+
+    // A manager pointed at a missing file starts empty and does not fail
+    let _manager = AlacrittyManager::with_config_and_state_file(Config::default(), Some(state_file));
+}
+
+#[test]
+fn test_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+
+    std::fs::write(&path, "alacritty_binary = \"/opt/alacritty/bin/alacritty\"\nsocket_timeout_secs = 2\nstate_file_path = \"/tmp/state.json\"\n").unwrap();
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.path(), Some(path.as_path()));
+    assert_eq!(config.alacritty_binary, "/opt/alacritty/bin/alacritty");
+    assert_eq!(config.socket_timeout(), Duration::from_secs(2));
+    assert_eq!(config.state_file_path, Some("/tmp/state.json".into()));
+    // Fields left out keep their defaults
+    assert_eq!(config.default_working_directory, None);
+    assert_eq!(config.title_prefix(), "alacritty-mcp");
+
+    let defaults = Config::default();
+    assert_eq!(defaults.alacritty_binary, "alacritty");
+    assert_eq!(defaults.socket_timeout_secs, 5);
+    assert_eq!(defaults.path(), None);
+
+    std::fs::write(&path, "alacrity_binary = \"typo\"\n").unwrap();
+    assert!(Config::load(&path).unwrap_err().to_string().contains("Invalid config file"));
+    assert!(Config::load(dir.path().join("missing.toml")).is_err());
+}
+
+#[test]
+fn test_config_default_path() {
+    let original_xdg = std::env::var_os("XDG_CONFIG_HOME");
+    let original_home = std::env::var_os("HOME");
+
+    std::env::set_var("XDG_CONFIG_HOME", "/xdg");
+    assert_eq!(Config::default_path(), Some("/xdg/alacritty-mcp/config.toml".into()));
+    std::env::remove_var("XDG_CONFIG_HOME");
+    std::env::set_var("HOME", "/home/user");
+    assert_eq!(Config::default_path(), Some("/home/user/.config/alacritty-mcp/config.toml".into()));
+
+    match original_xdg {
+        Some(dir) => std::env::set_var("XDG_CONFIG_HOME", dir),
+        None => std::env::remove_var("XDG_CONFIG_HOME"),
+    }
+    match original_home {
+        Some(dir) => std::env::set_var("HOME", dir),
+        None => std::env::remove_var("HOME"),
+    }
 }
 
 #[tokio::test]
@@ -35,7 +218,9 @@ async fn test_spawn_params_deserialization() {
         "command": "bash",
         "args": ["-c", "echo hello"],
         "working_directory": "/tmp",
-        "title": "Test Terminal"
+        "title": "Test Terminal",
+        "env": {"MY_VAR": "my value"},
+        "tags": ["build", "frontend"]
     });
 
     let params: SpawnParams = serde_json::from_value(json_data).unwrap();
@@ -44,6 +229,8 @@ async fn test_spawn_params_deserialization() {
     assert_eq!(params.args, Some(vec!["-c".to_string(), "echo hello".to_string()]));
     assert_eq!(params.working_directory, Some("/tmp".to_string()));
     assert_eq!(params.title, Some("Test Terminal".to_string()));
+    assert_eq!(params.env.unwrap()["MY_VAR"], "my value");
+    assert_eq!(params.tags, Some(vec!["build".to_string(), "frontend".to_string()]));
 }
 
 #[tokio::test]
@@ -56,6 +243,7 @@ async fn test_spawn_params_minimal() {
     assert_eq!(params.args, None);
     assert_eq!(params.working_directory, None);
     assert_eq!(params.title, None);
+    assert_eq!(params.env, None);
 }
 
 #[tokio::test]
@@ -71,6 +259,21 @@ async fn test_send_keys_params() {
     assert_eq!(params.keys, "ctrl+c");
 }
 
+#[tokio::test]
+async fn test_send_text_params() {
+    let json_data = json!({
+        "instance_id": "test-id",
+        "text": "echo \"héllo wörld\" | grep -o 'ö'",
+        "delay_ms": 12
+    });
+
+    let params: SendTextParams = serde_json::from_value(json_data).unwrap();
+    
+    assert_eq!(params.instance_id, "test-id");
+    assert_eq!(params.text, "echo \"héllo wörld\" | grep -o 'ö'");
+    assert_eq!(params.delay_ms, Some(12));
+}
+
 #[tokio::test]
 async fn test_screenshot_params() {
     let json_data = json!({
@@ -96,6 +299,43 @@ async fn test_screenshot_params_default_format() {
     assert_eq!(params.format, None);
 }
 
+#[test]
+fn test_scrollback_page_slice() {
+    let text = "line 0\nline 1\nline 2\nline 3\nline 4";
+
+    let page = ScrollbackPage::slice(text, None, None);
+    assert_eq!(page.total_lines, 5);
+    assert_eq!(page.lines.len(), 5);
+
+    let page = ScrollbackPage::slice(text, Some(1), Some(3));
+    assert_eq!((page.start_line, page.end_line), (1, 3));
+    assert_eq!(page.lines, vec!["line 1", "line 2"]);
+
+    // Out-of-range bounds are clamped rather than rejected
+    let page = ScrollbackPage::slice(text, Some(4), Some(100));
+    assert_eq!((page.start_line, page.end_line), (4, 5));
+    assert_eq!(page.lines, vec!["line 4"]);
+
+    let page = ScrollbackPage::slice(text, Some(10), None);
+    assert!(page.lines.is_empty());
+}
+
+#[tokio::test]
+async fn test_close_params() {
+    let json_data = json!({
+        "instance_id": "test-id",
+        "timeout_ms": 250
+    });
+
+    let params: CloseParams = serde_json::from_value(json_data).unwrap();
+    
+    assert_eq!(params.instance_id, "test-id");
+    assert_eq!(params.timeout_ms, Some(250));
+
+    let params: CloseParams = serde_json::from_value(json!({"instance_id": "test-id"})).unwrap();
+    assert_eq!(params.timeout_ms, None);
+}
+
 #[tokio::test]
 async fn test_json_rpc_request_deserialization() {
     let json_data = json!({
@@ -158,7 +398,6 @@ async fn test_json_rpc_error_response() {
 
 #[test]
 fn test_base64_encoding() {
-    // Test our custom base64 implementation
     use alacritty_mcp::alacritty_manager::base64;
     
     let test_data = b"Hello, World!";
@@ -166,12 +405,424 @@ fn test_base64_encoding() {
     
     // This should match the standard base64 encoding
     assert_eq!(encoded, "SGVsbG8sIFdvcmxkIQ==");
+    assert_eq!(base64::decode(&encoded).unwrap(), test_data);
     
     let empty_data = b"";
     let encoded_empty = base64::encode(empty_data);
     assert_eq!(encoded_empty, "");
+    assert!(base64::decode(&encoded_empty).unwrap().is_empty());
     
     let single_byte = b"A";
     let encoded_single = base64::encode(single_byte);
     assert_eq!(encoded_single, "QQ==");
-}
\ No newline at end of file
+    assert_eq!(base64::decode(&encoded_single).unwrap(), single_byte);
+
+    // Round-trip every byte value, which exercises all padding lengths
+    let all_bytes: Vec<u8> = (0..=255).collect();
+    for len in 0..all_bytes.len() {
+        let data = &all_bytes[..len];
+        assert_eq!(base64::decode(&base64::encode(data)).unwrap(), data);
+    }
+
+    assert!(base64::decode("not base64!").is_err());
+
+    // Whitespace is skipped, but padding must be complete and correct
+    assert_eq!(base64::decode("SGVs bG8s\nIFdv\r\ncmxk IQ==").unwrap(), test_data);
+    assert!(base64::decode("SGVsbG8sIFdvcmxkIQ").is_err());
+    assert!(base64::decode("SGVsbG8sIFdvcmxkIQ=").is_err());
+    assert!(base64::decode("QQ===").is_err());
+}
+
+#[test]
+fn test_base64_url_safe() {
+    use alacritty_mcp::alacritty_manager::base64;
+
+    // 0xfb 0xff encodes to `+/8=` in the standard alphabet
+    let data = [0xfb, 0xff];
+    assert_eq!(base64::encode(&data), "+/8=");
+    assert_eq!(base64::encode_url_safe(&data), "-_8=");
+    assert_eq!(base64::decode_url_safe("-_8=").unwrap(), data);
+    assert_eq!(base64::decode_url_safe("-_8").unwrap(), data);
+    assert_eq!(base64::decode_url_safe(" -_\n8= ").unwrap(), data);
+
+    // Each decoder rejects the other's alphabet
+    assert!(base64::decode("-_8=").is_err());
+    assert!(base64::decode_url_safe("+/8=").is_err());
+
+    let all_bytes: Vec<u8> = (0..=255).collect();
+    for len in 0..all_bytes.len() {
+        let data = &all_bytes[..len];
+        assert_eq!(base64::decode_url_safe(&base64::encode_url_safe(data)).unwrap(), data);
+    }
+}
+#[test]
+fn test_rate_limiter_token_bucket() {
+    let mut limiter = RateLimiter::new(2.0);
+    let start = Instant::now();
+
+    // A full bucket allows a burst of `rate` requests, then callers must wait
+    assert!(limiter.check_at("tools/list", start).is_ok());
+    assert!(limiter.check_at("tools/list", start).is_ok());
+    let retry_after = limiter.check_at("tools/list", start).unwrap_err();
+    assert_eq!(retry_after, Duration::from_millis(500));
+
+    // Each key has its own bucket
+    assert!(limiter.check_at("tools/call:send_keys", start).is_ok());
+
+    // Half a second refills one token at 2 requests per second
+    let later = start + Duration::from_millis(500);
+    assert!(limiter.check_at("tools/list", later).is_ok());
+    assert!(limiter.check_at("tools/list", later).is_err());
+}
+
+#[test]
+fn test_instance_filter() {
+    let mut instances = HashMap::new();
+    let mut build = test_instance("build", 1);
+    build.title = "cargo build".to_string();
+    build.command = "cargo".to_string();
+    build.window_id = Some(4242);
+    instances.insert(build.id.clone(), build);
+    let mut logs = test_instance("logs", 2);
+    logs.title = "tail logs".to_string();
+    logs.command = "tail".to_string();
+    instances.insert(logs.id.clone(), logs);
+    instances.insert("shell".to_string(), test_instance("shell", 3));
+
+    let matching = |filter: InstanceFilter| {
+        let mut ids: Vec<String> = instances.values()
+            .filter(|instance| filter.matches(instance))
+            .map(|instance| instance.id.clone())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(matching(InstanceFilter::default()), vec!["build", "logs", "shell"]);
+    assert_eq!(matching(InstanceFilter { title_contains: Some("logs".to_string()), ..Default::default() }), vec!["logs"]);
+    assert_eq!(matching(InstanceFilter { command_eq: Some("shell".to_string()), ..Default::default() }), vec!["shell"]);
+    assert_eq!(matching(InstanceFilter { command_eq: Some("car".to_string()), ..Default::default() }), Vec::<String>::new());
+    assert_eq!(matching(InstanceFilter { has_window: Some(true), ..Default::default() }), vec!["build"]);
+    assert_eq!(matching(InstanceFilter { has_window: Some(false), ..Default::default() }), vec!["logs", "shell"]);
+
+    // Conditions combine with AND
+    let filter = InstanceFilter {
+        title_contains: Some("l".to_string()),
+        command_eq: None,
+        has_window: Some(false),
+    };
+    assert_eq!(matching(filter), vec!["logs", "shell"]);
+    let filter = InstanceFilter {
+        title_contains: Some("cargo".to_string()),
+        command_eq: Some("cargo".to_string()),
+        has_window: Some(false),
+    };
+    assert_eq!(matching(filter), Vec::<String>::new());
+
+    let params: ListInstancesParams = serde_json::from_value(json!({"filter": {"has_window": true}})).unwrap();
+    assert_eq!(params.filter.unwrap().has_window, Some(true));
+    assert!(serde_json::from_value::<ListInstancesParams>(json!({})).unwrap().filter.is_none());
+}
+
+#[test]
+fn test_prompt_library_substitutes_arguments() {
+    let library = PromptLibrary::builtin();
+    let names: Vec<&str> = library.list().iter().map(|prompt| prompt.name.as_str()).collect();
+    assert_eq!(names, vec!["run_command_and_wait", "open_file_in_neovim", "build_project"]);
+
+    let arguments = HashMap::from([
+        ("instance_id".to_string(), "abc".to_string()),
+        ("command".to_string(), "make test".to_string()),
+    ]);
+    let (_, messages) = library.get("run_command_and_wait", &arguments).unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].role, "user");
+    assert!(messages[0].content.text.contains("instance abc"));
+    assert!(messages[0].content.text.contains("`make test`"));
+    assert!(!messages[0].content.text.contains("{{"));
+
+    let missing = HashMap::from([("instance_id".to_string(), "abc".to_string())]);
+    let err = library.get("run_command_and_wait", &missing).unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+    assert!(matches!(library.get("no_such_prompt", &arguments).unwrap_err().downcast_ref(), Some(AlacrittyMcpError::InvalidParams(_))));
+}
+
+#[test]
+fn test_prompt_library_loads_user_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("prompts.toml");
+    std::fs::write(&path, r#"
+[[prompts]]
+name = "build_project"
+description = "Custom build"
+template = "Run make in {{instance_id}}"
+
+[[prompts.arguments]]
+name = "instance_id"
+description = "Instance"
+required = true
+
+[[prompts]]
+name = "greet"
+description = "Say hello"
+template = "Hello {{who}}"
+
+[[prompts.arguments]]
+name = "who"
+description = "Who to greet"
+"#).unwrap();
+
+    let mut library = PromptLibrary::builtin();
+    library.load_file(&path).unwrap();
+    library.load_file(&dir.path().join("missing.toml")).unwrap();
+    assert_eq!(library.list().len(), 4);
+
+    let arguments = HashMap::from([("instance_id".to_string(), "abc".to_string())]);
+    let (prompt, messages) = library.get("build_project", &arguments).unwrap();
+    assert_eq!(prompt.description, "Custom build");
+    assert_eq!(messages[0].content.text, "Run make in abc");
+
+    // Optional arguments default to an empty string
+    let (_, messages) = library.get("greet", &HashMap::new()).unwrap();
+    assert_eq!(messages[0].content.text, "Hello ");
+
+    std::fs::write(&path, "not = [valid").unwrap();
+    assert!(library.load_file(&path).is_err());
+}
+
+#[test]
+fn test_request_id_validation() {
+    let request = |id: serde_json::Value| -> JsonRpcRequest {
+        serde_json::from_value(json!({"jsonrpc": "2.0", "method": "ping", "id": id})).unwrap()
+    };
+
+    for valid in [json!(1), json!(-7), json!(1.0), json!("abc"), json!(null)] {
+        assert!(request(valid.clone()).validate_id().is_ok(), "{} should be accepted", valid);
+    }
+    let without_id: JsonRpcRequest = serde_json::from_value(json!({"jsonrpc": "2.0", "method": "ping"})).unwrap();
+    assert!(without_id.validate_id().is_ok());
+
+    let err = request(json!(1.5)).validate_id().unwrap_err();
+    assert!(err.contains("fractional"));
+    assert!(request(json!(-0.25)).validate_id().is_err());
+    assert!(request(json!({"nested": 1})).validate_id().is_err());
+    assert!(request(json!([1])).validate_id().is_err());
+    assert!(request(json!(true)).validate_id().is_err());
+}
+
+#[test]
+fn test_terminal_parser() {
+    assert_eq!(strip_ansi("\x1b[1;32mok\x1b[0m done\r"), "ok done");
+    assert_eq!(strip_ansi("\x1b]0;title\x07\x1b(Bplain\x1b]8;;http://x\x1b\\link"), "plainlink");
+
+    let grid = TerminalParser::new().parse("\x1b[32muser@host\x1b[0m:~$ \ncompiling...\n\n% \nroot# ls\n");
+    assert_eq!(grid.rows.len(), 5);
+    assert_eq!(grid.rows[0].text, "user@host:~$ ");
+    assert!(grid.rows[0].raw.starts_with("\x1b[32m"));
+    assert_eq!(grid.cols, 13);
+
+    let prompts: Vec<bool> = grid.rows.iter().map(|row| row.has_prompt).collect();
+    assert_eq!(prompts, vec![true, false, false, true, false]);
+    assert!(TerminalParser::new().parse("").rows.is_empty());
+}
+
+#[test]
+fn test_minimap() {
+    // Short lines are padded with blanks, escape sequences take no space
+    assert_eq!(minimap("ab  \n    \n\x1b[1mxy\x1b[0m"), "##..\n....\n##..\n");
+    assert_eq!(minimap(""), "");
+
+    // 80x40: each cell covers 2x2 characters
+    let screen: String = (0..40)
+        .map(|row| if row < 20 { format!("{}\n", "x".repeat(80)) } else { format!("{}\n", " ".repeat(80)) })
+        .collect();
+    let map = minimap(&screen);
+    let rows: Vec<&str> = map.lines().collect();
+    assert_eq!(rows.len(), MINIMAP_ROWS);
+    assert!(rows.iter().all(|row| row.len() == MINIMAP_COLS));
+    assert_eq!(rows[0], "#".repeat(40));
+    assert_eq!(rows[19], ".".repeat(40));
+
+    // Never larger than 40x20, even for odd sizes
+    let map = minimap(&"y".repeat(101));
+    assert_eq!(map, format!("{}\n", "#".repeat(34)));
+}
+
+#[test]
+fn test_diff_screens() {
+    let before = "$ ls\na.txt\n$ ";
+    let after = "$ ls\na.txt\n$ make\nok\n$ ";
+    let diff = diff_screens(before, after);
+    // The old prompt line lines up with the new one at the bottom
+    assert_eq!(diff.added, vec!["$ make", "ok"]);
+    assert!(diff.removed.is_empty());
+    assert_eq!(diff.unchanged_count, 3);
+
+    // Scrolling drops lines off the top
+    let diff = diff_screens("one\ntwo\nthree", "two\nthree\nfour");
+    assert_eq!(diff.added, vec!["four"]);
+    assert_eq!(diff.removed, vec!["one"]);
+    assert_eq!(diff.unchanged_count, 2);
+
+    let diff = diff_screens("same", "same");
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+    assert_eq!(diff.unchanged_count, 1);
+}
+
+#[test]
+fn test_command_output() {
+    let prompt = Regex::new(DEFAULT_PROMPT_PATTERN).unwrap();
+    let screen = "old output\nuser@host:~$ echo hi\nhi\nthere\nuser@host:~$ ";
+
+    assert_eq!(command_output(screen, 1, "echo hi", &prompt), Some(("hi\nthere".to_string(), None)));
+    // Still running: no prompt after the command yet
+    assert_eq!(command_output("user@host:~$ sleep 5\n", 0, "sleep 5", &prompt), None);
+    // Only rows from `from_line` on are searched for the command
+    assert_eq!(command_output(screen, 4, "echo hi", &prompt), None);
+
+    // Continuation rows of a multi-line command are not taken for the prompt
+    let screen = "$ for i in 1 2\n> do echo $i; done\n1\n2\n$ ";
+    assert_eq!(command_output(screen, 0, "for i in 1 2\ndo echo $i; done", &prompt), Some(("1\n2".to_string(), None)));
+
+    // A prompt that shows `$?` gives the exit code
+    let prompt = Regex::new(r"^\[(?P<exit_code>\d+)\] \$ ").unwrap();
+    let screen = "[0] $ false\n\x1b[31merror\x1b[0m\n[1] $ ";
+    assert_eq!(command_output(screen, 0, "false", &prompt), Some(("error".to_string(), Some(1))));
+}
+
+#[tokio::test]
+async fn test_in_flight_requests() {
+    let params = json!({"id": 7, "requestId": "abc"});
+    assert_eq!(cancelled_request_id("$/cancelRequest", Some(&params)), Some(&json!(7)));
+    assert_eq!(cancelled_request_id("notifications/cancelled", Some(&params)), Some(&json!("abc")));
+    assert_eq!(cancelled_request_id("tools/call", Some(&params)), None);
+    assert_eq!(cancelled_request_id("$/cancelRequest", None), None);
+
+    let in_flight = InFlightRequests::new();
+    let task = tokio::spawn(tokio::time::sleep(Duration::from_secs(60)));
+    in_flight.insert(&json!(7), task.abort_handle());
+
+    // Numeric and string IDs are different requests
+    assert!(!in_flight.cancel(&json!("7")));
+    // Requests with an ID of their own are not cancellations
+    assert_eq!(cancellation_target(r#"{"jsonrpc": "2.0", "method": "ping", "id": 7}"#), None);
+    let id = cancellation_target(r#"{"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": 7}}"#).unwrap();
+    assert!(in_flight.cancel(&id));
+    assert!(task.await.unwrap_err().is_cancelled());
+    assert!(!in_flight.cancel(&json!(7)));
+}
+
+#[test]
+fn test_error_codes() {
+    let cases = [
+        (AlacrittyMcpError::InvalidParams("bad".to_string()), -32602),
+        (AlacrittyMcpError::InstanceNotFound("id".to_string()), -32001),
+        (AlacrittyMcpError::WindowNotFound(42), -32003),
+        (AlacrittyMcpError::XdotoolFailed("Failed to focus window: BadWindow".to_string()), -32004),
+        (AlacrittyMcpError::NeovimSocketNotFound(42), -32005),
+        (AlacrittyMcpError::NeovimRpcTimeout { command: "nvim".to_string(), timeout: Duration::from_secs(5) }, -32006),
+    ];
+    for (error, code) in cases {
+        assert_eq!(error.code(), code, "{:?}", error);
+        assert_eq!(error_code(&anyhow::Error::from(error)), code);
+    }
+
+    // Context added on top does not hide the variant
+    let wrapped = anyhow::Error::from(AlacrittyMcpError::WindowNotFound(7)).context("While focusing");
+    assert_eq!(error_code(&wrapped), -32003);
+    assert_eq!(error_code(&anyhow::anyhow!("something else")), -32603);
+
+    assert_eq!(AlacrittyMcpError::InstanceNotFound("abc".to_string()).to_string(), "Instance not found: abc");
+    assert_eq!(AlacrittyMcpError::NeovimSocketNotFound(9).to_string(), "Could not find Neovim socket for PID 9");
+}
+
+#[test]
+fn test_search_output_parsing() {
+    let rg = [
+        r#"{"type":"begin","data":{"path":{"text":"src/main.rs"}}}"#,
+        r#"{"type":"context","data":{"path":{"text":"src/main.rs"},"lines":{"text":"// é\n"},"line_number":1,"submatches":[]}}"#,
+        r#"{"type":"match","data":{"path":{"text":"src/main.rs"},"lines":{"text":"é fn main() {\n"},"line_number":2,"submatches":[{"match":{"text":"main"},"start":6,"end":10}]}}"#,
+        r#"{"type":"match","data":{"path":{"text":"src/main.rs"},"lines":{"text":"    main();\n"},"line_number":3,"submatches":[{"match":{"text":"main"},"start":4,"end":8}]}}"#,
+        r#"{"type":"context","data":{"path":{"text":"src/main.rs"},"lines":{"text":"}\n"},"line_number":4,"submatches":[]}}"#,
+        r#"{"type":"end","data":{"path":{"text":"src/main.rs"}}}"#,
+        r#"{"type":"summary","data":{}}"#,
+    ].join("\n");
+    let results = parse_rg_json(&rg, 2, 10);
+    assert_eq!(results.len(), 2);
+    // Columns count characters, not bytes
+    assert_eq!((results[0].file_path.as_str(), results[0].line, results[0].column), ("src/main.rs", 2, 6));
+    assert_eq!(results[0].match_text, "main");
+    assert_eq!(results[0].context_before, vec!["// é"]);
+    // A neighbouring match is context too
+    assert_eq!(results[0].context_after, vec!["    main();", "}"]);
+    assert_eq!(results[1].context_before, vec!["// é", "é fn main() {"]);
+    assert_eq!(parse_rg_json(&rg, 2, 1).len(), 1);
+
+    let grep = "./a.txt\x001-before\n./a.txt\x002:say hello\n--\nb-c.txt\x0010:hello-world: again\n";
+    let regex = Regex::new("hel+o").unwrap();
+    let results = parse_grep_output(grep, &regex, 2, 10);
+    assert_eq!(results.len(), 2);
+    assert_eq!((results[0].file_path.as_str(), results[0].line, results[0].column), ("a.txt", 2, 5));
+    assert_eq!(results[0].context_before, vec!["before"]);
+    assert!(results[0].context_after.is_empty());
+    assert_eq!((results[1].file_path.as_str(), results[1].line), ("b-c.txt", 10));
+    assert_eq!(results[1].match_text, "hello");
+
+    // A match the Rust regex does not see again covers the whole line
+    let results = parse_grep_output("a.txt\x003:[[:digit:]]\n", &Regex::new("x").unwrap(), 2, 10);
+    assert_eq!((results[0].column, results[0].match_text.as_str()), (1, "[[:digit:]]"));
+}
+
+#[test]
+fn test_memory_usage_parsing() {
+    let status = "Name:\talacritty\nVmPeak:\t  912340 kB\nVmSize:\t  901200 kB\nVmRSS:\t  123456 kB\nVmSwap:\t       8 kB\n";
+    let usage = MemoryUsage::from_proc_status(status);
+    assert_eq!(usage, MemoryUsage { rss_kb: 123456, virtual_kb: 901200, swap_kb: 8 });
+
+    // Zombies have no Vm* lines at all
+    assert_eq!(MemoryUsage::from_proc_status("Name:\talacritty\nState:\tZ (zombie)\n"), MemoryUsage::default());
+
+    let total: MemoryUsage = [usage, MemoryUsage { rss_kb: 4, virtual_kb: 0, swap_kb: 2 }].into_iter().sum();
+    assert_eq!(total, MemoryUsage { rss_kb: 123460, virtual_kb: 901200, swap_kb: 10 });
+}
+
+#[test]
+fn test_monitor_screenshot_count() {
+    assert_eq!(monitor_screenshot_count(5, 500), 10);
+    assert_eq!(monitor_screenshot_count(1, 300), 3);
+    // Always at least one, never more than the cap
+    assert_eq!(monitor_screenshot_count(0, 1000), 1);
+    assert_eq!(monitor_screenshot_count(1, 5000), 1);
+    assert_eq!(monitor_screenshot_count(3600, 10), MAX_MONITOR_SCREENSHOTS);
+    assert_eq!(monitor_screenshot_count(u32::MAX, 1), MAX_MONITOR_SCREENSHOTS);
+}
+
+#[test]
+fn test_tool_content_serialization() {
+    let image = ToolContent::from_data_url("data:image/png;base64,UE5H").unwrap();
+    assert_eq!(serde_json::to_value(&image).unwrap(), serde_json::json!({
+        "type": "image",
+        "data": "UE5H",
+        "mimeType": "image/png"
+    }));
+    assert!(ToolContent::from_data_url("UE5H").is_none());
+    assert!(ToolContent::from_data_url("data:image/png,UE5H").is_none());
+
+    let text = ToolContent::from("hello".to_string());
+    assert_eq!(serde_json::to_value(&text).unwrap(), serde_json::json!({"type": "text", "text": "hello"}));
+}
+
+#[test]
+fn test_secret_env_keys() {
+    for key in ["GITHUB_TOKEN", "AWS_SECRET_ACCESS_KEY", "db_password", "SSH_KEY_PATH", "ApiKey"] {
+        assert!(is_secret_env_key(key), "{}", key);
+    }
+    for key in ["PATH", "HOME", "TERM", "LANG"] {
+        assert!(!is_secret_env_key(key), "{}", key);
+    }
+
+    // Our own environment, as the kernel recorded it at exec
+    let environment = read_environment(std::process::id()).unwrap();
+    assert!(environment.windows(2).all(|pair| pair[0].key <= pair[1].key));
+    assert!(read_environment(u32::MAX).is_err());
+}